@@ -0,0 +1,130 @@
+# 按通达信公式独立计算默认参数下的指标数值, 生成 sh603339.txt, 不依赖本仓库的实现
+# python3 reference.py ../sh/lday/sh603339.day 20220801
+import math, struct, sys
+NAN = float('nan')
+def isnan(x): return x != x
+
+def load(path):
+    d = open(path, 'rb').read()
+    rows = []
+    for i in range(0, len(d), 32):
+        date, o, h, l, c, amount, vol, _ = struct.unpack('<IIIIIfII', d[i:i+32])
+        rows.append((date, o/100, h/100, l/100, c/100, float(vol), amount))
+    return rows
+
+def ref(x, n): return [x[i-n] if i >= n else NAN for i in range(len(x))]
+def ma(x, n):
+    out = []
+    for i in range(len(x)):
+        if i+1 < n: out.append(NAN); continue
+        w = x[i+1-n:i+1]
+        out.append(NAN if any(isnan(v) for v in w) else sum(w)/n)
+    return out
+def sum_(x, n):
+    out = []
+    for i in range(len(x)):
+        w = x[:i+1] if n == 0 else x[max(0, i+1-n):i+1]
+        v = [a for a in w if not isnan(a)]
+        out.append(sum(v) if v else NAN)
+    return out
+def hhv(x, n): return [max(x[max(0, i+1-n):i+1]) for i in range(len(x))]
+def llv(x, n): return [min(x[max(0, i+1-n):i+1]) for i in range(len(x))]
+def std(x, n):
+    out = []
+    for i in range(len(x)):
+        if i+1 < n: out.append(NAN); continue
+        w = x[i+1-n:i+1]; m = sum(w)/n
+        out.append(math.sqrt(sum((a-m)**2 for a in w)/(n-1)))
+    return out
+def avedev(x, n):
+    out = []
+    for i in range(len(x)):
+        if i+1 < n: out.append(NAN); continue
+        w = x[i+1-n:i+1]; m = sum(w)/n
+        out.append(sum(abs(a-m) for a in w)/n)
+    return out
+def sma(x, n, m):
+    out, y = [], NAN
+    for a in x:
+        if not isnan(a):
+            y = a if isnan(y) else (m*a + (n-m)*y)/n
+        out.append(y)
+    return out
+def ema(x, n): return sma(x, n+1, 2)
+def div(a, b): return [NAN if isnan(p) or isnan(q) else (0.0 if q == 0 else p/q) for p, q in zip(a, b)]
+def op(f, *xs): return [NAN if any(isnan(v) for v in vs) else f(*vs) for vs in zip(*xs)]
+
+rows = load(sys.argv[1])
+D = [r[0] for r in rows]; O = [r[1] for r in rows]; H = [r[2] for r in rows]
+L = [r[3] for r in rows]; C = [r[4] for r in rows]; V = [r[5] for r in rows]
+RC = ref(C, 1)
+cols = []
+
+# KDJ(9,3,3)
+rsv = op(lambda x: x*100, div(op(lambda c, l: c-l, C, llv(L, 9)), op(lambda h, l: h-l, hhv(H, 9), llv(L, 9))))
+K = sma(rsv, 3, 1); Dd = sma(K, 3, 1)
+cols += [('KDJ.K', K), ('KDJ.D', Dd), ('KDJ.J', op(lambda k, d: 3*k-2*d, K, Dd))]
+# BOLL(20,2)
+B = ma(C, 20); S = std(C, 20)
+cols += [('BOLL.BOLL', B), ('BOLL.UB', op(lambda b, s: b+2*s, B, S)), ('BOLL.LB', op(lambda b, s: b-2*s, B, S))]
+# DMI(14,6)
+tr = op(lambda h, l, rc: max(h-l, abs(h-rc), abs(rc-l)), H, L, RC)
+mtr = sum_(tr, 14)
+hd = op(lambda h, p: h-p, H, ref(H, 1)); ld = op(lambda p, l: p-l, ref(L, 1), L)
+dmp = sum_(op(lambda a, b: a if a > 0 and a > b else 0.0, hd, ld), 14)
+dmm = sum_(op(lambda a, b: b if b > 0 and b > a else 0.0, hd, ld), 14)
+pdi = div(op(lambda x: x*100, dmp), mtr); mdi = div(op(lambda x: x*100, dmm), mtr)
+adx = ma(op(lambda x: x*100, div(op(lambda m, p: abs(m-p), mdi, pdi), op(lambda m, p: m+p, mdi, pdi))), 6)
+adxr = op(lambda a, b: (a+b)/2, adx, ref(adx, 6))
+cols += [('DMI.PDI', pdi), ('DMI.MDI', mdi), ('DMI.ADX', adx), ('DMI.ADXR', adxr)]
+# CCI(14)
+typ = [(h+l+c)/3 for h, l, c in zip(H, L, C)]
+cols += [('CCI.CCI', div(op(lambda t, m: t-m, typ, ma(typ, 14)), op(lambda a: 0.015*a, avedev(typ, 14))))]
+# BIAS(6,12,24)
+for n, name in [(6, 'BIAS1'), (12, 'BIAS2'), (24, 'BIAS3')]:
+    m = ma(C, n); cols.append(('BIAS.' + name, op(lambda x: x*100, div(op(lambda c, a: c-a, C, m), m))))
+# PSY(12,6)
+psy = op(lambda x: x/12*100, sum_(op(lambda c, r: 1.0 if c > r else 0.0, C, RC), 12))
+cols += [('PSY.PSY', psy), ('PSY.PSYMA', ma(psy, 6))]
+# OBV(30)
+va = op(lambda c, r, v: v if c > r else -v, C, RC, V)
+obv = sum_(op(lambda c, r, a: 0.0 if c == r else a, C, RC, va), 0)
+cols += [('OBV.OBV', obv), ('OBV.MAOBV', ma(obv, 30))]
+# VR(26,6)
+th = sum_(op(lambda c, r, v: v if c > r else 0.0, C, RC, V), 26)
+tl = sum_(op(lambda c, r, v: v if c < r else 0.0, C, RC, V), 26)
+tq = sum_(op(lambda c, r, v: v if c == r else 0.0, C, RC, V), 26)
+vr = op(lambda x: 100*x, div(op(lambda h, q: h*2+q, th, tq), op(lambda l, q: l*2+q, tl, tq)))
+cols += [('VR.VR', vr), ('VR.MAVR', ma(vr, 6))]
+# WR(10,6)
+for n, name in [(10, 'WR1'), (6, 'WR2')]:
+    hh, ll = hhv(H, n), llv(L, n)
+    cols.append(('WR.' + name, op(lambda x: 100*x, div(op(lambda h, c: h-c, hh, C), op(lambda h, l: h-l, hh, ll)))))
+# BRAR(26)
+br = op(lambda x: x*100, div(sum_(op(lambda h, r: max(0.0, h-r), H, RC), 26), sum_(op(lambda r, l: max(0.0, r-l), RC, L), 26)))
+ar = op(lambda x: x*100, div(sum_([h-o for h, o in zip(H, O)], 26), sum_([o-l for o, l in zip(O, L)], 26)))
+cols += [('BRAR.BR', br), ('BRAR.AR', ar)]
+# CR(26,10,20,40,62)
+mid = op(lambda x: x/2, ref([h+l for h, l in zip(H, L)], 1))
+cr = op(lambda x: x*100, div(sum_(op(lambda h, m: max(0.0, h-m), H, mid), 26), sum_(op(lambda m, l: max(0.0, m-l), mid, L), 26)))
+cols.append(('CR.CR', cr))
+for i, m in enumerate([10, 20, 40, 62]):
+    cols.append(('CR.MA%d' % (i+1), ref(ma(cr, m), int(m/2.5+1))))
+# TRIX(12,9)
+mtr3 = ema(ema(ema(C, 12), 12), 12)
+trix = op(lambda x: x*100, div(op(lambda a, b: a-b, mtr3, ref(mtr3, 1)), ref(mtr3, 1)))
+cols += [('TRIX.TRIX', trix), ('TRIX.MATRIX', ma(trix, 9))]
+# DMA(10,50,10)
+dif = op(lambda a, b: a-b, ma(C, 10), ma(C, 50))
+cols += [('DMA.DIF', dif), ('DMA.DIFMA', ma(dif, 10))]
+# EXPMA(12,50)
+cols += [('EXPMA.EXP1', ema(C, 12)), ('EXPMA.EXP2', ema(C, 50))]
+# MACD(12,26,9)
+dif = op(lambda a, b: a-b, ema(C, 12), ema(C, 26)); dea = ema(dif, 9)
+cols += [('MACD.DIF', dif), ('MACD.DEA', dea), ('MACD.MACD', op(lambda a, b: (a-b)*2, dif, dea))]
+
+start = int(sys.argv[2])
+print('日期\t' + '\t'.join(name for name, _ in cols))
+for i, d in enumerate(D):
+    if d < start: continue
+    print('%d\t' % d + '\t'.join('' if isnan(v[i]) else '%.3f' % v[i] for _, v in cols))
@@ -0,0 +1,117 @@
+use rust_decimal::prelude::ToPrimitive;
+use serde::{Deserialize, Serialize};
+
+use crate::{data::DayTradeUnit, event::CorporateAction};
+
+/// 复权方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Adjust {
+    /// 不复权
+    #[default]
+    None,
+    /// 前复权, 最新价格不变, 历史价格向下调整
+    Forward,
+    /// 后复权, 上市首日价格不变, 之后的价格向上调整
+    Backward,
+}
+
+/// 每根K线的后复权因子, 后复权价格 = 原始价格 * 因子
+///
+/// 除权除息日的因子按前一日收盘价计算:
+/// 除权参考价 = (前收盘 - 每股派息 + 配股价 * 每股配股) / (1 + 每股送转 + 每股配股)
+pub fn factors(bars: &[DayTradeUnit], actions: &[CorporateAction]) -> Vec<f64> {
+    let mut factor = 1.0;
+    let mut factors = Vec::with_capacity(bars.len());
+    for (i, bar) in bars.iter().enumerate() {
+        if i > 0 {
+            let prev = &bars[i - 1];
+            let mut close = prev.trade_data.close as f64 / 100.0;
+            for action in actions.iter().filter(|action| action.date > prev.date && action.date <= bar.date) {
+                let cash = action.cash_per_share.to_f64().unwrap_or(0.0);
+                let shares = action.shares_per_share.to_f64().unwrap_or(0.0);
+                let rights_price = action.rights_price.to_f64().unwrap_or(0.0);
+                let rights = action.rights_per_share.to_f64().unwrap_or(0.0);
+                let reference = (close - cash + rights_price * rights) / (1.0 + shares + rights);
+                if reference > 0.0 {
+                    factor *= close / reference;
+                    close = reference;
+                }
+            }
+        }
+        factors.push(factor);
+    }
+    factors
+}
+
+/// 复权后的K线, 只调整价格, 成交量和成交额保持原值
+pub fn adjust(bars: &[DayTradeUnit], actions: &[CorporateAction], mode: Adjust) -> Vec<DayTradeUnit> {
+    let factors = factors(bars, actions);
+    let base = match mode {
+        Adjust::None => return bars.to_vec(),
+        Adjust::Forward => factors.last().copied().unwrap_or(1.0),
+        Adjust::Backward => 1.0,
+    };
+    bars.iter()
+        .zip(factors)
+        .map(|(bar, factor)| {
+            let scale = |price: i32| (price as f64 * factor / base).round() as i32;
+            let mut bar = bar.clone();
+            bar.trade_data.open = scale(bar.trade_data.open);
+            bar.trade_data.high = scale(bar.trade_data.high);
+            bar.trade_data.low = scale(bar.trade_data.low);
+            bar.trade_data.close = scale(bar.trade_data.close);
+            bar
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use crate::{
+        data::{DayTradeUnit, TradeUnit},
+        event::CorporateAction,
+    };
+
+    use super::{adjust, factors, Adjust};
+
+    fn bar(date: i32, close: i32) -> DayTradeUnit {
+        DayTradeUnit { date, trade_data: TradeUnit { open: close, close, high: close, low: close, volume: 100, amount: 0.0 } }
+    }
+
+    #[test]
+    fn adjust_prices() {
+        let bars = vec![bar(20220104, 2000), bar(20220105, 2100), bar(20220107, 1050), bar(20220110, 1100)];
+        // 20220106 每股派息 0 元, 10 送 10
+        let actions = vec![CorporateAction {
+            code: "603339",
+            date: 20220106,
+            cash_per_share: Decimal::ZERO,
+            shares_per_share: Decimal::ONE,
+            rights_price: Decimal::ZERO,
+            rights_per_share: Decimal::ZERO,
+        }];
+        assert_eq!(factors(&bars, &actions), vec![1.0, 1.0, 2.0, 2.0]);
+
+        let closes = |mode| adjust(&bars, &actions, mode).iter().map(|bar| bar.trade_data.close).collect::<Vec<i32>>();
+        assert_eq!(closes(Adjust::None), vec![2000, 2100, 1050, 1100]);
+        assert_eq!(closes(Adjust::Forward), vec![1000, 1050, 1050, 1100]);
+        assert_eq!(closes(Adjust::Backward), vec![2000, 2100, 2100, 2200]);
+
+        // 每股派息 1 元, 除权参考价 20, 因子 21 / 20
+        let action = CorporateAction {
+            code: "603339",
+            date: 20220107,
+            cash_per_share: Decimal::ONE,
+            shares_per_share: Decimal::ZERO,
+            rights_price: Decimal::ZERO,
+            rights_per_share: Decimal::ZERO,
+        };
+        assert!((factors(&bars, std::slice::from_ref(&action))[2] - 1.05).abs() < 1e-9);
+
+        // 10 配 5, 配股价 9 元: 除权参考价 (21 + 9 * 0.5) / 1.5 = 17, 因子 21 / 17
+        let rights = CorporateAction { cash_per_share: Decimal::ZERO, rights_price: Decimal::from(9), rights_per_share: Decimal::new(5, 1), ..action };
+        assert!((factors(&bars, &[rights])[2] - 21.0 / 17.0).abs() < 1e-9);
+    }
+}
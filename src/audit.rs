@@ -0,0 +1,429 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs, io,
+    path::Path,
+};
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{
+    adjust::factors,
+    data::{codes_in, get_day_path_in, intern, price_limit, DataSourceError, DayTradeUnit, DayTradeUnitSize, StockCode},
+    event::CorporateAction,
+};
+
+#[derive(Debug, Error)]
+pub enum AuditError {
+    #[error("read data file fail")]
+    IoError {
+        #[from]
+        source: io::Error,
+    },
+    #[error("data source error")]
+    DataSourceError {
+        #[from]
+        source: DataSourceError,
+    },
+    #[error("export report fail")]
+    JsonError {
+        #[from]
+        source: serde_json::Error,
+    },
+    #[error("malformed gbbq line {0}")]
+    GbbqFormat(usize),
+}
+
+pub type Result<T, E = AuditError> = std::result::Result<T, E>;
+
+/// 数据问题
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Issue {
+    /// 文件无法读取
+    Unreadable { error: String },
+    /// 文件末尾有不完整的记录
+    Truncated { bytes: u64 },
+    /// 首尾之间日历上有而文件中没有的交易日, 停牌也会出现在这里
+    MissingDays { from: i32, to: i32, days: usize },
+    ZeroVolume { date: i32 },
+    /// 与前一根K线日期相同
+    Duplicate { date: i32 },
+    /// 早于前一根K线的日期
+    OutOfOrder { date: i32, previous: i32 },
+    /// 最高价低于开盘收盘价, 最低价高于开盘收盘价, 或价格不为正
+    Ohlc { date: i32, open: i32, high: i32, low: i32, close: i32 },
+    /// 最高或最低价相对除权后的前收盘价涨跌幅超过涨跌停幅度
+    Jump { date: i32, reference: f64, change: f64 },
+    /// 最后一根K线落后日历的交易日数
+    Stale { last: i32, latest: i32, behind: usize },
+}
+
+impl Issue {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Issue::Unreadable { .. } => "unreadable",
+            Issue::Truncated { .. } => "truncated",
+            Issue::MissingDays { .. } => "missing_days",
+            Issue::ZeroVolume { .. } => "zero_volume",
+            Issue::Duplicate { .. } => "duplicate",
+            Issue::OutOfOrder { .. } => "out_of_order",
+            Issue::Ohlc { .. } => "ohlc",
+            Issue::Jump { .. } => "jump",
+            Issue::Stale { .. } => "stale",
+        }
+    }
+}
+
+/// 一个日线文件的检查结果
+#[derive(Debug, Clone, Serialize)]
+pub struct FileAudit {
+    pub code: StockCode,
+    pub bars: usize,
+    pub first: Option<i32>,
+    pub last: Option<i32>,
+    pub issues: Vec<Issue>,
+}
+
+/// 检查报告
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditReport {
+    /// 交易日历的来源, 日历股票代码或 union 表示所有文件日期的并集
+    pub calendar: String,
+    pub trading_days: usize,
+    /// 日历的最后一个交易日
+    pub latest: i32,
+    /// 每类问题的数量
+    pub summary: BTreeMap<&'static str, usize>,
+    pub files: Vec<FileAudit>,
+}
+
+impl AuditReport {
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// 有问题的文件
+    pub fn with_issues(&self) -> impl Iterator<Item = &FileAudit> {
+        self.files.iter().filter(|file| !file.issues.is_empty())
+    }
+}
+
+/**
+ * 检查通达信日线数据
+ *
+ * 交易日历取指数的日线(默认上证指数 sh000001), 指数文件不存在时用所有文件日期的并集
+ *
+ * ```ignore
+ * let report = Audit::new().jump(0.11).actions(load_gbbq("gbbq.csv")?).run(Path::new(&root))?;
+ * ```
+ */
+pub struct Audit {
+    calendar: StockCode,
+    jump: Option<f64>,
+    listing_days: usize,
+    stale_days: usize,
+    actions: HashMap<String, Vec<CorporateAction>>,
+}
+
+impl Default for Audit {
+    fn default() -> Self {
+        Audit { calendar: "sh000001", jump: None, listing_days: 5, stale_days: 0, actions: HashMap::new() }
+    }
+}
+
+impl Audit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 作为交易日历的股票代码
+    pub fn calendar(mut self, code: StockCode) -> Self {
+        self.calendar = code;
+        self
+    }
+
+    /// 涨跌幅阈值, 所有代码使用同一个阈值
+    ///
+    /// 默认按代码的涨跌停幅度 [`price_limit`] 加上 1% 的价格取整误差, 不是A股的代码(指数等)不检查涨跌幅
+    pub fn jump(mut self, jump: f64) -> Self {
+        self.jump = Some(jump);
+        self
+    }
+
+    /// 文件开头不检查涨跌幅的K线数, 默认 5, 即新股上市不设涨跌幅限制的天数
+    pub fn listing_days(mut self, days: usize) -> Self {
+        self.listing_days = days;
+        self
+    }
+
+    /// 最后一根K线最多落后日历的交易日数, 默认 0
+    pub fn stale_days(mut self, days: usize) -> Self {
+        self.stale_days = days;
+        self
+    }
+
+    /// 除权除息, 以带市场前缀的代码为键, 见 [`load_gbbq`]
+    pub fn actions(mut self, actions: HashMap<String, Vec<CorporateAction>>) -> Self {
+        self.actions = actions;
+        self
+    }
+
+    /// 检查目录下 sh/lday sz/lday 的全部日线文件
+    pub fn run(&self, root: &Path) -> Result<AuditReport> {
+        let codes = codes_in(root)?;
+        let read = |code: StockCode| -> Result<(Vec<DayTradeUnit>, u64)> {
+            let path = get_day_path_in(root, code)?;
+            let bytes = fs::metadata(&path)?.len() % DayTradeUnitSize as u64;
+            Ok((DayTradeUnit::read_from(&path, 0)?, bytes))
+        };
+        let (source, calendar) = match read(self.calendar) {
+            Ok((bars, _)) if !bars.is_empty() => {
+                (self.calendar.to_string(), bars.iter().map(|bar| bar.date).collect::<BTreeSet<i32>>())
+            }
+            _ => {
+                let mut dates = BTreeSet::new();
+                for code in codes.iter() {
+                    if let Ok((bars, _)) = read(code) {
+                        dates.extend(bars.iter().map(|bar| bar.date));
+                    }
+                }
+                ("union".to_string(), dates)
+            }
+        };
+        let calendar: Vec<i32> = calendar.into_iter().collect();
+        let mut summary = BTreeMap::new();
+        let mut files = Vec::with_capacity(codes.len());
+        for code in codes {
+            let (bars, issues) = match read(code) {
+                Ok((bars, bytes)) => {
+                    let mut issues = self.audit_bars(code, &bars, &calendar);
+                    if bytes > 0 {
+                        issues.insert(0, Issue::Truncated { bytes });
+                    }
+                    (bars, issues)
+                }
+                Err(e) => (vec![], vec![Issue::Unreadable { error: e.to_string() }]),
+            };
+            for issue in issues.iter() {
+                *summary.entry(issue.kind()).or_insert(0) += 1;
+            }
+            files.push(FileAudit {
+                code,
+                bars: bars.len(),
+                first: bars.first().map(|bar| bar.date),
+                last: bars.last().map(|bar| bar.date),
+                issues,
+            });
+        }
+        Ok(AuditReport {
+            calendar: source,
+            trading_days: calendar.len(),
+            latest: calendar.last().copied().unwrap_or_default(),
+            summary,
+            files,
+        })
+    }
+
+    /// 检查一只股票的K线, `calendar` 为升序的交易日
+    pub fn audit_bars(&self, code: &str, bars: &[DayTradeUnit], calendar: &[i32]) -> Vec<Issue> {
+        let mut issues = vec![];
+        let actions = self.actions.get(code).map(Vec::as_slice).unwrap_or_default();
+        let jump = self.jump.or_else(|| price_limit(code).map(|limit| limit + 0.01));
+        for (i, bar) in bars.iter().enumerate() {
+            let data = &bar.trade_data;
+            if data.volume == 0 {
+                issues.push(Issue::ZeroVolume { date: bar.date });
+            }
+            if data.low <= 0 || data.high < data.open.max(data.close).max(data.low) || data.low > data.open.min(data.close) {
+                issues.push(Issue::Ohlc { date: bar.date, open: data.open, high: data.high, low: data.low, close: data.close });
+            }
+            if i == 0 {
+                continue;
+            }
+            let prev = &bars[i - 1];
+            if bar.date == prev.date {
+                issues.push(Issue::Duplicate { date: bar.date });
+                continue;
+            }
+            if bar.date < prev.date {
+                issues.push(Issue::OutOfOrder { date: bar.date, previous: prev.date });
+                continue;
+            }
+            let jump = match jump {
+                Some(jump) if i >= self.listing_days && prev.trade_data.close > 0 => jump,
+                _ => continue,
+            };
+            // 除权参考价 = 前收盘 / 除权因子
+            let reference = prev.trade_data.close as f64 / 100.0 / factors(&bars[i - 1..=i], actions)[1];
+            let change = [data.high, data.low]
+                .iter()
+                .map(|price| *price as f64 / 100.0 / reference - 1.0)
+                .fold(0.0, |max: f64, change| if change.abs() > max.abs() { change } else { max });
+            if change.abs() > jump {
+                issues.push(Issue::Jump { date: bar.date, reference, change });
+            }
+        }
+
+        let dates: BTreeSet<i32> = bars.iter().map(|bar| bar.date).collect();
+        if let (Some(first), Some(last)) = (dates.first().copied(), dates.last().copied()) {
+            let mut gap: Option<(i32, i32, usize)> = None;
+            for date in calendar.iter().copied().filter(|date| *date > first && *date < last) {
+                if dates.contains(&date) {
+                    if let Some((from, to, days)) = gap.take() {
+                        issues.push(Issue::MissingDays { from, to, days });
+                    }
+                } else {
+                    gap = Some(gap.map_or((date, date, 1), |(from, _, days)| (from, date, days + 1)));
+                }
+            }
+            let behind = calendar.iter().filter(|date| **date > last).count();
+            if behind > self.stale_days {
+                issues.push(Issue::Stale { last, latest: calendar[calendar.len() - 1], behind });
+            }
+        }
+        issues
+    }
+}
+
+/**
+ * 读取 csv 格式的除权除息数据, 只保留类别 1 (除权除息)
+ *
+ * 通达信 `T0002/hq_cache/gbbq` 是加密的, 先用 pytdx 的 GbbqReader 解密后导出 csv,
+ * 需要 market code datetime category hongli_panqianliutong peigujia_qianzongguben songgu_qianzongguben peigu_houzongguben 列,
+ * 其中派息, 送转股和配股为每 10 股, 配股价为每股
+ */
+pub fn load_gbbq<P: AsRef<Path>>(path: P) -> Result<HashMap<String, Vec<CorporateAction>>> {
+    parse_gbbq(&fs::read_to_string(path)?)
+}
+
+fn parse_gbbq(text: &str) -> Result<HashMap<String, Vec<CorporateAction>>> {
+    let mut lines = text.lines();
+    let header: Vec<&str> = lines.next().unwrap_or_default().split(',').map(str::trim).collect();
+    let column = |name: &str| header.iter().position(|column| *column == name).ok_or(AuditError::GbbqFormat(1));
+    let (market, code, date, category) = (column("market")?, column("code")?, column("datetime")?, column("category")?);
+    let (cash, shares) = (column("hongli_panqianliutong")?, column("songgu_qianzongguben")?);
+    let (rights_price, rights) = (column("peigujia_qianzongguben")?, column("peigu_houzongguben")?);
+    let mut actions: HashMap<String, Vec<CorporateAction>> = HashMap::new();
+    for (i, line) in lines.enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let values: Vec<&str> = line.split(',').map(str::trim).collect();
+        let value = |index: usize| values.get(index).copied().ok_or(AuditError::GbbqFormat(i + 2));
+        if value(category)? != "1" {
+            continue;
+        }
+        let market = if value(market)? == "1" { "sh" } else { "sz" };
+        let key = format!("{}{:0>6}", market, value(code)?);
+        let decimal = |index: usize| -> Result<Decimal> { value(index)?.parse().map_err(|_| AuditError::GbbqFormat(i + 2)) };
+        let per_share = |index: usize| -> Result<Decimal> { Ok(decimal(index)? / Decimal::TEN) };
+        let action = CorporateAction {
+            code: intern(&key),
+            date: value(date)?.replace('-', "").parse().map_err(|_| AuditError::GbbqFormat(i + 2))?,
+            cash_per_share: per_share(cash)?,
+            shares_per_share: per_share(shares)?,
+            rights_price: decimal(rights_price)?,
+            rights_per_share: per_share(rights)?,
+        };
+        actions.entry(key).or_default().push(action);
+    }
+    for list in actions.values_mut() {
+        list.sort_by_key(|action| action.date);
+    }
+    Ok(actions)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, path::Path};
+
+    use rust_decimal_macros::dec;
+
+    use crate::data::{DayTradeUnit, TradeUnit};
+
+    use super::{parse_gbbq, Audit, Issue};
+
+    fn bar(date: i32, open: i32, high: i32, low: i32, close: i32, volume: i32) -> DayTradeUnit {
+        DayTradeUnit { date, trade_data: TradeUnit { open, high, low, close, volume, amount: 0.0 } }
+    }
+
+    #[test]
+    fn audit_bars() {
+        let gbbq = "market,code,datetime,category,hongli_panqianliutong,peigujia_qianzongguben,songgu_qianzongguben,peigu_houzongguben\n\
+                    1,603339,20220110,1,0.0,0.0,10.0,0.0\n\
+                    1,603339,20220111,2,0.0,0.0,0.0,0.0\n";
+        let actions = parse_gbbq(gbbq).unwrap();
+        assert_eq!(actions["sh603339"].len(), 1);
+        assert_eq!(actions["sh603339"][0].shares_per_share, dec!(1));
+
+        let calendar = [20220104, 20220105, 20220106, 20220107, 20220110, 20220111, 20220112, 20220113, 20220114];
+        let bars = vec![
+            bar(20220104, 2000, 2020, 1990, 2000, 100),
+            bar(20220107, 2000, 2010, 1990, 2000, 0),
+            // 10 送 10, 除权后不是异常涨跌
+            bar(20220110, 1000, 1010, 990, 1000, 100),
+            bar(20220111, 1500, 1500, 1500, 1500, 100),
+            bar(20220111, 1500, 1500, 1500, 1500, 100),
+            bar(20220112, 1500, 1400, 1400, 1500, 100),
+        ];
+        let audit = Audit::new().listing_days(0).actions(actions);
+        let issues = audit.audit_bars("sh603339", &bars, &calendar);
+        assert_eq!(
+            issues,
+            vec![
+                Issue::ZeroVolume { date: 20220107 },
+                Issue::Jump { date: 20220111, reference: 10.0, change: 0.5 },
+                Issue::Duplicate { date: 20220111 },
+                Issue::Ohlc { date: 20220112, open: 1500, high: 1400, low: 1400, close: 1500 },
+                Issue::MissingDays { from: 20220105, to: 20220106, days: 2 },
+                Issue::Stale { last: 20220112, latest: 20220114, behind: 2 },
+            ]
+        );
+        // 没有除权数据时除权日也是异常涨跌
+        let issues = Audit::new().listing_days(0).stale_days(2).audit_bars("sh603339", &bars, &calendar);
+        assert!(matches!(issues[1], Issue::Jump { date: 20220110, .. }));
+        assert!(!issues.iter().any(|issue| matches!(issue, Issue::Stale { .. })));
+    }
+
+    #[test]
+    fn price_limits() {
+        let calendar = [20220104, 20220105, 20220106];
+        let bars = vec![bar(20220104, 1000, 1000, 1000, 1000, 100), bar(20220105, 1150, 1150, 1150, 1150, 100), bar(20220106, 1150, 1150, 1150, 1150, 100)];
+        let jumps = |audit: &Audit, code: &str| {
+            audit.audit_bars(code, &bars, &calendar).into_iter().filter(|issue| matches!(issue, Issue::Jump { .. })).count()
+        };
+        // 涨 15% 超过主板的 10%, 没有超过创业板和科创板的 20%, 指数不检查
+        let audit = Audit::new().listing_days(0);
+        assert_eq!(jumps(&audit, "sh603339"), 1);
+        assert_eq!(jumps(&audit, "sz300750"), 0);
+        assert_eq!(jumps(&audit, "sh688981"), 0);
+        assert_eq!(jumps(&audit, "sh000001"), 0);
+        // 指定阈值时所有代码使用同一个阈值
+        assert_eq!(jumps(&audit.jump(0.12), "sz300750"), 1);
+
+        // 10 配 3, 配股价 5 元: 除权参考价 (10 + 5 * 0.3) / 1.3 = 8.85, 不是异常涨跌
+        let gbbq = "market,code,datetime,category,hongli_panqianliutong,peigujia_qianzongguben,songgu_qianzongguben,peigu_houzongguben\n\
+                    1,603339,20220105,1,0.0,5.0,0.0,3.0\n";
+        let actions = parse_gbbq(gbbq).unwrap();
+        assert_eq!((actions["sh603339"][0].rights_price, actions["sh603339"][0].rights_per_share), (dec!(5), dec!(0.3)));
+        let bars = vec![bar(20220104, 1000, 1000, 1000, 1000, 100), bar(20220105, 885, 885, 800, 800, 100)];
+        let audit = Audit::new().listing_days(0).actions(actions);
+        assert!(audit.audit_bars("sh603339", &bars, &calendar[..2]).is_empty());
+        assert_eq!(Audit::new().listing_days(0).audit_bars("sh603339", &bars, &calendar[..2]).len(), 1);
+    }
+
+    #[test]
+    fn audit_root() {
+        let root = env::var("MILLIONS_TDX").unwrap();
+        let report = Audit::new().run(Path::new(&root)).unwrap();
+        assert_eq!(report.calendar, "union");
+        assert_eq!(report.files.len(), 2);
+        assert_eq!(report.latest, 20221101);
+        assert!(report.files.iter().all(|file| file.last == Some(20221101) && file.bars > 0));
+        assert!(!report.summary.contains_key("unreadable"));
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(json["files"].as_array().unwrap().len(), 2);
+    }
+}
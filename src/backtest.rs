@@ -1,16 +1,52 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    rc::Rc,
+};
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
 use crate::{
-    data::{DayTradeUnit, DayTradeUnitIter, StockCode, StockTradeData, TradeDataSource},
-    strategy::{Account, Trade, TradeError},
+    data::{date_to_time, DataSourceError, DayTradeUnit, StockCode, StockTradeData, TradeDataSource},
+    event::{Context, CorporateAction, Event, EventQueue, Fill, OrderKind, OrderRequest, Side},
+    optimizer::Params,
+    result::{BackTestConfig, BacktestResult, RejectedOrder},
+    serise::SeriseRegistry,
+    statistics::BenchmarkStatistics,
+    strategy::{Account, CostMethod, ExitRule, FeeModel, Trade, TradeError},
+    tick::{Tick, TickError, TickSource},
 };
 
+#[cfg(test)]
+use std::println as warn;
+#[cfg(not(test))]
+use log::warn;
+
 use thiserror::Error;
-#[derive(Debug, Error, PartialEq)]
+#[derive(Debug, Error)]
 pub enum BackTestError {
     #[error("trade error")]
     TradeError {
         #[from]
         source: TradeError,
     },
+    #[error("data source error")]
+    DataSourceError {
+        #[from]
+        source: DataSourceError,
+    },
+    #[error("parse date fail")]
+    ParseDateError {
+        #[from]
+        source: std::num::ParseIntError,
+    },
+    #[error("invalid config: {0}")]
+    ConfigError(String),
+    #[error("read ticks fail")]
+    TickError {
+        #[from]
+        source: TickError,
+    },
 }
 
 pub type Result<T, E = BackTestError> = std::result::Result<T, E>;
@@ -18,175 +54,1173 @@ pub type Result<T, E = BackTestError> = std::result::Result<T, E>;
 pub struct BackTest<'a> {
     /** 回测账户 */
     account: Account,
+    /** 初始资金 */
+    capital: Decimal,
+    /** 股票池 */
+    codes: Vec<StockCode>,
+    /** 截止到当前交易日的历史K线 */
+    data: HashMap<StockCode, Vec<DayTradeUnit>>,
     /** 策略 */
-    // strategy: &'a dyn Strategy,
-    code: StockCode,
-    data: Vec<DayTradeUnit>,
-    strategy: &'a mut dyn Strategy,
+    strategy: Box<dyn PortfolioStrategy + 'a>,
+    /** 事件队列 */
+    events: EventQueue,
+    /** 委托和定时器编号 */
+    next_id: u64,
+    /** 成交时机 */
+    fill_timing: FillTiming,
+    /** 滑点 */
+    slippage: Slippage,
+    /** 等待下一根K线成交的委托 */
+    parked: Vec<OrderRequest>,
+    /** 基准, 例如 sh000300 */
+    benchmark_code: Option<StockCode>,
+    /** 回测区间内的基准K线 */
+    benchmark: Vec<DayTradeUnit>,
+    /** 被拒绝的委托 */
+    rejected: Vec<RejectedOrder>,
+    /** 各股票的指标 */
+    serises: HashMap<StockCode, SeriseRegistry>,
+    /** 分笔成交, 按分笔撮合时使用 */
+    ticks: Option<Box<dyn TickSource + 'a>>,
+    /** 当日各股票每笔分笔已被委托消耗的量(股), 同一天的多笔委托不重复使用 */
+    tick_used: HashMap<(StockCode, i32), Vec<Decimal>>,
+    /** 策略参数, 只记录在回测结果中 */
+    params: Params,
+    /** K线数据源 */
+    source: Box<dyn TradeDataSource + 'a>,
 }
 
-pub trait Strategy {
-    fn next(&mut self, account: &mut Account, stock_trade_info: &Vec<DayTradeUnit>);
+/// 委托的成交时机
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum FillTiming {
+    /// 按下单当根K线的收盘价成交, 策略看到收盘价后还能以收盘价成交, 存在未来函数
+    SameClose,
+    /// 按下一根K线的开盘价成交
+    #[default]
+    NextOpen,
+    /// 按下一根K线的均价 成交额/成交量 成交
+    NextVwap,
+    /// 按下一交易日的分笔成交撮合, 没有分笔数据时按开盘价成交, 见 [`BackTestBuilder::ticks`]
+    NextTicks,
 }
 
-impl<'a> BackTest<'a> {
-    fn new(code: StockCode, strategy: &'a mut dyn Strategy) -> Result<Self> {
-        // todo: change to builder pattern
-        let mut account = Account::new("100000", "0.00025")?;
+/// 市价委托的滑点, 买入价上浮 卖出价下调, 限价委托按限价或更优的价格成交不计滑点
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Slippage {
+    #[default]
+    None,
+    /// 每股固定价差 例如 0.01 元
+    Fixed(Decimal),
+    /// 按成交价的比例 例如 0.001 表示千分之一
+    Percent(Decimal),
+}
+
+impl Slippage {
+    /// 计入滑点后的成交价, 先加减滑点再保留两位小数, 卖出价不低于 0.01
+    pub fn apply(&self, price: Decimal, side: Side) -> Decimal {
+        let slip = match self {
+            Slippage::None => return price,
+            Slippage::Fixed(slip) => *slip,
+            Slippage::Percent(rate) => price * rate,
+        };
+        match side {
+            Side::Buy => (price + slip).round_dp(2),
+            Side::Sell => (price - slip).round_dp(2).max(Decimal::new(1, 2)),
+        }
+    }
+}
+
+/// 回测的构造器
+///
+/// ```ignore
+/// let mut back_test = BackTest::builder()
+///     .codes(vec!["603339", "603338"])
+///     .capital("500000")
+///     .fees(FeeModel { min_brokerage: dec!(5), ..Default::default() })
+///     .slippage(Slippage::Percent(dec!(0.001)))
+///     .benchmark("sh000300")
+///     .strategy(strategy)
+///     .build()?;
+/// ```
+pub struct BackTestBuilder<'a> {
+    codes: Vec<StockCode>,
+    capital: String,
+    fees: FeeModel,
+    cost_method: CostMethod,
+    slippage: Slippage,
+    fill_timing: FillTiming,
+    benchmark: Option<StockCode>,
+    strategy: Option<Box<dyn PortfolioStrategy + 'a>>,
+    ticks: Option<Box<dyn TickSource + 'a>>,
+    params: Params,
+    source: Option<Box<dyn TradeDataSource + 'a>>,
+}
+
+impl<'a> Default for BackTestBuilder<'a> {
+    fn default() -> Self {
+        BackTestBuilder {
+            codes: vec![],
+            capital: "100000".to_string(),
+            fees: FeeModel::default(),
+            cost_method: CostMethod::default(),
+            slippage: Slippage::default(),
+            fill_timing: FillTiming::default(),
+            benchmark: None,
+            strategy: None,
+            ticks: None,
+            params: Params::new(),
+            source: None,
+        }
+    }
+}
+
+impl<'a> BackTestBuilder<'a> {
+    /// 股票池
+    pub fn codes(mut self, codes: Vec<StockCode>) -> Self {
+        self.codes = codes;
+        self
+    }
+
+    /// 初始资金, 默认 100000
+    pub fn capital(mut self, capital: &str) -> Self {
+        self.capital = capital.to_string();
+        self
+    }
+
+    pub fn fees(mut self, fees: FeeModel) -> Self {
+        self.fees = fees;
+        self
+    }
+
+    pub fn cost_method(mut self, cost_method: CostMethod) -> Self {
+        self.cost_method = cost_method;
+        self
+    }
+
+    pub fn slippage(mut self, slippage: Slippage) -> Self {
+        self.slippage = slippage;
+        self
+    }
+
+    pub fn fill_timing(mut self, fill_timing: FillTiming) -> Self {
+        self.fill_timing = fill_timing;
+        self
+    }
+
+    /// 比较基准, 指数需要带市场前缀, 例如 sh000300
+    pub fn benchmark(mut self, code: StockCode) -> Self {
+        self.benchmark = Some(code);
+        self
+    }
+
+    pub fn strategy(mut self, strategy: impl PortfolioStrategy + 'a) -> Self {
+        self.strategy = Some(Box::new(strategy));
+        self
+    }
+
+    /// 分笔成交, 配合 [`FillTiming::NextTicks`] 使用
+    pub fn ticks(mut self, ticks: impl TickSource + 'a) -> Self {
+        self.ticks = Some(Box::new(ticks));
+        self
+    }
+
+    /// 策略参数, 随回测结果保存, 不会传给策略
+    pub fn params(mut self, params: Params) -> Self {
+        self.params = params;
+        self
+    }
+
+    /// K线数据源, 例如 [`crate::store::LocalStore`], 默认为 `MILLIONS_TDX` 下的通达信数据
+    pub fn source(mut self, source: impl TradeDataSource + 'a) -> Self {
+        self.source = Some(Box::new(source));
+        self
+    }
+
+    pub fn build(self) -> Result<BackTest<'a>> {
+        let strategy = self.strategy.ok_or_else(|| BackTestError::ConfigError("strategy is required".to_string()))?;
+        if self.codes.is_empty() {
+            return Err(BackTestError::ConfigError("codes is empty".to_string()));
+        }
+        let account = Account::new(&self.capital, "0.00025")?
+            .with_fee_model(self.fees)
+            .with_cost_method(self.cost_method);
         Ok(BackTest {
-            code,
-            data: vec![],
+            capital: account.get_balance(),
+            codes: self.codes,
+            data: HashMap::new(),
             account,
             strategy,
+            events: EventQueue::new(),
+            next_id: 0,
+            fill_timing: self.fill_timing,
+            slippage: self.slippage,
+            parked: vec![],
+            benchmark_code: self.benchmark,
+            benchmark: vec![],
+            rejected: vec![],
+            serises: HashMap::new(),
+            ticks: self.ticks,
+            tick_used: HashMap::new(),
+            params: self.params,
+            source: self.source.unwrap_or_else(|| Box::new(StockTradeData {})),
         })
     }
+}
 
-    fn run(&mut self, from: &str, to: &str) -> () {
-        let start: i32 = from.parse().unwrap();
-        let end: i32 = to.parse().unwrap();
-        let data = StockTradeData {};
-        let iter = data.day_duration(self.code, None, None).unwrap();
-        for i in iter {
-            if i.date < start {
-                continue;
-            }
-            if i.date > end {
-                continue;
+/// 单只股票的策略, 通过 `Context` 下单, 委托由回测引擎撮合
+pub trait Strategy {
+    /// 回测开始前声明指标, 通过 `Context::serise` 读取
+    fn serises(&self, _registry: &mut SeriseRegistry) {}
+
+    fn next(&mut self, ctx: &mut Context, stock_trade_info: &Vec<DayTradeUnit>);
+}
+
+/// 组合策略, 由回测引擎按事件回调
+pub trait PortfolioStrategy {
+    /// 回测开始前为股票池内每只股票声明指标, 引擎在每根K线自动计算
+    fn serises(&self, _code: StockCode, _registry: &mut SeriseRegistry) {}
+
+    /// 新的交易日行情, 收到股票池内所有股票的快照
+    fn on_bar(&mut self, ctx: &mut Context, market: &MarketSnapshot);
+
+    /// 委托成交
+    fn on_fill(&mut self, _ctx: &mut Context, _fill: &Fill) {}
+
+    /// 委托被拒绝
+    fn on_order_rejected(&mut self, _ctx: &mut Context, _order: &OrderRequest, _reason: &TradeError) {}
+
+    /// 交易日开盘前, 此时的委托按当日K线撮合, 参考价与上一交易日 `on_bar` 中的委托相同:
+    /// 例如 `NextOpen` 为当日开盘价, `SameClose` 为当日收盘价
+    fn on_day_start(&mut self, _ctx: &mut Context, _date: i32) {}
+
+    fn on_day_end(&mut self, _ctx: &mut Context, _date: i32) {}
+
+    /// 通过 `Context::schedule` 设置的定时器到期
+    fn on_timer(&mut self, _ctx: &mut Context, _id: u64) {}
+}
+
+impl<T: PortfolioStrategy + ?Sized> PortfolioStrategy for &mut T {
+    fn serises(&self, code: StockCode, registry: &mut SeriseRegistry) {
+        (**self).serises(code, registry)
+    }
+
+    fn on_bar(&mut self, ctx: &mut Context, market: &MarketSnapshot) {
+        (**self).on_bar(ctx, market)
+    }
+
+    fn on_fill(&mut self, ctx: &mut Context, fill: &Fill) {
+        (**self).on_fill(ctx, fill)
+    }
+
+    fn on_order_rejected(&mut self, ctx: &mut Context, order: &OrderRequest, reason: &TradeError) {
+        (**self).on_order_rejected(ctx, order, reason)
+    }
+
+    fn on_day_start(&mut self, ctx: &mut Context, date: i32) {
+        (**self).on_day_start(ctx, date)
+    }
+
+    fn on_day_end(&mut self, ctx: &mut Context, date: i32) {
+        (**self).on_day_end(ctx, date)
+    }
+
+    fn on_timer(&mut self, ctx: &mut Context, id: u64) {
+        (**self).on_timer(ctx, id)
+    }
+}
+
+/// 把单只股票的策略适配为组合策略, 仅在该股票有交易的日期调用
+struct SingleStock<'a> {
+    code: StockCode,
+    strategy: &'a mut dyn Strategy,
+}
+
+impl<'a> PortfolioStrategy for SingleStock<'a> {
+    fn serises(&self, code: StockCode, registry: &mut SeriseRegistry) {
+        if code == self.code {
+            self.strategy.serises(registry);
+        }
+    }
+
+    fn on_bar(&mut self, ctx: &mut Context, market: &MarketSnapshot) {
+        if market.bar(self.code).is_some() {
+            if let Some(history) = market.data.get(self.code) {
+                self.strategy.next(ctx, history);
             }
-            self.data.push(i);
-            self.strategy.next(&mut self.account, &self.data);
         }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use std::{
-        any::{request_ref, request_value, Any, Demand, Provider},
-        collections::HashMap,
-    };
+/// 某个交易日股票池的快照, 各股票的K线已按日期对齐
+pub struct MarketSnapshot<'a> {
+    date: i32,
+    /** 当日有交易的股票, 停牌的股票不在其中 */
+    trading: &'a Vec<StockCode>,
+    data: &'a HashMap<StockCode, Vec<DayTradeUnit>>,
+}
 
-    use ta::{
-        indicators::{
-            ExponentialMovingAverage, MovingAverageConvergenceDivergence,
-            MovingAverageConvergenceDivergenceOutput, SimpleMovingAverage,
-        },
-        Next, Period,
-    };
+impl<'a> MarketSnapshot<'a> {
+    /// 当前交易日 例如 20230103
+    pub fn date(&self) -> i32 {
+        self.date
+    }
 
-    use crate::{data::DayTradeUnit, strategy::Account};
+    /// 当日有交易的股票
+    pub fn codes(&self) -> &[StockCode] {
+        self.trading
+    }
 
-    use super::{BackTest, Strategy};
+    /// 当日K线, 停牌或未上市时为 None
+    pub fn bar(&self, code: StockCode) -> Option<&DayTradeUnit> {
+        self.data
+            .get(code)
+            .and_then(|history| history.last())
+            .filter(|bar| bar.date == self.date)
+    }
 
-    pub trait Serise: Provider {
-        fn add(&mut self, value: f64);
+    /// 截止到当日的历史K线
+    pub fn history(&self, code: StockCode) -> &[DayTradeUnit] {
+        self.data.get(code).map(|history| &history[..]).unwrap_or(&[])
     }
 
-    impl dyn Serise {
-        fn get<T: 'static>(&self) -> Option<&T> {
-            request_ref(self)
-        }
+    /// 最近一个交易日的收盘价, 停牌时为停牌前的收盘价
+    pub fn close(&self, code: StockCode) -> Option<Decimal> {
+        close_of(self.data, code)
+    }
+}
+
+/// 把快照中的历史K线同步到策略保存的共享副本, 只追加新的K线, 换了回测区间时整体复制
+///
+/// 脚本和 Python 策略把K线交给外部代码时共享同一份, 避免每个交易日复制全部历史
+pub(crate) fn sync_history(bars: &mut Rc<Vec<DayTradeUnit>>, history: &[DayTradeUnit]) {
+    if bars.len() > history.len() || bars.first().map(|bar| bar.date) != history.first().map(|bar| bar.date) {
+        *bars = Rc::new(history.to_vec());
+    } else if bars.len() < history.len() {
+        let len = bars.len();
+        Rc::make_mut(bars).extend_from_slice(&history[len..]);
     }
-    struct SeriseContainer<F, D> {
-        data: Vec<D>,
-        formula: F,
+}
+
+impl<'a> BackTest<'a> {
+    pub fn new(code: StockCode, strategy: &'a mut dyn Strategy) -> Result<Self> {
+        Self::with_universe(vec![code], SingleStock { code, strategy })
     }
-    impl SeriseContainer<SimpleMovingAverage, f64> {
-        fn new(formula: SimpleMovingAverage) -> Self {
-            SeriseContainer {
-                data: vec![],
-                formula: formula,
+
+    /// 多只股票组合回测, 共用一个账户
+    pub fn with_universe(codes: Vec<StockCode>, strategy: impl PortfolioStrategy + 'a) -> Result<Self> {
+        Self::builder().codes(codes).strategy(strategy).build()
+    }
+
+    pub fn builder() -> BackTestBuilder<'a> {
+        BackTestBuilder::default()
+    }
+
+    /// 设置比较基准, 指数需要带市场前缀, 例如 sh000300 沪深300, sh000001 上证指数
+    pub fn set_benchmark(&mut self, code: StockCode) {
+        self.benchmark_code = Some(code);
+    }
+
+    /// 记录策略参数, 见 [`BackTestBuilder::params`]
+    pub fn set_params(&mut self, params: Params) {
+        self.params = params;
+    }
+
+    /// 设置K线数据源, 见 [`BackTestBuilder::source`]
+    pub fn set_source(&mut self, source: impl TradeDataSource + 'a) {
+        self.source = Box::new(source);
+    }
+
+    /// 回测区间内的基准K线
+    pub fn benchmark(&self) -> &[DayTradeUnit] {
+        &self.benchmark
+    }
+
+    /// 相对基准的统计, 没有设置基准时为 None
+    pub fn benchmark_statistics(&self) -> Option<BenchmarkStatistics> {
+        self.benchmark_code?;
+        Some(BenchmarkStatistics::new(self.account.equity_curve(), &self.benchmark))
+    }
+
+    /// 设置委托的成交时机, 默认下一根K线开盘价成交
+    pub fn set_fill_timing(&mut self, fill_timing: FillTiming) {
+        self.fill_timing = fill_timing;
+    }
+
+    pub fn fill_timing(&self) -> FillTiming {
+        self.fill_timing
+    }
+
+    pub fn account(&self) -> &Account {
+        &self.account
+    }
+
+    /// 股票池
+    pub fn codes(&self) -> &[StockCode] {
+        &self.codes
+    }
+
+    /// 截止到当前交易日的历史K线, 回测结束后为回测区间内的全部K线
+    pub fn history(&self, code: StockCode) -> &[DayTradeUnit] {
+        self.data.get(code).map(|history| &history[..]).unwrap_or(&[])
+    }
+
+    /// 在回测开始前加入外部事件, 例如公司行为或定时器
+    pub fn schedule(&mut self, event: Event) {
+        self.events.push(event);
+    }
+
+    /// 加入除权除息事件
+    pub fn add_corporate_action(&mut self, action: CorporateAction) {
+        self.events.push(Event::CorporateAction(action));
+    }
+
+    /// 回测 [from, to] 区间, 日期格式 20230103
+    pub fn run(&mut self, from: &str, to: &str) -> Result<BacktestResult> {
+        let start: i32 = from.parse()?;
+        let end: i32 = to.parse()?;
+        let config = BackTestConfig {
+            codes: self.codes.clone(),
+            from: start,
+            to: end,
+            capital: self.account.get_balance(),
+            fees: self.account.fee_model(),
+            cost_method: self.account.cost_method(),
+            fill_timing: self.fill_timing,
+            slippage: self.slippage,
+            ticks: self.ticks.is_some(),
+            params: self.params.clone(),
+            benchmark: self.benchmark_code,
+        };
+        let mut days: BTreeMap<i32, Vec<(StockCode, DayTradeUnit)>> = BTreeMap::new();
+        for code in self.codes.iter() {
+            for bar in self.source.day_duration(code, None, None)? {
+                if bar.date >= start && bar.date <= end {
+                    days.entry(bar.date).or_default().push((*code, bar));
+                }
             }
         }
+        if let Some(code) = self.benchmark_code {
+            self.benchmark = self
+                .source
+                .day_duration(code, None, None)?
+                .filter(|bar| bar.date >= start && bar.date <= end)
+                .collect();
+        }
+        // 通过 preload 预先载入K线时指标已经声明
+        if self.serises.is_empty() {
+            self.register_serises();
+        }
+        for (date, bars) in days {
+            self.events.push(Event::DayStart(date));
+            self.events.push(Event::MarketData(date, bars));
+            self.events.push(Event::DayEnd(date));
+        }
+
+        while let Some(event) = self.events.pop() {
+            self.dispatch(event)?;
+        }
+        // 最后一个交易日之后提交的委托没有机会成交
+        for order in std::mem::take(&mut self.parked) {
+            self.dispatch(Event::Rejected(order, TradeError::NoMarketData))?;
+        }
+        Ok(self.result(config))
     }
-    impl Serise for SeriseContainer<SimpleMovingAverage, f64> {
-        fn add(&mut self, value: f64) {
-            let result = self.formula.next(value);
-            self.data.push(result);
+
+    /// 回测开始前为每只股票声明指标
+    fn register_serises(&mut self) {
+        self.serises.clear();
+        for code in self.codes.iter() {
+            let mut registry = SeriseRegistry::new();
+            self.strategy.serises(code, &mut registry);
+            self.serises.insert(code, registry);
+        }
+    }
+
+    /// 预先载入历史K线, 只计算指标, 不通知策略也不撮合, 用于实盘前或回测区间开始前积累指标
+    ///
+    /// 需要在第一次 [`BackTest::step`] 或 [`BackTest::run`] 之前调用
+    pub fn preload(&mut self, code: StockCode, bars: &[DayTradeUnit]) {
+        if self.serises.is_empty() {
+            self.register_serises();
+        }
+        for bar in bars {
+            self.data.entry(code).or_default().push(bar.clone());
+            if let Some(registry) = self.serises.get_mut(code) {
+                registry.feed(bar);
+            }
         }
     }
-    impl Provider for SeriseContainer<SimpleMovingAverage, f64> {
-        fn provide<'a>(&'a self, demand: &mut Demand<'a>) {
-            demand.provide_ref(&self.data);
+
+    /// 逐日推进, 处理一个交易日的行情以及日期不晚于该交易日的事件
+    ///
+    /// 与 [`BackTest::run`] 的撮合规则相同, 用于实时行情驱动的模拟交易
+    pub fn step(&mut self, date: i32, bars: Vec<(StockCode, DayTradeUnit)>) -> Result<()> {
+        if self.serises.is_empty() {
+            self.register_serises();
+        }
+        self.events.push(Event::DayStart(date));
+        self.events.push(Event::MarketData(date, bars));
+        self.events.push(Event::DayEnd(date));
+        while self.events.peek_date().map(|next| next <= date).unwrap_or(false) {
+            if let Some(event) = self.events.pop() {
+                self.dispatch(event)?;
+            }
         }
+        Ok(())
+    }
+
+    /// [from, to] 区间的回测结果, 逐日推进时可以随时查看
+    pub fn result_between(&self, from: i32, to: i32) -> BacktestResult {
+        self.result(BackTestConfig {
+            codes: self.codes.clone(),
+            from,
+            to,
+            capital: self.capital,
+            fees: self.account.fee_model(),
+            cost_method: self.account.cost_method(),
+            fill_timing: self.fill_timing,
+            slippage: self.slippage,
+            ticks: self.ticks.is_some(),
+            params: self.params.clone(),
+            benchmark: self.benchmark_code,
+        })
+    }
+
+    fn result(&self, config: BackTestConfig) -> BacktestResult {
+        let prices: Vec<(StockCode, Decimal)> = self
+            .codes
+            .iter()
+            .filter_map(|code| close_of(&self.data, code).map(|close| (*code, close)))
+            .collect();
+        BacktestResult::new(config, &self.account, &prices, self.rejected.clone(), self.benchmark_statistics())
     }
-    impl SeriseContainer<ExponentialMovingAverage, f64> {
-        fn new(formula: ExponentialMovingAverage) -> Self {
-            SeriseContainer {
-                data: vec![],
-                formula: formula,
+
+    /// 处理单个事件
+    fn dispatch(&mut self, event: Event) -> Result<()> {
+        // 订单按事件日期记录, 而不是运行回测的时间
+        if let Some(time) = date_to_time(event.date()) {
+            self.account.set_time(time);
+        }
+        match event {
+            Event::DayStart(date) => {
+                let mut ctx = Context::new(&self.account, date, &mut self.next_id).with_serises(&self.serises);
+                self.strategy.on_day_start(&mut ctx, date);
+                // 开盘前的委托与上一交易日的委托一起在当日K线到达时撮合
+                for event in ctx.into_events() {
+                    match event {
+                        Event::Order(order) => self.parked.push(order),
+                        event => self.events.push(event),
+                    }
+                }
+            }
+            Event::CorporateAction(action) => {
+                self.account.apply_corporate_action(action.code, action.cash_per_share, action.shares_per_share)?;
+            }
+            Event::MarketData(date, bars) => {
+                let mut trading = vec![];
+                for (code, bar) in bars.iter() {
+                    self.data.entry(code).or_default().push(bar.clone());
+                    if let Some(registry) = self.serises.get_mut(code) {
+                        registry.feed(bar);
+                    }
+                    trading.push(*code);
+                }
+                // 上一交易日的委托在开盘时先成交, 成交回报在策略看到当日K线之前通知
+                for order in std::mem::take(&mut self.parked) {
+                    let event = match bars.iter().find(|(code, ..)| *code == order.code) {
+                        Some((_, bar)) => self.execute(order, bar)?,
+                        None => Event::Rejected(order, TradeError::NoMarketData),
+                    };
+                    self.dispatch(event)?;
+                }
+                for (code, bar) in bars.iter() {
+                    self.check_exits(code, bar);
+                }
+                let market = MarketSnapshot { date, trading: &trading, data: &self.data };
+                let mut ctx = Context::new(&self.account, date, &mut self.next_id).with_serises(&self.serises);
+                self.strategy.on_bar(&mut ctx, &market);
+                for event in ctx.into_events() {
+                    self.events.push(event);
+                }
+            }
+            Event::Order(order) => match self.fill_timing {
+                FillTiming::SameClose => {
+                    let today = self
+                        .data
+                        .get(order.code)
+                        .and_then(|history| history.last())
+                        .filter(|bar| bar.date == order.date)
+                        .cloned();
+                    let event = match today {
+                        Some(bar) => self.execute(order, &bar)?,
+                        None => Event::Rejected(order, TradeError::NoMarketData),
+                    };
+                    self.events.push(event);
+                }
+                FillTiming::NextOpen | FillTiming::NextVwap | FillTiming::NextTicks => self.parked.push(order),
+            },
+            Event::Fill(fill) => self.callback(fill.date, |strategy, ctx| strategy.on_fill(ctx, &fill)),
+            Event::Rejected(order, reason) => {
+                self.rejected.push(RejectedOrder::new(&order, &reason));
+                self.callback(order.date, |strategy, ctx| strategy.on_order_rejected(ctx, &order, &reason))
+            }
+            Event::AttachExit(date, code, rule) => self.attach_exit(code, rule, date),
+            Event::Timer(date, id) => self.callback(date, |strategy, ctx| strategy.on_timer(ctx, id)),
+            Event::DayEnd(date) => {
+                self.callback(date, |strategy, ctx| strategy.on_day_end(ctx, date));
+                let prices: HashMap<StockCode, Decimal> = self
+                    .codes
+                    .iter()
+                    .filter_map(|code| close_of(&self.data, code).map(|close| (*code, close)))
+                    .collect();
+                self.account.snapshot(date, &prices);
             }
         }
+        Ok(())
     }
-    impl Serise for SeriseContainer<ExponentialMovingAverage, f64> {
-        fn add(&mut self, value: f64) {
-            todo!()
+
+    /// 调用策略回调, 回调中提交的委托和定时器放回事件队列
+    fn callback<F>(&mut self, date: i32, f: F)
+    where
+        F: FnOnce(&mut (dyn PortfolioStrategy + 'a), &mut Context),
+    {
+        let mut ctx = Context::new(&self.account, date, &mut self.next_id).with_serises(&self.serises);
+        f(self.strategy.as_mut(), &mut ctx);
+        for event in ctx.into_events() {
+            self.events.push(event);
+        }
+    }
+
+    /// 止损止盈在策略看到当日K线之前按盘中价格触发, 成交回报照常通知策略
+    fn check_exits(&mut self, code: StockCode, bar: &DayTradeUnit) {
+        let vol = match self.account.get_position(code) {
+            Ok(Some(position)) => position.volume(),
+            _ => return,
+        };
+        match self.account.check_exits(code, &bar.trade_data) {
+            Ok(Some(price)) => {
+                warn!("{} exit triggered at {} on {}", code, price, bar.date);
+                self.next_id += 1;
+                let order = OrderRequest {
+                    id: self.next_id,
+                    code,
+                    side: Side::Sell,
+                    vol,
+                    kind: OrderKind::Limit(price),
+                    date: bar.date,
+                    exits: vec![],
+                };
+                self.events.push(Event::Fill(Fill { order, date: bar.date, price }));
+            }
+            Ok(None) => {}
+            Err(e) => warn!("{} exit failed on {}: {}", code, bar.date, e),
         }
     }
-    impl Provider for SeriseContainer<ExponentialMovingAverage, f64> {
-        fn provide<'a>(&'a self, demand: &mut Demand<'a>) {
-            demand.provide_ref(&self.data);
+
+    /// 给持仓附加止损止盈, 持仓在附加前已卖出时忽略
+    fn attach_exit(&mut self, code: StockCode, rule: ExitRule, date: i32) {
+        if let Err(e) = self.account.attach_exit(code, rule) {
+            warn!("{} attach exit failed on {}: {}", code, date, e);
         }
     }
-    type MACDFomula = MovingAverageConvergenceDivergence;
-    type MACD = MovingAverageConvergenceDivergenceOutput;
-    impl SeriseContainer<MACDFomula, MACD> {
-        fn new(formula: MACDFomula) -> Self {
-            SeriseContainer {
-                data: vec![],
-                formula: formula,
+
+    /// 用K线撮合委托, 返回成交或拒绝事件
+    ///
+    /// 参考价由成交时机决定; 限价委托在参考价不劣于限价时按参考价成交,
+    /// 否则在下一根K线盘中触及限价时按限价成交. 分笔文件读取失败时回测出错, 而不是退回按开盘价成交
+    fn execute(&mut self, order: OrderRequest, bar: &DayTradeUnit) -> Result<Event> {
+        if self.fill_timing == FillTiming::NextTicks {
+            let ticks = match self.ticks.as_ref() {
+                Some(source) => source.ticks(order.code, bar.date)?,
+                None => None,
+            };
+            if let Some(ticks) = ticks {
+                return Ok(self.execute_ticks(order, bar.date, &ticks));
             }
         }
+        let unit = &bar.trade_data;
+        let reference = match self.fill_timing {
+            FillTiming::SameClose => Some(price_of(unit.close)),
+            FillTiming::NextOpen | FillTiming::NextTicks => Some(price_of(unit.open)),
+            FillTiming::NextVwap if unit.volume > 0 => {
+                Decimal::from_f32_retain(unit.amount).map(|amount| (amount / Decimal::from(unit.volume)).round_dp(2))
+            }
+            FillTiming::NextVwap => None,
+        };
+        let reference = match reference {
+            Some(price) => price,
+            None => return Ok(Event::Rejected(order, TradeError::NoMarketData)),
+        };
+        let intraday = self.fill_timing != FillTiming::SameClose;
+        let price = match (order.kind, order.side) {
+            (OrderKind::Market, side) => self.slippage.apply(reference, side),
+            (OrderKind::Limit(limit), Side::Buy) if reference <= limit => reference,
+            (OrderKind::Limit(limit), Side::Buy) if intraday && price_of(unit.low) <= limit => limit,
+            (OrderKind::Limit(limit), Side::Sell) if reference >= limit => reference,
+            (OrderKind::Limit(limit), Side::Sell) if intraday && price_of(unit.high) >= limit => limit,
+            _ => return Ok(Event::Rejected(order, TradeError::LimitNotReached)),
+        };
+        Ok(self.fill(order, bar.date, price))
     }
-    impl Serise for SeriseContainer<MACDFomula, MACD> {
-        fn add(&mut self, value: f64) {
-            let result = self.formula.next(value);
-            self.data.push(result);
+
+    /// 用分笔撮合委托
+    ///
+    /// 按时间顺序消耗价格不劣于限价的分笔成交量, 直到覆盖委托数量, 以这些分笔的加权均价成交,
+    /// 市价委托再计滑点; 当日可成交的量不足时拒绝. 同一股票当日先成交的委托消耗的量, 后面的委托不能再用
+    fn execute_ticks(&mut self, order: OrderRequest, date: i32, ticks: &[Tick]) -> Event {
+        self.tick_used.retain(|(_, day), _| *day == date);
+        let used = self.tick_used.entry((order.code, date)).or_default();
+        used.resize(ticks.len(), Decimal::ZERO);
+        let mut taken = vec![];
+        let mut remaining = order.vol;
+        let mut amount = Decimal::ZERO;
+        for (i, tick) in ticks.iter().enumerate() {
+            let price = price_of(tick.price);
+            let reachable = match (order.kind, order.side) {
+                (OrderKind::Market, _) => true,
+                (OrderKind::Limit(limit), Side::Buy) => price <= limit,
+                (OrderKind::Limit(limit), Side::Sell) => price >= limit,
+            };
+            if !reachable {
+                continue;
+            }
+            let available = Decimal::from(tick.volume) * Decimal::ONE_HUNDRED - used[i];
+            if available <= Decimal::ZERO {
+                continue;
+            }
+            let vol = remaining.min(available);
+            taken.push((i, vol));
+            amount += price * vol;
+            remaining -= vol;
+            if remaining.is_zero() {
+                break;
+            }
         }
+        if !remaining.is_zero() || order.vol.is_zero() {
+            let reason = match order.kind {
+                OrderKind::Market => TradeError::NoMarketData,
+                OrderKind::Limit(_) => TradeError::LimitNotReached,
+            };
+            return Event::Rejected(order, reason);
+        }
+        let average = (amount / order.vol).round_dp(2);
+        let price = match order.kind {
+            OrderKind::Market => self.slippage.apply(average, order.side),
+            OrderKind::Limit(_) => average,
+        };
+        let code = order.code;
+        let event = self.fill(order, date, price);
+        if let (Event::Fill(_), Some(used)) = (&event, self.tick_used.get_mut(&(code, date))) {
+            for (i, vol) in taken {
+                used[i] += vol;
+            }
+        }
+        event
     }
-    impl Provider for SeriseContainer<MACDFomula, MACD> {
-        fn provide<'a>(&'a self, demand: &mut Demand<'a>) {
-            demand.provide_ref(&self.data);
+
+    fn fill(&mut self, order: OrderRequest, date: i32, price: Decimal) -> Event {
+        let filled = match order.side {
+            Side::Buy => self.account.buy(order.code, &price.to_string(), &order.vol.to_string()),
+            Side::Sell => self.account.sell(order.code, &price.to_string(), &order.vol.to_string()),
+        };
+        match filled {
+            Ok(()) => {
+                if order.side == Side::Buy {
+                    for rule in order.exits.iter() {
+                        self.attach_exit(order.code, rule.clone(), date);
+                    }
+                }
+                Event::Fill(Fill { order, date, price })
+            }
+            Err(reason) => Event::Rejected(order, reason),
         }
     }
+}
+
+/// 通达信价格为 价格*100 的整数
+fn price_of(raw: i32) -> Decimal {
+    Decimal::new(raw as i64, 2)
+}
+
+fn close_of(data: &HashMap<StockCode, Vec<DayTradeUnit>>, code: StockCode) -> Option<Decimal> {
+    data.get(code)
+        .and_then(|history| history.last())
+        .map(|bar| price_of(bar.trade_data.close))
+}
+
+#[cfg(test)]
+mod tests {
+    use ta::indicators::{
+        MovingAverageConvergenceDivergence as MACDFomula, MovingAverageConvergenceDivergenceOutput as MACD,
+        SimpleMovingAverage,
+    };
+
+    use rust_decimal::Decimal;
+    use std::collections::HashMap;
+
+    use crate::{
+        data::{DayTradeUnit, StockCode},
+        event::{Context, Fill, OrderKind, OrderRequest},
+        serise::SeriseRegistry,
+        strategy::{ExitRule, Trade, TradeError},
+        tick::{Direction, Tick, TickFiles},
+    };
+
+    use super::{BackTest, BackTestError, FillTiming, MarketSnapshot, PortfolioStrategy, Side, Slippage, Strategy};
+
     pub struct MACross {
-        serises: HashMap<&'static str, Box<dyn Serise>>,
+        code: StockCode,
+        checked: usize,
     }
 
     impl MACross {
-        fn new() -> Self {
-            let sma5 = Box::new(SeriseContainer::<SimpleMovingAverage, f64>::new(
-                SimpleMovingAverage::new(5).unwrap(),
-            ));
-            let sma5: Box<dyn Serise> = sma5;
-            let macd = Box::new(SeriseContainer::<MACDFomula, MACD>::new(
-                MACDFomula::new(3, 6, 4).unwrap(),
-            ));
-            let macd: Box<dyn Serise> = macd;
-            let mut serises = HashMap::new();
-            serises.insert("sma5", sma5);
-            serises.insert("macd", macd);
-            MACross { serises }
+        fn new(code: StockCode) -> Self {
+            MACross { code, checked: 0 }
         }
     }
 
     impl Strategy for MACross {
-        fn next(&mut self, account: &mut Account, stock_trade_info: &Vec<DayTradeUnit>) {
-            if let Some(today) = stock_trade_info.last() {
-                for (k, v) in self.serises.iter_mut() {
-                    v.add(f64::from(today.trade_data.close));
-                }
-                let macd: &Vec<MACD> = self.serises.get("macd").unwrap().get().unwrap();
-                println!("macd: {:?}", macd)
+        fn serises(&self, registry: &mut SeriseRegistry) {
+            registry.add("sma5", SimpleMovingAverage::new(5).unwrap());
+            registry.add("macd", MACDFomula::new(3, 6, 4).unwrap());
+        }
+
+        fn next(&mut self, ctx: &mut Context, stock_trade_info: &Vec<DayTradeUnit>) {
+            let macd: &Vec<MACD> = ctx.serise(self.code, "macd").unwrap();
+            let sma5: &Vec<f64> = ctx.serise(self.code, "sma5").unwrap();
+            assert_eq!(macd.len(), stock_trade_info.len());
+            assert!(ctx.serise::<f64>(self.code, "macd").is_none());
+            if stock_trade_info.len() >= 5 {
+                let closes: i32 = stock_trade_info.iter().rev().take(5).map(|bar| bar.trade_data.close).sum();
+                assert!((sma5.last().unwrap() - closes as f64 / 500.0).abs() < 1e-9);
+                self.checked += 1;
             }
         }
     }
 
     #[test]
     fn iter_day_info() {
-        let mut ma = MACross::new();
+        let mut ma = MACross::new("603339");
         let strategy = &mut ma as &mut dyn Strategy;
-        let mut backTest = BackTest::new("603339", strategy).unwrap();
-        backTest.run("20220901", "20230103");
+        let mut back_test = BackTest::new("603339", strategy).unwrap();
+        back_test.run("20220901", "20230103").unwrap();
+        drop(back_test);
+        assert!(ma.checked > 0);
+    }
+
+    /// 每天买入当日涨幅更大的股票
+    struct Rotation {
+        dates: Vec<i32>,
+    }
+
+    impl PortfolioStrategy for Rotation {
+        fn on_bar(&mut self, ctx: &mut Context, market: &MarketSnapshot) {
+            self.dates.push(market.date());
+            let strongest: Option<(&StockCode, &DayTradeUnit)> = market
+                .codes()
+                .iter()
+                .filter_map(|code| market.bar(code).map(|bar| (code, bar)))
+                .max_by_key(|(_, bar)| bar.trade_data.close * 1000 / bar.trade_data.open);
+            if let Some((code, bar)) = strongest {
+                let price = Decimal::new(bar.trade_data.close as i64, 2).to_string();
+                let _ = ctx.buy(code, &price, "100");
+            }
+        }
+    }
+
+    #[test]
+    fn portfolio() {
+        let mut rotation = Rotation { dates: vec![] };
+        let mut back_test = BackTest::with_universe(vec!["603339", "603338"], &mut rotation).unwrap();
+        back_test.run("20220901", "20230103").unwrap();
+        let positions = back_test.account().get_positions();
+        assert_eq!(positions.len(), 2);
+        assert!(back_test.benchmark_statistics().is_none());
+        let days = back_test.account().equity_curve().len();
+        drop(back_test);
+        assert_eq!(days, rotation.dates.len());
+        assert!(rotation.dates.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    /// 记录回调顺序
+    #[derive(Default)]
+    struct Recorder {
+        calls: Vec<String>,
+    }
+
+    impl PortfolioStrategy for Recorder {
+        fn on_bar(&mut self, ctx: &mut Context, market: &MarketSnapshot) {
+            self.calls.push(format!("bar {}", market.date()));
+            if market.date() == 20221031 {
+                ctx.buy("603339", "1000", "100").unwrap();
+                ctx.buy("603339", "1000", "1000000").unwrap();
+                ctx.schedule(market.date());
+            }
+        }
+
+        fn on_fill(&mut self, _ctx: &mut Context, fill: &Fill) {
+            self.calls.push(format!("fill {} {}", fill.order.id, fill.price));
+        }
+
+        fn on_order_rejected(&mut self, _ctx: &mut Context, order: &OrderRequest, reason: &TradeError) {
+            assert_eq!(reason, &TradeError::OutOfBalance);
+            self.calls.push(format!("rejected {}", order.id));
+        }
+
+        fn on_day_start(&mut self, _ctx: &mut Context, date: i32) {
+            self.calls.push(format!("start {}", date));
+        }
+
+        fn on_day_end(&mut self, _ctx: &mut Context, date: i32) {
+            self.calls.push(format!("end {}", date));
+        }
+
+        fn on_timer(&mut self, _ctx: &mut Context, id: u64) {
+            self.calls.push(format!("timer {}", id));
+        }
+    }
+
+    /// 开盘前下单
+    #[derive(Default)]
+    struct Opening {
+        calls: Vec<String>,
+    }
+
+    impl PortfolioStrategy for Opening {
+        fn on_bar(&mut self, _ctx: &mut Context, market: &MarketSnapshot) {
+            self.calls.push(format!("bar {}", market.date()));
+        }
+
+        fn on_fill(&mut self, _ctx: &mut Context, fill: &Fill) {
+            self.calls.push(format!("fill {} {}", fill.date, fill.price));
+        }
+
+        fn on_day_start(&mut self, ctx: &mut Context, date: i32) {
+            if date == 20221101 {
+                ctx.buy("603339", "1000", "100").unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn day_start_orders() {
+        // on_day_start 的委托按当天的K线成交, 不会推迟到下一交易日
+        for (fill_timing, fill) in [(FillTiming::NextOpen, "fill 20221101 13.80"), (FillTiming::SameClose, "fill 20221101 13.74")] {
+            let mut opening = Opening::default();
+            let mut back_test = BackTest::with_universe(vec!["603339"], &mut opening).unwrap();
+            back_test.set_fill_timing(fill_timing);
+            back_test.run("20221031", "20221101").unwrap();
+            drop(back_test);
+            assert_eq!(opening.calls, vec!["bar 20221031", fill, "bar 20221101"]);
+        }
+    }
+
+    #[test]
+    fn event_callbacks() {
+        // 默认下一根K线开盘价成交, 成交回报先于当日K线
+        let mut recorder = Recorder::default();
+        let mut back_test = BackTest::with_universe(vec!["603339"], &mut recorder).unwrap();
+        back_test.run("20221031", "20221101").unwrap();
+        assert_eq!(back_test.account().get_positions().len(), 1);
+        drop(back_test);
+        assert_eq!(
+            recorder.calls,
+            vec![
+                "start 20221031", "bar 20221031", "timer 3", "end 20221031",
+                "start 20221101", "fill 1 13.80", "rejected 2", "bar 20221101", "end 20221101",
+            ]
+        );
+
+        // 当根K线收盘价成交
+        let mut recorder = Recorder::default();
+        let mut back_test = BackTest::with_universe(vec!["603339"], &mut recorder).unwrap();
+        back_test.set_fill_timing(FillTiming::SameClose);
+        back_test.run("20221031", "20221101").unwrap();
+        drop(back_test);
+        assert_eq!(
+            recorder.calls,
+            vec![
+                "start 20221031", "bar 20221031", "fill 1 13.77", "rejected 2", "timer 3", "end 20221031",
+                "start 20221101", "bar 20221101", "end 20221101",
+            ]
+        );
+
+        // 均价成交
+        let mut recorder = Recorder::default();
+        let mut back_test = BackTest::with_universe(vec!["603339"], &mut recorder).unwrap();
+        back_test.set_fill_timing(FillTiming::NextVwap);
+        back_test.run("20221031", "20221101").unwrap();
+        drop(back_test);
+        assert_eq!(recorder.calls[5], "fill 1 13.62");
+
+        // 分笔成交, 第一笔委托用掉第一笔分笔, 第二笔委托只能消耗第二笔分笔, 资金不足; 没有分笔数据的交易日按开盘价成交
+        let tick = |time, price, volume| Tick { date: 20221101, time, price, volume, count: 1, direction: Direction::Neutral };
+        for (ticks, fill) in [(vec![tick(92500, 1375, 1), tick(93100, 1360, 20000)], "fill 1 13.75"), (vec![], "fill 1 13.80")] {
+            let mut source: HashMap<(StockCode, i32), Vec<Tick>> = HashMap::new();
+            if !ticks.is_empty() {
+                source.insert(("603339", 20221101), ticks);
+            }
+            let mut recorder = Recorder::default();
+            BackTest::builder()
+                .codes(vec!["603339"])
+                .fill_timing(FillTiming::NextTicks)
+                .ticks(source)
+                .strategy(&mut recorder)
+                .build()
+                .unwrap()
+                .run("20221031", "20221101")
+                .unwrap();
+            assert_eq!(recorder.calls[5], fill);
+            assert_eq!(recorder.calls[6], "rejected 2");
+        }
+
+        // 分笔文件损坏时回测出错, 不会悄悄按开盘价成交
+        let dir = std::env::temp_dir().join(format!("millions-ticks-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("20221101")).unwrap();
+        std::fs::write(dir.join("20221101").join("sh603339.txt"), "09:30\t13.82\n").unwrap();
+        let mut recorder = Recorder::default();
+        let result = BackTest::builder()
+            .codes(vec!["603339"])
+            .fill_timing(FillTiming::NextTicks)
+            .ticks(TickFiles::new(&dir))
+            .strategy(&mut recorder)
+            .build()
+            .unwrap()
+            .run("20221031", "20221101");
+        assert!(matches!(result, Err(BackTestError::TickError { .. })));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// 同一天对同一股票下两笔市价委托
+    #[derive(Default)]
+    struct TwoBuys {
+        fills: Vec<String>,
+    }
+
+    impl PortfolioStrategy for TwoBuys {
+        fn on_bar(&mut self, ctx: &mut Context, market: &MarketSnapshot) {
+            if market.date() == 20221031 {
+                for _ in 0..2 {
+                    ctx.submit("603339", Side::Buy, Decimal::ONE_HUNDRED, OrderKind::Market);
+                }
+            }
+        }
+
+        fn on_fill(&mut self, _ctx: &mut Context, fill: &Fill) {
+            self.fills.push(fill.price.to_string());
+        }
+    }
+
+    #[test]
+    fn ticks_shared_by_orders() {
+        // 每笔分笔只有 1 手, 第二笔委托不能再用第一笔委托消耗过的量
+        let tick = |time, price| Tick { date: 20221101, time, price, volume: 1, count: 1, direction: Direction::Neutral };
+        let source: HashMap<(StockCode, i32), Vec<Tick>> = [(("603339", 20221101), vec![tick(93000, 1375), tick(93100, 1380)])].into();
+        let mut strategy = TwoBuys::default();
+        let result = BackTest::builder()
+            .codes(vec!["603339"])
+            .fill_timing(FillTiming::NextTicks)
+            .ticks(source)
+            .strategy(&mut strategy)
+            .build()
+            .unwrap()
+            .run("20221031", "20221101")
+            .unwrap();
+        assert_eq!(strategy.fills, vec!["13.75", "13.80"]);
+        assert!(result.rejected.is_empty());
+    }
+
+    /// 买入时附加止损, 之后给已有持仓附加止盈
+    #[derive(Default)]
+    struct Protected {
+        calls: Vec<String>,
+    }
+
+    impl PortfolioStrategy for Protected {
+        fn on_bar(&mut self, ctx: &mut Context, market: &MarketSnapshot) {
+            match market.date() {
+                20221011 | 20221013 => {
+                    ctx.buy("603339", "20", "100").unwrap();
+                    if market.date() == 20221011 {
+                        ctx.attach_exit("603339", ExitRule::StopLoss(Decimal::from(12))).unwrap();
+                    }
+                }
+                20221014 => ctx.attach_exit("603339", ExitRule::TakeProfit(Decimal::new(1250, 2))).unwrap(),
+                20221010 | 20221012 => {
+                    assert_eq!(ctx.attach_exit("603339", ExitRule::TrailingStop(Decimal::ONE)), Err(TradeError::OutOfPosition))
+                }
+                _ => {}
+            }
+        }
+
+        fn on_fill(&mut self, _ctx: &mut Context, fill: &Fill) {
+            self.calls.push(format!("{} {:?} {}", fill.date, fill.order.side, fill.price));
+        }
+    }
+
+    #[test]
+    fn strategy_exits() {
+        let mut protected = Protected::default();
+        let mut back_test = BackTest::with_universe(vec!["603339"], &mut protected).unwrap();
+        back_test.run("20221010", "20221017").unwrap();
+        drop(back_test);
+        // 10-12 开盘 12.41 买入, 盘中最低 11.58 触发 12.00 止损; 10-14 开盘 11.86 买入, 10-18 才触及 12.50 止盈
+        assert_eq!(protected.calls, vec!["20221012 Buy 12.41", "20221012 Sell 12", "20221014 Buy 11.86"]);
+
+        let mut protected = Protected::default();
+        let mut back_test = BackTest::with_universe(vec!["603339"], &mut protected).unwrap();
+        back_test.run("20221010", "20221018").unwrap();
+        let held = back_test.account().get_position("603339").unwrap().map(|position| position.volume());
+        assert_eq!(held.unwrap_or_default(), Decimal::ZERO);
+        drop(back_test);
+        assert_eq!(protected.calls[3], "20221018 Sell 12.50");
+    }
+
+    #[test]
+    fn benchmark() {
+        let mut recorder = Recorder::default();
+        let mut back_test = BackTest::with_universe(vec!["603339"], &mut recorder).unwrap();
+        back_test.set_benchmark("sh603338");
+        back_test.run("20220901", "20221101").unwrap();
+        assert_eq!(back_test.benchmark().len(), back_test.account().equity_curve().len());
+        let stats = back_test.benchmark_statistics().unwrap();
+        assert_eq!(stats.excess_curve.len(), back_test.benchmark().len());
+    }
+
+    #[test]
+    fn slippage() {
+        let buy = |slippage: Slippage, price: Decimal| slippage.apply(price, Side::Buy);
+        assert_eq!(buy(Slippage::None, Decimal::new(10123, 3)), Decimal::new(10123, 3));
+        // 均价成交时价格不止两位小数, 滑点加到价格上再取整
+        assert_eq!(buy(Slippage::Percent(Decimal::new(1, 3)), Decimal::new(10123, 3)), Decimal::new(1013, 2));
+        assert_eq!(buy(Slippage::Fixed(Decimal::new(1, 2)), Decimal::new(10123, 3)), Decimal::new(1013, 2));
+        assert_eq!(Slippage::Percent(Decimal::new(3, 3)).apply(Decimal::new(500, 2), Side::Sell), Decimal::new(498, 2));
+        assert_eq!(Slippage::Fixed(Decimal::ONE).apply(Decimal::new(50, 2), Side::Sell), Decimal::new(1, 2));
+    }
+
+    #[test]
+    fn result() {
+        let mut recorder = Recorder::default();
+        let mut back_test = BackTest::with_universe(vec!["603339"], &mut recorder).unwrap();
+        let result = back_test.run("20221031", "20221101").unwrap();
+        drop(back_test);
+        assert_eq!(result.config.from, 20221031);
+        assert_eq!(result.config.capital, Decimal::from(100000));
+        assert_eq!(result.equity_curve.len(), 2);
+        assert_eq!(result.trades.len(), 1);
+        let trade = &result.trades[0];
+        assert_eq!((trade.date, trade.price, trade.vol), (20221101, Decimal::new(1380, 2), Decimal::from(100)));
+        assert_eq!(trade.fees(), Decimal::new(52, 2));
+        assert_eq!(result.rejected.len(), 1);
+        assert_eq!(result.rejected[0].reason, "out of balance");
+        assert_eq!(result.positions[0].market_value, Decimal::new(137400, 2));
+        assert_eq!(result.cash + result.positions[0].market_value, result.final_equity());
+
+        let json = result.to_json().unwrap();
+        assert!(json.contains("\"fill_timing\": \"NextOpen\""));
+        assert!(json.contains("\"cost_method\": \"Average\"") && json.contains("\"ticks\": false"));
+        let trades = result.trades_csv();
+        assert_eq!(trades.lines().nth(1), Some("20221101,603339,Buy,100,13.80,1380.00,0,0.5,0.02,0"));
+        assert_eq!(result.rejected_csv().lines().count(), 2);
     }
 }
@@ -0,0 +1,223 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    backtest::{BackTest, BackTestError, FillTiming, Slippage},
+    data::{intern, StockCode},
+    formula::{Formula, FormulaError, FormulaStrategy},
+    optimizer::Params,
+    result::BacktestResult,
+    strategies,
+    strategy::{CostMethod, FeeModel},
+    tick::TickFiles,
+};
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("read config fail")]
+    IoError {
+        #[from]
+        source: io::Error,
+    },
+    #[error("parse config fail")]
+    TomlError {
+        #[from]
+        source: toml::de::Error,
+    },
+    #[error("parse formula fail")]
+    FormulaError {
+        #[from]
+        source: FormulaError,
+    },
+    #[error("unknown strategy {0}")]
+    UnknownStrategy(String),
+    #[error("backtest fail")]
+    BackTestError {
+        #[from]
+        source: BackTestError,
+    },
+}
+
+pub type Result<T, E = ConfigError> = std::result::Result<T, E>;
+
+/// 回测配置文件, 与代码一起提交即可重现回测
+///
+/// ```toml
+/// codes = ["603339", "603338"]
+/// from = 20220101
+/// to = 20221101
+/// capital = "100000"
+/// fill_timing = "NextOpen"
+/// slippage = { Percent = "0.001" }
+/// benchmark = "sh000300"
+///
+/// [fees]
+/// brokerage = "0.00025"
+/// min_brokerage = "5"
+///
+/// [strategy]
+/// name = "ma-cross"
+///
+/// [strategy.params]
+/// fast = 5
+/// slow = 20
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RunConfig {
+    /// 股票池
+    pub codes: Vec<String>,
+    /// 开始日期 例如 20220101
+    pub from: i32,
+    /// 结束日期
+    pub to: i32,
+    /// 初始资金, 默认 100000
+    #[serde(default = "default_capital")]
+    pub capital: Decimal,
+    #[serde(default)]
+    pub fill_timing: FillTiming,
+    #[serde(default)]
+    pub slippage: Slippage,
+    #[serde(default)]
+    pub cost_method: CostMethod,
+    /// 比较基准 例如 sh000300
+    pub benchmark: Option<String>,
+    /// 分笔成交目录, fill_timing 为 NextTicks 时使用, 见 [`TickFiles`]
+    pub ticks: Option<PathBuf>,
+    /// 没有给出的费率取默认值
+    #[serde(default)]
+    pub fees: FeeModel,
+    pub strategy: StrategyConfig,
+}
+
+/// 回测使用的策略
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StrategyConfig {
+    /// 内置策略名称, 见 [`strategies::NAMES`], 使用公式文件时为 formula
+    pub name: String,
+    /// 通达信公式文件, 相对路径相对于配置文件所在目录, 取 BUY SELL 输出线
+    pub formula: Option<PathBuf>,
+    /// 策略参数, `percent` 为每次买入使用余额的百分比
+    #[serde(default)]
+    pub params: Params,
+}
+
+fn default_capital() -> Decimal {
+    Decimal::new(100000, 0)
+}
+
+impl RunConfig {
+    pub fn parse(text: &str) -> Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    /// 读取配置文件, 公式文件和分笔目录的相对路径换算为相对于配置文件
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let mut config = Self::parse(&fs::read_to_string(path)?)?;
+        if let Some(dir) = path.parent() {
+            for file in [config.strategy.formula.as_mut(), config.ticks.as_mut()].into_iter().flatten() {
+                if file.is_relative() {
+                    *file = dir.join(&file);
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn stock_codes(&self) -> Vec<StockCode> {
+        self.codes.iter().map(|code| intern(code)).collect()
+    }
+
+    /// 按配置创建策略
+    pub fn build_strategy(&self) -> Result<FormulaStrategy> {
+        let strategy = &self.strategy;
+        match (strategy.name.as_str(), strategy.formula.as_ref()) {
+            ("formula", Some(path)) => {
+                let mut params = strategy.params.clone();
+                let percent = params.remove("percent").unwrap_or(90.0).clamp(0.0, 100.0) as u8;
+                let mut formula = Formula::parse(&fs::read_to_string(path)?)?;
+                for (param, value) in params {
+                    formula = formula.param(&param, value);
+                }
+                Ok(FormulaStrategy::with_signals(formula, "BUY", "SELL", percent)?)
+            }
+            (name, _) => strategies::by_name(name, &strategy.params).ok_or_else(|| ConfigError::UnknownStrategy(name.to_string())),
+        }
+    }
+
+    /// 按配置创建回测, 策略由调用者持有, 回测结束后还可以查看K线
+    pub fn build<'a>(&self, strategy: &'a mut FormulaStrategy) -> Result<BackTest<'a>> {
+        let mut builder = BackTest::builder()
+            .codes(self.stock_codes())
+            .capital(&self.capital.to_string())
+            .fees(self.fees)
+            .cost_method(self.cost_method)
+            .slippage(self.slippage)
+            .fill_timing(self.fill_timing)
+            .params(self.strategy.params.clone())
+            .strategy(strategy);
+        if let Some(benchmark) = self.benchmark.as_ref() {
+            builder = builder.benchmark(intern(benchmark));
+        }
+        if let Some(ticks) = self.ticks.as_ref() {
+            builder = builder.ticks(TickFiles::new(ticks));
+        }
+        Ok(builder.build()?)
+    }
+
+    pub fn run(&self) -> Result<BacktestResult> {
+        let mut strategy = self.build_strategy()?;
+        let mut back_test = self.build(&mut strategy)?;
+        Ok(back_test.run(&self.from.to_string(), &self.to.to_string())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, path::Path};
+
+    use rust_decimal::Decimal;
+
+    use crate::backtest::{FillTiming, Slippage};
+
+    use super::{ConfigError, RunConfig};
+
+    #[test]
+    fn run_config() {
+        let root = env::var("MILLIONS_TDX").unwrap();
+        let config = RunConfig::open(Path::new(&root).join("backtest.toml")).unwrap();
+        assert_eq!(config.codes, vec!["603339", "603338"]);
+        assert_eq!(config.fill_timing, FillTiming::NextOpen);
+        assert_eq!(config.slippage, Slippage::Percent(Decimal::new(1, 3)));
+        assert_eq!(config.fees.min_brokerage, Decimal::new(5, 0));
+        assert_eq!(config.fees.stamp_duty, Decimal::new(1, 3));
+
+        let result = config.run().unwrap();
+        assert_eq!(result.config.slippage, config.slippage);
+        assert_eq!((result.config.cost_method, &result.config.params), (config.cost_method, &config.strategy.params));
+        assert_eq!(result.config.capital, Decimal::new(100000, 0));
+        assert!(result.trades.iter().filter(|trade| !trade.vol.is_zero()).all(|trade| trade.brokerage_fee >= Decimal::new(5, 0)));
+        // 相同的配置得到相同的结果
+        assert_eq!(result.to_json().unwrap(), config.run().unwrap().to_json().unwrap());
+
+        let config = RunConfig::parse("codes = [\"603339\"]\nfrom = 1\nto = 2\n[strategy]\nname = \"missing\"\n").unwrap();
+        assert_eq!(config.capital, Decimal::new(100000, 0));
+        assert!(matches!(config.run(), Err(ConfigError::UnknownStrategy(_))));
+
+        // 拼错的键报错, 而不是静默取默认值
+        let text = "codes = [\"603339\"]\nfrom = 1\nto = 2\nslipage = { Percent = \"0.001\" }\n[strategy]\nname = \"ma-cross\"\n";
+        assert!(RunConfig::parse(text).is_err());
+        let text = "codes = [\"603339\"]\nfrom = 1\nto = 2\n[strategy]\nname = \"ma-cross\"\n[strategy.param]\nfast = 5\n";
+        assert!(RunConfig::parse(text).is_err());
+        let text = "codes = [\"603339\"]\nfrom = 1\nto = 2\n[fees]\nmin_brokrage = \"5\"\n[strategy]\nname = \"ma-cross\"\n";
+        assert!(RunConfig::parse(text).is_err());
+    }
+}
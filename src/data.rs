@@ -1,5 +1,5 @@
 use byteorder::{ByteOrder, LittleEndian};
-use chrono::{Utc, DateTime};
+use chrono::{Utc, DateTime, Datelike, NaiveDate, TimeZone};
 #[cfg(test)]
 use std::{println as info, println as warn};
 #[cfg(not(test))] 
@@ -11,6 +11,8 @@ use std::{
     mem::{align_of, size_of},
     path::{Path, PathBuf}, time::Duration,
     convert::{Into, From}, any::Any,
+    collections::{BTreeSet, HashMap}, fs,
+    sync::Mutex,
 };
 
 
@@ -27,7 +29,7 @@ pub struct Header {
 
 pub const HeaderSize: usize = 0x14;
 
-trait Deserializer {
+pub(crate) trait Deserializer {
     fn deserializer(buffer: &[u8]) -> Self;
 }
 
@@ -54,8 +56,7 @@ pub const StockSize: usize = 7 + 4;
 
 impl Deserializer for StockItem {
     fn deserializer(buffer: &[u8]) -> Self {
-        println!("{:?}", &buffer[0..6]);
-        let code = String::from_utf8(buffer[0..6].to_vec()).unwrap();
+        let code = String::from_utf8_lossy(&buffer[0..6]).to_string();
         StockItem {
             code,
             offset: LittleEndian::read_u32(&buffer[7..11]),
@@ -74,7 +75,78 @@ impl Deserializer for StockFinanceValue {
     }
 }
 
-#[derive(Debug)]
+/**
+ * 通达信专业财务数据, 即 `vipdoc/cw/gpcw20220930.dat`
+ *
+ * 文件由 Header, 每只股票一个 StockItem 索引, 以及每只股票 report_size 字节的 f32 数据组成,
+ * 各列的含义见 example/finance.txt
+ */
+#[derive(Debug, Clone, Default)]
+pub struct FinanceData {
+    report_date: i32,
+    values: HashMap<String, Vec<f32>>,
+}
+
+impl FinanceData {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut buffer = vec![];
+        File::open(path)?.read_to_end(&mut buffer)?;
+        Self::parse(&buffer)
+    }
+
+    pub fn parse(buffer: &[u8]) -> Result<Self> {
+        if buffer.len() < HeaderSize {
+            return Err(DataSourceError::FinanceFormat)
+        }
+        let header = Header::deserializer(&buffer[..HeaderSize]);
+        let count = header.max_count as u16 as usize;
+        let report_size = header.report_size as usize;
+        let mut values = HashMap::with_capacity(count);
+        for i in 0..count {
+            let start = HeaderSize + i * StockSize;
+            let item = buffer.get(start..start + StockSize).ok_or(DataSourceError::FinanceFormat)?;
+            let item = StockItem::deserializer(item);
+            let offset = item.offset as usize;
+            let report = buffer.get(offset..offset + report_size).ok_or(DataSourceError::FinanceFormat)?;
+            let cols = report
+                .chunks_exact(StockFinanceColValueSize)
+                .map(|col| StockFinanceValue::deserializer(col).0)
+                .collect();
+            values.insert(item.code, cols);
+        }
+        Ok(FinanceData { report_date: header.report_date, values })
+    }
+
+    /// 报告期 例如 20220930
+    pub fn report_date(&self) -> i32 {
+        self.report_date
+    }
+
+    /// 财务数据, 列号与 finance.txt 一致从 1 开始, 例如 `4` 每股净资产, `6` 净资产收益率
+    ///
+    /// 股票代码可以带市场前缀
+    pub fn get(&self, code: &str, column: usize) -> Option<f32> {
+        let code = code.strip_prefix("sh").or_else(|| code.strip_prefix("sz")).unwrap_or(code);
+        self.values.get(code)?.get(column.checked_sub(1)?).copied()
+    }
+
+    /// 全部股票代码和财务数据, 按代码排列
+    pub fn rows(&self) -> Vec<(&str, &[f32])> {
+        let mut rows: Vec<(&str, &[f32])> = self.values.iter().map(|(code, values)| (code.as_str(), &values[..])).collect();
+        rows.sort_by_key(|(code, _)| *code);
+        rows
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct TradeUnit {
     pub open: i32,
     pub close: i32,
@@ -98,13 +170,72 @@ impl Deserializer for TradeUnit {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DayTradeUnit {
     pub date: i32,
     pub trade_data: TradeUnit
 }
 pub const DayTradeUnitSize: usize = 32;
 
+impl DayTradeUnit {
+    /// 从第 `skip` 条记录开始读取日线文件, 通达信正在写入的不完整记录留到下次读取
+    pub fn read_from(path: &Path, skip: u64) -> Result<Vec<DayTradeUnit>> {
+        let mut file = File::open(path)?;
+        let records = file.metadata()?.len() / DayTradeUnitSize as u64;
+        if skip >= records {
+            return Ok(vec![]);
+        }
+        file.seek(io::SeekFrom::Start(skip * DayTradeUnitSize as u64))?;
+        let mut buffer = vec![0u8; (records - skip) as usize * DayTradeUnitSize];
+        file.read_exact(&mut buffer)?;
+        Ok(buffer.chunks_exact(DayTradeUnitSize).map(DayTradeUnit::deserializer).collect())
+    }
+
+    /// 编码为日线文件的一条记录
+    pub fn to_bytes(&self) -> [u8; DayTradeUnitSize] {
+        let mut buffer = [0u8; DayTradeUnitSize];
+        LittleEndian::write_i32(&mut buffer[..4], self.date);
+        LittleEndian::write_i32(&mut buffer[4..8], self.trade_data.open);
+        LittleEndian::write_i32(&mut buffer[8..12], self.trade_data.high);
+        LittleEndian::write_i32(&mut buffer[12..16], self.trade_data.low);
+        LittleEndian::write_i32(&mut buffer[16..20], self.trade_data.close);
+        LittleEndian::write_f32(&mut buffer[20..24], self.trade_data.amount);
+        LittleEndian::write_i32(&mut buffer[24..28], self.trade_data.volume);
+        buffer
+    }
+}
+
+/// 供 `ta` 指标使用, 价格换算为元
+impl ta::Open for DayTradeUnit {
+    fn open(&self) -> f64 {
+        self.trade_data.open as f64 / 100.0
+    }
+}
+
+impl ta::High for DayTradeUnit {
+    fn high(&self) -> f64 {
+        self.trade_data.high as f64 / 100.0
+    }
+}
+
+impl ta::Low for DayTradeUnit {
+    fn low(&self) -> f64 {
+        self.trade_data.low as f64 / 100.0
+    }
+}
+
+impl ta::Close for DayTradeUnit {
+    fn close(&self) -> f64 {
+        self.trade_data.close as f64 / 100.0
+    }
+}
+
+impl ta::Volume for DayTradeUnit {
+    fn volume(&self) -> f64 {
+        self.trade_data.volume as f64
+    }
+}
+
 impl Deserializer for DayTradeUnit {
     fn deserializer(buffer: &[u8]) -> Self {
         DayTradeUnit { 
@@ -114,25 +245,58 @@ impl Deserializer for DayTradeUnit {
 }
 
 
-#[derive(Debug)]
+/// 分钟线, 即 `minline/*.lc1` 和 `fzline/*.lc5`, 价格为 f32, 读取时换算为分
+#[derive(Debug, Clone)]
 pub struct MinuteTradeUnit {
-    date: i32,
-    // 0点至目前的分钟数
-    offset: i16,
-    tradeData: TradeUnit
+    /// 日期 例如 20230103
+    pub date: i32,
+    /// 0点至目前的分钟数
+    pub offset: i16,
+    pub trade_data: TradeUnit
 }
 pub const MinuteTradeUnitSize: usize = 32;
 
+impl MinuteTradeUnit {
+    /// 时分 例如 935 表示 09:35
+    pub fn time(&self) -> i32 {
+        self.offset as i32 / 60 * 100 + self.offset as i32 % 60
+    }
+
+    /// 编码为分钟线文件的一条记录
+    pub fn to_bytes(&self) -> [u8; MinuteTradeUnitSize] {
+        let mut buffer = [0u8; MinuteTradeUnitSize];
+        let zip_day = (self.date / 10000 - 2004) * 2048 + self.date % 10000;
+        LittleEndian::write_u16(&mut buffer[0..2], zip_day as u16);
+        LittleEndian::write_i16(&mut buffer[2..4], self.offset);
+        let price = |price: i32| price as f32 / 100.0;
+        LittleEndian::write_f32(&mut buffer[4..8], price(self.trade_data.open));
+        LittleEndian::write_f32(&mut buffer[8..12], price(self.trade_data.high));
+        LittleEndian::write_f32(&mut buffer[12..16], price(self.trade_data.low));
+        LittleEndian::write_f32(&mut buffer[16..20], price(self.trade_data.close));
+        LittleEndian::write_f32(&mut buffer[20..24], self.trade_data.amount);
+        LittleEndian::write_i32(&mut buffer[24..28], self.trade_data.volume);
+        buffer
+    }
+}
+
 impl Deserializer for MinuteTradeUnit {
     fn deserializer(buffer: &[u8]) -> Self {
-        let date_raw = LittleEndian::read_i16(&buffer[0..1]);
-        let year = date_raw.div_floor(2048) + 2004;
-        let month = (date_raw % 2048).div_floor(100);
+        let date_raw = LittleEndian::read_u16(&buffer[0..2]) as i32;
+        let year = date_raw.div_euclid(2048) + 2004;
+        let month = (date_raw % 2048).div_euclid(100);
         let day = (date_raw % 2048) % 100;
+        let price = |buffer: &[u8]| (LittleEndian::read_f32(buffer) * 100.0).round() as i32;
         MinuteTradeUnit { 
-            date: 0, // todo
-            offset: LittleEndian::read_i16(&buffer[2..3]),
-            tradeData: TradeUnit::deserializer(&buffer[4..]) 
+            date: year * 10000 + month * 100 + day,
+            offset: LittleEndian::read_i16(&buffer[2..4]),
+            trade_data: TradeUnit {
+                open: price(&buffer[4..8]),
+                high: price(&buffer[8..12]),
+                low: price(&buffer[12..16]),
+                close: price(&buffer[16..20]),
+                amount: LittleEndian::read_f32(&buffer[20..24]),
+                volume: LittleEndian::read_i32(&buffer[24..28]),
+            }
         }
     }
 }
@@ -151,21 +315,73 @@ pub enum DataSourceError {
         source: env::VarError
     },
     #[error("stock not exist in market")]
-    StockCodeNotExistInMarket
+    StockCodeNotExistInMarket,
+    #[error("malformed finance file")]
+    FinanceFormat,
+    #[error("no trade data at the time")]
+    NoTradeData,
 }
 
 pub type Result<T, E = DataSourceError> = std::result::Result<T, E>;
 
 /**
- * 股票代码
+ * 股票代码, 也可以带市场前缀, 例如 sh000300 表示沪深300指数
  */
 pub type StockCode = &'static str;
+
+/// 运行时得到的股票代码, 例如目录扫描或命令行参数, 转换为 `StockCode`
+///
+/// 同一代码只分配一次并存活到进程结束, 重复扫描全市场不会重复分配
+pub fn intern(code: &str) -> StockCode {
+    static CODES: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+    let mut codes = CODES.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(code) = codes.get(code) {
+        return code;
+    }
+    let code: StockCode = Box::leak(code.to_string().into_boxed_str());
+    codes.insert(code);
+    code
+}
+
+/// 是否为A股股票代码(带市场前缀), 指数 基金 债券等返回 false
+///
+/// 上海 60 68, 深圳 00 30, 北京 4 8 920
+pub fn is_a_share(code: &str) -> bool {
+    const PREFIXES: [&str; 15] = [
+        "sh600", "sh601", "sh603", "sh605", "sh688", "sh689", "sz000", "sz001", "sz002", "sz003", "sz300", "sz301", "bj4",
+        "bj8", "bj920",
+    ];
+    PREFIXES.iter().any(|prefix| code.starts_with(prefix))
+}
+
+/// A股的涨跌停幅度(带市场前缀): 主板 10%, 创业板和科创板 20%, 北交所 30%, 不是A股时为 None
+///
+/// ST 股票的 5% 和创业板 2020 年改革前的 10% 无法从代码判断, 按所在板块的幅度
+pub fn price_limit(code: &str) -> Option<f64> {
+    if !is_a_share(code) {
+        None
+    } else if code.starts_with("bj") {
+        Some(0.3)
+    } else if ["sh688", "sh689", "sz300", "sz301"].iter().any(|prefix| code.starts_with(prefix)) {
+        Some(0.2)
+    } else {
+        Some(0.1)
+    }
+}
+
 pub trait WhereIsFrom {
     /// 判断股票属于哪个市场
     fn where_is_from(&self) -> Option<Market>;
 }
 impl WhereIsFrom for &str {
     fn where_is_from(&self) -> Option<Market> {
+        // 指数代码与股票代码会重复, 例如 000001 既是平安银行也是上证指数, 需要用前缀区分
+        if self.starts_with("sh") {
+            return Some(Market::SH)
+        }
+        if self.starts_with("sz") {
+            return Some(Market::SZ)
+        }
         if self.starts_with("600") 
             || self.starts_with("601") 
             || self.starts_with("603")
@@ -181,13 +397,15 @@ impl WhereIsFrom for &str {
             || self.starts_with("300")
             || self.starts_with("301")
             || self.starts_with("200")
-            || self.starts_with("201") {
+            || self.starts_with("201")
+            || self.starts_with("399") {
             return Some(Market::SZ)
         }
         None
     }
 }
 /// 市场
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Market {
     SZ,
     SH
@@ -197,24 +415,43 @@ pub enum Market {
  * 1 通过code找到文件路径
  * 2 通过code判断是属于哪个市场
  */
-fn get_day_path_by_code(code: StockCode) -> Result<PathBuf> {
+pub fn get_day_path_by_code(code: StockCode) -> Result<PathBuf> {
     let root = env::var("MILLIONS_TDX")?;
+    let data_path = get_day_path_in(Path::new(&root), code)?;
+    info!("day trade datafile path: {:?}", data_path);
+    Ok(data_path)
+}
+
+/// 指定通达信目录下的日线文件路径
+pub fn get_day_path_in(root: &Path, code: StockCode) -> Result<PathBuf> {
     let market = code.where_is_from().ok_or(DataSourceError::StockCodeNotExistInMarket)?;
+    let code = code.strip_prefix("sh").or_else(|| code.strip_prefix("sz")).unwrap_or(code);
     match market {
-        Market::SZ => {
-            let file_name = format!("sz{}.day", code);
-            let data_path = Path::new(&root).join("sz").join("lday").join(file_name);
-            info!("day trade datafile path: {:?}", data_path);
-            return Ok(data_path)
-        },
-        Market::SH =>  {
-            let file_name = format!("sh{}.day", code);
-            let data_path = Path::new(&root).join("sh").join("lday").join(file_name);
-            info!("day trade datafile path: {:?}", data_path);
-            return Ok(data_path)
-        },
+        Market::SZ => Ok(root.join("sz").join("lday").join(format!("sz{}.day", code))),
+        Market::SH => Ok(root.join("sh").join("lday").join(format!("sh{}.day", code))),
     }
 }
+/// 分钟线文件路径, `minutes` 为 1 时是 minline/*.lc1, 为 5 时是 fzline/*.lc5
+pub fn get_minute_path_by_code(code: StockCode, minutes: u8) -> Result<PathBuf> {
+    let root = env::var("MILLIONS_TDX")?;
+    get_minute_path_in(Path::new(&root), code, minutes)
+}
+
+/// 指定通达信目录下的分钟线文件路径
+pub fn get_minute_path_in(root: &Path, code: StockCode, minutes: u8) -> Result<PathBuf> {
+    let market = match code.where_is_from().ok_or(DataSourceError::StockCodeNotExistInMarket)? {
+        Market::SH => "sh",
+        Market::SZ => "sz",
+    };
+    let code = code.strip_prefix("sh").or_else(|| code.strip_prefix("sz")).unwrap_or(code);
+    let (dir, ext) = if minutes == 5 { ("fzline", "lc5") } else { ("minline", "lc1") };
+    Ok(root.join(market).join(dir).join(format!("{}{}.{}", market, code, ext)))
+}
+
+/// 指定通达信目录下的财务数据文件路径, 即 `cw/gpcw20220930.dat`
+pub fn get_finance_path_in(root: &Path, report_date: i32) -> PathBuf {
+    root.join("cw").join(format!("gpcw{}.dat", report_date))
+}
 /**
  * 股票价格
  */
@@ -228,6 +465,18 @@ pub type Volume = i32;
  */
 pub type TradeTime = DateTime<Utc>;
 
+/// 通达信日期(例如 20230103)转换为交易时间
+pub fn date_to_time(date: i32) -> Option<TradeTime> {
+    NaiveDate::from_ymd_opt(date / 10000, (date / 100 % 100) as u32, (date % 100) as u32)
+        .and_then(|day| day.and_hms_opt(0, 0, 0))
+        .map(|time| Utc.from_utc_datetime(&time))
+}
+
+/// 交易时间转换为通达信日期
+pub fn time_to_date(time: &TradeTime) -> i32 {
+    time.year() * 10000 + time.month() as i32 * 100 + time.day() as i32
+}
+
 
 
 pub trait TradeDataSource{
@@ -236,6 +485,46 @@ pub trait TradeDataSource{
     fn minue(&self, code: StockCode, day: TradeTime) -> Result<MinuteTradeUnit>;
     fn day_duration(&self, code: StockCode, from: Option<TradeTime>, to: Option<TradeTime>) -> Result<DayTradeUnitIter>;
     fn minue_duration(&self, code: StockCode, day: TradeTime, from: TradeTime, to: TradeTime) -> MinuteTradeUnitIter;
+    /// 全部股票代码, 带市场前缀
+    fn codes(&self) -> Result<Vec<StockCode>>;
+    /// 报告期的财务数据, 例如 20220930
+    fn finance(&self, report_date: i32) -> Result<FinanceData>;
+    /// 全部分钟线, `minutes` 为 1 或 5
+    fn minute_bars(&self, code: StockCode, minutes: u8) -> Result<MinuteTradeUnitIter>;
+}
+
+impl<T: TradeDataSource + ?Sized> TradeDataSource for &T {
+    fn prepare(&self) -> Result<()> {
+        (**self).prepare()
+    }
+
+    fn day(&self, code: StockCode, day: TradeTime) -> Result<DayTradeUnit> {
+        (**self).day(code, day)
+    }
+
+    fn minue(&self, code: StockCode, day: TradeTime) -> Result<MinuteTradeUnit> {
+        (**self).minue(code, day)
+    }
+
+    fn day_duration(&self, code: StockCode, from: Option<TradeTime>, to: Option<TradeTime>) -> Result<DayTradeUnitIter> {
+        (**self).day_duration(code, from, to)
+    }
+
+    fn minue_duration(&self, code: StockCode, day: TradeTime, from: TradeTime, to: TradeTime) -> MinuteTradeUnitIter {
+        (**self).minue_duration(code, day, from, to)
+    }
+
+    fn codes(&self) -> Result<Vec<StockCode>> {
+        (**self).codes()
+    }
+
+    fn finance(&self, report_date: i32) -> Result<FinanceData> {
+        (**self).finance(report_date)
+    }
+
+    fn minute_bars(&self, code: StockCode, minutes: u8) -> Result<MinuteTradeUnitIter> {
+        (**self).minute_bars(code, minutes)
+    }
 }
 
 pub struct StockTradeData {
@@ -247,13 +536,37 @@ pub struct DayTradeUnitIter {
 }
 
 impl DayTradeUnitIter {
-    fn new(path: &Path) -> Result<Self> {
+    pub fn new(path: &Path) -> Result<Self> {
         
         let mut file = File::open(path)?;
         Ok(DayTradeUnitIter { 
             file: file
         })
     }
+
+    /// 跳到第一根日期不早于 `date` 的K线, 只读取二分查找经过的记录
+    pub fn seek_to(&mut self, date: i32) -> Result<()> {
+        seek_sorted(&mut self.file, DayTradeUnitSize, date as i64, |record| LittleEndian::read_i32(&record[..4]) as i64)
+    }
+}
+
+/// 在按时间排列的定长记录文件中二分查找第一条不早于 `key` 的记录, 并把文件定位到这条记录
+fn seek_sorted(file: &mut File, size: usize, key: i64, key_of: impl Fn(&[u8]) -> i64) -> Result<()> {
+    let records = file.metadata()?.len() / size as u64;
+    let (mut low, mut high) = (0, records);
+    let mut record = vec![0u8; size];
+    while low < high {
+        let mid = (low + high) / 2;
+        file.seek(io::SeekFrom::Start(mid * size as u64))?;
+        file.read_exact(&mut record)?;
+        if key_of(&record) < key {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    file.seek(io::SeekFrom::Start(low * size as u64))?;
+    Ok(())
 }
 
 impl Iterator for DayTradeUnitIter {
@@ -272,13 +585,46 @@ impl Iterator for DayTradeUnitIter {
     }
 }
 
-pub struct MinuteTradeUnitIter(MinuteTradeUnit);
+pub struct MinuteTradeUnitIter {
+    file: Option<File>,
+    /** 只返回该日期 from 到 to 分钟之间的K线 */
+    range: Option<(i32, i16, i16)>,
+}
+
+impl MinuteTradeUnitIter {
+    pub fn new(path: &Path) -> Result<Self> {
+        Ok(MinuteTradeUnitIter { file: Some(File::open(path)?), range: None })
+    }
+
+    /// `date` 当日 0 点起 `from` 到 `to` 分钟(含)的K线, 文件不存在时为空
+    pub fn between(path: &Path, date: i32, from: i16, to: i16) -> Self {
+        MinuteTradeUnitIter { file: File::open(path).ok(), range: Some((date, from, to)) }
+    }
+
+    /// 跳到第一根时间不早于 `date` 日 `time` 时分(例如 935)的K线
+    pub fn seek_to(&mut self, date: i32, time: i32) -> Result<()> {
+        let Some(file) = self.file.as_mut() else { return Ok(()) };
+        let key = |bar: MinuteTradeUnit| bar.date as i64 * 10000 + bar.time() as i64;
+        let target = date as i64 * 10000 + time as i64;
+        seek_sorted(file, MinuteTradeUnitSize, target, |record| key(MinuteTradeUnit::deserializer(record)))
+    }
+}
 
 impl Iterator for MinuteTradeUnitIter {
     type Item = MinuteTradeUnit;
 
     fn next(&mut self) -> Option<Self::Item> {
-        todo!()
+        let file = self.file.as_mut()?;
+        let mut buff = [0u8; MinuteTradeUnitSize];
+        while file.read_exact(&mut buff[..]).is_ok() {
+            let bar = MinuteTradeUnit::deserializer(&buff);
+            match self.range {
+                Some((date, _, _)) if bar.date > date => return None,
+                Some((date, from, to)) if bar.date < date || bar.offset < from || bar.offset > to => continue,
+                _ => return Some(bar),
+            }
+        }
+        None
     }
 }
 
@@ -304,12 +650,48 @@ impl TradeDataSource for StockTradeData {
     fn minue_duration(&self, code: StockCode, day: TradeTime, from: TradeTime, to: TradeTime) -> MinuteTradeUnitIter {
         todo!()
     }
+
+    fn codes(&self) -> Result<Vec<StockCode>> {
+        codes_in(Path::new(&env::var("MILLIONS_TDX")?))
+    }
+
+    fn finance(&self, report_date: i32) -> Result<FinanceData> {
+        let root = env::var("MILLIONS_TDX")?;
+        FinanceData::open(get_finance_path_in(Path::new(&root), report_date))
+    }
+
+    fn minute_bars(&self, code: StockCode, minutes: u8) -> Result<MinuteTradeUnitIter> {
+        MinuteTradeUnitIter::new(&get_minute_path_by_code(code, minutes)?)
+    }
+}
+
+/// 扫描目录下 sh/lday sz/lday 的日线文件得到股票代码, 目录不存在时跳过
+pub fn codes_in(root: &Path) -> Result<Vec<StockCode>> {
+    let mut codes = vec![];
+    for market in ["sh", "sz"] {
+        let dir = root.join(market).join("lday");
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        for entry in entries {
+            let name = entry?.file_name();
+            if let Some(code) = name.to_str().and_then(|name| name.strip_suffix(".day")) {
+                codes.push(intern(code));
+            }
+        }
+    }
+    codes.sort_unstable();
+    Ok(codes)
 }
 
 
 #[cfg(test)]
 mod tests {
-    use super::{StockTradeData, TradeDataSource};
+    use std::{env, fs};
+
+    use super::{is_a_share, price_limit, FinanceData, MinuteTradeUnitIter, StockTradeData, TradeDataSource, HeaderSize, StockSize};
 
 
     #[test]
@@ -321,4 +703,64 @@ mod tests {
             println!("date: {}, close: {}, amount: {}", i.date, i.trade_data.close, i.trade_data.amount)
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn codes() {
+        let codes = StockTradeData {}.codes().unwrap();
+        assert_eq!(codes, vec!["sh603338", "sh603339"]);
+        // 重复扫描得到同一份代码
+        assert!(std::ptr::eq(codes[1], StockTradeData {}.codes().unwrap()[1]));
+        assert!(codes.iter().all(|code| is_a_share(code)));
+        for code in ["sh000001", "sh880001", "sh510300", "sz159915", "sh019547", "sz123001", "sz399001"] {
+            assert!(!is_a_share(code), "{}", code);
+        }
+        assert!(is_a_share("sz300750") && is_a_share("sh688981") && is_a_share("bj830799"));
+        let limits: Vec<_> = ["sh603339", "sz000001", "sz300750", "sh688981", "bj830799", "sh000001"].map(price_limit).into();
+        assert_eq!(limits, vec![Some(0.1), Some(0.1), Some(0.2), Some(0.2), Some(0.3), None]);
+    }
+
+    #[test]
+    fn finance() {
+        let mut buffer = vec![0u8; HeaderSize];
+        buffer[2..6].copy_from_slice(&20220930i32.to_le_bytes());
+        buffer[6..8].copy_from_slice(&2i16.to_le_bytes());
+        buffer[12..16].copy_from_slice(&8i32.to_le_bytes());
+        let data = HeaderSize + 2 * StockSize;
+        for (i, code) in ["603339", "000001"].iter().enumerate() {
+            buffer.extend_from_slice(code.as_bytes());
+            buffer.push(0);
+            buffer.extend_from_slice(&((data + i * 8) as u32).to_le_bytes());
+        }
+        for value in [0.5f32, 4.2, 1.0, 10.5] {
+            buffer.extend_from_slice(&value.to_le_bytes());
+        }
+        let finance = FinanceData::parse(&buffer).unwrap();
+        assert_eq!(finance.report_date(), 20220930);
+        assert_eq!(finance.len(), 2);
+        assert_eq!(finance.get("sh603339", 2), Some(4.2));
+        assert_eq!(finance.get("000001", 1), Some(1.0));
+        assert_eq!(finance.get("000001", 3), None);
+        assert_eq!(finance.get("600000", 1), None);
+        assert!(FinanceData::parse(&buffer[..40]).is_err());
+    }
+
+    #[test]
+    fn minute() {
+        let mut buffer = vec![];
+        // 2022-11-01 09:31
+        buffer.extend_from_slice(&(18u16 * 2048 + 1101).to_le_bytes());
+        buffer.extend_from_slice(&571i16.to_le_bytes());
+        for value in [13.5f32, 13.62, 13.41, 13.6, 136000.0] {
+            buffer.extend_from_slice(&value.to_le_bytes());
+        }
+        buffer.extend_from_slice(&10000i32.to_le_bytes());
+        buffer.extend_from_slice(&[0u8; 4]);
+        let path = env::temp_dir().join("millions_minute.lc1");
+        fs::write(&path, &buffer).unwrap();
+        let bars: Vec<_> = MinuteTradeUnitIter::new(&path).unwrap().collect();
+        assert_eq!(bars.len(), 1);
+        assert_eq!((bars[0].date, bars[0].time()), (20221101, 931));
+        assert_eq!((bars[0].trade_data.open, bars[0].trade_data.high, bars[0].trade_data.close), (1350, 1362, 1360));
+        assert_eq!(bars[0].trade_data.volume, 10000);
+    }
+}
@@ -0,0 +1,384 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+    ops::Deref,
+};
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{
+    data::{DayTradeUnit, StockCode},
+    serise::SeriseRegistry,
+    strategy::{Account, ExitRule, Trade, TradeError, Result},
+};
+
+/// 订单方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+/// 委托价格类型
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum OrderKind {
+    /// 市价
+    Market,
+    /// 限价
+    Limit(Decimal),
+}
+
+/// 委托, 由策略提交, 由执行环节撮合
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderRequest {
+    pub id: u64,
+    pub code: StockCode,
+    pub side: Side,
+    /// 委托数量(股)
+    pub vol: Decimal,
+    pub kind: OrderKind,
+    /// 下单日期
+    pub date: i32,
+    /// 买入成交后附加到持仓的止损止盈
+    pub exits: Vec<ExitRule>,
+}
+
+/// 成交回报
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fill {
+    pub order: OrderRequest,
+    /// 成交日期
+    pub date: i32,
+    /// 成交价
+    pub price: Decimal,
+}
+
+/// 公司行为: 派息, 送转与配股
+///
+/// 配股需要持有人缴款认购, 回测账户不会自动参与, 配股字段只用于计算复权因子
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorporateAction {
+    pub code: StockCode,
+    /// 除权除息日
+    pub date: i32,
+    /// 每股派息
+    pub cash_per_share: Decimal,
+    /// 每股送转股数
+    pub shares_per_share: Decimal,
+    /// 配股价
+    pub rights_price: Decimal,
+    /// 每股配股数
+    pub rights_per_share: Decimal,
+}
+
+/// 回测事件
+#[derive(Debug)]
+pub enum Event {
+    /// 交易日开始
+    DayStart(i32),
+    /// 公司行为
+    CorporateAction(CorporateAction),
+    /// 行情, 同一交易日所有股票的K线
+    MarketData(i32, Vec<(StockCode, DayTradeUnit)>),
+    /// 委托
+    Order(OrderRequest),
+    /// 成交
+    Fill(Fill),
+    /// 委托被拒绝
+    Rejected(OrderRequest, TradeError),
+    /// 给已有持仓附加止损止盈
+    AttachExit(i32, StockCode, ExitRule),
+    /// 定时器, 日期和定时器编号
+    Timer(i32, u64),
+    /// 交易日结束
+    DayEnd(i32),
+}
+
+impl Event {
+    pub fn date(&self) -> i32 {
+        match self {
+            Event::DayStart(date)
+            | Event::MarketData(date, ..)
+            | Event::AttachExit(date, ..)
+            | Event::Timer(date, ..)
+            | Event::DayEnd(date) => *date,
+            Event::CorporateAction(action) => action.date,
+            Event::Order(order) | Event::Rejected(order, ..) => order.date,
+            Event::Fill(fill) => fill.date,
+        }
+    }
+
+    /// 同一日期内事件的处理顺序
+    fn priority(&self) -> u8 {
+        match self {
+            Event::DayStart(..) => 0,
+            Event::CorporateAction(..) => 1,
+            Event::MarketData(..) => 2,
+            Event::Order(..) | Event::AttachExit(..) => 3,
+            Event::Fill(..) | Event::Rejected(..) => 4,
+            Event::Timer(..) => 5,
+            Event::DayEnd(..) => 6,
+        }
+    }
+}
+
+struct Queued {
+    date: i32,
+    priority: u8,
+    seq: u64,
+    event: Event,
+}
+
+impl Queued {
+    fn key(&self) -> (i32, u8, u64) {
+        (self.date, self.priority, self.seq)
+    }
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Queued {}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // BinaryHeap 是大顶堆, 反转后最早的事件先出队
+        other.key().cmp(&self.key())
+    }
+}
+
+/// 按时间排序的事件队列, 同一时间按事件类型排序, 再按入队顺序
+#[derive(Default)]
+pub struct EventQueue {
+    heap: BinaryHeap<Queued>,
+    seq: u64,
+}
+
+impl EventQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, event: Event) {
+        self.seq += 1;
+        self.heap.push(Queued {
+            date: event.date(),
+            priority: event.priority(),
+            seq: self.seq,
+            event,
+        });
+    }
+
+    pub fn pop(&mut self) -> Option<Event> {
+        self.heap.pop().map(|queued| queued.event)
+    }
+
+    /// 最早事件的日期
+    pub fn peek_date(&self) -> Option<i32> {
+        self.heap.peek().map(|queued| queued.date)
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+}
+
+/// 策略回调的上下文: 只读的账户, 以及提交委托, 止损止盈和定时器的出口
+///
+/// 委托不会立即成交, 回调返回后由回测引擎放入事件队列撮合
+pub struct Context<'a> {
+    account: &'a Account,
+    date: i32,
+    next_id: &'a mut u64,
+    orders: Vec<OrderRequest>,
+    exits: Vec<(StockCode, ExitRule)>,
+    timers: Vec<(i32, u64)>,
+    serises: Option<&'a HashMap<StockCode, SeriseRegistry>>,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(account: &'a Account, date: i32, next_id: &'a mut u64) -> Self {
+        Context { account, date, next_id, orders: vec![], exits: vec![], timers: vec![], serises: None }
+    }
+
+    /// 附带各股票的指标
+    pub fn with_serises(mut self, serises: &'a HashMap<StockCode, SeriseRegistry>) -> Self {
+        self.serises = Some(serises);
+        self
+    }
+
+    /// 股票的全部指标
+    pub fn serises(&self, code: StockCode) -> Option<&SeriseRegistry> {
+        self.serises?.get(code)
+    }
+
+    /// 截止到当前交易日的指标历史, 例如 `ctx.serise::<f64>("603339", "sma5")`
+    pub fn serise<T: 'static>(&self, code: StockCode, name: &str) -> Option<&Vec<T>> {
+        self.serises(code)?.get(name)
+    }
+
+    pub fn account(&self) -> &Account {
+        self.account
+    }
+
+    /// 当前日期
+    pub fn date(&self) -> i32 {
+        self.date
+    }
+
+    /// 提交委托, 返回委托编号
+    pub fn submit(&mut self, code: StockCode, side: Side, vol: Decimal, kind: OrderKind) -> u64 {
+        *self.next_id += 1;
+        let id = *self.next_id;
+        self.orders.push(OrderRequest { id, code, side, vol, kind, date: self.date, exits: vec![] });
+        id
+    }
+
+    /// 给持仓附加止损止盈规则, 由回测引擎在之后的K线上检查, 触发时全部卖出
+    ///
+    /// 本次回调已提交该股票的买入委托时, 规则随最后一笔买入委托在成交后附加;
+    /// 否则附加到已有持仓, 没有持仓时返回 `OutOfPosition`
+    pub fn attach_exit(&mut self, code: StockCode, rule: ExitRule) -> Result<()> {
+        if let Some(order) = self.orders.iter_mut().rev().find(|order| order.code == code && order.side == Side::Buy) {
+            order.exits.push(rule);
+            return Ok(());
+        }
+        match self.account.get_position(code)? {
+            Some(position) if position.volume() > Decimal::ZERO => {}
+            _ => return Err(TradeError::OutOfPosition),
+        }
+        self.exits.push((code, rule));
+        Ok(())
+    }
+
+    /// 在指定日期触发定时器, 返回定时器编号
+    pub fn schedule(&mut self, date: i32) -> u64 {
+        *self.next_id += 1;
+        let id = *self.next_id;
+        self.timers.push((date, id));
+        id
+    }
+
+    /// 取出回调期间提交的委托, 止损止盈和定时器
+    pub fn into_events(self) -> Vec<Event> {
+        let date = self.date;
+        self.orders
+            .into_iter()
+            .map(Event::Order)
+            .chain(self.exits.into_iter().map(|(code, rule)| Event::AttachExit(date, code, rule)))
+            .chain(self.timers.into_iter().map(|(date, id)| Event::Timer(date, id)))
+            .collect()
+    }
+}
+
+impl<'a> Deref for Context<'a> {
+    type Target = Account;
+
+    fn deref(&self) -> &Self::Target {
+        self.account
+    }
+}
+
+/// 按百分比换算成整手(100股)数量
+pub(crate) fn lots_of(amount: Decimal, price: Decimal) -> Decimal {
+    if price.is_zero() {
+        return Decimal::ZERO;
+    }
+    (amount / price / Decimal::from(100)).floor() * Decimal::from(100)
+}
+
+/// 以限价委托的方式下单, 与直接操作账户的接口保持一致
+impl<'a> Trade for Context<'a> {
+    fn buy(&mut self, code: StockCode, price: &str, vol: &str) -> Result<()> {
+        let price = Decimal::from_str_exact(price)?;
+        let vol = Decimal::from_str_exact(vol)?;
+        self.submit(code, Side::Buy, vol, OrderKind::Limit(price));
+        Ok(())
+    }
+
+    fn sell(&mut self, code: StockCode, price: &str, vol: &str) -> Result<()> {
+        let price = Decimal::from_str_exact(price)?;
+        let vol = Decimal::from_str_exact(vol)?;
+        match self.account.get_position(code)? {
+            Some(position) if position.volume() >= vol => {}
+            _ => return Err(TradeError::OutOfPosition),
+        }
+        self.submit(code, Side::Sell, vol, OrderKind::Limit(price));
+        Ok(())
+    }
+
+    fn buy_part(&mut self, code: StockCode, price: &str, percent: u8) {
+        if let Ok(price) = Decimal::from_str_exact(price) {
+            let amount = self.account.get_balance() * Decimal::from(percent) / Decimal::from(100);
+            let vol = lots_of(amount, price);
+            if !vol.is_zero() {
+                self.submit(code, Side::Buy, vol, OrderKind::Limit(price));
+            }
+        }
+    }
+
+    fn sell_part(&mut self, code: StockCode, price: &str, percent: u8) {
+        if let (Ok(price), Ok(Some(position))) = (Decimal::from_str_exact(price), self.account.get_position(code)) {
+            let vol = (position.volume() * Decimal::from(percent) / Decimal::from(100)).floor();
+            if !vol.is_zero() {
+                self.submit(code, Side::Sell, vol, OrderKind::Limit(price));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::{Event, EventQueue, OrderKind, OrderRequest, Side};
+
+    #[test]
+    fn queue_order() {
+        let mut queue = EventQueue::new();
+        queue.push(Event::DayEnd(20230104));
+        queue.push(Event::Timer(20230103, 1));
+        queue.push(Event::Order(OrderRequest {
+            id: 2,
+            code: "603339",
+            side: Side::Buy,
+            vol: Decimal::from(100),
+            kind: OrderKind::Market,
+            date: 20230103,
+            exits: vec![],
+        }));
+        queue.push(Event::DayEnd(20230103));
+        queue.push(Event::DayStart(20230103));
+        queue.push(Event::Timer(20230103, 3));
+        assert_eq!(queue.len(), 6);
+
+        let order: Vec<String> = std::iter::from_fn(|| queue.pop())
+            .map(|event| match event {
+                Event::DayStart(date) => format!("start {}", date),
+                Event::Order(order) => format!("order {}", order.id),
+                Event::Timer(_, id) => format!("timer {}", id),
+                Event::DayEnd(date) => format!("end {}", date),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(
+            order,
+            vec!["start 20230103", "order 2", "timer 1", "timer 3", "end 20230103", "end 20230104"]
+        );
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,712 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::Path,
+};
+
+use rust_decimal::{prelude::FromPrimitive, Decimal};
+use ta::{Close, Volume};
+
+use crate::{
+    adjust::{self, Adjust},
+    backtest::{MarketSnapshot, PortfolioStrategy},
+    data::{DayTradeUnit, FinanceData, Result, StockCode, TradeDataSource},
+    event::{lots_of, Context, CorporateAction, Fill, OrderKind, OrderRequest, Side},
+    screener::ScreenContext,
+    statistics::{covariance, daily_returns, mean, std_dev},
+    strategy::TradeError,
+};
+
+/// 某个交易日各股票的因子值
+pub type CrossSection = Vec<(StockCode, f64)>;
+
+/// 截面因子, 由截止到计算日的K线和已披露的财务数据计算
+pub trait Factor {
+    fn name(&self) -> String;
+
+    /// 数据不足时为 None
+    fn value(&self, ctx: &ScreenContext) -> Option<f64>;
+}
+
+/// 动量, `days` 日收益率
+pub struct Momentum(pub usize);
+
+impl Factor for Momentum {
+    fn name(&self) -> String {
+        format!("momentum{}", self.0)
+    }
+
+    fn value(&self, ctx: &ScreenContext) -> Option<f64> {
+        let base = ctx.bars.len().checked_sub(self.0 + 1).map(|i| ctx.bars[i].close())?;
+        Some(ctx.bar().close() / base - 1.0)
+    }
+}
+
+/// 波动率, `days` 日收益率的样本标准差
+pub struct Volatility(pub usize);
+
+impl Factor for Volatility {
+    fn name(&self) -> String {
+        format!("volatility{}", self.0)
+    }
+
+    fn value(&self, ctx: &ScreenContext) -> Option<f64> {
+        let start = ctx.bars.len().checked_sub(self.0 + 1)?;
+        let closes: Vec<f64> = ctx.bars[start..].iter().map(|bar| bar.close()).collect();
+        Some(std_dev(&daily_returns(&closes)))
+    }
+}
+
+/// 换手率, `days` 日平均成交量 / 已上市流通A股(财务数据第 239 列)
+pub struct Turnover(pub usize);
+
+impl Factor for Turnover {
+    fn name(&self) -> String {
+        format!("turnover{}", self.0)
+    }
+
+    fn value(&self, ctx: &ScreenContext) -> Option<f64> {
+        let start = ctx.bars.len().checked_sub(self.0)?;
+        let shares = ctx.finance(239).filter(|shares| *shares > 0.0)?;
+        let volume = mean(&ctx.bars[start..].iter().map(|bar| bar.volume()).collect::<Vec<f64>>());
+        Some(volume / shares)
+    }
+}
+
+/// 估值因子, 取市盈率 市净率的倒数, 亏损和净资产为负的股票也能排序
+///
+/// 价格取当日实际收盘价, 不受复权影响
+pub enum Valuation {
+    /// 基本每股收益(第 1 列) / 收盘价
+    EarningsYield,
+    /// 每股净资产(第 4 列) / 收盘价
+    BookToPrice,
+}
+
+impl Factor for Valuation {
+    fn name(&self) -> String {
+        match self {
+            Valuation::EarningsYield => "ep".to_string(),
+            Valuation::BookToPrice => "bp".to_string(),
+        }
+    }
+
+    fn value(&self, ctx: &ScreenContext) -> Option<f64> {
+        let column = match self {
+            Valuation::EarningsYield => 1,
+            Valuation::BookToPrice => 4,
+        };
+        Some(ctx.finance(column)? * ctx.adjust_factor / ctx.bar().close())
+    }
+}
+
+/// 财务报告的法定披露截止日, 截止日之后才能使用, 避免未来函数
+///
+/// 一季报 4月30日, 半年报 8月31日, 三季报 10月31日, 年报次年 4月30日
+pub fn disclosure_date(report_date: i32) -> i32 {
+    let year = report_date / 10000;
+    match report_date % 10000 {
+        331 => year * 10000 + 430,
+        630 => year * 10000 + 831,
+        930 => year * 10000 + 1031,
+        _ => (year + 1) * 10000 + 430,
+    }
+}
+
+/// 通达信行业分类, 即 `T0002/hq_cache/tdxhy.cfg`
+///
+/// 每行 `市场|代码|通达信行业|...`, 行业代码按级别逐级加长, 例如 T01 T0101 T010101
+#[derive(Debug, Clone, Default)]
+pub struct Industries {
+    industries: HashMap<String, String>,
+}
+
+impl Industries {
+    /// # Parameter
+    ///
+    /// * `level` - 行业级别 1 2 3, 级别越高分类越细
+    pub fn open<P: AsRef<Path>>(path: P, level: usize) -> Result<Self> {
+        Ok(Self::parse(&String::from_utf8_lossy(&fs::read(path)?), level))
+    }
+
+    pub fn parse(text: &str, level: usize) -> Self {
+        let industries = text
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split('|').skip(1);
+                let code = fields.next()?.trim();
+                let industry = fields.next()?.trim();
+                let len = (1 + 2 * level).min(industry.len());
+                (!code.is_empty() && !industry.is_empty()).then(|| (code.to_string(), industry[..len].to_string()))
+            })
+            .collect();
+        Industries { industries }
+    }
+
+    /// 股票代码可以带市场前缀
+    pub fn get(&self, code: &str) -> Option<&str> {
+        let code = code.strip_prefix("sh").or_else(|| code.strip_prefix("sz")).unwrap_or(code);
+        self.industries.get(code).map(|industry| industry.as_str())
+    }
+}
+
+/// 去极值, 超出 中位数 ± n * 1.4826 * MAD 的值压缩到边界
+pub fn winsorize(section: &mut CrossSection, n: f64) {
+    let values: Vec<f64> = section.iter().map(|(_, v)| *v).collect();
+    let Some(median) = median(&values) else { return };
+    let mad = median_of(values.iter().map(|v| (v - median).abs())).unwrap_or(0.0) * 1.4826;
+    let (lower, upper) = (median - n * mad, median + n * mad);
+    for (_, value) in section.iter_mut() {
+        *value = value.clamp(lower, upper);
+    }
+}
+
+/// 标准化为均值 0 标准差 1, 标准差为 0 时全部为 0
+pub fn standardize(section: &mut CrossSection) {
+    let values: Vec<f64> = section.iter().map(|(_, v)| *v).collect();
+    let (avg, std) = (mean(&values), std_dev(&values));
+    for (_, value) in section.iter_mut() {
+        *value = if std > 0.0 { (*value - avg) / std } else { 0.0 };
+    }
+}
+
+/// 行业中性化, 减去所属行业的均值, 等价于对行业哑变量回归取残差
+///
+/// 没有行业分类的股票归为同一组
+pub fn neutralize(section: &mut CrossSection, industries: &Industries) {
+    let mut groups: HashMap<&str, (f64, usize)> = HashMap::new();
+    for (code, value) in section.iter() {
+        let group = groups.entry(industries.get(code).unwrap_or("")).or_default();
+        group.0 += value;
+        group.1 += 1;
+    }
+    for (code, value) in section.iter_mut() {
+        let (sum, count) = groups[industries.get(code).unwrap_or("")];
+        *value -= sum / count as f64;
+    }
+}
+
+/// Rank IC, 两个截面共有股票的 Spearman 相关系数, 少于 3 只股票或没有差异时为 None
+pub fn rank_ic(factor: &CrossSection, returns: &CrossSection) -> Option<f64> {
+    let returns: HashMap<StockCode, f64> = returns.iter().copied().collect();
+    let (x, y): (Vec<f64>, Vec<f64>) =
+        factor.iter().filter_map(|(code, value)| returns.get(code).map(|ret| (*value, *ret))).unzip();
+    if x.len() < 3 {
+        return None;
+    }
+    let (rx, ry) = (ranks(&x), ranks(&y));
+    let std = std_dev(&rx) * std_dev(&ry);
+    (std > 0.0).then(|| covariance(&rx, &ry) / std)
+}
+
+/// 从 1 开始的排名, 相同的值取平均排名
+fn ranks(values: &[f64]) -> Vec<f64> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|a, b| values[*a].partial_cmp(&values[*b]).unwrap_or(Ordering::Equal));
+    let mut ranks = vec![0.0; values.len()];
+    let mut i = 0;
+    while i < order.len() {
+        let mut j = i;
+        while j + 1 < order.len() && values[order[j + 1]] == values[order[i]] {
+            j += 1;
+        }
+        for k in i..=j {
+            ranks[order[k]] = (i + j) as f64 / 2.0 + 1.0;
+        }
+        i = j + 1;
+    }
+    ranks
+}
+
+fn median(values: &[f64]) -> Option<f64> {
+    median_of(values.iter().copied())
+}
+
+fn median_of(values: impl Iterator<Item = f64>) -> Option<f64> {
+    let mut values: Vec<f64> = values.collect();
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    let mid = values.len() / 2;
+    Some(if mid * 2 == values.len() { (values[mid - 1] + values[mid]) / 2.0 } else { values[mid] })
+}
+
+/// 因子在各交易日的截面, 按日期排列
+#[derive(Debug, Clone, PartialEq)]
+pub struct FactorValues {
+    pub name: String,
+    pub values: Vec<(i32, CrossSection)>,
+}
+
+impl FactorValues {
+    pub fn get(&self, date: i32) -> Option<&CrossSection> {
+        self.values.binary_search_by_key(&date, |(d, _)| *d).ok().map(|i| &self.values[i].1)
+    }
+
+    fn map(mut self, f: impl Fn(&mut CrossSection)) -> Self {
+        for (_, section) in self.values.iter_mut() {
+            f(section);
+        }
+        self
+    }
+
+    /// 逐日去极值, 见 [`winsorize`]
+    pub fn winsorize(self, n: f64) -> Self {
+        self.map(|section| winsorize(section, n))
+    }
+
+    pub fn standardize(self) -> Self {
+        self.map(standardize)
+    }
+
+    pub fn neutralize(self, industries: &Industries) -> Self {
+        self.map(|section| neutralize(section, industries))
+    }
+
+    /// 每日的 Rank IC, `returns` 一般为 [`Panel::forward_returns`]
+    pub fn rank_ic(&self, returns: &FactorValues) -> Vec<(i32, f64)> {
+        self.values
+            .iter()
+            .filter_map(|(date, section)| Some((*date, rank_ic(section, returns.get(*date)?)?)))
+            .collect()
+    }
+
+    /// IC 衰减, 持有 1 到 `periods` 日的平均 Rank IC
+    pub fn ic_decay(&self, panel: &Panel, periods: usize) -> Vec<(usize, f64)> {
+        (1..=periods)
+            .map(|period| {
+                let ics: Vec<f64> = self.rank_ic(&panel.forward_returns(period)).into_iter().map(|(_, ic)| ic).collect();
+                (period, mean(&ics))
+            })
+            .collect()
+    }
+
+    /// 分组收益, 每日按因子值从小到大等分为 `groups` 组, 计算各组的平均未来收益
+    pub fn quantile_returns(&self, returns: &FactorValues, groups: usize) -> QuantileReturns {
+        let mut result = QuantileReturns { groups, dates: vec![], returns: vec![] };
+        for (date, section) in self.values.iter() {
+            let Some(forward) = returns.get(*date) else { continue };
+            let forward: HashMap<StockCode, f64> = forward.iter().copied().collect();
+            let mut pairs: Vec<(f64, f64)> =
+                section.iter().filter_map(|(code, value)| forward.get(code).map(|ret| (*value, *ret))).collect();
+            if pairs.len() < groups || groups == 0 {
+                continue;
+            }
+            pairs.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+            let mut buckets = vec![vec![]; groups];
+            let n = pairs.len();
+            for (i, (_, ret)) in pairs.into_iter().enumerate() {
+                buckets[i * groups / n].push(ret);
+            }
+            result.dates.push(*date);
+            result.returns.push(buckets.iter().map(|bucket| mean(bucket)).collect());
+        }
+        result
+    }
+}
+
+/// Rank IC 的统计
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IcSummary {
+    pub mean: f64,
+    pub std: f64,
+    /// IC 均值 / IC 标准差
+    pub ir: f64,
+    /// IC 为正的比例
+    pub positive: f64,
+}
+
+impl IcSummary {
+    pub fn new(ics: &[(i32, f64)]) -> Self {
+        let values: Vec<f64> = ics.iter().map(|(_, ic)| *ic).collect();
+        let (avg, std) = (mean(&values), std_dev(&values));
+        IcSummary {
+            mean: avg,
+            std,
+            ir: if std > 0.0 { avg / std } else { 0.0 },
+            positive: mean(&values.iter().map(|ic| if *ic > 0.0 { 1.0 } else { 0.0 }).collect::<Vec<f64>>()),
+        }
+    }
+}
+
+/// 分组收益, 第 0 组因子值最小
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuantileReturns {
+    pub groups: usize,
+    pub dates: Vec<i32>,
+    /// 每日各组的平均收益
+    pub returns: Vec<Vec<f64>>,
+}
+
+impl QuantileReturns {
+    /// 各组收益的均值
+    pub fn mean(&self) -> Vec<f64> {
+        (0..self.groups)
+            .map(|group| mean(&self.returns.iter().map(|returns| returns[group]).collect::<Vec<f64>>()))
+            .collect()
+    }
+
+    /// 多空收益, 最高组减最低组的均值
+    pub fn long_short(&self) -> f64 {
+        let means = self.mean();
+        match (means.first(), means.last()) {
+            (Some(low), Some(high)) => high - low,
+            _ => 0.0,
+        }
+    }
+}
+
+/// 因子研究用的行情面板, 股票池内各股票的后复权K线和财务数据
+pub struct Panel {
+    codes: Vec<StockCode>,
+    /** 区间内至少一只股票有交易的日期 */
+    dates: Vec<i32>,
+    /** 截止到结束日期的全部后复权K线, 开始日期之前的K线用于计算回看窗口 */
+    bars: HashMap<StockCode, Vec<DayTradeUnit>>,
+    /** 每根K线的后复权因子 */
+    factors: HashMap<StockCode, Vec<f64>>,
+    /** 按报告期排列 */
+    finance: Vec<FinanceData>,
+}
+
+impl Panel {
+    /// 加载股票池在 [from, to] 区间的行情, 日期格式 20230103
+    ///
+    /// K线按 `actions` 后复权, 动量 波动率和未来收益不受除权除息影响; `actions` 中的代码写法与 `codes` 一致
+    pub fn load<S: TradeDataSource>(
+        source: &S,
+        codes: &[StockCode],
+        actions: &[CorporateAction],
+        from: i32,
+        to: i32,
+    ) -> Result<Self> {
+        let mut histories = HashMap::new();
+        for &code in codes {
+            let history: Vec<DayTradeUnit> = source.day_duration(code, None, None)?.take_while(|bar| bar.date <= to).collect();
+            histories.insert(code, history);
+        }
+        Ok(Self::with_bars(codes, histories, actions, from))
+    }
+
+    fn with_bars(
+        codes: &[StockCode],
+        histories: HashMap<StockCode, Vec<DayTradeUnit>>,
+        actions: &[CorporateAction],
+        from: i32,
+    ) -> Self {
+        let (mut bars, mut factors) = (HashMap::new(), HashMap::new());
+        let mut dates = vec![];
+        for (code, history) in histories {
+            let actions: Vec<CorporateAction> = actions.iter().filter(|action| action.code == code).cloned().collect();
+            dates.extend(history.iter().map(|bar| bar.date).filter(|date| *date >= from));
+            bars.insert(code, adjust::adjust(&history, &actions, Adjust::Backward));
+            factors.insert(code, adjust::factors(&history, &actions));
+        }
+        dates.sort_unstable();
+        dates.dedup();
+        Panel { codes: codes.to_vec(), dates, bars, factors, finance: vec![] }
+    }
+
+    /// 加入一期财务数据, 披露截止日之后的交易日才会使用
+    pub fn with_finance(mut self, finance: FinanceData) -> Self {
+        self.finance.push(finance);
+        self.finance.sort_by_key(|finance| finance.report_date());
+        self
+    }
+
+    pub fn codes(&self) -> &[StockCode] {
+        &self.codes
+    }
+
+    pub fn dates(&self) -> &[i32] {
+        &self.dates
+    }
+
+    /// 截止到 `date`(含) 的后复权K线
+    pub fn history(&self, code: StockCode, date: i32) -> &[DayTradeUnit] {
+        let bars = self.bars.get(code).map(|bars| &bars[..]).unwrap_or(&[]);
+        &bars[..bars.partition_point(|bar| bar.date <= date)]
+    }
+
+    /// `date` 已经披露的最新一期财务数据
+    pub fn finance(&self, date: i32) -> Option<&FinanceData> {
+        self.finance.iter().rev().find(|finance| disclosure_date(finance.report_date()) < date)
+    }
+
+    /// 逐日计算截面因子, 当日停牌或数据不足的股票不在截面中
+    pub fn factor(&self, factor: &dyn Factor) -> FactorValues {
+        self.sections(factor.name(), |ctx| factor.value(ctx))
+    }
+
+    /// 未来 `period` 日收益率, 即第 t + period 根K线收盘价 / 第 t 根K线收盘价 - 1
+    pub fn forward_returns(&self, period: usize) -> FactorValues {
+        let values = self
+            .dates
+            .iter()
+            .map(|&date| {
+                let section = self
+                    .codes
+                    .iter()
+                    .filter_map(|&code| {
+                        let bars = &self.bars[code];
+                        let i = bars.binary_search_by_key(&date, |bar| bar.date).ok()?;
+                        Some((code, bars.get(i + period)?.close() / bars[i].close() - 1.0))
+                    })
+                    .collect();
+                (date, section)
+            })
+            .collect();
+        FactorValues { name: format!("return{}", period), values }
+    }
+
+    fn sections(&self, name: String, value: impl Fn(&ScreenContext) -> Option<f64>) -> FactorValues {
+        let values = self
+            .dates
+            .iter()
+            .map(|&date| {
+                let finance = self.finance(date);
+                let section = self
+                    .codes
+                    .iter()
+                    .filter_map(|&code| {
+                        let bars = self.history(code, date);
+                        if bars.last()?.date != date {
+                            return None;
+                        }
+                        let adjust_factor = self.factors[code][bars.len() - 1];
+                        let value = value(&ScreenContext { code, bars, finance, adjust_factor })?;
+                        value.is_finite().then_some((code, value))
+                    })
+                    .collect();
+                (date, section)
+            })
+            .collect();
+        FactorValues { name, values }
+    }
+}
+
+/// 因子组合的权重
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Weighting {
+    /// 等权
+    Equal,
+    /// 按因子值加权, 入选股票中因子值不大于 0 的不配置
+    Score,
+}
+
+/// 按因子值调仓的组合策略
+///
+/// 每 `every` 个交易日选出因子值最大的 `top` 只股票, 按权重调整到目标仓位.
+/// 目标市值按总权益的 95% 计算, 留出次日开盘价变动和交易费用的余量
+pub struct FactorStrategy {
+    targets: BTreeMap<i32, CrossSection>,
+    /** 已提交但还没有回报的委托 */
+    pending: HashSet<StockCode>,
+}
+
+impl FactorStrategy {
+    pub fn new(values: &FactorValues, top: usize, every: usize, weighting: Weighting) -> Self {
+        let targets = values
+            .values
+            .iter()
+            .step_by(every.max(1))
+            .map(|(date, section)| {
+                let mut selected = section.clone();
+                selected.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+                selected.truncate(top);
+                let weights: Vec<f64> = match weighting {
+                    Weighting::Equal => vec![1.0; selected.len()],
+                    Weighting::Score => selected.iter().map(|(_, value)| value.max(0.0)).collect(),
+                };
+                let total: f64 = weights.iter().sum();
+                let target = selected
+                    .iter()
+                    .zip(weights)
+                    .filter(|(_, weight)| *weight > 0.0)
+                    .map(|((code, _), weight)| (*code, weight / total))
+                    .collect();
+                (*date, target)
+            })
+            .collect();
+        FactorStrategy { targets, pending: HashSet::new() }
+    }
+
+    /// 各调仓日的目标权重
+    pub fn targets(&self) -> &BTreeMap<i32, CrossSection> {
+        &self.targets
+    }
+}
+
+impl PortfolioStrategy for FactorStrategy {
+    fn on_bar(&mut self, ctx: &mut Context, market: &MarketSnapshot) {
+        let Some(target) = self.targets.get(&market.date()) else { return };
+        let positions = ctx.get_positions();
+        let market_value: Decimal = positions
+            .iter()
+            .filter_map(|position| market.close(position.code()).map(|close| close * position.volume()))
+            .sum();
+        let equity = (ctx.get_balance() + market_value) * Decimal::new(95, 2);
+        let mut orders = vec![];
+        for position in positions.iter() {
+            if !target.iter().any(|(code, _)| *code == position.code()) {
+                orders.push((position.code(), -position.volume()));
+            }
+        }
+        for (code, weight) in target.iter() {
+            let Some(close) = market.bar(code).and(market.close(code)) else { continue };
+            let amount = equity * Decimal::from_f64(*weight).unwrap_or_default();
+            let held = positions.iter().find(|p| p.code() == *code).map(|p| p.volume()).unwrap_or_default();
+            orders.push((*code, lots_of(amount, close) - held));
+        }
+        // 先卖后买, 卖出的资金用于买入
+        orders.sort_by_key(|(_, vol)| *vol);
+        for (code, vol) in orders {
+            if vol.is_zero() || self.pending.contains(code) {
+                continue;
+            }
+            let side = if vol.is_sign_negative() { Side::Sell } else { Side::Buy };
+            ctx.submit(code, side, vol.abs(), OrderKind::Market);
+            self.pending.insert(code);
+        }
+    }
+
+    fn on_fill(&mut self, _ctx: &mut Context, fill: &Fill) {
+        self.pending.remove(fill.order.code);
+    }
+
+    fn on_order_rejected(&mut self, _ctx: &mut Context, order: &OrderRequest, _reason: &TradeError) {
+        self.pending.remove(order.code);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rust_decimal::Decimal;
+
+    use crate::{
+        backtest::BackTest,
+        data::{DayTradeUnit, StockTradeData, TradeUnit},
+        event::CorporateAction,
+    };
+
+    use super::{
+        neutralize, rank_ic, standardize, winsorize, FactorStrategy, IcSummary, Industries, Momentum, Panel,
+        Volatility, Weighting,
+    };
+
+    #[test]
+    fn cross_section() {
+        let mut section = vec![("600000", 1.0), ("600001", 2.0), ("600002", 3.0), ("000001", 4.0), ("000002", 100.0)];
+        winsorize(&mut section, 3.0);
+        // 中位数 3, MAD 1, 上界 3 + 3 * 1.4826
+        assert!((section[4].1 - (3.0 + 3.0 * 1.4826)).abs() < 1e-9);
+        assert_eq!(section[0].1, 1.0);
+
+        standardize(&mut section);
+        let sum: f64 = section.iter().map(|(_, v)| v).sum();
+        assert!(sum.abs() < 1e-9);
+
+        let industries = Industries::parse("1|600000|T1001|||X1\n1|600001|T1002|||X1\n0|000001|T0501|||X2\n", 1);
+        assert_eq!(industries.get("sh600000"), Some("T10"));
+        let mut section = vec![("600000", 1.0), ("600001", 3.0), ("000001", 5.0), ("000002", 7.0)];
+        neutralize(&mut section, &industries);
+        assert_eq!(section, vec![("600000", -1.0), ("600001", 1.0), ("000001", 0.0), ("000002", 0.0)]);
+
+        let factor = vec![("a", 1.0), ("b", 2.0), ("c", 3.0), ("d", 3.0)];
+        assert!((rank_ic(&factor, &vec![("a", 0.1), ("b", 0.2), ("c", 0.3), ("d", 0.3)]).unwrap() - 1.0).abs() < 1e-9);
+        assert!((rank_ic(&factor, &vec![("a", 0.3), ("b", 0.2), ("c", 0.1), ("d", 0.1)]).unwrap() + 1.0).abs() < 1e-9);
+        assert_eq!(rank_ic(&factor, &vec![("a", 0.1), ("b", 0.2)]), None);
+    }
+
+    #[test]
+    fn research() {
+        let panel = Panel::load(&StockTradeData {}, &["603339", "603338"], &[], 20220601, 20221101).unwrap();
+        let momentum = panel.factor(&Momentum(20)).winsorize(3.0).standardize();
+        let section = momentum.get(20221101).unwrap();
+        assert_eq!(section.len(), 2);
+        assert!((section[0].1 + section[1].1).abs() < 1e-9);
+
+        // 两只股票时分组收益就是各自的收益
+        let returns = panel.forward_returns(5);
+        assert!(returns.get(20221101).unwrap().is_empty());
+        let quantile = momentum.quantile_returns(&returns, 2);
+        assert!(!quantile.dates.is_empty());
+        assert!(quantile.long_short().is_finite());
+        assert_eq!(IcSummary::new(&[(1, 0.1), (2, -0.1), (3, 0.3)]).positive, 2.0 / 3.0);
+
+        let volatility = panel.factor(&Volatility(20));
+        let mut strategy = FactorStrategy::new(&volatility, 1, 20, Weighting::Equal);
+        assert!(strategy.targets().values().all(|target| target.len() == 1 && target[0].1 == 1.0));
+        let mut back_test = BackTest::with_universe(panel.codes().to_vec(), &mut strategy).unwrap();
+        let result = back_test.run("20220601", "20221101").unwrap();
+        assert!(!result.trades.is_empty());
+    }
+
+    /// 5 只股票每日固定涨幅依次为 0% 1% 2% 3% 4%, 动量和未来收益的排名一致
+    #[test]
+    fn synthetic() {
+        let codes = ["a", "b", "c", "d", "e"];
+        let histories: HashMap<_, _> = codes
+            .iter()
+            .enumerate()
+            .map(|(k, &code)| {
+                let bars = (0..30)
+                    .map(|t| {
+                        let mut close = 1000.0 * (1.0 + 0.01 * k as f64).powi(t);
+                        // e 在第 15 根K线 10 送 10, 原始价格减半
+                        if code == "e" && t >= 15 {
+                            close /= 2.0;
+                        }
+                        let close = close.round() as i32;
+                        let trade_data = TradeUnit { open: close, high: close, low: close, close, volume: 100, amount: 0.0 };
+                        DayTradeUnit { date: 20230101 + t, trade_data }
+                    })
+                    .collect::<Vec<_>>();
+                (code, bars)
+            })
+            .collect();
+        let bonus = CorporateAction {
+            code: "e",
+            date: 20230116,
+            cash_per_share: Decimal::ZERO,
+            shares_per_share: Decimal::ONE,
+            rights_price: Decimal::ZERO,
+            rights_per_share: Decimal::ZERO,
+        };
+        let panel = Panel::with_bars(&codes, histories, &[bonus], 20230101);
+
+        let momentum = panel.factor(&Momentum(5));
+        assert_eq!(momentum.get(20230106).unwrap().len(), 5);
+        assert!(momentum.get(20230105).unwrap().is_empty());
+        // 复权后除权日前后的动量不受影响
+        let section = momentum.get(20230118).unwrap();
+        assert!((section[4].1 - (1.04f64.powi(5) - 1.0)).abs() < 1e-3);
+
+        let returns = panel.forward_returns(3);
+        let ics = momentum.rank_ic(&returns);
+        // 有动量和未来收益的日期为第 6 到第 27 根K线
+        assert_eq!(ics.len(), 22);
+        assert!(ics.iter().all(|(_, ic)| (ic - 1.0).abs() < 1e-9), "{:?}", ics);
+        for (period, ic) in momentum.ic_decay(&panel, 5) {
+            assert!((ic - 1.0).abs() < 1e-9, "{} {}", period, ic);
+        }
+
+        let quantile = momentum.quantile_returns(&returns, 5);
+        assert_eq!(quantile.dates.len(), 22);
+        let means = quantile.mean();
+        for (k, mean) in means.iter().enumerate() {
+            assert!((mean - (1.0 + 0.01 * k as f64).powi(3) + 1.0).abs() < 1e-3, "{:?}", means);
+        }
+        assert!((quantile.long_short() - (1.04f64.powi(3) - 1.0)).abs() < 1e-3);
+
+        // 复权价格 / 复权因子 为实际价格, 估值因子按实际价格计算
+        let raw = (1000.0 * 1.04f64.powi(20) / 2.0).round();
+        let adjusted = panel.history("e", 20230121).last().unwrap().trade_data.close as f64;
+        assert_eq!(panel.factors["e"][20], 2.0);
+        assert!((adjusted / panel.factors["e"][20] - raw).abs() <= 1.0);
+    }
+}
@@ -0,0 +1,836 @@
+use std::collections::{hash_map::Entry, HashMap, HashSet};
+
+use rust_decimal::Decimal;
+use ta::Next;
+use thiserror::Error;
+
+use crate::{
+    backtest::{MarketSnapshot, PortfolioStrategy},
+    data::{DayTradeUnit, StockCode},
+    event::{lots_of, Context, Fill, OrderKind, OrderRequest, Side},
+    strategy::TradeError,
+};
+
+#[derive(Debug, Error, PartialEq)]
+pub enum FormulaError {
+    #[error("syntax error at {pos}: {message}")]
+    SyntaxError { pos: usize, message: String },
+    #[error("unknown variable {0}")]
+    UnknownVariable(String),
+    #[error("unknown function {0}")]
+    UnknownFunction(String),
+    #[error("{name} expects {expected} arguments, found {found}")]
+    ArityError { name: String, expected: usize, found: usize },
+    #[error("missing output line {0}")]
+    MissingOutput(String),
+}
+
+pub type Result<T, E = FormulaError> = std::result::Result<T, E>;
+
+/// 指标序列, 没有值的位置为 NaN, 条件成立为 1 不成立为 0
+pub type Values = Vec<f64>;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Symbol(&'static str),
+}
+
+/// 符号按长度降序匹配
+const SYMBOLS: [&str; 19] = [
+    ":=", ">=", "<=", "<>", "!=", "&&", "||", ":", ";", ",", "(", ")", "+", "-", "*", "/", ">", "<", "=",
+];
+
+/// 词法分析, 返回 (位置, 记号); 跳过 `{}` 注释和 `//` 行注释
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>> {
+    let chars: Vec<(usize, char)> = source.char_indices().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let (pos, c) = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '{' {
+            while i < chars.len() && chars[i].1 != '}' {
+                i += 1;
+            }
+            i += 1;
+        } else if source[pos..].starts_with("//") {
+            while i < chars.len() && chars[i].1 != '\n' {
+                i += 1;
+            }
+        } else if c.is_ascii_digit() || c == '.' {
+            let start = i;
+            while i < chars.len() && (chars[i].1.is_ascii_digit() || chars[i].1 == '.') {
+                i += 1;
+            }
+            let end = chars.get(i).map(|(pos, _)| *pos).unwrap_or(source.len());
+            let number = source[pos..end]
+                .parse()
+                .map_err(|_| FormulaError::SyntaxError { pos: chars[start].0, message: "invalid number".into() })?;
+            tokens.push((pos, Token::Number(number)));
+        } else if c.is_alphanumeric() || c == '_' {
+            while i < chars.len() && (chars[i].1.is_alphanumeric() || chars[i].1 == '_') {
+                i += 1;
+            }
+            let end = chars.get(i).map(|(pos, _)| *pos).unwrap_or(source.len());
+            tokens.push((pos, Token::Ident(source[pos..end].to_uppercase())));
+        } else {
+            let symbol = SYMBOLS
+                .iter()
+                .find(|symbol| source[pos..].starts_with(**symbol))
+                .ok_or_else(|| FormulaError::SyntaxError { pos, message: format!("unexpected '{}'", c) })?;
+            tokens.push((pos, Token::Symbol(symbol)));
+            i += symbol.len();
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Number(f64),
+    Var(String),
+    Call(String, Vec<Expr>),
+    Neg(Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+/// 语句: `NAME:expr;` 输出线, `NAME:=expr;` 中间变量, `expr;` 无名输出线
+#[derive(Debug, Clone, PartialEq)]
+struct Statement {
+    name: String,
+    output: bool,
+    expr: Expr,
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + offset).map(|(_, token)| token)
+    }
+
+    fn error(&self, message: &str) -> FormulaError {
+        let pos = self.tokens.get(self.pos).map(|(pos, _)| *pos).unwrap_or(self.end);
+        FormulaError::SyntaxError { pos, message: message.to_string() }
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        if self.peek() == Some(&Token::Symbol(symbol_of(symbol))) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<()> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", symbol)))
+        }
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Ident(ident)) if ident == keyword) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn statements(&mut self) -> Result<Vec<Statement>> {
+        let mut statements = vec![];
+        let mut unnamed = 0;
+        while self.peek().is_some() {
+            if self.eat(";") {
+                continue;
+            }
+            let (name, output) = match (self.peek(), self.peek_at(1)) {
+                (Some(Token::Ident(name)), Some(Token::Symbol(":"))) => (Some(name.clone()), true),
+                (Some(Token::Ident(name)), Some(Token::Symbol(":="))) => (Some(name.clone()), false),
+                _ => (None, true),
+            };
+            if name.is_some() {
+                self.pos += 2;
+            }
+            let expr = self.or()?;
+            // 画线属性, 例如 COLORRED, NODRAW, 不影响计算
+            while self.eat(",") {
+                match self.peek() {
+                    Some(Token::Ident(_)) => self.pos += 1,
+                    _ => return Err(self.error("expected attribute")),
+                }
+            }
+            if self.peek().is_some() {
+                self.expect(";")?;
+            }
+            let name = name.unwrap_or_else(|| {
+                unnamed += 1;
+                format!("OUT{}", unnamed)
+            });
+            statements.push(Statement { name, output, expr });
+        }
+        Ok(statements)
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut left = self.and()?;
+        while self.eat("||") || self.eat_keyword("OR") {
+            left = Expr::Binary("OR", Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut left = self.comparison()?;
+        while self.eat("&&") || self.eat_keyword("AND") {
+            left = Expr::Binary("AND", Box::new(left), Box::new(self.comparison()?));
+        }
+        Ok(left)
+    }
+
+    fn comparison(&mut self) -> Result<Expr> {
+        let mut left = self.additive()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Symbol(op)) if [">", "<", ">=", "<=", "=", "<>", "!="].contains(op) => *op,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            let op = if op == "!=" { "<>" } else { op };
+            left = Expr::Binary(op, Box::new(left), Box::new(self.additive()?));
+        }
+    }
+
+    fn additive(&mut self) -> Result<Expr> {
+        let mut left = self.multiplicative()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Symbol(op)) if *op == "+" || *op == "-" => *op,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.multiplicative()?));
+        }
+    }
+
+    fn multiplicative(&mut self) -> Result<Expr> {
+        let mut left = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Symbol(op)) if *op == "*" || *op == "/" => *op,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        if self.eat("-") {
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        self.eat("+");
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        match self.peek().cloned() {
+            Some(Token::Number(number)) => {
+                self.pos += 1;
+                Ok(Expr::Number(number))
+            }
+            Some(Token::Ident(name)) => {
+                self.pos += 1;
+                if !self.eat("(") {
+                    return Ok(Expr::Var(name));
+                }
+                let mut args = vec![];
+                if !self.eat(")") {
+                    loop {
+                        args.push(self.or()?);
+                        if self.eat(")") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                Ok(Expr::Call(name, args))
+            }
+            Some(Token::Symbol("(")) => {
+                self.pos += 1;
+                let expr = self.or()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            _ => Err(self.error("expected expression")),
+        }
+    }
+}
+
+fn symbol_of(symbol: &str) -> &'static str {
+    SYMBOLS.iter().find(|s| **s == symbol).copied().unwrap_or("")
+}
+
+/// 通达信公式
+///
+/// 支持行情变量 `O H L C V AMOUNT` (及全称 `OPEN HIGH LOW CLOSE VOL`),
+/// 函数 `MA EMA SMA REF HHV LLV COUNT SUM BARSLAST CROSS IF ABS MAX MIN NOT STD AVEDEV`,
+/// 运算符 `+ - * / > < >= <= = <> AND OR`
+///
+/// ```ignore
+/// let formula = Formula::parse("MA5:MA(C,5); MA10:MA(C,10); BUY:CROSS(MA5,MA10);")?;
+/// let output = formula.eval(&bars)?;
+/// let buy = output.get("BUY").unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Formula {
+    statements: Vec<Statement>,
+    params: HashMap<String, f64>,
+}
+
+/// 公式计算结果, 输出线按公式中的顺序排列
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormulaOutput {
+    lines: Vec<(String, Values)>,
+}
+
+impl FormulaOutput {
+    pub fn get(&self, name: &str) -> Option<&Values> {
+        let name = name.to_uppercase();
+        self.lines.iter().find(|(line, _)| *line == name).map(|(_, values)| values)
+    }
+
+    pub fn lines(&self) -> &[(String, Values)] {
+        &self.lines
+    }
+}
+
+impl Formula {
+    pub fn parse(source: &str) -> Result<Self> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, pos: 0, end: source.len() };
+        let statements = parser.statements()?;
+        Ok(Formula { statements, params: HashMap::new() })
+    }
+
+    /// 设置公式参数, 例如通达信公式编辑器中定义的 N M
+    pub fn param(mut self, name: &str, value: f64) -> Self {
+        self.params.insert(name.to_uppercase(), value);
+        self
+    }
+
+    /// 输出线名称
+    pub fn outputs(&self) -> Vec<&str> {
+        self.statements.iter().filter(|s| s.output).map(|s| s.name.as_str()).collect()
+    }
+
+    /// 在整个K线序列上计算全部输出线
+    pub fn eval(&self, bars: &[DayTradeUnit]) -> Result<FormulaOutput> {
+        let mut state = FormulaState::new(self)?;
+        for bar in bars {
+            state.push(bar);
+        }
+        Ok(state.output())
+    }
+}
+
+/// 支持的函数及参数个数
+const FUNCTIONS: [(&str, usize); 19] = [
+    ("MA", 2), ("SUM", 2), ("HHV", 2), ("LLV", 2), ("COUNT", 2), ("STD", 2), ("AVEDEV", 2), ("EMA", 2), ("EXPMA", 2),
+    ("SMA", 3), ("REF", 2), ("BARSLAST", 1), ("CROSS", 2), ("IF", 3), ("IFF", 3), ("ABS", 1), ("NOT", 1), ("MAX", 2),
+    ("MIN", 2),
+];
+
+/// 编译后的表达式节点, 引用的节点下标总是小于自身
+#[derive(Debug, Clone)]
+enum Op {
+    Number(f64),
+    Quote(fn(&DayTradeUnit) -> f64),
+    Neg(usize),
+    Binary(&'static str, usize, usize),
+    Call(&'static str, Vec<usize>),
+}
+
+struct Compiler<'a> {
+    ops: Vec<Op>,
+    vars: HashMap<String, usize>,
+    params: &'a HashMap<String, f64>,
+}
+
+impl<'a> Compiler<'a> {
+    fn push(&mut self, op: Op) -> usize {
+        self.ops.push(op);
+        self.ops.len() - 1
+    }
+
+    fn compile(&mut self, expr: &Expr) -> Result<usize> {
+        let op = match expr {
+            Expr::Number(number) => Op::Number(*number),
+            Expr::Var(name) => return self.variable(name),
+            Expr::Neg(expr) => Op::Neg(self.compile(expr)?),
+            Expr::Binary(op, left, right) => {
+                let left = self.compile(left)?;
+                Op::Binary(op, left, self.compile(right)?)
+            }
+            Expr::Call(name, args) => {
+                let args = args.iter().map(|arg| self.compile(arg)).collect::<Result<Vec<usize>>>()?;
+                let (function, expected) = FUNCTIONS
+                    .iter()
+                    .find(|(function, _)| function == name)
+                    .ok_or_else(|| FormulaError::UnknownFunction(name.clone()))?;
+                if args.len() != *expected {
+                    return Err(FormulaError::ArityError { name: name.clone(), expected: *expected, found: args.len() });
+                }
+                Op::Call(function, args)
+            }
+        };
+        Ok(self.push(op))
+    }
+
+    fn variable(&mut self, name: &str) -> Result<usize> {
+        let quote: fn(&DayTradeUnit) -> f64 = match name {
+            "O" | "OPEN" => |bar| bar.trade_data.open as f64 / 100.0,
+            "H" | "HIGH" => |bar| bar.trade_data.high as f64 / 100.0,
+            "L" | "LOW" => |bar| bar.trade_data.low as f64 / 100.0,
+            "C" | "CLOSE" => |bar| bar.trade_data.close as f64 / 100.0,
+            "V" | "VOL" => |bar| bar.trade_data.volume as f64,
+            "AMOUNT" | "AMO" => |bar| bar.trade_data.amount as f64,
+            _ => {
+                return match (self.vars.get(name), self.params.get(name)) {
+                    (Some(index), _) => Ok(*index),
+                    (None, Some(value)) => Ok(self.push(Op::Number(*value))),
+                    (None, None) => Err(FormulaError::UnknownVariable(name.to_string())),
+                }
+            }
+        };
+        Ok(self.push(Op::Quote(quote)))
+    }
+}
+
+/// 公式的增量计算状态, 每推入一根K线只计算这根K线上的值, 结果与在整个序列上 [`Formula::eval`] 相同
+///
+/// 回测逐日推进时使用, 不必每个交易日在全部历史上重新计算
+#[derive(Debug, Clone)]
+pub struct FormulaState {
+    ops: Vec<Op>,
+    /** 输出线名称和对应的节点 */
+    outputs: Vec<(String, usize)>,
+    /** 每个节点到目前为止的值 */
+    values: Vec<Values>,
+    len: usize,
+    /** 第一根K线的日期 */
+    first: Option<i32>,
+}
+
+impl FormulaState {
+    pub fn new(formula: &Formula) -> Result<Self> {
+        let mut compiler = Compiler { ops: vec![], vars: HashMap::new(), params: &formula.params };
+        let mut outputs = vec![];
+        for statement in formula.statements.iter() {
+            let index = compiler.compile(&statement.expr)?;
+            if statement.output {
+                outputs.push((statement.name.clone(), index));
+            }
+            compiler.vars.insert(statement.name.clone(), index);
+        }
+        let values = vec![vec![]; compiler.ops.len()];
+        Ok(FormulaState { ops: compiler.ops, outputs, values, len: 0, first: None })
+    }
+
+    /// 已计算的K线数量
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// 推入下一根K线
+    pub fn push(&mut self, bar: &DayTradeUnit) {
+        self.first.get_or_insert(bar.date);
+        let i = self.len;
+        for (k, op) in self.ops.iter().enumerate() {
+            let (done, rest) = self.values.split_at_mut(k);
+            let own = &mut rest[0];
+            let value = match op {
+                Op::Number(number) => *number,
+                Op::Quote(quote) => quote(bar),
+                Op::Neg(x) => -done[*x][i],
+                Op::Binary(op, a, b) => binary(op, done[*a][i], done[*b][i]),
+                Op::Call(name, args) => {
+                    let args: Vec<&[f64]> = args.iter().map(|arg| &done[*arg][..]).collect();
+                    call(name, &args, own, i)
+                }
+            };
+            own.push(value);
+        }
+        self.len += 1;
+    }
+
+    /// 与K线历史同步, 只推入新增的K线; 历史换了开头或变短时从头计算
+    pub fn sync(&mut self, history: &[DayTradeUnit]) {
+        if self.len > history.len() || (self.len > 0 && self.first != history.first().map(|bar| bar.date)) {
+            self.values.iter_mut().for_each(Vec::clear);
+            self.len = 0;
+            self.first = None;
+        }
+        for bar in &history[self.len..] {
+            self.push(bar);
+        }
+    }
+
+    /// 输出线到目前为止的值
+    pub fn line(&self, name: &str) -> Option<&Values> {
+        let name = name.to_uppercase();
+        self.outputs.iter().find(|(line, _)| *line == name).map(|(_, index)| &self.values[*index])
+    }
+
+    /// 输出线的最新值, 还没有K线时为 NaN
+    pub fn latest(&self, name: &str) -> Option<f64> {
+        self.line(name).map(|values| values.last().copied().unwrap_or(f64::NAN))
+    }
+
+    /// 全部输出线的最新值, 顺序同 `Formula::outputs`
+    pub fn current(&self) -> Vec<f64> {
+        self.outputs
+            .iter()
+            .map(|(_, index)| self.values[*index].last().copied().unwrap_or(f64::NAN))
+            .collect()
+    }
+
+    pub fn output(&self) -> FormulaOutput {
+        let lines = self.outputs.iter().map(|(name, index)| (name.clone(), self.values[*index].clone())).collect();
+        FormulaOutput { lines }
+    }
+}
+
+fn truth(value: bool) -> f64 {
+    if value {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+fn binary(op: &str, a: f64, b: f64) -> f64 {
+    if a.is_nan() || b.is_nan() {
+        return f64::NAN;
+    }
+    match op {
+        "+" => a + b,
+        "-" => a - b,
+        "*" => a * b,
+        // 与通达信一致, 除数为0时结果为0
+        "/" if b == 0.0 => 0.0,
+        "/" => a / b,
+        ">" => truth(a > b),
+        "<" => truth(a < b),
+        ">=" => truth(a >= b),
+        "<=" => truth(a <= b),
+        "=" => truth(a == b),
+        "<>" => truth(a != b),
+        "AND" => truth(a != 0.0 && b != 0.0),
+        "OR" => truth(a != 0.0 || b != 0.0),
+        _ => f64::NAN,
+    }
+}
+
+/// 周期参数, 无效时为 None
+fn period(value: f64) -> Option<usize> {
+    if value.is_nan() || value < 0.0 {
+        None
+    } else {
+        Some(value as usize)
+    }
+}
+
+/// 第 i 根K线上的周期窗口, 周期为0表示从第一根K线开始; `full` 为 true 时数据不足周期的位置没有值
+fn window(x: &[f64], n: f64, i: usize, full: bool, f: impl Fn(&[f64]) -> f64) -> f64 {
+    match period(n) {
+        Some(0) => f(&x[..=i]),
+        Some(n) if i + 1 >= n => f(&x[i + 1 - n..=i]),
+        Some(_) if !full => f(&x[..=i]),
+        _ => f64::NAN,
+    }
+}
+
+/// 递推平滑 Y = (weight * X + (1 - weight) * Y'), 第一个有效值取 X
+fn smooth(x: f64, prev: Option<&f64>, weight: Option<f64>) -> f64 {
+    let prev = prev.copied().unwrap_or(f64::NAN);
+    match weight {
+        _ if x.is_nan() => prev,
+        Some(_) if prev.is_nan() => x,
+        Some(w) => w * x + (1.0 - w) * prev,
+        None => f64::NAN,
+    }
+}
+
+/// 函数在第 i 根K线上的值, `args` 为各参数到第 i 根为止的值, `out` 为之前各K线上的结果
+fn call(name: &str, args: &[&[f64]], out: &[f64], i: usize) -> f64 {
+    let x = args[0];
+    match name {
+        "MA" => window(x, args[1][i], i, true, |w| w.iter().sum::<f64>() / w.len() as f64),
+        // 跳过没有值的位置, 例如第一根K线上的 REF(C,1)
+        "SUM" => window(x, args[1][i], i, false, |w| {
+            let valid: Vec<f64> = w.iter().cloned().filter(|v| !v.is_nan()).collect();
+            if valid.is_empty() {
+                f64::NAN
+            } else {
+                valid.iter().sum()
+            }
+        }),
+        "HHV" => window(x, args[1][i], i, false, |w| w.iter().cloned().fold(f64::NAN, f64::max)),
+        "LLV" => window(x, args[1][i], i, false, |w| w.iter().cloned().fold(f64::NAN, f64::min)),
+        "COUNT" => window(x, args[1][i], i, false, |w| w.iter().filter(|v| **v != 0.0 && !v.is_nan()).count() as f64),
+        "STD" => window(x, args[1][i], i, true, crate::statistics::std_dev),
+        "AVEDEV" => window(x, args[1][i], i, true, crate::statistics::avedev),
+        "EMA" | "EXPMA" => {
+            let weight = period(args[1][i]).filter(|n| *n > 0).map(|n| 2.0 / (n as f64 + 1.0));
+            smooth(x[i], out.last(), weight)
+        }
+        "SMA" => {
+            let (n, m) = (args[1][i], args[2][i]);
+            smooth(x[i], out.last(), if n > 0.0 { Some(m / n) } else { None })
+        }
+        "REF" => match period(args[1][i]) {
+            Some(n) if n <= i => x[i - n],
+            _ => f64::NAN,
+        },
+        // 上一次条件成立到现在的周期数, 还没有成立过时没有值
+        "BARSLAST" => match out.last() {
+            _ if x[i] != 0.0 && !x[i].is_nan() => 0.0,
+            Some(last) => last + 1.0,
+            None => f64::NAN,
+        },
+        "CROSS" => {
+            let (a, b) = (x, args[1]);
+            match i {
+                0 => 0.0,
+                _ if [a[i], b[i], a[i - 1], b[i - 1]].iter().any(|v| v.is_nan()) => 0.0,
+                _ => truth(a[i - 1] <= b[i - 1] && a[i] > b[i]),
+            }
+        }
+        "IF" | "IFF" => match x[i] {
+            c if c.is_nan() => f64::NAN,
+            c if c != 0.0 => args[1][i],
+            _ => args[2][i],
+        },
+        "ABS" => x[i].abs(),
+        "NOT" if x[i].is_nan() => f64::NAN,
+        "NOT" => truth(x[i] == 0.0),
+        "MAX" | "MIN" if x[i].is_nan() || args[1][i].is_nan() => f64::NAN,
+        "MAX" => x[i].max(args[1][i]),
+        "MIN" => x[i].min(args[1][i]),
+        _ => f64::NAN,
+    }
+}
+
+/// 公式指标, 可以放入 `SeriseRegistry`, 每根K线输出各输出线的最新值, 顺序同 `Formula::outputs`
+pub struct FormulaIndicator {
+    state: FormulaState,
+}
+
+impl FormulaIndicator {
+    /// 公式在这里编译, 未知的变量和函数等错误直接返回
+    pub fn new(formula: Formula) -> Result<Self> {
+        Ok(FormulaIndicator { state: FormulaState::new(&formula)? })
+    }
+}
+
+impl Next<&DayTradeUnit> for FormulaIndicator {
+    type Output = Vec<f64>;
+
+    fn next(&mut self, input: &DayTradeUnit) -> Self::Output {
+        self.state.push(input);
+        self.state.current()
+    }
+}
+
+/// 由公式输出线产生买卖信号的策略
+///
+/// 买入线最新值非0且空仓时按余额的百分比市价买入, 卖出线最新值非0且持仓时市价全部卖出
+pub struct FormulaStrategy {
+    /** 编译好的公式, 还没有推入K线, 每只股票从它复制一份 */
+    compiled: FormulaState,
+    buy: String,
+    sell: String,
+    percent: u8,
+    /** 已提交但还没有回报的委托 */
+    pending: HashSet<StockCode>,
+    /** 每只股票的计算状态, 随行情追加 */
+    states: HashMap<StockCode, FormulaState>,
+}
+
+impl FormulaStrategy {
+    /// 买卖信号取公式中的 BUY 和 SELL 输出线, 满仓买入
+    ///
+    /// 数量按信号当日收盘价计算, 次日高开时委托会因余额不足被拒绝
+    pub fn new(formula: Formula) -> Result<Self> {
+        Self::with_signals(formula, "BUY", "SELL", 100)
+    }
+
+    /// 公式在这里编译, 编译错误或者公式没有买卖信号对应的输出线时返回错误
+    pub fn with_signals(formula: Formula, buy: &str, sell: &str, percent: u8) -> Result<Self> {
+        let compiled = FormulaState::new(&formula)?;
+        let (buy, sell) = (buy.to_uppercase(), sell.to_uppercase());
+        for name in [&buy, &sell] {
+            if compiled.line(name).is_none() {
+                return Err(FormulaError::MissingOutput(name.clone()));
+            }
+        }
+        Ok(FormulaStrategy { compiled, buy, sell, percent, pending: HashSet::new(), states: HashMap::new() })
+    }
+
+    /// 最新一根K线上的买卖信号, 只计算上次之后新增的K线
+    fn signals(&mut self, code: StockCode, history: &[DayTradeUnit]) -> (bool, bool) {
+        let state = match self.states.entry(code) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(self.compiled.clone()),
+        };
+        state.sync(history);
+        let signal = |name: &str| state.latest(name).map(|value| value != 0.0 && !value.is_nan()).unwrap_or(false);
+        (signal(&self.buy), signal(&self.sell))
+    }
+}
+
+impl PortfolioStrategy for FormulaStrategy {
+    fn on_bar(&mut self, ctx: &mut Context, market: &MarketSnapshot) {
+        for code in market.codes() {
+            if self.pending.contains(code) {
+                continue;
+            }
+            let (buy, sell) = self.signals(code, market.history(code));
+            let held = match ctx.get_position(code) {
+                Ok(Some(position)) => position.volume(),
+                _ => Decimal::ZERO,
+            };
+            if sell && !held.is_zero() {
+                ctx.submit(code, Side::Sell, held, OrderKind::Market);
+                self.pending.insert(code);
+            } else if buy && held.is_zero() {
+                let close = market.close(code).unwrap_or_default();
+                let amount = ctx.get_balance() * Decimal::from(self.percent) / Decimal::from(100);
+                let vol = lots_of(amount, close);
+                if !vol.is_zero() {
+                    ctx.submit(code, Side::Buy, vol, OrderKind::Market);
+                    self.pending.insert(code);
+                }
+            }
+        }
+    }
+
+    fn on_fill(&mut self, _ctx: &mut Context, fill: &Fill) {
+        self.pending.remove(fill.order.code);
+    }
+
+    fn on_order_rejected(&mut self, _ctx: &mut Context, order: &OrderRequest, _reason: &TradeError) {
+        self.pending.remove(order.code);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        backtest::BackTest,
+        data::{DayTradeUnit, StockTradeData, TradeDataSource, TradeUnit},
+    };
+
+    use super::{Formula, FormulaError, FormulaIndicator, FormulaState, FormulaStrategy};
+
+    fn bars(closes: &[i32]) -> Vec<DayTradeUnit> {
+        closes
+            .iter()
+            .enumerate()
+            .map(|(i, close)| DayTradeUnit {
+                date: 20230101 + i as i32,
+                trade_data: TradeUnit { open: *close, high: close + 10, low: close - 10, close: *close, volume: 100, amount: 0.0 },
+            })
+            .collect()
+    }
+
+    #[test]
+    fn eval() {
+        let formula = Formula::parse(
+            "{均线} MA2:MA(C,2); MA3:=MA(C,N);\n\
+             X:CROSS(MA2,MA3), COLORRED; // 金叉\n\
+             R:REF(C,1); HH:HHV(H,3); LL:LLV(L,0); CNT:COUNT(C>R,2);\n\
+             S:SMA(C,3,1); E:EMA(C,3); B:BARSLAST(C<R); T:IF(C>=11 AND NOT(C=12),1,-1)",
+        )
+        .unwrap()
+        .param("N", 3.0);
+        assert_eq!(formula.outputs(), vec!["MA2", "X", "R", "HH", "LL", "CNT", "S", "E", "B", "T"]);
+        let output = formula.eval(&bars(&[1000, 900, 1100, 1200, 1150])).unwrap();
+        let line = |name: &str| output.get(name).unwrap().clone();
+
+        assert!(line("MA2")[0].is_nan());
+        assert_eq!(line("MA2")[1..], [9.5, 10.0, 11.5, 11.75]);
+        // MA3 从第3根K线开始有值, 第4根K线 MA2 上穿 MA3
+        assert_eq!(line("X"), vec![0.0, 0.0, 0.0, 1.0, 0.0]);
+        assert_eq!(line("R")[1..], [10.0, 9.0, 11.0, 12.0]);
+        assert_eq!(line("HH"), vec![10.1, 10.1, 11.1, 12.1, 12.1]);
+        assert_eq!(line("LL"), vec![9.9, 8.9, 8.9, 8.9, 8.9]);
+        assert_eq!(line("CNT"), vec![0.0, 0.0, 1.0, 2.0, 1.0]);
+        assert!((line("S")[1] - (9.0 + 2.0 * 10.0) / 3.0).abs() < 1e-9);
+        assert_eq!(line("E")[..2], [10.0, 0.5 * 9.0 + 0.5 * 10.0]);
+        assert!(line("B")[0].is_nan());
+        assert_eq!(line("B")[1..], [0.0, 1.0, 2.0, 0.0]);
+        assert_eq!(line("T"), vec![-1.0, -1.0, 1.0, -1.0, 1.0]);
+
+        // 逐根推入与整体计算一致, 历史换了开头时从头计算
+        let bars = bars(&[1000, 900, 1100, 1200, 1150]);
+        let mut state = FormulaState::new(&formula).unwrap();
+        state.sync(&bars[..2]);
+        state.sync(&bars);
+        assert_eq!(format!("{:?}", state.output()), format!("{:?}", output));
+        state.sync(&bars[1..]);
+        assert_eq!((state.len(), state.latest("R")), (4, Some(12.0)));
+        assert!(state.line("MA3").is_none());
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(Formula::parse("A:MA(C,5"), Err(FormulaError::SyntaxError { .. })));
+        assert!(matches!(Formula::parse("A:C#2"), Err(FormulaError::SyntaxError { pos: 3, .. })));
+        let bars = bars(&[1000]);
+        assert_eq!(Formula::parse("A:FOO(C)").unwrap().eval(&bars), Err(FormulaError::UnknownFunction("FOO".into())));
+        assert_eq!(Formula::parse("A:MA5").unwrap().eval(&bars), Err(FormulaError::UnknownVariable("MA5".into())));
+        assert!(matches!(Formula::parse("A:REF(C)").unwrap().eval(&bars), Err(FormulaError::ArityError { .. })));
+        // 编译错误和缺少买卖输出线在构造时返回
+        assert!(FormulaIndicator::new(Formula::parse("A:MA5").unwrap()).is_err());
+        let err = FormulaStrategy::new(Formula::parse("B:C>O; SELL:C<O").unwrap()).err();
+        assert_eq!(err, Some(FormulaError::MissingOutput("BUY".into())));
+        assert!(FormulaStrategy::new(Formula::parse("BUY:C>X; SELL:C<O").unwrap()).is_err());
+    }
+
+    #[test]
+    fn strategy() {
+        let formula = Formula::parse("MA5:MA(C,5); MA10:MA(C,10); BUY:CROSS(MA5,MA10); SELL:CROSS(MA10,MA5);").unwrap();
+        let bars: Vec<DayTradeUnit> = StockTradeData {}.day_duration("603339", None, None).unwrap().collect();
+        let output = formula.eval(&bars).unwrap();
+        assert_eq!(output.get("buy").unwrap().len(), bars.len());
+        // 按收盘价计算数量, 次日高开时满仓买入会余额不足, 留出余量
+        let strategy = FormulaStrategy::with_signals(formula, "buy", "sell", 90).unwrap();
+        let mut back_test = BackTest::with_universe(vec!["603339"], strategy).unwrap();
+        let result = back_test.run("20220101", "20221101").unwrap();
+        assert!(!result.trades.is_empty());
+        assert!(result.rejected.is_empty());
+        // 买卖交替
+        let kinds: Vec<String> = result.trades.iter().map(|trade| format!("{:?}", trade.kind)).collect();
+        assert!(kinds.chunks(2).all(|pair| pair[0] == "Buy" && pair.get(1).iter().all(|kind| *kind == "Sell")));
+    }
+}
@@ -0,0 +1,983 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use flate2::read::ZlibDecoder;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    data::{DayTradeUnit, Market, MinuteTradeUnit, StockCode, TradeUnit, WhereIsFrom},
+    event::CorporateAction,
+    tick::{Direction, Tick},
+};
+
+#[derive(Debug, Error)]
+pub enum HqError {
+    #[error("connect or read quote server fail")]
+    IoError {
+        #[from]
+        source: io::Error,
+    },
+    #[error("parse recording fail")]
+    JsonError {
+        #[from]
+        source: serde_json::Error,
+    },
+    #[error("decode recording hex fail")]
+    HexError {
+        #[from]
+        source: hex::FromHexError,
+    },
+    #[error("stock not exist in market")]
+    StockCodeNotExistInMarket,
+    #[error("response is truncated")]
+    Truncated,
+}
+
+pub type Result<T, E = HqError> = std::result::Result<T, E>;
+
+/// 通达信行情服务器的常用端口
+pub const DEFAULT_PORT: u16 = 7709;
+
+/// 连接后依次发送的握手包, 服务器的回应不需要解析
+const SETUP: [&str; 3] = [
+    "0c0218930001030003000d0001",
+    "0c0218940001030003000d0002",
+    "0c031899000120002000db0fd5d0c9ccd6a4a8af0000008fc22540130000d500c9ccbdf0d7ea00000002",
+];
+
+/// 日线及以上周期, 结果为 [`DayTradeUnit`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DayPeriod {
+    Day,
+    Week,
+    Month,
+    Quarter,
+    Year,
+}
+
+impl DayPeriod {
+    fn category(self) -> u16 {
+        match self {
+            DayPeriod::Day => 9,
+            DayPeriod::Week => 5,
+            DayPeriod::Month => 6,
+            DayPeriod::Quarter => 10,
+            DayPeriod::Year => 11,
+        }
+    }
+}
+
+/// 分钟线周期, 结果为 [`MinuteTradeUnit`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MinutePeriod {
+    Min1,
+    Min5,
+    Min15,
+    Min30,
+    Min60,
+}
+
+impl MinutePeriod {
+    fn category(self) -> u16 {
+        match self {
+            MinutePeriod::Min1 => 8,
+            MinutePeriod::Min5 => 0,
+            MinutePeriod::Min15 => 1,
+            MinutePeriod::Min30 => 2,
+            MinutePeriod::Min60 => 3,
+        }
+    }
+}
+
+/// 证券列表中的一项
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Security {
+    pub code: String,
+    pub name: String,
+    /// 每手股数
+    pub vol_unit: u16,
+    /// 价格小数位数
+    pub decimal_point: u8,
+    pub pre_close: f64,
+}
+
+/// 分笔成交
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Transaction {
+    /// 时分 例如 935 表示 09:35
+    pub time: i32,
+    /// 价格, 单位为分
+    pub price: i32,
+    /// 成交量, 单位为手
+    pub volume: i64,
+    /// 成交笔数, 历史分笔没有该字段, 为 0
+    pub count: i64,
+    /// 0 买 1 卖 2 集合竞价
+    pub direction: i64,
+}
+
+impl Transaction {
+    /// 转换为 [`Tick`], 行情服务器的分笔只精确到分钟
+    pub fn tick(&self, date: i32) -> Tick {
+        Tick {
+            date,
+            time: self.time * 100,
+            price: self.price,
+            volume: self.volume as i32,
+            count: self.count as i32,
+            direction: match self.direction {
+                0 => Direction::Buy,
+                1 => Direction::Sell,
+                _ => Direction::Neutral,
+            },
+        }
+    }
+}
+
+/// 分时数据中的一分钟
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MinutePrice {
+    /// 价格, 单位为分
+    pub price: i32,
+    /// 成交量, 单位为手
+    pub volume: i64,
+}
+
+/// 最新的基本财务数据, 股本和金额单位为万
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FinanceInfo {
+    pub code: String,
+    /// 流通股本
+    pub float_shares: f32,
+    pub province: u16,
+    pub industry: u16,
+    pub updated_date: u32,
+    pub ipo_date: u32,
+    /// 总股本
+    pub total_shares: f32,
+    /// 其余 29 列, 顺序与 pytdx 的 get_finance_info 相同, 从国家股到保留字段
+    pub values: Vec<f32>,
+}
+
+impl FinanceInfo {
+    /// 总资产
+    pub fn total_assets(&self) -> f32 {
+        self.values[6]
+    }
+
+    /// 净资产
+    pub fn net_assets(&self) -> f32 {
+        self.values[14]
+    }
+
+    /// 净利润
+    pub fn net_profit(&self) -> f32 {
+        self.values[25]
+    }
+}
+
+/// 除权除息及股本变动
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Xdxr {
+    pub date: i32,
+    /// 1 除权除息, 2 送配股上市, 3 非流通股上市, 5 股本变化, 11 扩缩股, 13 14 权证 等
+    pub category: u8,
+    /// 每 10 股派息
+    pub fenhong: f32,
+    /// 配股价
+    pub peigujia: f32,
+    /// 每 10 股送转股
+    pub songzhuangu: f32,
+    /// 每 10 股配股
+    pub peigu: f32,
+    /// 扩缩股比例
+    pub suogu: f32,
+    /// 前流通股本, 前总股本, 后流通股本, 后总股本, 单位为万
+    pub shares: [f32; 4],
+}
+
+impl Xdxr {
+    /// 除权除息转换为回测事件, 其他类别为 None
+    pub fn corporate_action(&self, code: StockCode) -> Option<CorporateAction> {
+        if self.category != 1 {
+            return None;
+        }
+        let per_share = |value: f32| Decimal::from_f32_retain(value).unwrap_or_default() / Decimal::TEN;
+        Some(CorporateAction {
+            code,
+            date: self.date,
+            cash_per_share: per_share(self.fenhong),
+            shares_per_share: per_share(self.songzhuangu),
+            rights_price: Decimal::from_f32_retain(self.peigujia).unwrap_or_default(),
+            rights_per_share: per_share(self.peigu),
+        })
+    }
+}
+
+/// 一次请求和服务器原始回应(含包头), 用于录制和回放
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Recording {
+    #[serde(with = "hex")]
+    pub request: Vec<u8>,
+    #[serde(with = "hex")]
+    pub response: Vec<u8>,
+}
+
+/// 读取录制文件, 每行一个 json 格式的 [`Recording`]
+pub fn load_recordings<P: AsRef<Path>>(path: P) -> Result<Vec<Recording>> {
+    let mut recordings = vec![];
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            recordings.push(serde_json::from_str(&line)?);
+        }
+    }
+    Ok(recordings)
+}
+
+/// 写入录制文件
+pub fn save_recordings<P: AsRef<Path>>(path: P, recordings: &[Recording]) -> Result<()> {
+    let mut file = File::create(path)?;
+    for recording in recordings {
+        writeln!(file, "{}", serde_json::to_string(recording)?)?;
+    }
+    Ok(())
+}
+
+/**
+ * 通达信行情服务器客户端, 协议与 pytdx 的 TdxHq_API 一致
+ *
+ * 价格统一换算为分, K线成交量换算为股, 与本地 `.day` 文件一致
+ *
+ * ```ignore
+ * let mut client = HqClient::connect(("119.147.212.81", 7709))?;
+ * let bars = client.bars("603339", DayPeriod::Day, 0, 800)?;
+ * ```
+ */
+pub struct HqClient {
+    stream: TcpStream,
+    /** 开启录制时记录每次请求和回应 */
+    recordings: Option<Vec<Recording>>,
+}
+
+impl HqClient {
+    /// 连接并完成握手
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+        Self::open(addr, false)
+    }
+
+    /// 连接并录制所有请求和回应, 之后可以用 [`MockServer`] 回放
+    pub fn connect_recording<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+        Self::open(addr, true)
+    }
+
+    fn open<A: ToSocketAddrs>(addr: A, record: bool) -> Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_read_timeout(Some(Duration::from_secs(15)))?;
+        let mut client = HqClient { stream, recordings: if record { Some(vec![]) } else { None } };
+        for setup in SETUP {
+            client.request(&hex::decode(setup)?)?;
+        }
+        Ok(client)
+    }
+
+    /// 读超时, 默认 15 秒
+    pub fn timeout(self, timeout: Duration) -> Result<Self> {
+        self.stream.set_read_timeout(Some(timeout))?;
+        Ok(self)
+    }
+
+    /// 取出已录制的请求和回应
+    pub fn take_recordings(&mut self) -> Vec<Recording> {
+        self.recordings.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// 市场的证券数量
+    pub fn security_count(&mut self, market: Market) -> Result<u16> {
+        let mut pkg = hex::decode("0c0c186c0001080008004e04")?;
+        pkg.write_u16::<LittleEndian>(market_id(market))?;
+        pkg.extend_from_slice(&[0x75, 0xc7, 0x33, 0x01]);
+        let body = self.request(&pkg)?;
+        Body::new(&body).u16()
+    }
+
+    /// 证券列表, 每次最多返回 1000 项, 从 `start` 开始
+    pub fn security_list(&mut self, market: Market, start: u16) -> Result<Vec<Security>> {
+        let mut pkg = hex::decode("0c0118640101060006005004")?;
+        pkg.write_u16::<LittleEndian>(market_id(market))?;
+        pkg.write_u16::<LittleEndian>(start)?;
+        let body = self.request(&pkg)?;
+        let mut body = Body::new(&body);
+        let count = body.u16()?;
+        let mut securities = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let code = String::from_utf8_lossy(body.bytes(6)?).into_owned();
+            let vol_unit = body.u16()?;
+            let (name, ..) = encoding_rs::GBK.decode(body.bytes(8)?);
+            body.skip(4)?;
+            let decimal_point = body.u8()?;
+            let pre_close = get_volume(body.u32()?);
+            body.skip(4)?;
+            securities.push(Security {
+                code,
+                name: name.trim_end_matches('\0').to_string(),
+                vol_unit,
+                decimal_point,
+                pre_close,
+            });
+        }
+        Ok(securities)
+    }
+
+    /// 日线及以上周期的K线, `start` 为从最新一根往前的偏移, 每次最多 800 根, 按日期升序
+    pub fn bars(&mut self, code: &str, period: DayPeriod, start: u16, count: u16) -> Result<Vec<DayTradeUnit>> {
+        let bars = self.security_bars(code, period.category(), false, start, count)?;
+        Ok(bars.into_iter().map(|(date, _, trade_data)| DayTradeUnit { date, trade_data }).collect())
+    }
+
+    /// 分钟K线, 参数同 [`HqClient::bars`]
+    pub fn minute_bars(&mut self, code: &str, period: MinutePeriod, start: u16, count: u16) -> Result<Vec<MinuteTradeUnit>> {
+        let bars = self.security_bars(code, period.category(), true, start, count)?;
+        Ok(bars.into_iter().map(|(date, offset, trade_data)| MinuteTradeUnit { date, offset, trade_data }).collect())
+    }
+
+    fn security_bars(&mut self, code: &str, category: u16, minute: bool, start: u16, count: u16) -> Result<Vec<(i32, i16, TradeUnit)>> {
+        let (market, code) = split_code(code)?;
+        let mut pkg = hex::decode("0c01086401011c001c002d05")?;
+        pkg.write_u16::<LittleEndian>(market)?;
+        pkg.extend_from_slice(code.as_bytes());
+        for value in [category, 1, start, count] {
+            pkg.write_u16::<LittleEndian>(value)?;
+        }
+        pkg.extend_from_slice(&[0; 10]);
+        let body = self.request(&pkg)?;
+        let mut body = Body::new(&body);
+        let num = body.u16()?;
+        let mut bars = Vec::with_capacity(num as usize);
+        // 价格单位为厘, 开盘价相对前一根收盘价, 其余相对开盘价
+        let mut pre_close = 0;
+        for _ in 0..num {
+            let (date, offset) = body.datetime(minute)?;
+            let open = pre_close + body.price()?;
+            let close = open + body.price()?;
+            let high = open + body.price()?;
+            let low = open + body.price()?;
+            let volume = get_volume(body.u32()?);
+            let amount = get_volume(body.u32()?);
+            pre_close = close;
+            let fen = |li: i64| (li as f64 / 10.0).round() as i32;
+            bars.push((date, offset, TradeUnit {
+                open: fen(open),
+                high: fen(high),
+                low: fen(low),
+                close: fen(close),
+                amount: amount as f32,
+                volume: (volume * 100.0).round() as i32,
+            }));
+        }
+        Ok(bars)
+    }
+
+    /// 当日分时数据
+    pub fn minute_prices(&mut self, code: &str) -> Result<Vec<MinutePrice>> {
+        let (market, code) = split_code(code)?;
+        let mut pkg = hex::decode("0c1b080001010e000e001d05")?;
+        pkg.write_u16::<LittleEndian>(market)?;
+        pkg.extend_from_slice(code.as_bytes());
+        pkg.write_u32::<LittleEndian>(0)?;
+        let body = self.request(&pkg)?;
+        parse_minute_prices(&body, 4)
+    }
+
+    /// 历史某日的分时数据
+    pub fn history_minute_prices(&mut self, code: &str, date: i32) -> Result<Vec<MinutePrice>> {
+        let (market, code) = split_code(code)?;
+        let mut pkg = hex::decode("0c01300001010d000d00b40f")?;
+        pkg.write_u32::<LittleEndian>(date as u32)?;
+        pkg.write_u8(market as u8)?;
+        pkg.extend_from_slice(code.as_bytes());
+        let body = self.request(&pkg)?;
+        parse_minute_prices(&body, 6)
+    }
+
+    /// 当日分笔成交, `start` 为从最新一笔往前的偏移, 每次最多 2000 笔, 按时间升序
+    pub fn transactions(&mut self, code: &str, start: u16, count: u16) -> Result<Vec<Transaction>> {
+        let (market, code) = split_code(code)?;
+        let mut pkg = hex::decode("0c17080101010e000e00c50e")?;
+        pkg.write_u16::<LittleEndian>(market)?;
+        pkg.extend_from_slice(code.as_bytes());
+        pkg.write_u16::<LittleEndian>(start)?;
+        pkg.write_u16::<LittleEndian>(count)?;
+        let body = self.request(&pkg)?;
+        let mut body = Body::new(&body);
+        let num = body.u16()?;
+        let mut price = 0;
+        let mut transactions = Vec::with_capacity(num as usize);
+        for _ in 0..num {
+            let time = body.time()?;
+            price += body.price()?;
+            let volume = body.price()?;
+            let count = body.price()?;
+            let direction = body.price()?;
+            body.price()?;
+            transactions.push(Transaction { time, price: price as i32, volume, count, direction });
+        }
+        Ok(transactions)
+    }
+
+    /// 历史某日的分笔成交
+    pub fn history_transactions(&mut self, code: &str, date: i32, start: u16, count: u16) -> Result<Vec<Transaction>> {
+        let (market, code) = split_code(code)?;
+        let mut pkg = hex::decode("0c013001000112001200b50f")?;
+        pkg.write_u32::<LittleEndian>(date as u32)?;
+        pkg.write_u16::<LittleEndian>(market)?;
+        pkg.extend_from_slice(code.as_bytes());
+        pkg.write_u16::<LittleEndian>(start)?;
+        pkg.write_u16::<LittleEndian>(count)?;
+        let body = self.request(&pkg)?;
+        let mut body = Body::new(&body);
+        let num = body.u16()?;
+        body.skip(4)?;
+        let mut price = 0;
+        let mut transactions = Vec::with_capacity(num as usize);
+        for _ in 0..num {
+            let time = body.time()?;
+            price += body.price()?;
+            let volume = body.price()?;
+            let direction = body.price()?;
+            body.price()?;
+            transactions.push(Transaction { time, price: price as i32, volume, count: 0, direction });
+        }
+        Ok(transactions)
+    }
+
+    /// 最新的基本财务数据
+    pub fn finance_info(&mut self, code: &str) -> Result<FinanceInfo> {
+        let body = self.stock_request("0c1f187600010b000b0010000100", code)?;
+        let mut body = Body::new(&body);
+        body.skip(2)?;
+        body.skip(1)?;
+        let code = String::from_utf8_lossy(body.bytes(6)?).into_owned();
+        let float_shares = body.f32()?;
+        let province = body.u16()?;
+        let industry = body.u16()?;
+        let updated_date = body.u32()?;
+        let ipo_date = body.u32()?;
+        let total_shares = body.f32()?;
+        let values = (0..29).map(|_| body.f32()).collect::<Result<Vec<f32>>>()?;
+        Ok(FinanceInfo { code, float_shares, province, industry, updated_date, ipo_date, total_shares, values })
+    }
+
+    /// 除权除息及股本变动, 按日期升序
+    pub fn xdxr(&mut self, code: &str) -> Result<Vec<Xdxr>> {
+        let body = self.stock_request("0c1f187600010b000b000f000100", code)?;
+        if body.len() < 11 {
+            return Ok(vec![]);
+        }
+        let mut body = Body::new(&body);
+        body.skip(9)?;
+        let num = body.u16()?;
+        let mut items = Vec::with_capacity(num as usize);
+        for _ in 0..num {
+            body.skip(7 + 1)?;
+            let (date, _) = body.datetime(false)?;
+            let category = body.u8()?;
+            let mut item = Xdxr { date, category, fenhong: 0.0, peigujia: 0.0, songzhuangu: 0.0, peigu: 0.0, suogu: 0.0, shares: [0.0; 4] };
+            match category {
+                1 => {
+                    item.fenhong = body.f32()?;
+                    item.peigujia = body.f32()?;
+                    item.songzhuangu = body.f32()?;
+                    item.peigu = body.f32()?;
+                }
+                11 | 12 => {
+                    body.skip(8)?;
+                    item.suogu = body.f32()?;
+                    body.skip(4)?;
+                }
+                13 | 14 => {
+                    // 行权价和份数, 与股票无关, 记在配股价和配股字段
+                    item.peigujia = body.f32()?;
+                    body.skip(4)?;
+                    item.peigu = body.f32()?;
+                    body.skip(4)?;
+                }
+                _ => {
+                    for share in item.shares.iter_mut() {
+                        *share = get_volume(body.u32()?) as f32;
+                    }
+                }
+            }
+            items.push(item);
+        }
+        Ok(items)
+    }
+
+    /// 除权除息事件, 可以直接交给回测
+    pub fn corporate_actions(&mut self, code: StockCode) -> Result<Vec<CorporateAction>> {
+        Ok(self.xdxr(code)?.iter().filter_map(|item| item.corporate_action(code)).collect())
+    }
+
+    fn stock_request(&mut self, header: &str, code: &str) -> Result<Vec<u8>> {
+        let (market, code) = split_code(code)?;
+        let mut pkg = hex::decode(header)?;
+        pkg.write_u8(market as u8)?;
+        pkg.extend_from_slice(code.as_bytes());
+        self.request(&pkg)
+    }
+
+    /// 发送请求并读取解压后的回应
+    fn request(&mut self, pkg: &[u8]) -> Result<Vec<u8>> {
+        self.stream.write_all(pkg)?;
+        let mut header = [0u8; 16];
+        self.stream.read_exact(&mut header)?;
+        let zip_size = LittleEndian::read_u16(&header[12..14]) as usize;
+        let unzip_size = LittleEndian::read_u16(&header[14..16]) as usize;
+        let mut raw = vec![0u8; zip_size];
+        self.stream.read_exact(&mut raw)?;
+        if let Some(recordings) = self.recordings.as_mut() {
+            let mut response = header.to_vec();
+            response.extend_from_slice(&raw);
+            recordings.push(Recording { request: pkg.to_vec(), response });
+        }
+        if zip_size == unzip_size {
+            return Ok(raw);
+        }
+        let mut body = Vec::with_capacity(unzip_size);
+        ZlibDecoder::new(&raw[..]).read_to_end(&mut body)?;
+        Ok(body)
+    }
+}
+
+/**
+ * 回放录制回应的行情服务器, 用于离线测试
+ *
+ * 按请求的字节完全匹配回应; 没有录制的握手包回应空包, 其他请求直接断开连接
+ */
+pub struct MockServer {
+    addr: SocketAddr,
+    stopped: Arc<AtomicBool>,
+}
+
+impl MockServer {
+    /// 监听 127.0.0.1 的随机端口
+    pub fn start(recordings: Vec<Recording>) -> Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let mut responses: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+        for setup in SETUP {
+            responses.insert(hex::decode(setup)?, vec![0; 16]);
+        }
+        for recording in recordings {
+            responses.insert(recording.request, recording.response);
+        }
+        let responses = Arc::new(responses);
+        let flag = stopped.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if flag.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    let responses = responses.clone();
+                    thread::spawn(move || serve(stream, &responses));
+                }
+            }
+        });
+        Ok(MockServer { addr, stopped })
+    }
+
+    /// 回放录制文件
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::start(load_recordings(path)?)
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // 唤醒阻塞在 accept 的线程
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+fn serve(mut stream: TcpStream, responses: &HashMap<Vec<u8>, Vec<u8>>) {
+    loop {
+        // 请求头 10 字节, 第 6 到 8 字节为包体长度
+        let mut pkg = vec![0u8; 10];
+        if stream.read_exact(&mut pkg).is_err() {
+            return;
+        }
+        let len = LittleEndian::read_u16(&pkg[6..8]) as usize;
+        pkg.resize(10 + len, 0);
+        if stream.read_exact(&mut pkg[10..]).is_err() {
+            return;
+        }
+        match responses.get(&pkg) {
+            Some(response) if stream.write_all(response).is_ok() => {}
+            _ => {
+                let _ = stream.shutdown(Shutdown::Both);
+                return;
+            }
+        }
+    }
+}
+
+fn market_id(market: Market) -> u16 {
+    match market {
+        Market::SZ => 0,
+        Market::SH => 1,
+    }
+}
+
+/// 市场编号和不带前缀的 6 位代码
+fn split_code(code: &str) -> Result<(u16, &str)> {
+    let market = code.where_is_from().ok_or(HqError::StockCodeNotExistInMarket)?;
+    let code = code.strip_prefix("sh").or_else(|| code.strip_prefix("sz")).unwrap_or(code);
+    if code.len() != 6 {
+        return Err(HqError::StockCodeNotExistInMarket);
+    }
+    Ok((market_id(market), code))
+}
+
+fn parse_minute_prices(body: &[u8], skip: usize) -> Result<Vec<MinutePrice>> {
+    let mut body = Body::new(body);
+    let num = body.u16()?;
+    body.skip(skip - 2)?;
+    let mut price = 0;
+    let mut prices = Vec::with_capacity(num as usize);
+    for _ in 0..num {
+        price += body.price()?;
+        body.price()?;
+        let volume = body.price()?;
+        prices.push(MinutePrice { price: price as i32, volume });
+    }
+    Ok(prices)
+}
+
+/// pytdx 的 get_volume 用整数运算拼出的浮点数, 结果与按 IEEE 754 单精度解释这 4 个字节相同
+fn get_volume(raw: u32) -> f64 {
+    f32::from_bits(raw) as f64
+}
+
+/// 解析回应包体的游标
+struct Body<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Body<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Body { buf, pos: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self.buf.get(self.pos..self.pos + len).ok_or(HqError::Truncated)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<()> {
+        self.bytes(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(LittleEndian::read_u16(self.bytes(2)?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(LittleEndian::read_u32(self.bytes(4)?))
+    }
+
+    fn f32(&mut self) -> Result<f32> {
+        Ok(LittleEndian::read_f32(self.bytes(4)?))
+    }
+
+    /// 变长整数: 首字节低 6 位为数值, 0x40 为负号, 0x80 表示后面还有字节, 之后每字节 7 位
+    fn price(&mut self) -> Result<i64> {
+        let byte = self.u8()? as i64;
+        let negative = byte & 0x40 != 0;
+        let mut value = byte & 0x3f;
+        let mut more = byte & 0x80 != 0;
+        let mut shift = 6;
+        while more {
+            let byte = self.u8()? as i64;
+            value += (byte & 0x7f) << shift;
+            more = byte & 0x80 != 0;
+            shift += 7;
+        }
+        Ok(if negative { -value } else { value })
+    }
+
+    /// 日期和 0 点至今的分钟数, 日线只有日期, 分钟数记为收盘 15:00
+    fn datetime(&mut self, minute: bool) -> Result<(i32, i16)> {
+        if minute {
+            let zip_day = self.u16()? as i32;
+            let minutes = self.u16()? as i16;
+            let year = (zip_day >> 11) + 2004;
+            let month = (zip_day % 2048) / 100;
+            let day = zip_day % 2048 % 100;
+            Ok((year * 10000 + month * 100 + day, minutes))
+        } else {
+            Ok((self.u32()? as i32, 15 * 60))
+        }
+    }
+
+    /// 时分 例如 935
+    fn time(&mut self) -> Result<i32> {
+        let minutes = self.u16()? as i32;
+        Ok(minutes / 60 * 100 + minutes % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{LittleEndian, WriteBytesExt};
+    use flate2::{write::ZlibEncoder, Compression};
+    use rust_decimal_macros::dec;
+    use std::io::Write;
+
+    use crate::data::Market;
+
+    use super::{DayPeriod, HqClient, HqError, MinutePeriod, MockServer, Recording};
+
+    /// 与 Body::price 相反的编码
+    fn price(out: &mut Vec<u8>, value: i64) {
+        let mut rest = value.abs();
+        let mut byte = (rest & 0x3f) as u8;
+        if value < 0 {
+            byte |= 0x40;
+        }
+        rest >>= 6;
+        loop {
+            if rest > 0 {
+                byte |= 0x80;
+            }
+            out.push(byte);
+            if rest == 0 {
+                break;
+            }
+            byte = (rest & 0x7f) as u8;
+            rest >>= 7;
+        }
+    }
+
+    /// 按服务器格式打包回应, `zip` 时压缩包体
+    fn response(body: &[u8], zip: bool) -> Vec<u8> {
+        let raw = if zip {
+            let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+            encoder.write_all(body).unwrap();
+            encoder.finish().unwrap()
+        } else {
+            body.to_vec()
+        };
+        let mut out = vec![0xb1, 0xcb, 0x74, 0x00, 0x0c, 0x01, 0x28, 0x00, 0x00, 0x2d, 0x05, 0x00];
+        out.write_u16::<LittleEndian>(raw.len() as u16).unwrap();
+        out.write_u16::<LittleEndian>(body.len() as u16).unwrap();
+        out.extend_from_slice(&raw);
+        out
+    }
+
+    fn request(header: &str, tail: &[u8]) -> Vec<u8> {
+        let mut pkg = hex::decode(header).unwrap();
+        pkg.extend_from_slice(tail);
+        pkg
+    }
+
+    #[test]
+    fn decode_responses() {
+        let mut recordings = vec![];
+
+        // 日线: 13.07 13.86 12.99 13.77 与 13.80 13.82 13.39 13.74
+        let mut tail = vec![1, 0];
+        tail.extend_from_slice(b"603339");
+        for value in [9u16, 1, 0, 2] {
+            tail.write_u16::<LittleEndian>(value).unwrap();
+        }
+        tail.extend_from_slice(&[0; 10]);
+        let mut body = vec![2, 0];
+        let mut pre_close = 0;
+        for (date, open, high, low, close, vol, amount) in
+            [(20221031u32, 13070i64, 13860, 12990, 13770, 103910.09f32, 141276320.0f32), (20221101, 13800, 13820, 13390, 13740, 85577.64, 116514200.0)]
+        {
+            body.write_u32::<LittleEndian>(date).unwrap();
+            price(&mut body, open - pre_close);
+            price(&mut body, close - open);
+            price(&mut body, high - open);
+            price(&mut body, low - open);
+            body.write_u32::<LittleEndian>(vol.to_bits()).unwrap();
+            body.write_u32::<LittleEndian>(amount.to_bits()).unwrap();
+            pre_close = close;
+        }
+        recordings.push(Recording { request: request("0c01086401011c001c002d05", &tail), response: response(&body, true) });
+
+        // 5 分钟线: 2022-11-01 09:35
+        let mut tail = vec![1, 0];
+        tail.extend_from_slice(b"603339");
+        for value in [0u16, 1, 0, 1] {
+            tail.write_u16::<LittleEndian>(value).unwrap();
+        }
+        tail.extend_from_slice(&[0; 10]);
+        let mut body = vec![1, 0];
+        body.write_u16::<LittleEndian>(((2022 - 2004) << 11) + 1101).unwrap();
+        body.write_u16::<LittleEndian>(9 * 60 + 35).unwrap();
+        for value in [13800, -30, 20, -40] {
+            price(&mut body, value);
+        }
+        body.write_u32::<LittleEndian>(1200.0f32.to_bits()).unwrap();
+        body.write_u32::<LittleEndian>(1656000.0f32.to_bits()).unwrap();
+        recordings.push(Recording { request: request("0c01086401011c001c002d05", &tail), response: response(&body, false) });
+
+        // 证券列表
+        let mut body = vec![1, 0];
+        body.extend_from_slice(b"603339");
+        body.write_u16::<LittleEndian>(100).unwrap();
+        let (name, ..) = encoding_rs::GBK.encode("四方科技");
+        body.extend_from_slice(&name);
+        body.extend_from_slice(&[0; 4]);
+        body.push(2);
+        body.write_u32::<LittleEndian>(13.74f32.to_bits()).unwrap();
+        body.extend_from_slice(&[0; 4]);
+        recordings.push(Recording { request: request("0c0118640101060006005004", &[1, 0, 0, 0]), response: response(&body, false) });
+
+        // 分笔成交: 14:56 13.74 买 12 手 3 笔, 14:57 13.75 卖 5 手 1 笔
+        let mut tail = vec![1, 0];
+        tail.extend_from_slice(b"603339");
+        tail.extend_from_slice(&[0, 0, 2, 0]);
+        let mut body = vec![2, 0];
+        for (minutes, diff, vol, num, direction) in [(896u16, 1374i64, 12, 3, 0), (897, 1, 5, 1, 1)] {
+            body.write_u16::<LittleEndian>(minutes).unwrap();
+            for value in [diff, vol, num, direction, 0] {
+                price(&mut body, value);
+            }
+        }
+        recordings.push(Recording { request: request("0c17080101010e000e00c50e", &tail), response: response(&body, false) });
+
+        // 除权除息: 20220608 10 派 1.5 送 3 配 2, 配股价 9 元
+        let mut tail = vec![1];
+        tail.extend_from_slice(b"603339");
+        let mut body = vec![0; 9];
+        body.write_u16::<LittleEndian>(1).unwrap();
+        body.push(1);
+        body.extend_from_slice(b"603339");
+        body.push(0);
+        body.write_u32::<LittleEndian>(20220608).unwrap();
+        body.push(1);
+        for value in [1.5f32, 9.0, 3.0, 2.0] {
+            body.write_f32::<LittleEndian>(value).unwrap();
+        }
+        recordings.push(Recording { request: request("0c1f187600010b000b000f000100", &tail), response: response(&body, false) });
+
+        let dir = std::env::temp_dir().join(format!("millions-hq-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("recordings.jsonl");
+        super::save_recordings(&path, &recordings).unwrap();
+        let server = MockServer::open(&path).unwrap();
+        let mut client = HqClient::connect_recording(server.addr()).unwrap();
+
+        let bars = client.bars("603339", DayPeriod::Day, 0, 2).unwrap();
+        assert_eq!(bars.len(), 2);
+        assert_eq!(bars[0].date, 20221031);
+        assert_eq!((bars[0].trade_data.open, bars[0].trade_data.high, bars[0].trade_data.low, bars[0].trade_data.close), (1307, 1386, 1299, 1377));
+        assert_eq!((bars[1].trade_data.open, bars[1].trade_data.close), (1380, 1374));
+        assert_eq!(bars[0].trade_data.volume, 10391009);
+        assert_eq!(bars[0].trade_data.amount, 141276320.0);
+
+        let minutes = client.minute_bars("sh603339", MinutePeriod::Min5, 0, 1).unwrap();
+        assert_eq!((minutes[0].date, minutes[0].time()), (20221101, 935));
+        assert_eq!((minutes[0].trade_data.open, minutes[0].trade_data.close, minutes[0].trade_data.high, minutes[0].trade_data.low), (1380, 1377, 1382, 1376));
+        assert_eq!(minutes[0].trade_data.volume, 120000);
+
+        let securities = client.security_list(Market::SH, 0).unwrap();
+        assert_eq!(securities[0].name, "四方科技");
+        assert!((securities[0].pre_close - 13.74).abs() < 1e-6);
+
+        let ticks = client.transactions("603339", 0, 2).unwrap();
+        assert_eq!(ticks.iter().map(|tick| (tick.time, tick.price, tick.volume, tick.count, tick.direction)).collect::<Vec<_>>(), vec![(1456, 1374, 12, 3, 0), (1457, 1375, 5, 1, 1)]);
+
+        let actions = client.corporate_actions("603339").unwrap();
+        assert_eq!((actions[0].date, actions[0].cash_per_share, actions[0].shares_per_share), (20220608, dec!(0.15), dec!(0.3)));
+        assert_eq!((actions[0].rights_price, actions[0].rights_per_share), (dec!(9), dec!(0.2)));
+
+        // 录制的内容可以原样回放
+        assert_eq!(client.take_recordings().len(), 3 + 5);
+
+        // 没有录制的请求会断开连接
+        assert!(matches!(client.security_count(Market::SZ), Err(HqError::IoError { .. })));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// 真实服务器的录制文件, 由 `capture` 生成
+    fn captured() -> std::path::PathBuf {
+        std::path::Path::new(&std::env::var("MILLIONS_TDX").unwrap()).join("hq").join("recordings.jsonl")
+    }
+
+    /// 录制真实服务器的回应, 需要网络, 服务器地址取环境变量 MILLIONS_HQ, 例如 119.147.212.81:7709
+    ///
+    /// `cargo test capture -- --ignored` 写入 `$MILLIONS_TDX/hq/recordings.jsonl`, 供 `replay_captured` 回放
+    #[test]
+    #[ignore]
+    fn capture() {
+        let mut client = HqClient::connect_recording(std::env::var("MILLIONS_HQ").unwrap()).unwrap();
+        client.bars("603339", DayPeriod::Day, 0, 800).unwrap();
+        client.minute_bars("603339", MinutePeriod::Min5, 0, 48).unwrap();
+        client.security_list(Market::SH, 0).unwrap();
+        client.corporate_actions("603339").unwrap();
+        std::fs::create_dir_all(captured().parent().unwrap()).unwrap();
+        super::save_recordings(captured(), &client.take_recordings()).unwrap();
+    }
+
+    /// 回放真实服务器的录制, 日线与本地 `.day` 文件重叠的交易日必须一致
+    ///
+    /// 仓库中没有真实服务器的录制, 先运行 `capture` 再用 `cargo test replay_captured -- --ignored` 运行, 没有录制文件时失败
+    #[test]
+    #[ignore]
+    fn replay_captured() {
+        assert!(captured().exists(), "{} not found, run `cargo test capture -- --ignored` first", captured().display());
+        let server = MockServer::open(captured()).unwrap();
+        let mut client = HqClient::connect(server.addr()).unwrap();
+
+        let bars = client.bars("603339", DayPeriod::Day, 0, 800).unwrap();
+        assert!(!bars.is_empty());
+        assert!(bars.windows(2).all(|pair| pair[0].date < pair[1].date));
+        for bar in bars.iter() {
+            let t = &bar.trade_data;
+            assert!(t.low <= t.open.min(t.close) && t.high >= t.open.max(t.close) && t.low > 0, "{:?}", bar);
+        }
+        let local = crate::data::DayTradeUnit::read_from(&crate::data::get_day_path_by_code("603339").unwrap(), 0).unwrap();
+        for bar in bars.iter() {
+            if let Some(expected) = local.iter().find(|expected| expected.date == bar.date) {
+                let (t, e) = (&bar.trade_data, &expected.trade_data);
+                assert_eq!((t.open, t.high, t.low, t.close, t.volume), (e.open, e.high, e.low, e.close, e.volume), "{}", bar.date);
+            }
+        }
+
+        let minutes = client.minute_bars("603339", MinutePeriod::Min5, 0, 48).unwrap();
+        assert!(minutes.iter().all(|bar| bar.trade_data.low <= bar.trade_data.high));
+        assert!(client.security_list(Market::SH, 0).unwrap().iter().all(|security| security.code.len() == 6));
+        client.corporate_actions("603339").unwrap();
+    }
+}
@@ -0,0 +1,1075 @@
+//! 通达信常用指标, `ta` 中没有或算法不同的部分
+//!
+//! 计算规则与通达信公式一致: 数据不足周期时 MA STD AVEDEV 没有值(NaN),
+//! SUM HHV LLV COUNT 按已有数据计算, EMA SMA 的第一个值取输入本身, 除数为0时结果为0。
+//! 每个指标的文档给出对应的通达信公式, 可以用 `formula` 模块计算同一公式核对。
+//! 默认参数下的数值与 `example/indicators` 中按同样公式独立实现的参考结果核对,
+//! 还没有与通达信客户端导出的数据核对过。
+
+use std::{collections::VecDeque, fmt};
+
+use ta::{errors::TaError, Close, High, Low, Next, Open, Period, Reset, Volume};
+
+use crate::statistics::{avedev, std_dev};
+
+/// 滚动窗口, `n` 为0时保留全部数据
+#[derive(Debug, Clone)]
+struct Window {
+    n: usize,
+    buf: VecDeque<f64>,
+}
+
+impl Window {
+    fn new(n: usize) -> Self {
+        Window { n, buf: VecDeque::new() }
+    }
+
+    fn push(&mut self, x: f64) {
+        self.buf.push_back(x);
+        if self.n > 0 && self.buf.len() > self.n {
+            self.buf.pop_front();
+        }
+    }
+
+    fn full(&self) -> bool {
+        self.n == 0 || self.buf.len() == self.n
+    }
+
+    fn values(&self) -> Vec<f64> {
+        self.buf.iter().cloned().collect()
+    }
+}
+
+/// MA(X,N)
+#[derive(Debug, Clone)]
+struct Ma(Window);
+
+impl Ma {
+    fn new(n: usize) -> Self {
+        Ma(Window::new(n))
+    }
+
+    fn next(&mut self, x: f64) -> f64 {
+        self.0.push(x);
+        if self.0.full() {
+            self.0.buf.iter().sum::<f64>() / self.0.buf.len() as f64
+        } else {
+            f64::NAN
+        }
+    }
+}
+
+/// SUM(X,N), 跳过没有值的位置
+#[derive(Debug, Clone)]
+struct Sum(Window);
+
+impl Sum {
+    fn new(n: usize) -> Self {
+        Sum(Window::new(n))
+    }
+
+    fn next(&mut self, x: f64) -> f64 {
+        self.0.push(x);
+        let valid: Vec<f64> = self.0.buf.iter().cloned().filter(|v| !v.is_nan()).collect();
+        if valid.is_empty() {
+            f64::NAN
+        } else {
+            valid.iter().sum()
+        }
+    }
+}
+
+/// HHV(X,N) 或 LLV(X,N)
+#[derive(Debug, Clone)]
+struct Extreme {
+    window: Window,
+    highest: bool,
+}
+
+impl Extreme {
+    fn hhv(n: usize) -> Self {
+        Extreme { window: Window::new(n), highest: true }
+    }
+
+    fn llv(n: usize) -> Self {
+        Extreme { window: Window::new(n), highest: false }
+    }
+
+    fn next(&mut self, x: f64) -> f64 {
+        self.window.push(x);
+        let f = if self.highest { f64::max } else { f64::min };
+        self.window.buf.iter().cloned().fold(f64::NAN, f)
+    }
+}
+
+/// COUNT(X,N)
+#[derive(Debug, Clone)]
+struct Count(Window);
+
+impl Count {
+    fn next(&mut self, x: f64) -> f64 {
+        self.0.push(x);
+        self.0.buf.iter().filter(|v| **v != 0.0 && !v.is_nan()).count() as f64
+    }
+}
+
+/// STD(X,N) 或 AVEDEV(X,N)
+#[derive(Debug, Clone)]
+struct Deviation {
+    window: Window,
+    f: fn(&[f64]) -> f64,
+}
+
+impl Deviation {
+    fn next(&mut self, x: f64) -> f64 {
+        self.window.push(x);
+        if self.window.full() {
+            (self.f)(&self.window.values())
+        } else {
+            f64::NAN
+        }
+    }
+}
+
+/// Y = weight * X + (1 - weight) * Y', 即 EMA(X,N) 和 SMA(X,N,M)
+#[derive(Debug, Clone)]
+struct Smooth {
+    weight: f64,
+    prev: f64,
+}
+
+impl Smooth {
+    fn ema(n: usize) -> Self {
+        Smooth { weight: 2.0 / (n as f64 + 1.0), prev: f64::NAN }
+    }
+
+    fn sma(n: usize, m: usize) -> Self {
+        Smooth { weight: m as f64 / n as f64, prev: f64::NAN }
+    }
+
+    fn next(&mut self, x: f64) -> f64 {
+        if !x.is_nan() {
+            self.prev = if self.prev.is_nan() { x } else { self.weight * x + (1.0 - self.weight) * self.prev };
+        }
+        self.prev
+    }
+}
+
+/// REF(X,N)
+#[derive(Debug, Clone)]
+struct Ref(Window);
+
+impl Ref {
+    fn new(n: usize) -> Self {
+        Ref(Window::new(n + 1))
+    }
+
+    fn next(&mut self, x: f64) -> f64 {
+        self.0.push(x);
+        if self.0.full() {
+            self.0.buf[0]
+        } else {
+            f64::NAN
+        }
+    }
+}
+
+fn div(a: f64, b: f64) -> f64 {
+    if a.is_nan() || b.is_nan() {
+        f64::NAN
+    } else if b == 0.0 {
+        0.0
+    } else {
+        a / b
+    }
+}
+
+/// 通达信 MAX(A,B), 任一方没有值时没有值
+fn max(a: f64, b: f64) -> f64 {
+    if a.is_nan() || b.is_nan() {
+        f64::NAN
+    } else {
+        a.max(b)
+    }
+}
+
+/// IF(COND,A,B)
+fn iff(cond: f64, a: f64, b: f64) -> f64 {
+    if cond.is_nan() {
+        f64::NAN
+    } else if cond != 0.0 {
+        a
+    } else {
+        b
+    }
+}
+
+/// 比较, 任一方没有值时没有值
+fn compare(a: f64, b: f64, f: fn(&f64, &f64) -> bool) -> f64 {
+    if a.is_nan() || b.is_nan() {
+        f64::NAN
+    } else if f(&a, &b) {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+fn check(params: &[usize]) -> Result<(), TaError> {
+    if params.contains(&0) {
+        Err(TaError::InvalidParameter)
+    } else {
+        Ok(())
+    }
+}
+
+/// 实现 `Period` `Reset` `Display`, 重置时按原参数重新创建
+macro_rules! indicator {
+    ($name:ident, $period:ident, $($param:ident),+) => {
+        impl Period for $name {
+            fn period(&self) -> usize {
+                self.$period
+            }
+        }
+
+        impl Reset for $name {
+            fn reset(&mut self) {
+                *self = $name::new($(self.$param),+).unwrap();
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let params: Vec<String> = vec![$(self.$param.to_string()),+];
+                write!(f, "{}({})", stringify!($name), params.join(", "))
+            }
+        }
+    };
+}
+
+/// 随机指标
+///
+/// ```text
+/// RSV:=(C-LLV(L,N))/(HHV(H,N)-LLV(L,N))*100;
+/// K:SMA(RSV,M1,1); D:SMA(K,M2,1); J:3*K-2*D;
+/// ```
+#[derive(Debug, Clone)]
+pub struct KDJ {
+    n: usize,
+    m1: usize,
+    m2: usize,
+    hhv: Extreme,
+    llv: Extreme,
+    k: Smooth,
+    d: Smooth,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KDJOutput {
+    pub k: f64,
+    pub d: f64,
+    pub j: f64,
+}
+
+impl KDJ {
+    pub fn new(n: usize, m1: usize, m2: usize) -> Result<Self, TaError> {
+        check(&[n, m1, m2])?;
+        Ok(KDJ {
+            n,
+            m1,
+            m2,
+            hhv: Extreme::hhv(n),
+            llv: Extreme::llv(n),
+            k: Smooth::sma(m1, 1),
+            d: Smooth::sma(m2, 1),
+        })
+    }
+}
+
+impl<T: High + Low + Close> Next<&T> for KDJ {
+    type Output = KDJOutput;
+
+    fn next(&mut self, input: &T) -> Self::Output {
+        let (hhv, llv) = (self.hhv.next(input.high()), self.llv.next(input.low()));
+        let rsv = div(input.close() - llv, hhv - llv) * 100.0;
+        let k = self.k.next(rsv);
+        let d = self.d.next(k);
+        KDJOutput { k, d, j: 3.0 * k - 2.0 * d }
+    }
+}
+
+impl Default for KDJ {
+    fn default() -> Self {
+        KDJ::new(9, 3, 3).unwrap()
+    }
+}
+
+indicator!(KDJ, n, n, m1, m2);
+
+/// 布林线
+///
+/// ```text
+/// BOLL:MA(C,M); UB:BOLL+P*STD(C,M); LB:BOLL-P*STD(C,M);
+/// ```
+#[derive(Debug, Clone)]
+pub struct BOLL {
+    m: usize,
+    p: usize,
+    ma: Ma,
+    std: Deviation,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BOLLOutput {
+    pub boll: f64,
+    pub ub: f64,
+    pub lb: f64,
+}
+
+impl BOLL {
+    pub fn new(m: usize, p: usize) -> Result<Self, TaError> {
+        check(&[m, p])?;
+        Ok(BOLL { m, p, ma: Ma::new(m), std: Deviation { window: Window::new(m), f: std_dev } })
+    }
+}
+
+impl<T: Close> Next<&T> for BOLL {
+    type Output = BOLLOutput;
+
+    fn next(&mut self, input: &T) -> Self::Output {
+        let boll = self.ma.next(input.close());
+        let std = self.std.next(input.close());
+        BOLLOutput { boll, ub: boll + self.p as f64 * std, lb: boll - self.p as f64 * std }
+    }
+}
+
+impl Default for BOLL {
+    fn default() -> Self {
+        BOLL::new(20, 2).unwrap()
+    }
+}
+
+indicator!(BOLL, m, m, p);
+
+/// 趋向指标
+///
+/// ```text
+/// MTR:=SUM(MAX(MAX(H-L,ABS(H-REF(C,1))),ABS(REF(C,1)-L)),N);
+/// HD:=H-REF(H,1); LD:=REF(L,1)-L;
+/// DMP:=SUM(IF(HD>0 AND HD>LD,HD,0),N); DMM:=SUM(IF(LD>0 AND LD>HD,LD,0),N);
+/// PDI:DMP*100/MTR; MDI:DMM*100/MTR;
+/// ADX:MA(ABS(MDI-PDI)/(MDI+PDI)*100,M); ADXR:(ADX+REF(ADX,M))/2;
+/// ```
+#[derive(Debug, Clone)]
+pub struct DMI {
+    n: usize,
+    m: usize,
+    prev: Option<(f64, f64, f64)>,
+    mtr: Sum,
+    dmp: Sum,
+    dmm: Sum,
+    adx: Ma,
+    adx_ref: Ref,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DMIOutput {
+    pub pdi: f64,
+    pub mdi: f64,
+    pub adx: f64,
+    pub adxr: f64,
+}
+
+impl DMI {
+    pub fn new(n: usize, m: usize) -> Result<Self, TaError> {
+        check(&[n, m])?;
+        Ok(DMI {
+            n,
+            m,
+            prev: None,
+            mtr: Sum::new(n),
+            dmp: Sum::new(n),
+            dmm: Sum::new(n),
+            adx: Ma::new(m),
+            adx_ref: Ref::new(m),
+        })
+    }
+}
+
+impl<T: High + Low + Close> Next<&T> for DMI {
+    type Output = DMIOutput;
+
+    fn next(&mut self, input: &T) -> Self::Output {
+        let (h, l, c) = (input.high(), input.low(), input.close());
+        let (ref_h, ref_l, ref_c) = self.prev.unwrap_or((f64::NAN, f64::NAN, f64::NAN));
+        self.prev = Some((h, l, c));
+        let mtr = self.mtr.next(max(max(h - l, (h - ref_c).abs()), (ref_c - l).abs()));
+        let (hd, ld) = (h - ref_h, ref_l - l);
+        let and = |a: f64, b: f64| if a.is_nan() || b.is_nan() { f64::NAN } else { ((a != 0.0) && (b != 0.0)) as i32 as f64 };
+        let dmp = self.dmp.next(iff(and(compare(hd, 0.0, f64::gt), compare(hd, ld, f64::gt)), hd, 0.0));
+        let dmm = self.dmm.next(iff(and(compare(ld, 0.0, f64::gt), compare(ld, hd, f64::gt)), ld, 0.0));
+        let pdi = div(dmp * 100.0, mtr);
+        let mdi = div(dmm * 100.0, mtr);
+        let adx = self.adx.next(div((mdi - pdi).abs(), mdi + pdi) * 100.0);
+        let adxr = (adx + self.adx_ref.next(adx)) / 2.0;
+        DMIOutput { pdi, mdi, adx, adxr }
+    }
+}
+
+impl Default for DMI {
+    fn default() -> Self {
+        DMI::new(14, 6).unwrap()
+    }
+}
+
+indicator!(DMI, n, n, m);
+
+/// 顺势指标
+///
+/// ```text
+/// TYP:=(H+L+C)/3; CCI:(TYP-MA(TYP,N))/(0.015*AVEDEV(TYP,N));
+/// ```
+#[derive(Debug, Clone)]
+pub struct CCI {
+    n: usize,
+    ma: Ma,
+    avedev: Deviation,
+}
+
+impl CCI {
+    pub fn new(n: usize) -> Result<Self, TaError> {
+        check(&[n])?;
+        Ok(CCI { n, ma: Ma::new(n), avedev: Deviation { window: Window::new(n), f: avedev } })
+    }
+}
+
+impl<T: High + Low + Close> Next<&T> for CCI {
+    type Output = f64;
+
+    fn next(&mut self, input: &T) -> Self::Output {
+        let typ = (input.high() + input.low() + input.close()) / 3.0;
+        let ma = self.ma.next(typ);
+        div(typ - ma, 0.015 * self.avedev.next(typ))
+    }
+}
+
+impl Default for CCI {
+    fn default() -> Self {
+        CCI::new(14).unwrap()
+    }
+}
+
+indicator!(CCI, n, n);
+
+/// 乖离率
+///
+/// ```text
+/// BIAS1:(C-MA(C,L1))/MA(C,L1)*100; BIAS2 BIAS3 同理
+/// ```
+#[derive(Debug, Clone)]
+pub struct BIAS {
+    l1: usize,
+    l2: usize,
+    l3: usize,
+    ma: [Ma; 3],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BIASOutput {
+    pub bias1: f64,
+    pub bias2: f64,
+    pub bias3: f64,
+}
+
+impl BIAS {
+    pub fn new(l1: usize, l2: usize, l3: usize) -> Result<Self, TaError> {
+        check(&[l1, l2, l3])?;
+        Ok(BIAS { l1, l2, l3, ma: [Ma::new(l1), Ma::new(l2), Ma::new(l3)] })
+    }
+}
+
+impl<T: Close> Next<&T> for BIAS {
+    type Output = BIASOutput;
+
+    fn next(&mut self, input: &T) -> Self::Output {
+        let c = input.close();
+        let [bias1, bias2, bias3] = [0, 1, 2].map(|i| {
+            let ma = self.ma[i].next(c);
+            div(c - ma, ma) * 100.0
+        });
+        BIASOutput { bias1, bias2, bias3 }
+    }
+}
+
+impl Default for BIAS {
+    fn default() -> Self {
+        BIAS::new(6, 12, 24).unwrap()
+    }
+}
+
+indicator!(BIAS, l1, l1, l2, l3);
+
+/// 心理线
+///
+/// ```text
+/// PSY:COUNT(C>REF(C,1),N)/N*100; PSYMA:MA(PSY,M);
+/// ```
+#[derive(Debug, Clone)]
+pub struct PSY {
+    n: usize,
+    m: usize,
+    prev: f64,
+    count: Count,
+    ma: Ma,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PSYOutput {
+    pub psy: f64,
+    pub psyma: f64,
+}
+
+impl PSY {
+    pub fn new(n: usize, m: usize) -> Result<Self, TaError> {
+        check(&[n, m])?;
+        Ok(PSY { n, m, prev: f64::NAN, count: Count(Window::new(n)), ma: Ma::new(m) })
+    }
+}
+
+impl<T: Close> Next<&T> for PSY {
+    type Output = PSYOutput;
+
+    fn next(&mut self, input: &T) -> Self::Output {
+        let c = input.close();
+        let up = compare(c, self.prev, f64::gt);
+        self.prev = c;
+        let psy = div(self.count.next(up), self.n as f64) * 100.0;
+        PSYOutput { psy, psyma: self.ma.next(psy) }
+    }
+}
+
+impl Default for PSY {
+    fn default() -> Self {
+        PSY::new(12, 6).unwrap()
+    }
+}
+
+indicator!(PSY, n, n, m);
+
+/// 能量潮
+///
+/// ```text
+/// VA:=IF(C>REF(C,1),V,-V); OBV:SUM(IF(C=REF(C,1),0,VA),0); MAOBV:MA(OBV,M);
+/// ```
+#[derive(Debug, Clone)]
+pub struct OBV {
+    m: usize,
+    prev: f64,
+    obv: Sum,
+    ma: Ma,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OBVOutput {
+    pub obv: f64,
+    pub maobv: f64,
+}
+
+impl OBV {
+    pub fn new(m: usize) -> Result<Self, TaError> {
+        check(&[m])?;
+        Ok(OBV { m, prev: f64::NAN, obv: Sum::new(0), ma: Ma::new(m) })
+    }
+}
+
+impl<T: Close + Volume> Next<&T> for OBV {
+    type Output = OBVOutput;
+
+    fn next(&mut self, input: &T) -> Self::Output {
+        let (c, v) = (input.close(), input.volume());
+        let va = iff(compare(c, self.prev, f64::gt), v, -v);
+        let obv = self.obv.next(iff(compare(c, self.prev, f64::eq), 0.0, va));
+        self.prev = c;
+        OBVOutput { obv, maobv: self.ma.next(obv) }
+    }
+}
+
+impl Default for OBV {
+    fn default() -> Self {
+        OBV::new(30).unwrap()
+    }
+}
+
+indicator!(OBV, m, m);
+
+/// 成交量变异率
+///
+/// ```text
+/// TH:=SUM(IF(C>REF(C,1),V,0),N); TL:=SUM(IF(C<REF(C,1),V,0),N); TQ:=SUM(IF(C=REF(C,1),V,0),N);
+/// VR:100*(TH*2+TQ)/(TL*2+TQ); MAVR:MA(VR,M);
+/// ```
+#[derive(Debug, Clone)]
+pub struct VR {
+    n: usize,
+    m: usize,
+    prev: f64,
+    th: Sum,
+    tl: Sum,
+    tq: Sum,
+    ma: Ma,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VROutput {
+    pub vr: f64,
+    pub mavr: f64,
+}
+
+impl VR {
+    pub fn new(n: usize, m: usize) -> Result<Self, TaError> {
+        check(&[n, m])?;
+        Ok(VR { n, m, prev: f64::NAN, th: Sum::new(n), tl: Sum::new(n), tq: Sum::new(n), ma: Ma::new(m) })
+    }
+}
+
+impl<T: Close + Volume> Next<&T> for VR {
+    type Output = VROutput;
+
+    fn next(&mut self, input: &T) -> Self::Output {
+        let (c, v) = (input.close(), input.volume());
+        let th = self.th.next(iff(compare(c, self.prev, f64::gt), v, 0.0));
+        let tl = self.tl.next(iff(compare(c, self.prev, f64::lt), v, 0.0));
+        let tq = self.tq.next(iff(compare(c, self.prev, f64::eq), v, 0.0));
+        self.prev = c;
+        let vr = div(100.0 * (th * 2.0 + tq), tl * 2.0 + tq);
+        VROutput { vr, mavr: self.ma.next(vr) }
+    }
+}
+
+impl Default for VR {
+    fn default() -> Self {
+        VR::new(26, 6).unwrap()
+    }
+}
+
+indicator!(VR, n, n, m);
+
+/// 威廉指标
+///
+/// ```text
+/// WR1:100*(HHV(H,N)-C)/(HHV(H,N)-LLV(L,N)); WR2:100*(HHV(H,N1)-C)/(HHV(H,N1)-LLV(L,N1));
+/// ```
+#[derive(Debug, Clone)]
+pub struct WR {
+    n: usize,
+    n1: usize,
+    hhv: [Extreme; 2],
+    llv: [Extreme; 2],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WROutput {
+    pub wr1: f64,
+    pub wr2: f64,
+}
+
+impl WR {
+    pub fn new(n: usize, n1: usize) -> Result<Self, TaError> {
+        check(&[n, n1])?;
+        Ok(WR { n, n1, hhv: [Extreme::hhv(n), Extreme::hhv(n1)], llv: [Extreme::llv(n), Extreme::llv(n1)] })
+    }
+}
+
+impl<T: High + Low + Close> Next<&T> for WR {
+    type Output = WROutput;
+
+    fn next(&mut self, input: &T) -> Self::Output {
+        let [wr1, wr2] = [0, 1].map(|i| {
+            let hhv = self.hhv[i].next(input.high());
+            let llv = self.llv[i].next(input.low());
+            div(100.0 * (hhv - input.close()), hhv - llv)
+        });
+        WROutput { wr1, wr2 }
+    }
+}
+
+impl Default for WR {
+    fn default() -> Self {
+        WR::new(10, 6).unwrap()
+    }
+}
+
+indicator!(WR, n, n, n1);
+
+/// 情绪指标
+///
+/// ```text
+/// BR:SUM(MAX(0,H-REF(C,1)),N)/SUM(MAX(0,REF(C,1)-L),N)*100;
+/// AR:SUM(H-O,N)/SUM(O-L,N)*100;
+/// ```
+#[derive(Debug, Clone)]
+pub struct BRAR {
+    n: usize,
+    prev: f64,
+    br: [Sum; 2],
+    ar: [Sum; 2],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BRAROutput {
+    pub br: f64,
+    pub ar: f64,
+}
+
+impl BRAR {
+    pub fn new(n: usize) -> Result<Self, TaError> {
+        check(&[n])?;
+        Ok(BRAR { n, prev: f64::NAN, br: [Sum::new(n), Sum::new(n)], ar: [Sum::new(n), Sum::new(n)] })
+    }
+}
+
+impl<T: Open + High + Low + Close> Next<&T> for BRAR {
+    type Output = BRAROutput;
+
+    fn next(&mut self, input: &T) -> Self::Output {
+        let (o, h, l) = (input.open(), input.high(), input.low());
+        let br = div(self.br[0].next(max(0.0, h - self.prev)), self.br[1].next(max(0.0, self.prev - l))) * 100.0;
+        let ar = div(self.ar[0].next(h - o), self.ar[1].next(o - l)) * 100.0;
+        self.prev = input.close();
+        BRAROutput { br, ar }
+    }
+}
+
+impl Default for BRAR {
+    fn default() -> Self {
+        BRAR::new(26).unwrap()
+    }
+}
+
+indicator!(BRAR, n, n);
+
+/// 带状能量线
+///
+/// ```text
+/// MID:=REF(H+L,1)/2;
+/// CR:SUM(MAX(0,H-MID),N)/SUM(MAX(0,MID-L),N)*100;
+/// MA1:REF(MA(CR,M1),M1/2.5+1); MA2 MA3 MA4 同理
+/// ```
+#[derive(Debug, Clone)]
+pub struct CR {
+    n: usize,
+    m1: usize,
+    m2: usize,
+    m3: usize,
+    m4: usize,
+    prev: f64,
+    up: Sum,
+    down: Sum,
+    ma: [(Ma, Ref); 4],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CROutput {
+    pub cr: f64,
+    pub ma1: f64,
+    pub ma2: f64,
+    pub ma3: f64,
+    pub ma4: f64,
+}
+
+impl CR {
+    pub fn new(n: usize, m1: usize, m2: usize, m3: usize, m4: usize) -> Result<Self, TaError> {
+        check(&[n, m1, m2, m3, m4])?;
+        // 通达信对 REF 的周期取整
+        let ma = [m1, m2, m3, m4].map(|m| (Ma::new(m), Ref::new((m as f64 / 2.5 + 1.0) as usize)));
+        Ok(CR { n, m1, m2, m3, m4, prev: f64::NAN, up: Sum::new(n), down: Sum::new(n), ma })
+    }
+}
+
+impl<T: High + Low> Next<&T> for CR {
+    type Output = CROutput;
+
+    fn next(&mut self, input: &T) -> Self::Output {
+        let (h, l) = (input.high(), input.low());
+        let mid = div(self.prev, 2.0);
+        self.prev = h + l;
+        let cr = div(self.up.next(max(0.0, h - mid)), self.down.next(max(0.0, mid - l))) * 100.0;
+        let [ma1, ma2, ma3, ma4] = [0, 1, 2, 3].map(|i| {
+            let (ma, delay) = &mut self.ma[i];
+            delay.next(ma.next(cr))
+        });
+        CROutput { cr, ma1, ma2, ma3, ma4 }
+    }
+}
+
+impl Default for CR {
+    fn default() -> Self {
+        CR::new(26, 10, 20, 40, 62).unwrap()
+    }
+}
+
+indicator!(CR, n, n, m1, m2, m3, m4);
+
+/// 三重指数平滑平均线
+///
+/// ```text
+/// MTR:=EMA(EMA(EMA(C,N),N),N); TRIX:(MTR-REF(MTR,1))/REF(MTR,1)*100; MATRIX:MA(TRIX,M);
+/// ```
+#[derive(Debug, Clone)]
+pub struct TRIX {
+    n: usize,
+    m: usize,
+    ema: [Smooth; 3],
+    prev: Ref,
+    ma: Ma,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TRIXOutput {
+    pub trix: f64,
+    pub matrix: f64,
+}
+
+impl TRIX {
+    pub fn new(n: usize, m: usize) -> Result<Self, TaError> {
+        check(&[n, m])?;
+        Ok(TRIX { n, m, ema: [Smooth::ema(n), Smooth::ema(n), Smooth::ema(n)], prev: Ref::new(1), ma: Ma::new(m) })
+    }
+}
+
+impl<T: Close> Next<&T> for TRIX {
+    type Output = TRIXOutput;
+
+    fn next(&mut self, input: &T) -> Self::Output {
+        let mtr = self.ema.iter_mut().fold(input.close(), |x, ema| ema.next(x));
+        let prev = self.prev.next(mtr);
+        let trix = div(mtr - prev, prev) * 100.0;
+        TRIXOutput { trix, matrix: self.ma.next(trix) }
+    }
+}
+
+impl Default for TRIX {
+    fn default() -> Self {
+        TRIX::new(12, 9).unwrap()
+    }
+}
+
+indicator!(TRIX, n, n, m);
+
+/// 平行线差
+///
+/// ```text
+/// DIF:MA(C,N1)-MA(C,N2); DIFMA:MA(DIF,M);
+/// ```
+#[derive(Debug, Clone)]
+pub struct DMA {
+    n1: usize,
+    n2: usize,
+    m: usize,
+    short: Ma,
+    long: Ma,
+    ma: Ma,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DMAOutput {
+    pub dif: f64,
+    pub difma: f64,
+}
+
+impl DMA {
+    pub fn new(n1: usize, n2: usize, m: usize) -> Result<Self, TaError> {
+        check(&[n1, n2, m])?;
+        Ok(DMA { n1, n2, m, short: Ma::new(n1), long: Ma::new(n2), ma: Ma::new(m) })
+    }
+}
+
+impl<T: Close> Next<&T> for DMA {
+    type Output = DMAOutput;
+
+    fn next(&mut self, input: &T) -> Self::Output {
+        let dif = self.short.next(input.close()) - self.long.next(input.close());
+        DMAOutput { dif, difma: self.ma.next(dif) }
+    }
+}
+
+impl Default for DMA {
+    fn default() -> Self {
+        DMA::new(10, 50, 10).unwrap()
+    }
+}
+
+indicator!(DMA, n2, n1, n2, m);
+
+/// 指数平均线
+///
+/// ```text
+/// EXP1:EMA(C,M1); EXP2:EMA(C,M2);
+/// ```
+#[derive(Debug, Clone)]
+pub struct EXPMA {
+    m1: usize,
+    m2: usize,
+    ema: [Smooth; 2],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EXPMAOutput {
+    pub exp1: f64,
+    pub exp2: f64,
+}
+
+impl EXPMA {
+    pub fn new(m1: usize, m2: usize) -> Result<Self, TaError> {
+        check(&[m1, m2])?;
+        Ok(EXPMA { m1, m2, ema: [Smooth::ema(m1), Smooth::ema(m2)] })
+    }
+}
+
+impl<T: Close> Next<&T> for EXPMA {
+    type Output = EXPMAOutput;
+
+    fn next(&mut self, input: &T) -> Self::Output {
+        let [exp1, exp2] = [0, 1].map(|i| self.ema[i].next(input.close()));
+        EXPMAOutput { exp1, exp2 }
+    }
+}
+
+impl Default for EXPMA {
+    fn default() -> Self {
+        EXPMA::new(12, 50).unwrap()
+    }
+}
+
+indicator!(EXPMA, m2, m1, m2);
+
+/// 平滑异同平均线, 柱线为 (DIF-DEA)*2, 与 `ta` 的 MACD 不同
+///
+/// ```text
+/// DIF:EMA(C,SHORT)-EMA(C,LONG); DEA:EMA(DIF,MID); MACD:(DIF-DEA)*2;
+/// ```
+#[derive(Debug, Clone)]
+pub struct MACD {
+    short: usize,
+    long: usize,
+    mid: usize,
+    fast: Smooth,
+    slow: Smooth,
+    signal: Smooth,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MACDOutput {
+    pub dif: f64,
+    pub dea: f64,
+    pub macd: f64,
+}
+
+impl MACD {
+    pub fn new(short: usize, long: usize, mid: usize) -> Result<Self, TaError> {
+        check(&[short, long, mid])?;
+        Ok(MACD { short, long, mid, fast: Smooth::ema(short), slow: Smooth::ema(long), signal: Smooth::ema(mid) })
+    }
+}
+
+impl<T: Close> Next<&T> for MACD {
+    type Output = MACDOutput;
+
+    fn next(&mut self, input: &T) -> Self::Output {
+        let dif = self.fast.next(input.close()) - self.slow.next(input.close());
+        let dea = self.signal.next(dif);
+        MACDOutput { dif, dea, macd: (dif - dea) * 2.0 }
+    }
+}
+
+impl Default for MACD {
+    fn default() -> Self {
+        MACD::new(12, 26, 9).unwrap()
+    }
+}
+
+indicator!(MACD, long, short, long, mid);
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, env, fs, path::Path};
+
+    use ta::{Next, Reset};
+
+    use crate::data::{DayTradeUnit, StockTradeData, TradeDataSource};
+
+    use super::{BIAS, BOLL, BRAR, CCI, CR, DMA, DMI, EXPMA, KDJ, MACD, OBV, PSY, TRIX, VR, WR};
+
+    /// 指标在每根K线上的输出, 按 `指标.输出线` 命名
+    fn run<I, O>(columns: &mut HashMap<String, Vec<f64>>, mut indicator: I, names: &[&str], fields: fn(&O) -> Vec<f64>, bars: &[DayTradeUnit])
+    where
+        I: for<'a> Next<&'a DayTradeUnit, Output = O>,
+    {
+        for bar in bars {
+            for (name, value) in names.iter().zip(fields(&indicator.next(bar))) {
+                columns.entry(name.to_string()).or_default().push(value);
+            }
+        }
+    }
+
+    /// 与 example/indicators 中的指标数值逐根K线比较, 数值保留三位小数
+    /// 参考数据由 `example/indicators/reference.py` 计算, 不是通达信客户端导出的
+    #[test]
+    fn matches_reference_impl() {
+        let bars: Vec<DayTradeUnit> = StockTradeData {}.day_duration("603339", None, None).unwrap().collect();
+        let mut columns = HashMap::new();
+        let c = &mut columns;
+        run(c, KDJ::default(), &["KDJ.K", "KDJ.D", "KDJ.J"], |o| vec![o.k, o.d, o.j], &bars);
+        run(c, BOLL::default(), &["BOLL.BOLL", "BOLL.UB", "BOLL.LB"], |o| vec![o.boll, o.ub, o.lb], &bars);
+        run(c, DMI::default(), &["DMI.PDI", "DMI.MDI", "DMI.ADX", "DMI.ADXR"], |o| vec![o.pdi, o.mdi, o.adx, o.adxr], &bars);
+        run(c, CCI::default(), &["CCI.CCI"], |o| vec![*o], &bars);
+        run(c, BIAS::default(), &["BIAS.BIAS1", "BIAS.BIAS2", "BIAS.BIAS3"], |o| vec![o.bias1, o.bias2, o.bias3], &bars);
+        run(c, PSY::default(), &["PSY.PSY", "PSY.PSYMA"], |o| vec![o.psy, o.psyma], &bars);
+        run(c, OBV::default(), &["OBV.OBV", "OBV.MAOBV"], |o| vec![o.obv, o.maobv], &bars);
+        run(c, VR::default(), &["VR.VR", "VR.MAVR"], |o| vec![o.vr, o.mavr], &bars);
+        run(c, WR::default(), &["WR.WR1", "WR.WR2"], |o| vec![o.wr1, o.wr2], &bars);
+        run(c, BRAR::default(), &["BRAR.BR", "BRAR.AR"], |o| vec![o.br, o.ar], &bars);
+        run(c, CR::default(), &["CR.CR", "CR.MA1", "CR.MA2", "CR.MA3", "CR.MA4"], |o| vec![o.cr, o.ma1, o.ma2, o.ma3, o.ma4], &bars);
+        run(c, TRIX::default(), &["TRIX.TRIX", "TRIX.MATRIX"], |o| vec![o.trix, o.matrix], &bars);
+        run(c, DMA::default(), &["DMA.DIF", "DMA.DIFMA"], |o| vec![o.dif, o.difma], &bars);
+        run(c, EXPMA::default(), &["EXPMA.EXP1", "EXPMA.EXP2"], |o| vec![o.exp1, o.exp2], &bars);
+        run(c, MACD::default(), &["MACD.DIF", "MACD.DEA", "MACD.MACD"], |o| vec![o.dif, o.dea, o.macd], &bars);
+
+        let path = Path::new(&env::var("MILLIONS_TDX").unwrap()).join("indicators/sh603339.txt");
+        let text = fs::read_to_string(path).unwrap();
+        let mut lines = text.lines().filter(|line| !line.starts_with('#'));
+        let header: Vec<&str> = lines.next().unwrap().split('\t').skip(1).collect();
+        assert_eq!(header.len(), columns.len());
+        let mut rows = 0;
+        for line in lines {
+            let mut cells = line.split('\t');
+            let date: i32 = cells.next().unwrap().parse().unwrap();
+            let i = bars.iter().position(|bar| bar.date == date).unwrap();
+            for (name, cell) in header.iter().zip(cells) {
+                let value = columns[*name][i];
+                let expected: f64 = cell.parse().unwrap();
+                assert!((value - expected).abs() <= 5e-4 + 1e-9 * expected.abs(), "{} at {}: {} != {}", name, date, value, expected);
+            }
+            rows += 1;
+        }
+        assert_eq!(rows, 61);
+    }
+
+    #[test]
+    fn macd() {
+        let bars: Vec<DayTradeUnit> = StockTradeData {}.day_duration("603339", None, None).unwrap().collect();
+        let mut macd = MACD::new(2, 3, 2).unwrap();
+        // 第一根K线 EMA 取收盘价, DIF 为 0
+        let first = macd.next(&bars[0]);
+        assert_eq!((first.dif, first.dea, first.macd), (0.0, 0.0, 0.0));
+        let (c0, c1) = (bars[0].trade_data.close as f64 / 100.0, bars[1].trade_data.close as f64 / 100.0);
+        let second = macd.next(&bars[1]);
+        let dif = (2.0 / 3.0 * c1 + (1.0 - 2.0 / 3.0) * c0) - (0.5 * c1 + 0.5 * c0);
+        assert!((second.dif - dif).abs() < 1e-9);
+        assert!((second.macd - (dif - dif * 2.0 / 3.0) * 2.0).abs() < 1e-9);
+
+        macd.reset();
+        assert_eq!(macd.next(&bars[0]), first);
+        assert_eq!(macd.to_string(), "MACD(2, 3, 2)");
+    }
+}
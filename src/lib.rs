@@ -1,6 +1,26 @@
-#![feature(int_roundings)]
-#![feature(provide_any)]
 pub mod data;
 pub mod strategy;
 pub mod backtest;
+pub mod event;
 pub mod statistics;
+pub mod result;
+pub mod report;
+pub mod serise;
+pub mod formula;
+pub mod indicator;
+pub mod screener;
+pub mod factor;
+pub mod optimizer;
+pub mod robustness;
+pub mod strategies;
+pub mod config;
+pub mod adjust;
+pub mod paper;
+pub mod hq;
+pub mod store;
+pub mod audit;
+pub mod tick;
+#[cfg(feature = "script")]
+pub mod script;
+#[cfg(feature = "python")]
+mod python;
@@ -1,35 +1,571 @@
+use std::{env, error::Error, ffi::OsStr, fs, path::PathBuf, time::Duration};
 
-fn main() {
-    // let parent = env::current_dir().unwrap();
-    // let data_path = Path::new(&parent).join("example").join("gpcw20190630.dat");
-    // let mut file = File::open(data_path).unwrap();
-    // let mut buff = [0u8; HeaderSize];
-    // file.read(&mut buff[..]).unwrap();
-    // let header = Header::deserializer(&buff);
-    // println!("{:?}", &header);
-
-    // //https://github.com/rainx/pytdx/pull/150/files
-    // file.seek(std::io::SeekFrom::Start(HeaderSize as u64));
-    // println!(
-    //     "seek: {:?}, header size: {:?}, align size: {:?}",
-    //     std::io::SeekFrom::Start(size_of::<Header>().try_into().unwrap()),
-    //     size_of::<Header>(),
-    //     align_of::<Header>()
-    // );
-    // let mut stockBuff = [0u8; StockSize];
-    // file.read(&mut stockBuff);
-    // let stockHeader = StockItem::deserializer(&stockBuff);
-    // // A6A0
-    // println!("{:?}", &stockHeader);
-    // let cols = header.report_size / 4;
-    // println!("data cols count: {:?}", cols);
-    // let col:u64 = 94;
-    // file.seek(std::io::SeekFrom::Start(u64::from(stockHeader.offset) + col * 4));
-    // let mut data_col = [0u8; StockFinanceColValueSize];
-    // // let mut info_data = [0f32; 580];
-    // file.read(&mut data_col);
-    // let final_val = StockFinanceValue::deserializer(&data_col);
-    // println!("data cols count: {:?}", final_val);
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde_json::{json, Map, Value};
+use txd_data::{
+    audit::{load_gbbq, Audit},
+    backtest::{BackTest, PortfolioStrategy},
+    config::RunConfig,
+    data::{
+        date_to_time, get_day_path_by_code, intern, DayTradeUnitIter, FinanceData, MinuteTradeUnitIter, StockCode,
+        StockTradeData, TradeDataSource, WhereIsFrom,
+    },
+    formula::{Formula, FormulaStrategy},
+    optimizer::Params,
+    paper::{PaperTrader, TdxFileFeed},
+    store::LocalStore,
+    report::Report,
+    result::BacktestResult,
+    screener::{Condition, Score, Screener},
+    strategies,
+};
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+/// 通达信数据工具和回测
+#[derive(Parser)]
+#[command(name = "millions", version)]
+struct Cli {
+    /// 通达信数据目录(vipdoc), 默认取环境变量 MILLIONS_TDX
+    #[arg(long, global = true)]
+    root: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// 解码 .day .lc1 .lc5 日线分钟线或 gpcw*.dat 财务数据
+    Dump {
+        file: PathBuf,
+        #[arg(long, value_enum, default_value_t = Format::Table)]
+        format: Format,
+        /// 只输出前 N 行
+        #[arg(long)]
+        limit: Option<usize>,
+        /// 财务数据输出的列数
+        #[arg(long, default_value_t = 10)]
+        columns: usize,
+    },
+    /// 股票所属市场, 日线的日期范围, K线数和停牌缺口
+    Info {
+        code: String,
+        /// 相邻两根K线间隔超过的自然日数视为缺口, 默认跳过春节国庆长假
+        #[arg(long, default_value_t = 15)]
+        gap_days: i64,
+    },
+    /// 用内置策略或公式文件回测
+    Backtest {
+        /// 策略名称 ma-cross macd boll, formula 配合 --formula 使用, script 配合 --script 使用
+        strategy: String,
+        #[arg(required = true)]
+        codes: Vec<String>,
+        /// 开始日期 例如 20220101
+        #[arg(long)]
+        from: String,
+        #[arg(long)]
+        to: String,
+        /// 策略参数 例如 --param fast=5 --param slow=20
+        #[arg(long = "param", value_parser = parse_param)]
+        params: Vec<(String, f64)>,
+        /// 通达信公式文件, 取 BUY SELL 输出线
+        #[arg(long)]
+        formula: Option<PathBuf>,
+        /// Rhai 策略脚本, 参数通过 ctx.param 读取
+        #[arg(long)]
+        script: Option<PathBuf>,
+        /// 比较基准 例如 sh000300
+        #[arg(long)]
+        benchmark: Option<String>,
+        /// 从 store 命令导入的本地存储读取K线, 代替通达信目录
+        #[arg(long)]
+        store: Option<PathBuf>,
+        #[command(flatten)]
+        output: Output,
+    },
+    /// 按 TOML 配置文件回测
+    Run {
+        config: PathBuf,
+        /// 从 store 命令导入的本地存储读取K线, 代替通达信目录
+        #[arg(long)]
+        store: Option<PathBuf>,
+        #[command(flatten)]
+        output: Output,
+    },
+    /// 按 TOML 配置文件模拟交易, 监视通达信日线文件, 从配置的 from 开始处理新K线
+    Paper {
+        config: PathBuf,
+        /// 状态目录, 重启后从这里恢复账户
+        #[arg(long)]
+        state: PathBuf,
+        /// 检查文件的间隔(秒)
+        #[arg(long, default_value_t = 60)]
+        poll: u64,
+        /// 等待停牌股票的时间(秒)
+        #[arg(long, default_value_t = 600)]
+        settle: u64,
+        /// 超过这段时间(秒)没有新K线时退出, 默认一直运行
+        #[arg(long)]
+        idle: Option<u64>,
+        /// 第一个交易日之前载入的历史K线数量
+        #[arg(long, default_value_t = 0)]
+        warm_up: usize,
+    },
+    /// 检查全部日线文件的缺失交易日, 零成交量, 重复乱序日期, 价格异常, 异常涨跌和过期文件
+    Audit {
+        /// pytdx 导出的除权除息 csv, 用于排除除权日的涨跌
+        #[arg(long)]
+        gbbq: Option<PathBuf>,
+        /// 作为交易日历的指数
+        #[arg(long, default_value = "sh000001")]
+        calendar: String,
+        /// 涨跌幅阈值, 默认按代码判断涨跌停幅度: 主板 10%, 创业板和科创板 20%, 北交所 30%
+        #[arg(long)]
+        jump: Option<f64>,
+        /// 最后一根K线允许落后的交易日数
+        #[arg(long, default_value_t = 0)]
+        stale: usize,
+        /// json 报告文件, 默认输出到标准输出
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// 把通达信日线和分钟线增量导入本地存储, 不指定股票时导入全部
+    Store {
+        /// 存储目录
+        dir: PathBuf,
+        codes: Vec<String>,
+    },
+    /// 全市场选股
+    Screen {
+        /// 选股日期 例如 20221101
+        #[arg(long)]
+        date: i32,
+        /// 公式条件, 第一条输出线非 0 入选, 可以重复
+        #[arg(long)]
+        formula: Vec<String>,
+        /// 收盘价区间 MIN:MAX, 省略一侧表示不限
+        #[arg(long, value_parser = parse_range)]
+        close: Option<(f64, f64)>,
+        /// 涨跌幅(%)区间 MIN:MAX
+        #[arg(long, value_parser = parse_range)]
+        change: Option<(f64, f64)>,
+        /// 量比 DAYS:MIN
+        #[arg(long, value_parser = parse_volume_ratio)]
+        volume_ratio: Option<(usize, f64)>,
+        /// 财务数据报告期 例如 20220930, 读取 cw/gpcw20220930.dat
+        #[arg(long)]
+        report: Option<i32>,
+        /// 财务数据条件 COLUMN:MIN:MAX, 可以重复
+        #[arg(long, value_parser = parse_finance)]
+        finance: Vec<(usize, f64, f64)>,
+        /// 排序依据 change, volume:DAYS, finance:COLUMN 或 formula:公式
+        #[arg(long)]
+        score: Option<String>,
+        /// 只输出得分最高的 N 只
+        #[arg(long)]
+        top: Option<usize>,
+        #[arg(long, value_enum, default_value_t = Format::Table)]
+        format: Format,
+    },
+}
+
+/// 回测结果的输出
+#[derive(Args)]
+struct Output {
+    /// 回测结果写入 JSON 文件
+    #[arg(long)]
+    json: Option<PathBuf>,
+    /// 回测结果写入 CSV 目录
+    #[arg(long)]
+    csv: Option<PathBuf>,
+    /// HTML 报告
+    #[arg(long)]
+    report: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Table,
+    Csv,
+    Json,
+}
+
+fn parse_param(arg: &str) -> std::result::Result<(String, f64), String> {
+    let (name, value) = arg.split_once('=').ok_or("expect NAME=VALUE")?;
+    Ok((name.to_string(), value.parse().map_err(|_| format!("invalid number {}", value))?))
+}
+
+fn parse_bound(value: &str, default: f64) -> std::result::Result<f64, String> {
+    if value.is_empty() {
+        return Ok(default);
+    }
+    value.parse().map_err(|_| format!("invalid number {}", value))
+}
+
+fn parse_range(arg: &str) -> std::result::Result<(f64, f64), String> {
+    let (min, max) = arg.split_once(':').ok_or("expect MIN:MAX")?;
+    Ok((parse_bound(min, f64::MIN)?, parse_bound(max, f64::MAX)?))
+}
+
+fn parse_volume_ratio(arg: &str) -> std::result::Result<(usize, f64), String> {
+    let (days, min) = arg.split_once(':').ok_or("expect DAYS:MIN")?;
+    Ok((parse_days(days)?, parse_bound(min, f64::MIN)?))
+}
+
+fn parse_days(value: &str) -> std::result::Result<usize, String> {
+    match value.parse() {
+        Ok(days) if days > 0 => Ok(days),
+        _ => Err(format!("invalid days {}, expect a positive integer", value)),
+    }
+}
+
+fn parse_finance(arg: &str) -> std::result::Result<(usize, f64, f64), String> {
+    let (column, range) = arg.split_once(':').ok_or("expect COLUMN:MIN:MAX")?;
+    let (min, max) = parse_range(range)?;
+    Ok((column.parse().map_err(|_| format!("invalid column {}", column))?, min, max))
+}
+
+/// 按格式输出表格
+fn print(format: Format, headers: &[&str], rows: &[Vec<Value>]) {
+    let text = |value: &Value| match value {
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    };
+    match format {
+        Format::Table => {
+            let mut widths: Vec<usize> = headers.iter().map(|header| header.len()).collect();
+            for row in rows {
+                for (width, value) in widths.iter_mut().zip(row) {
+                    *width = (*width).max(text(value).len());
+                }
+            }
+            let line = |cells: Vec<String>| {
+                cells.iter().zip(widths.iter()).map(|(cell, width)| format!("{:>width$}", cell, width = width)).collect::<Vec<_>>().join("  ")
+            };
+            println!("{}", line(headers.iter().map(|header| header.to_string()).collect()));
+            for row in rows {
+                println!("{}", line(row.iter().map(text).collect()));
+            }
+        }
+        Format::Csv => {
+            println!("{}", headers.join(","));
+            for row in rows {
+                println!("{}", row.iter().map(text).collect::<Vec<_>>().join(","));
+            }
+        }
+        Format::Json => {
+            let rows: Vec<Value> = rows
+                .iter()
+                .map(|row| Value::Object(headers.iter().map(|header| header.to_string()).zip(row.iter().cloned()).collect::<Map<_, _>>()))
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&rows).unwrap_or_default());
+        }
+    }
+}
+
+fn price(value: i32) -> Value {
+    json!(value as f64 / 100.0)
+}
+
+fn dump(file: PathBuf, format: Format, limit: Option<usize>, columns: usize) -> Result<()> {
+    let limit = limit.unwrap_or(usize::MAX);
+    let extension = file.extension().and_then(OsStr::to_str).unwrap_or("").to_lowercase();
+    match extension.as_str() {
+        "day" => {
+            let rows: Vec<Vec<Value>> = DayTradeUnitIter::new(&file)?
+                .take(limit)
+                .map(|bar| {
+                    let t = bar.trade_data;
+                    vec![json!(bar.date), price(t.open), price(t.high), price(t.low), price(t.close), json!(t.volume), json!(t.amount)]
+                })
+                .collect();
+            print(format, &["date", "open", "high", "low", "close", "volume", "amount"], &rows);
+        }
+        "lc1" | "lc5" => {
+            let rows: Vec<Vec<Value>> = MinuteTradeUnitIter::new(&file)?
+                .take(limit)
+                .map(|bar| {
+                    let time = format!("{:02}:{:02}", bar.time() / 100, bar.time() % 100);
+                    let t = bar.trade_data;
+                    vec![json!(bar.date), json!(time), price(t.open), price(t.high), price(t.low), price(t.close), json!(t.volume), json!(t.amount)]
+                })
+                .collect();
+            print(format, &["date", "time", "open", "high", "low", "close", "volume", "amount"], &rows);
+        }
+        "dat" => {
+            let finance = FinanceData::open(&file)?;
+            let mut headers = vec!["code".to_string()];
+            headers.extend((1..=columns).map(|column| column.to_string()));
+            let rows: Vec<Vec<Value>> = finance
+                .rows()
+                .into_iter()
+                .take(limit)
+                .map(|(code, values)| {
+                    let mut row = vec![json!(code)];
+                    row.extend(values.iter().take(columns).map(|value| json!(value)));
+                    row
+                })
+                .collect();
+            // 只在表格输出时混在标准输出里, 避免破坏 csv 和 json
+            match format {
+                Format::Table => println!("report date: {}", finance.report_date()),
+                _ => eprintln!("report date: {}", finance.report_date()),
+            }
+            print(format, &headers.iter().map(|header| header.as_str()).collect::<Vec<_>>(), &rows);
+        }
+        _ => return Err(format!("unsupported file {:?}", file).into()),
+    }
+    Ok(())
+}
+
+fn info(code: String, gap_days: i64) -> Result<()> {
+    let code = intern(&code);
+    let market = code.where_is_from().ok_or("stock not exist in market")?;
+    let bars: Vec<_> = StockTradeData {}.day_duration(code, None, None)?.collect();
+    println!("{:<12}{}", "code", code);
+    println!("{:<12}{:?}", "market", market);
+    println!("{:<12}{}", "file", get_day_path_by_code(code)?.display());
+    println!("{:<12}{}", "bars", bars.len());
+    if let (Some(first), Some(last)) = (bars.first(), bars.last()) {
+        println!("{:<12}{} - {}", "range", first.date, last.date);
+    }
+    for pair in bars.windows(2) {
+        if let (Some(from), Some(to)) = (date_to_time(pair[0].date), date_to_time(pair[1].date)) {
+            let days = (to - from).num_days();
+            if days > gap_days {
+                println!("{:<12}{} - {} ({} days)", "gap", pair[0].date, pair[1].date, days);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn backtest(
+    name: String,
+    codes: Vec<String>,
+    from: String,
+    to: String,
+    params: Vec<(String, f64)>,
+    formula: Option<PathBuf>,
+    script: Option<PathBuf>,
+    benchmark: Option<String>,
+    store: Option<PathBuf>,
+    output: Output,
+) -> Result<()> {
+    let params: Params = params.into_iter().collect();
+    let mut strategy: Box<dyn PortfolioStrategy> = match (name.as_str(), formula) {
+        ("formula", Some(path)) => {
+            let percent = params.get("percent").copied().unwrap_or(90.0).clamp(0.0, 100.0) as u8;
+            let mut formula = Formula::parse(&fs::read_to_string(path)?)?;
+            for (param, value) in params.iter().filter(|(param, _)| *param != "percent") {
+                formula = formula.param(param, *value);
+            }
+            Box::new(FormulaStrategy::with_signals(formula, "BUY", "SELL", percent)?)
+        }
+        ("formula", None) => return Err("strategy formula requires --formula".into()),
+        ("script", _) => script_strategy(script, params.clone())?,
+        (name, _) => Box::new(
+            strategies::by_name(name, &params)
+                .ok_or_else(|| format!("unknown strategy {}, expect one of {}", name, strategies::NAMES.join(" ")))?,
+        ),
+    };
+    let codes: Vec<StockCode> = codes.into_iter().map(|code| intern(&code)).collect();
+    let mut back_test = BackTest::with_universe(codes.clone(), &mut *strategy)?;
+    back_test.set_params(params);
+    if let Some(benchmark) = benchmark {
+        back_test.set_benchmark(intern(&benchmark));
+    }
+    if let Some(dir) = store {
+        back_test.set_source(LocalStore::open(dir)?);
+    }
+    let result = back_test.run(&from, &to)?;
+    write(&result, &back_test, &format!("{} {}", name, codes.join(" ")), output)
+}
+
+#[cfg(feature = "script")]
+fn script_strategy(script: Option<PathBuf>, params: Params) -> Result<Box<dyn PortfolioStrategy>> {
+    let path = script.ok_or("strategy script requires --script")?;
+    Ok(Box::new(txd_data::script::ScriptStrategy::open(path)?.params(params)))
+}
+
+#[cfg(not(feature = "script"))]
+fn script_strategy(_script: Option<PathBuf>, _params: Params) -> Result<Box<dyn PortfolioStrategy>> {
+    Err("built without the script feature".into())
+}
+
+fn run_config(path: PathBuf, store: Option<PathBuf>, output: Output) -> Result<()> {
+    let config = RunConfig::open(&path)?;
+    let mut strategy = config.build_strategy()?;
+    let mut back_test = config.build(&mut strategy)?;
+    if let Some(dir) = store {
+        back_test.set_source(LocalStore::open(dir)?);
+    }
+    let result = back_test.run(&config.from.to_string(), &config.to.to_string())?;
+    write(&result, &back_test, &path.display().to_string(), output)
+}
 
+fn paper(config: PathBuf, state: PathBuf, poll: u64, settle: u64, idle: Option<u64>, warm_up: usize) -> Result<()> {
+    let config = RunConfig::open(config)?;
+    let mut strategy = config.build_strategy()?;
+    let back_test = config.build(&mut strategy)?;
+    let mut feed = TdxFileFeed::new(config.stock_codes(), config.from)
+        .poll_interval(Duration::from_secs(poll))
+        .settle(Duration::from_secs(settle));
+    if let Some(idle) = idle {
+        feed = feed.idle_timeout(Duration::from_secs(idle));
+    }
+    let mut trader = PaperTrader::new(back_test, feed, state).warm_up(warm_up);
+    let restored = trader.restore()?;
+    if let Some(date) = trader.last_date() {
+        println!("restored {} days, last {}", restored, date);
+    }
+    while trader.step()? {
+        let result = trader.result();
+        if let Some(snapshot) = result.equity_curve.last() {
+            println!("{} equity {} cash {} trades {}", snapshot.date, snapshot.equity, snapshot.cash, result.trades.len());
+        }
+    }
+    Ok(())
 }
 
+fn audit(gbbq: Option<PathBuf>, calendar: String, jump: Option<f64>, stale: usize, output: Option<PathBuf>) -> Result<()> {
+    let mut audit = Audit::new().calendar(intern(&calendar)).stale_days(stale);
+    if let Some(jump) = jump {
+        audit = audit.jump(jump);
+    }
+    if let Some(path) = gbbq {
+        audit = audit.actions(load_gbbq(path)?);
+    }
+    let report = audit.run(&PathBuf::from(env::var("MILLIONS_TDX")?))?;
+    match output {
+        Some(path) => {
+            fs::write(&path, report.to_json()?)?;
+            println!("{:<16}{} ({} days, latest {})", "calendar", report.calendar, report.trading_days, report.latest);
+            println!("{:<16}{}", "files", report.files.len());
+            for (kind, count) in report.summary.iter() {
+                println!("{:<16}{}", kind, count);
+            }
+        }
+        None => println!("{}", report.to_json()?),
+    }
+    Ok(())
+}
+
+fn store(dir: PathBuf, codes: Vec<String>) -> Result<()> {
+    let source = StockTradeData {};
+    let mut store = LocalStore::open(dir)?;
+    let refreshed = if codes.is_empty() {
+        store.refresh_all(&source)?
+    } else {
+        codes.into_iter().map(|code| intern(&code)).map(|code| Ok((code, store.refresh(&source, code)?))).collect::<Result<Vec<_>>>()?
+    };
+    println!("{:<12}{:>8}{:>8}{:>8}", "code", "day", "1min", "5min");
+    for (code, refresh) in refreshed {
+        println!("{:<12}{:>8}{:>8}{:>8}", code, refresh.day, refresh.minute1, refresh.minute5);
+    }
+    Ok(())
+}
+
+/// 打印统计, 按需写入 JSON CSV 和 HTML 报告
+fn write(result: &BacktestResult, back_test: &BackTest, title: &str, output: Output) -> Result<()> {
+    println!("{}", result.statistics);
+    if let Some(benchmark) = result.benchmark.as_ref() {
+        println!("{}", benchmark);
+    }
+    if let Some(path) = output.json {
+        result.write_json(path)?;
+    }
+    if let Some(dir) = output.csv {
+        result.write_csv(dir)?;
+    }
+    if let Some(path) = output.report {
+        let mut report = Report::new(result).title(title);
+        for code in result.config.codes.iter() {
+            report = report.bars(code, back_test.history(code));
+        }
+        report.write(path)?;
+    }
+    Ok(())
+}
+
+fn score(arg: &str) -> Result<Score> {
+    let (kind, value) = arg.split_once(':').unwrap_or((arg, ""));
+    Ok(match kind {
+        "change" => Score::ChangePercent,
+        "volume" => Score::VolumeRatio(parse_days(value)?),
+        "finance" => Score::Finance(value.parse()?),
+        "formula" => {
+            let formula = Formula::parse(value)?;
+            let line = formula.outputs().first().map(|line| line.to_string()).unwrap_or_default();
+            Score::Formula(formula, line)
+        }
+        _ => return Err(format!("unknown score {}", arg).into()),
+    })
+}
+
+fn main() {
+    if let Err(e) = run(Cli::parse()) {
+        eprintln!("error: {}", e);
+        let mut source = e.source();
+        while let Some(e) = source {
+            eprintln!("  caused by: {}", e);
+            source = e.source();
+        }
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> Result<()> {
+    if let Some(root) = cli.root {
+        env::set_var("MILLIONS_TDX", root);
+    }
+    match cli.command {
+        Command::Dump { file, format, limit, columns } => dump(file, format, limit, columns),
+        Command::Info { code, gap_days } => info(code, gap_days),
+        Command::Backtest { strategy, codes, from, to, params, formula, script, benchmark, store, output } => {
+            backtest(strategy, codes, from, to, params, formula, script, benchmark, store, output)
+        }
+        Command::Run { config, store, output } => run_config(config, store, output),
+        Command::Paper { config, state, poll, settle, idle, warm_up } => paper(config, state, poll, settle, idle, warm_up),
+        Command::Store { dir, codes } => store(dir, codes),
+        Command::Audit { gbbq, calendar, jump, stale, output } => audit(gbbq, calendar, jump, stale, output),
+        Command::Screen { date, formula, close, change, volume_ratio, report, finance, score: by, top, format } => {
+            let source = StockTradeData {};
+            let mut screener = Screener::new();
+            if let Some((min, max)) = close {
+                screener = screener.condition(Condition::Close { min, max });
+            }
+            if let Some((min, max)) = change {
+                screener = screener.condition(Condition::ChangePercent { min, max });
+            }
+            if let Some((days, min)) = volume_ratio {
+                screener = screener.condition(Condition::VolumeRatio { days, min });
+            }
+            for source in formula.iter() {
+                screener = screener.condition(Condition::formula(source)?);
+            }
+            for (column, min, max) in finance {
+                screener = screener.condition(Condition::Finance { column, min, max });
+            }
+            if let Some(report) = report {
+                screener = screener.finance(source.finance(report)?);
+            }
+            if let Some(by) = by {
+                screener = screener.score(score(&by)?);
+            }
+            let screening = screener.screen(&source, date)?;
+            for (code, e) in screening.skipped.iter() {
+                eprintln!("skipped {}: {}", code, e);
+            }
+            let rows: Vec<Vec<Value>> = screening
+                .results
+                .iter()
+                .take(top.unwrap_or(usize::MAX))
+                .map(|result| vec![json!(result.code), json!(result.score), json!(result.close)])
+                .collect();
+            print(format, &["code", "score", "close"], &rows);
+            Ok(())
+        }
+    }
+}
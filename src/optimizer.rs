@@ -0,0 +1,451 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicUsize, Ordering as AtomicOrdering},
+        OnceLock,
+    },
+    thread,
+};
+
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{
+    backtest::{BackTest, BackTestError, PortfolioStrategy},
+    data::{DataSourceError, DayTradeUnit, StockCode, StockTradeData, TradeDataSource},
+    result::BacktestResult,
+    statistics::{mean, Statistics},
+};
+
+#[derive(Debug, Error)]
+pub enum OptimizerError {
+    #[error("backtest error")]
+    BackTestError {
+        #[from]
+        source: BackTestError,
+    },
+    #[error("data source error")]
+    DataSourceError {
+        #[from]
+        source: DataSourceError,
+    },
+    #[error("not enough trading days")]
+    NotEnoughData,
+}
+
+pub type Result<T, E = OptimizerError> = std::result::Result<T, E>;
+
+/// 一组参数, 参数名 -> 参数值
+pub type Params = BTreeMap<String, f64>;
+
+/// 参数的取值范围
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParamRange {
+    pub name: String,
+    pub values: Vec<f64>,
+}
+
+impl ParamRange {
+    /// [start, end] 区间内按 step 取值, 例如 `ParamRange::new("fast", 3.0, 10.0, 1.0)`
+    pub fn new(name: &str, start: f64, end: f64, step: f64) -> Self {
+        let mut values = vec![];
+        if step > 0.0 {
+            let mut i = 0;
+            // 用乘法避免累加误差
+            while start + step * i as f64 <= end + step * 1e-9 {
+                values.push(start + step * i as f64);
+                i += 1;
+            }
+        }
+        ParamRange { name: name.to_string(), values }
+    }
+
+    /// 指定的取值
+    pub fn values(name: &str, values: &[f64]) -> Self {
+        ParamRange { name: name.to_string(), values: values.to_vec() }
+    }
+}
+
+/// 参数优化的排序指标, 统一为越大越好
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Metric {
+    TotalReturn,
+    AnnualizedReturn,
+    Sharpe,
+    Sortino,
+    Calmar,
+    /// 取负的最大回撤, 回撤越小越好
+    MaxDrawdown,
+    ProfitFactor,
+    WinRate,
+}
+
+impl Metric {
+    pub fn score(&self, statistics: &Statistics) -> f64 {
+        match self {
+            Metric::TotalReturn => statistics.total_return,
+            Metric::AnnualizedReturn => statistics.annualized_return,
+            Metric::Sharpe => statistics.sharpe,
+            Metric::Sortino => statistics.sortino,
+            Metric::Calmar => statistics.calmar,
+            Metric::MaxDrawdown => -statistics.max_drawdown,
+            Metric::ProfitFactor => statistics.profit_factor,
+            Metric::WinRate => statistics.win_rate,
+        }
+    }
+}
+
+/// 一组参数的回测结果
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Trial {
+    pub params: Params,
+    /// 排序指标的值
+    pub score: f64,
+    pub statistics: Statistics,
+}
+
+/// 前进分析的一个窗口, 在训练区间选出最优参数, 再用测试区间检验
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WalkForwardWindow {
+    /// 训练区间 [from, to]
+    pub train: (i32, i32),
+    /// 测试区间 [from, to]
+    pub test: (i32, i32),
+    /// 训练区间的最优参数
+    pub params: Params,
+    /// 训练区间(样本内)的指标
+    pub in_sample: f64,
+    /// 测试区间(样本外)的指标
+    pub out_of_sample: f64,
+    /// 测试区间的统计
+    pub statistics: Statistics,
+}
+
+/// 前进分析的结果
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WalkForward {
+    pub windows: Vec<WalkForwardWindow>,
+}
+
+impl WalkForward {
+    /// 样本外指标均值 / 样本内指标均值, 明显小于 1 说明参数过拟合
+    pub fn efficiency(&self) -> f64 {
+        let in_sample = mean(&self.windows.iter().map(|w| w.in_sample).collect::<Vec<f64>>());
+        let out_of_sample = mean(&self.windows.iter().map(|w| w.out_of_sample).collect::<Vec<f64>>());
+        if in_sample == 0.0 {
+            return 0.0;
+        }
+        out_of_sample / in_sample
+    }
+}
+
+/// 参数优化, 对参数网格中的每一组参数创建策略并行回测
+///
+/// ```ignore
+/// let formula = "BUY:CROSS(MA(C,FAST),MA(C,SLOW)); SELL:CROSS(MA(C,SLOW),MA(C,FAST));";
+/// let optimizer = Optimizer::new(vec!["603339"], |params: &Params| {
+///     let formula = Formula::parse(formula).unwrap().param("FAST", params["fast"]).param("SLOW", params["slow"]);
+///     FormulaStrategy::new(formula).unwrap()
+/// })
+/// .param(ParamRange::new("fast", 3.0, 10.0, 1.0))
+/// .param(ParamRange::new("slow", 20.0, 60.0, 10.0))
+/// .metric(Metric::Sharpe);
+/// let trials = optimizer.run(20200101, 20221231)?;
+/// ```
+pub struct Optimizer<F, D = StockTradeData> {
+    codes: Vec<StockCode>,
+    factory: F,
+    ranges: Vec<ParamRange>,
+    metric: Metric,
+    threads: usize,
+    /** 每段回测之前预先载入的K线数量 */
+    warm_up: usize,
+    source: D,
+    /** 股票池的全部日线, 第一次回测时读取, 之后各参数组合和各区间共用 */
+    histories: OnceLock<HashMap<StockCode, Vec<DayTradeUnit>>>,
+}
+
+impl<F, S> Optimizer<F>
+where
+    F: Fn(&Params) -> S + Sync,
+    S: PortfolioStrategy,
+{
+    /// 默认按夏普比率排序, 线程数为 CPU 核数, 预先载入 120 根K线, 数据源为 `MILLIONS_TDX` 下的通达信数据
+    pub fn new(codes: Vec<StockCode>, factory: F) -> Self {
+        Optimizer {
+            codes,
+            factory,
+            ranges: vec![],
+            metric: Metric::Sharpe,
+            threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            warm_up: 120,
+            source: StockTradeData {},
+            histories: OnceLock::new(),
+        }
+    }
+}
+
+impl<F, S, D> Optimizer<F, D>
+where
+    F: Fn(&Params) -> S + Sync,
+    S: PortfolioStrategy,
+    D: TradeDataSource + Sync,
+{
+    /// K线数据源, 例如 [`crate::store::LocalStore`]
+    pub fn source<T: TradeDataSource + Sync>(self, source: T) -> Optimizer<F, T> {
+        Optimizer {
+            codes: self.codes,
+            factory: self.factory,
+            ranges: self.ranges,
+            metric: self.metric,
+            threads: self.threads,
+            warm_up: self.warm_up,
+            source,
+            histories: OnceLock::new(),
+        }
+    }
+
+    pub fn param(mut self, range: ParamRange) -> Self {
+        self.ranges.push(range);
+        self
+    }
+
+    pub fn metric(mut self, metric: Metric) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// 每段回测之前预先载入区间开始前的 `bars` 根K线, 只用来计算指标, 不交易也不计入统计
+    ///
+    /// 否则较短的测试区间里长周期的指标还没有值, 策略发不出信号
+    pub fn warm_up(mut self, bars: usize) -> Self {
+        self.warm_up = bars;
+        self
+    }
+
+    /// 全部参数组合
+    pub fn grid(&self) -> Vec<Params> {
+        let mut grid = vec![Params::new()];
+        for range in self.ranges.iter() {
+            grid = grid
+                .into_iter()
+                .flat_map(|params| {
+                    range.values.iter().map(move |value| {
+                        let mut params = params.clone();
+                        params.insert(range.name.clone(), *value);
+                        params
+                    })
+                })
+                .collect();
+        }
+        grid
+    }
+
+    /// 回测 [from, to] 区间内的全部参数组合, 按指标从大到小排列
+    pub fn run(&self, from: i32, to: i32) -> Result<Vec<Trial>> {
+        let mut trials = self.run_all(&self.grid(), from, to)?;
+        trials.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
+        Ok(trials)
+    }
+
+    /// 用一组参数回测
+    pub fn trial(&self, params: &Params, from: i32, to: i32) -> Result<Trial> {
+        let result = self.backtest(params, from, to)?;
+        Ok(Trial { params: params.clone(), score: self.metric.score(&result.statistics), statistics: result.statistics })
+    }
+
+    /// 用一组参数回测 [from, to] 区间, 返回完整的回测结果
+    ///
+    /// K线取自内存中的 [`Optimizer::histories`], 逐日推进回测, 不再读取数据源
+    pub fn backtest(&self, params: &Params, from: i32, to: i32) -> Result<BacktestResult> {
+        let histories = self.histories()?;
+        let strategy = (self.factory)(params);
+        let mut back_test = BackTest::with_universe(self.codes.clone(), strategy)?;
+        back_test.set_params(params.clone());
+        let mut days: BTreeMap<i32, Vec<(StockCode, DayTradeUnit)>> = BTreeMap::new();
+        for code in self.codes.iter() {
+            let history = &histories[code];
+            let start = history.partition_point(|bar| bar.date < from);
+            back_test.preload(code, &history[start.saturating_sub(self.warm_up)..start]);
+            for bar in history[start..].iter().take_while(|bar| bar.date <= to) {
+                days.entry(bar.date).or_default().push((*code, bar.clone()));
+            }
+        }
+        for (date, bars) in days {
+            back_test.step(date, bars)?;
+        }
+        Ok(back_test.result_between(from, to))
+    }
+
+    /// 股票池的全部日线, 按日期排列, 只从数据源读取一次
+    pub fn histories(&self) -> Result<&HashMap<StockCode, Vec<DayTradeUnit>>> {
+        if let Some(histories) = self.histories.get() {
+            return Ok(histories);
+        }
+        let mut histories = HashMap::new();
+        for &code in self.codes.iter() {
+            let mut history: Vec<DayTradeUnit> = self.source.day_duration(code, None, None)?.collect();
+            history.sort_by_key(|bar| bar.date);
+            histories.insert(code, history);
+        }
+        // 多个线程同时读取时只保留第一份
+        Ok(self.histories.get_or_init(|| histories))
+    }
+
+    /// 样本外检验, 前 `train_ratio` 比例的交易日选参数, 其余交易日检验
+    ///
+    /// 区间内至少需要两个交易日, 否则返回 `NotEnoughData`
+    pub fn out_of_sample(&self, from: i32, to: i32, train_ratio: f64) -> Result<WalkForwardWindow> {
+        let dates = self.trading_dates(from, to)?;
+        if dates.len() < 2 {
+            return Err(OptimizerError::NotEnoughData);
+        }
+        let split = ((dates.len() as f64 * train_ratio) as usize).clamp(1, dates.len() - 1);
+        self.window(&dates[..split], &dates[split..])
+    }
+
+    /// 滚动前进分析, 每个窗口用 `train` 个交易日选参数, 随后 `test` 个交易日检验, 窗口每次前进 `test` 个交易日
+    ///
+    /// 每段回测都从空仓开始, 指标按 [`Optimizer::warm_up`] 预先载入之前的K线
+    pub fn walk_forward(&self, from: i32, to: i32, train: usize, test: usize) -> Result<WalkForward> {
+        let dates = self.trading_dates(from, to)?;
+        let mut windows = vec![];
+        let mut start = 0;
+        while train > 0 && test > 0 && start + train + test <= dates.len() {
+            windows.push(self.window(&dates[start..start + train], &dates[start + train..start + train + test])?);
+            start += test;
+        }
+        Ok(WalkForward { windows })
+    }
+
+    fn window(&self, train: &[i32], test: &[i32]) -> Result<WalkForwardWindow> {
+        let range = |dates: &[i32]| match (dates.first(), dates.last()) {
+            (Some(first), Some(last)) => Ok((*first, *last)),
+            _ => Err(OptimizerError::NotEnoughData),
+        };
+        let (train, test) = (range(train)?, range(test)?);
+        let best = self.run(train.0, train.1)?.into_iter().next().unwrap_or(Trial {
+            params: Params::new(),
+            score: 0.0,
+            statistics: Statistics::new(&[], &[]),
+        });
+        let checked = self.trial(&best.params, test.0, test.1)?;
+        Ok(WalkForwardWindow {
+            train,
+            test,
+            params: best.params,
+            in_sample: best.score,
+            out_of_sample: checked.score,
+            statistics: checked.statistics,
+        })
+    }
+
+    /// 股票池在区间内的交易日
+    fn trading_dates(&self, from: i32, to: i32) -> Result<Vec<i32>> {
+        let mut dates = vec![];
+        for history in self.histories()?.values() {
+            dates.extend(history.iter().map(|bar| bar.date).filter(|date| *date >= from && *date <= to));
+        }
+        dates.sort_unstable();
+        dates.dedup();
+        Ok(dates)
+    }
+
+    /// 多个线程依次领取参数组合, 结果按参数组合的顺序返回
+    fn run_all(&self, grid: &[Params], from: i32, to: i32) -> Result<Vec<Trial>> {
+        let next = AtomicUsize::new(0);
+        let mut results: Vec<(usize, Result<Trial>)> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..self.threads.min(grid.len()))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut results = vec![];
+                        loop {
+                            let i = next.fetch_add(1, AtomicOrdering::Relaxed);
+                            if i >= grid.len() {
+                                break;
+                            }
+                            results.push((i, self.trial(&grid[i], from, to)));
+                        }
+                        results
+                    })
+                })
+                .collect();
+            workers.into_iter().flat_map(|worker| worker.join().expect("backtest thread panicked")).collect()
+        });
+        results.sort_by_key(|(i, _)| *i);
+        results.into_iter().map(|(_, trial)| trial).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        backtest::BackTest,
+        data::StockTradeData,
+        formula::{Formula, FormulaStrategy},
+    };
+
+    use super::{Metric, Optimizer, OptimizerError, ParamRange, Params};
+
+    fn strategy(params: &Params) -> FormulaStrategy {
+        let formula = Formula::parse("BUY:CROSS(MA(C,FAST),MA(C,SLOW)); SELL:CROSS(MA(C,SLOW),MA(C,FAST));")
+            .unwrap()
+            .param("FAST", params["fast"])
+            .param("SLOW", params["slow"]);
+        FormulaStrategy::with_signals(formula, "BUY", "SELL", 90).unwrap()
+    }
+
+    fn optimizer() -> Optimizer<impl Fn(&Params) -> FormulaStrategy + Sync> {
+        Optimizer::new(vec!["603339"], strategy)
+        .param(ParamRange::new("fast", 3.0, 5.0, 1.0))
+        .param(ParamRange::values("slow", &[10.0, 20.0]))
+        .metric(Metric::TotalReturn)
+    }
+
+    #[test]
+    fn grid_search() {
+        let optimizer = optimizer();
+        assert_eq!(optimizer.grid().len(), 6);
+        let trials = optimizer.run(20220101, 20221101).unwrap();
+        assert_eq!(trials.len(), 6);
+        assert!(trials.windows(2).all(|w| w[0].score >= w[1].score));
+        // 多线程的结果与单独回测一致
+        let best = optimizer.trial(&trials[0].params, 20220101, 20221101).unwrap();
+        assert_eq!(best, trials[0]);
+
+        let walk_forward = optimizer.threads(2).walk_forward(20220101, 20221101, 100, 40).unwrap();
+        assert_eq!(walk_forward.windows.len(), 2);
+        assert!(walk_forward.windows[0].test.0 > walk_forward.windows[0].train.1);
+        assert!(walk_forward.windows[1].train.0 > walk_forward.windows[0].train.0);
+    }
+
+    #[test]
+    fn short_range() {
+        // 区间内不足两个交易日
+        assert!(matches!(optimizer().out_of_sample(20300101, 20301231, 0.7), Err(OptimizerError::NotEnoughData)));
+        assert!(matches!(optimizer().out_of_sample(20221101, 20221101, 0.7), Err(OptimizerError::NotEnoughData)));
+
+        // 20 个交易日的区间, 不预先载入时 MA(C,20) 只在最后一天有值, 不会产生交叉
+        let params: Params = [("fast".to_string(), 3.0), ("slow".to_string(), 20.0)].into_iter().collect();
+        let cold = optimizer().warm_up(0).trial(&params, 20221004, 20221101).unwrap();
+        let warm = optimizer().trial(&params, 20221004, 20221101).unwrap();
+        assert_eq!(cold.statistics.trades, 0);
+        assert!(warm.statistics.trades > 0);
+    }
+
+    #[test]
+    fn same_as_run() {
+        // 内存中逐日推进的结果与直接回测一致
+        let params: Params = [("fast".to_string(), 3.0), ("slow".to_string(), 10.0)].into_iter().collect();
+        let trial = optimizer().warm_up(0).source(StockTradeData {}).trial(&params, 20220101, 20221101).unwrap();
+        let result = BackTest::with_universe(vec!["603339"], strategy(&params)).unwrap().run("20220101", "20221101").unwrap();
+        assert!(result.statistics.trades > 0);
+        assert_eq!(trial.statistics, result.statistics);
+    }
+}
@@ -0,0 +1,522 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    backtest::{BackTest, BackTestError},
+    data::{get_day_path_in, intern, DataSourceError, DayTradeUnit, DayTradeUnitSize, StockCode, StockTradeData, TradeDataSource, TradeUnit},
+    result::{BacktestResult, ExportError},
+    strategy::Account,
+};
+
+#[derive(Debug, Error)]
+pub enum PaperError {
+    #[error("read or write state fail")]
+    IoError {
+        #[from]
+        source: io::Error,
+    },
+    #[error("parse journal fail")]
+    JsonError {
+        #[from]
+        source: serde_json::Error,
+    },
+    #[error("data source error")]
+    DataSourceError {
+        #[from]
+        source: DataSourceError,
+    },
+    #[error("backtest fail")]
+    BackTestError {
+        #[from]
+        source: BackTestError,
+    },
+    #[error("export fail")]
+    ExportError {
+        #[from]
+        source: ExportError,
+    },
+    #[error("MILLIONS_TDX is not set")]
+    RootError {
+        #[from]
+        source: env::VarError,
+    },
+    #[error("replayed account on {0} differs from account.json")]
+    AccountMismatch(i32),
+}
+
+pub type Result<T, E = PaperError> = std::result::Result<T, E>;
+
+/// 一个交易日股票池的K线
+pub type DayBars = (i32, Vec<(StockCode, DayTradeUnit)>);
+
+/// 实时行情, 按交易日推送股票池的K线
+pub trait LiveBarSource {
+    /// 阻塞到下一个交易日的K线, 行情结束时为 None
+    fn next_bars(&mut self) -> Result<Option<DayBars>>;
+
+    /// `before` 之前的历史日线, 用于预热指标, 默认从环境变量 MILLIONS_TDX 的目录读取
+    fn history(&self, code: StockCode, before: i32) -> Result<Vec<DayTradeUnit>> {
+        Ok(StockTradeData {}.day_duration(code, None, None)?.filter(|bar| bar.date < before).collect())
+    }
+}
+
+/// 任何按日期顺序产生K线的迭代器都可以作为行情, 便于回放和测试
+impl<I: Iterator<Item = DayBars>> LiveBarSource for I {
+    fn next_bars(&mut self) -> Result<Option<DayBars>> {
+        Ok(self.next())
+    }
+}
+
+/// 监视通达信日线文件, 客户端下载盘后数据追加新K线后推送
+///
+/// 某个交易日所有股票的文件都已写到该日(或更晚)时推送; 停牌的股票不会有该日K线,
+/// 该交易日第一次出现后等待 `settle` 仍不完整时不再等待, 推送已有的K线
+///
+/// ```ignore
+/// let feed = TdxFileFeed::new(vec!["603339"], 20230103).poll_interval(Duration::from_secs(60));
+/// ```
+pub struct TdxFileFeed {
+    root: Option<PathBuf>,
+    codes: Vec<StockCode>,
+    from: i32,
+    /** 每个文件已读取的记录数 */
+    records: HashMap<StockCode, u64>,
+    /** 每个文件最新K线的日期 */
+    latest: HashMap<StockCode, i32>,
+    /** 还没有推送的K线 */
+    pending: BTreeMap<i32, HashMap<StockCode, DayTradeUnit>>,
+    /** 交易日第一次出现的时间 */
+    seen: HashMap<i32, Instant>,
+    /** 最后推送的交易日 */
+    emitted: i32,
+    poll_interval: Duration,
+    settle: Duration,
+    idle_timeout: Option<Duration>,
+}
+
+impl TdxFileFeed {
+    /// 从 `from` 开始推送, 之前的K线视为历史, 目录取环境变量 MILLIONS_TDX
+    pub fn new(codes: Vec<StockCode>, from: i32) -> Self {
+        TdxFileFeed {
+            root: None,
+            codes,
+            from,
+            records: HashMap::new(),
+            latest: HashMap::new(),
+            pending: BTreeMap::new(),
+            seen: HashMap::new(),
+            emitted: 0,
+            poll_interval: Duration::from_secs(60),
+            settle: Duration::from_secs(600),
+            idle_timeout: None,
+        }
+    }
+
+    /// 通达信数据目录
+    pub fn root<P: AsRef<Path>>(mut self, root: P) -> Self {
+        self.root = Some(root.as_ref().to_path_buf());
+        self
+    }
+
+    /// 检查文件的间隔, 默认 60 秒
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// 等待停牌股票的时间, 默认 10 分钟
+    pub fn settle(mut self, settle: Duration) -> Self {
+        self.settle = settle;
+        self
+    }
+
+    /// 超过这段时间没有新K线时结束行情, 默认一直等待
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    fn data_root(&self) -> Result<PathBuf> {
+        match &self.root {
+            Some(root) => Ok(root.clone()),
+            None => Ok(PathBuf::from(env::var("MILLIONS_TDX")?)),
+        }
+    }
+
+    /// 读取文件新增的记录, 有新K线时返回 true
+    fn scan(&mut self) -> Result<bool> {
+        let root = self.data_root()?;
+        let mut found = false;
+        for code in self.codes.iter() {
+            let path = get_day_path_in(&root, code)?;
+            let len = match fs::metadata(&path) {
+                Ok(metadata) => metadata.len() / DayTradeUnitSize as u64,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let records = self.records.entry(code).or_insert(0);
+            // 文件变短说明被客户端重写, 从头读取, 已推送的日期会被跳过
+            if len < *records {
+                *records = 0;
+            }
+            let bars = DayTradeUnit::read_from(&path, *records)?;
+            *records += bars.len() as u64;
+            for bar in bars {
+                let latest = self.latest.entry(code).or_insert(bar.date);
+                *latest = (*latest).max(bar.date);
+                if bar.date >= self.from && bar.date > self.emitted {
+                    self.seen.entry(bar.date).or_insert_with(Instant::now);
+                    self.pending.entry(bar.date).or_default().insert(code, bar);
+                    found = true;
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// 最早的交易日齐全或等待超时后取出
+    fn ready(&mut self) -> Option<DayBars> {
+        let date = *self.pending.keys().next()?;
+        let complete = self.codes.iter().all(|code| self.latest.get(code).map(|latest| *latest >= date).unwrap_or(false));
+        let settled = self.seen.get(&date).map(|seen| seen.elapsed() >= self.settle).unwrap_or(true);
+        if !complete && !settled {
+            return None;
+        }
+        let mut day = self.pending.remove(&date)?;
+        self.seen.remove(&date);
+        self.emitted = date;
+        Some((date, self.codes.iter().filter_map(|code| day.remove(code).map(|bar| (*code, bar))).collect()))
+    }
+}
+
+impl LiveBarSource for TdxFileFeed {
+    fn next_bars(&mut self) -> Result<Option<DayBars>> {
+        let mut idle = Instant::now();
+        loop {
+            if self.scan()? {
+                idle = Instant::now();
+            }
+            if let Some(day) = self.ready() {
+                return Ok(Some(day));
+            }
+            if self.idle_timeout.map(|timeout| idle.elapsed() >= timeout).unwrap_or(false) {
+                return Ok(None);
+            }
+            thread::sleep(self.poll_interval);
+        }
+    }
+
+    /// 从监视的目录读取, 与推送的K线来自同一份数据
+    fn history(&self, code: StockCode, before: i32) -> Result<Vec<DayTradeUnit>> {
+        let bars = DayTradeUnit::read_from(&get_day_path_in(&self.data_root()?, code)?, 0)?;
+        Ok(bars.into_iter().filter(|bar| bar.date < before).collect())
+    }
+}
+
+/// 日志中的一个交易日, K线为 (代码, 开, 高, 低, 收, 成交量, 成交额)
+#[derive(Serialize, Deserialize)]
+struct JournalDay {
+    date: i32,
+    bars: Vec<(String, i32, i32, i32, i32, i32, f32)>,
+}
+
+/// 模拟交易, 用实时行情逐日推进回测引擎, 策略和撮合规则与回测相同
+///
+/// 状态目录中 `bars.jsonl` 记录成功处理的每个交易日的K线, 重启时按原顺序重放,
+/// 账户和策略的状态与中断前一致; `account.json` 为每个交易日结束后的账户, 格式与回测结果的 JSON 相同.
+/// 预热指标的历史K线取自行情的 [`LiveBarSource::history`]
+///
+/// ```ignore
+/// let mut strategy = strategies::by_name("ma-cross", &Params::new()).unwrap();
+/// let back_test = BackTest::with_universe(vec!["603339"], &mut strategy)?;
+/// let feed = TdxFileFeed::new(vec!["603339"], 20230103);
+/// PaperTrader::new(back_test, feed, "paper").warm_up(60).run()?;
+/// ```
+pub struct PaperTrader<'a, S> {
+    back_test: BackTest<'a>,
+    source: S,
+    dir: PathBuf,
+    warm_up: usize,
+    restored: bool,
+    first: Option<i32>,
+    last: Option<i32>,
+}
+
+impl<'a, S: LiveBarSource> PaperTrader<'a, S> {
+    pub fn new<P: AsRef<Path>>(back_test: BackTest<'a>, source: S, dir: P) -> Self {
+        PaperTrader {
+            back_test,
+            source,
+            dir: dir.as_ref().to_path_buf(),
+            warm_up: 0,
+            restored: false,
+            first: None,
+            last: None,
+        }
+    }
+
+    /// 第一个交易日之前载入 `days` 根历史K线计算指标, 重启时载入相同的K线
+    pub fn warm_up(mut self, days: usize) -> Self {
+        self.warm_up = days;
+        self
+    }
+
+    /// 重放状态目录中的日志, 返回重放的交易日数量, 第一次推进时自动调用
+    ///
+    /// 写日志时中断留下的不完整的最后一行被截掉, 这一天会由行情重新推送.
+    /// 重放后的账户与 `account.json` 不一致时返回 `AccountMismatch`, 例如换了策略参数或日志被改动
+    pub fn restore(&mut self) -> Result<usize> {
+        if self.restored {
+            return Ok(0);
+        }
+        self.restored = true;
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join("bars.jsonl");
+        if !path.exists() {
+            return Ok(0);
+        }
+        let text = fs::read_to_string(&path)?;
+        let mut days = vec![];
+        let mut valid = 0;
+        for line in text.split_inclusive('\n') {
+            let day = match serde_json::from_str::<JournalDay>(line) {
+                Ok(day) if line.ends_with('\n') => day,
+                _ if line.trim().is_empty() => {
+                    valid += line.len();
+                    continue;
+                }
+                // 只有最后一行可能不完整, 中间的行出错说明日志损坏
+                Err(e) if valid + line.len() < text.len() => return Err(e.into()),
+                _ => {
+                    warn!("truncate incomplete journal line {:?}", line);
+                    OpenOptions::new().write(true).open(&path)?.set_len(valid as u64)?;
+                    break;
+                }
+            };
+            valid += line.len();
+            days.push(day);
+        }
+        let count = days.len();
+        let previous = days.len().checked_sub(2).map(|i| days[i].date);
+        for day in days {
+            let bars = day
+                .bars
+                .into_iter()
+                .map(|(code, open, high, low, close, volume, amount)| {
+                    let code = self.code(&code);
+                    (code, DayTradeUnit { date: day.date, trade_data: TradeUnit { open, close, high, low, volume, amount } })
+                })
+                .collect();
+            self.apply(day.date, bars)?;
+        }
+        if count > 0 {
+            self.check_account(previous)?;
+        }
+        Ok(count)
+    }
+
+    /// 比较重放的结果和中断前写入的 `account.json`
+    ///
+    /// 每个交易日先写日志再写 `account.json`, 两者之间中断时 `account.json` 停在前一个交易日, 这时用重放的结果覆盖
+    fn check_account(&self, previous: Option<i32>) -> Result<()> {
+        let path = self.dir.join("account.json");
+        let saved: serde_json::Value = match fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => serde_json::Value::Null,
+            Err(e) => return Err(e.into()),
+        };
+        let replayed = self.result().to_json()?;
+        if saved == serde_json::from_str::<serde_json::Value>(&replayed)? {
+            return Ok(());
+        }
+        let last = saved["equity_curve"].as_array().and_then(|curve| curve.last()).and_then(|snapshot| snapshot["date"].as_i64());
+        if saved.is_null() || (previous.is_some() && last == previous.map(i64::from)) {
+            warn!("account.json is behind the journal, rewrite it with the replayed account");
+            fs::write(path, replayed)?;
+            return Ok(());
+        }
+        Err(PaperError::AccountMismatch(self.last.unwrap_or(0)))
+    }
+
+    /// 处理行情的下一个交易日, 行情结束时返回 false; 重启前已处理的交易日被跳过
+    pub fn step(&mut self) -> Result<bool> {
+        self.restore()?;
+        loop {
+            let (date, bars) = match self.source.next_bars()? {
+                Some(day) => day,
+                None => return Ok(false),
+            };
+            if self.last.map(|last| date <= last).unwrap_or(false) {
+                continue;
+            }
+            self.apply(date, bars.clone())?;
+            self.journal(date, &bars)?;
+            fs::write(self.dir.join("account.json"), self.result().to_json()?)?;
+            return Ok(true);
+        }
+    }
+
+    /// 一直运行到行情结束
+    pub fn run(&mut self) -> Result<()> {
+        while self.step()? {}
+        Ok(())
+    }
+
+    pub fn account(&self) -> &Account {
+        self.back_test.account()
+    }
+
+    /// 最后处理的交易日
+    pub fn last_date(&self) -> Option<i32> {
+        self.last
+    }
+
+    /// 从第一个交易日到目前的结果
+    pub fn result(&self) -> BacktestResult {
+        self.back_test.result_between(self.first.unwrap_or(0), self.last.unwrap_or(0))
+    }
+
+    pub fn back_test(&self) -> &BackTest<'a> {
+        &self.back_test
+    }
+
+    /// 日志中的代码换成股票池中的代码
+    fn code(&self, code: &str) -> StockCode {
+        self.back_test
+            .codes()
+            .iter()
+            .find(|c| **c == code)
+            .copied()
+            .unwrap_or_else(|| intern(code))
+    }
+
+    fn apply(&mut self, date: i32, bars: Vec<(StockCode, DayTradeUnit)>) -> Result<()> {
+        if self.first.is_none() {
+            self.first = Some(date);
+            if self.warm_up > 0 {
+                for code in self.back_test.codes().to_vec() {
+                    let history = self.source.history(code, date)?;
+                    self.back_test.preload(code, &history[history.len().saturating_sub(self.warm_up)..]);
+                }
+            }
+        }
+        self.back_test.step(date, bars)?;
+        self.last = Some(date);
+        Ok(())
+    }
+
+    /// 推进成功后写日志, 推进失败或写日志前中断的交易日重启后由行情重新推送
+    fn journal(&self, date: i32, bars: &[(StockCode, DayTradeUnit)]) -> Result<()> {
+        let day = JournalDay {
+            date,
+            bars: bars
+                .iter()
+                .map(|(code, bar)| {
+                    let unit = &bar.trade_data;
+                    (code.to_string(), unit.open, unit.high, unit.low, unit.close, unit.volume, unit.amount)
+                })
+                .collect(),
+        };
+        let mut file = OpenOptions::new().create(true).append(true).open(self.dir.join("bars.jsonl"))?;
+        writeln!(file, "{}", serde_json::to_string(&day)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::Path, time::Duration};
+
+    use crate::{backtest::BackTest, data::DayTradeUnit, optimizer::Params, strategies};
+
+    use super::{LiveBarSource, PaperError, PaperTrader, TdxFileFeed};
+
+    #[test]
+    fn file_feed() {
+        let root = env::temp_dir().join(format!("millions_feed_{}", std::process::id()));
+        let dir = root.join("sh").join("lday");
+        fs::create_dir_all(&dir).unwrap();
+        let source = Path::new(&env::var("MILLIONS_TDX").unwrap()).join("sh").join("lday").join("sh603339.day");
+        let data = fs::read(&source).unwrap();
+        let records = data.len() / 32;
+        // 先写入除最后 3 条以外的记录, 再追加一条不完整的记录
+        fs::write(dir.join("sh603339.day"), &data[..(records - 3) * 32 + 10]).unwrap();
+
+        let all = DayTradeUnit::read_from(&source, 0).unwrap();
+        let from = all[records - 5].date;
+        let mut feed = TdxFileFeed::new(vec!["603339"], from).root(&root).poll_interval(Duration::ZERO).idle_timeout(Duration::ZERO);
+        let dates: Vec<i32> = std::iter::from_fn(|| feed.next_bars().unwrap()).map(|(date, _)| date).collect();
+        assert_eq!(dates, vec![all[records - 5].date, all[records - 4].date]);
+        // 预热的历史K线来自监视的目录, 不完整的记录不读取
+        assert_eq!(feed.history("603339", i32::MAX).unwrap().len(), records - 3);
+        assert_eq!(feed.history("603339", from).unwrap().len(), records - 5);
+
+        fs::write(dir.join("sh603339.day"), &data).unwrap();
+        let (date, bars) = feed.next_bars().unwrap().unwrap();
+        assert_eq!(date, all[records - 3].date);
+        assert_eq!(bars[0].1.trade_data.close, all[records - 3].trade_data.close);
+        assert_eq!(std::iter::from_fn(|| feed.next_bars().unwrap()).count(), 2);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn paper_trader() {
+        let dir = env::temp_dir().join(format!("millions_paper_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let days = || {
+            let bars = DayTradeUnit::read_from(&crate::data::get_day_path_by_code("603339").unwrap(), 0).unwrap();
+            bars.into_iter().filter(|bar| bar.date >= 20210101 && bar.date <= 20221101).map(|bar| (bar.date, vec![("603339", bar)]))
+        };
+
+        // 中途重启: 第一次只处理前 200 个交易日
+        {
+            let mut strategy = strategies::by_name("ma-cross", &Params::new()).unwrap();
+            let back_test = BackTest::with_universe(vec!["603339"], &mut strategy).unwrap();
+            let mut trader = PaperTrader::new(back_test, days().take(200), &dir);
+            trader.run().unwrap();
+            assert_eq!(trader.result().equity_curve.len(), 200);
+        }
+        // 写日志时中断, 最后一行不完整
+        let journal = dir.join("bars.jsonl");
+        let text = fs::read_to_string(&journal).unwrap();
+        fs::write(&journal, format!("{}{{\"date\":2021", text)).unwrap();
+        let mut strategy = strategies::by_name("ma-cross", &Params::new()).unwrap();
+        let back_test = BackTest::with_universe(vec!["603339"], &mut strategy).unwrap();
+        let mut trader = PaperTrader::new(back_test, days(), &dir);
+        assert_eq!(trader.restore().unwrap(), 200);
+        assert_eq!(fs::read_to_string(&journal).unwrap(), text);
+        trader.run().unwrap();
+        let paper = trader.result();
+        assert!(dir.join("account.json").exists());
+
+        // 重放的账户与 account.json 不一致时报错
+        let account = dir.join("account.json");
+        let saved = fs::read_to_string(&account).unwrap();
+        let mut tampered: serde_json::Value = serde_json::from_str(&saved).unwrap();
+        tampered["cash"] = serde_json::Value::String("0".to_string());
+        fs::write(&account, tampered.to_string()).unwrap();
+        let mut strategy = strategies::by_name("ma-cross", &Params::new()).unwrap();
+        let back_test = BackTest::with_universe(vec!["603339"], &mut strategy).unwrap();
+        assert!(matches!(PaperTrader::new(back_test, days(), &dir).restore(), Err(PaperError::AccountMismatch(20221101))));
+        fs::write(&account, saved).unwrap();
+
+        // 与一次完整的回测结果相同
+        let mut strategy = strategies::by_name("ma-cross", &Params::new()).unwrap();
+        let expected = BackTest::with_universe(vec!["603339"], &mut strategy).unwrap().run("20210101", "20221101").unwrap();
+        assert!(!paper.trades.is_empty());
+        assert_eq!(paper.trades, expected.trades);
+        assert_eq!(paper.equity_curve, expected.equity_curve);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,632 @@
+use std::{
+    collections::HashMap,
+    env,
+    fmt::Display,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use chrono::Timelike;
+use pyo3::{
+    exceptions::{PyRuntimeError, PyValueError},
+    prelude::*,
+    types::{PyDict, PyList},
+};
+use rust_decimal::{
+    prelude::{FromPrimitive, ToPrimitive},
+    Decimal,
+};
+use serde::de::DeserializeOwned;
+
+use crate::{
+    adjust::{adjust, Adjust},
+    backtest::{sync_history, BackTest, FillTiming, MarketSnapshot, PortfolioStrategy, Slippage},
+    data::{
+        codes_in, get_day_path_in, get_finance_path_in, get_minute_path_in, intern, time_to_date, DataSourceError,
+        DayTradeUnit, DayTradeUnitIter, FinanceData, MinuteTradeUnit, MinuteTradeUnitIter, StockCode, TradeDataSource,
+        TradeTime,
+    },
+    event::{lots_of, Context, CorporateAction, Fill, OrderKind, OrderRequest, Side},
+    strategy::{CostMethod, FeeModel, TradeError},
+};
+
+/// 读取K线的一个字段
+type Field = fn(&DayTradeUnit) -> i32;
+
+fn error(e: impl Display) -> PyErr {
+    PyRuntimeError::new_err(e.to_string())
+}
+
+/// 按列转换为 NumPy 数组, 价格换算为元
+fn columns<'py>(py: Python<'py>, bars: &[DayTradeUnit]) -> PyResult<&'py PyDict> {
+    let numpy = py.import("numpy")?;
+    let dict = PyDict::new(py);
+    let array = |values: PyObject, dtype: &str| numpy.call_method1("asarray", (values, dtype));
+    dict.set_item("date", array(bars.iter().map(|bar| bar.date).collect::<Vec<i32>>().into_py(py), "int32")?)?;
+    let prices: [(&str, Field); 4] = [
+        ("open", |bar| bar.trade_data.open),
+        ("high", |bar| bar.trade_data.high),
+        ("low", |bar| bar.trade_data.low),
+        ("close", |bar| bar.trade_data.close),
+    ];
+    for (name, field) in prices {
+        let values: Vec<f64> = bars.iter().map(|bar| field(bar) as f64 / 100.0).collect();
+        dict.set_item(name, array(values.into_py(py), "float64")?)?;
+    }
+    dict.set_item("volume", array(bars.iter().map(|bar| bar.trade_data.volume as i64).collect::<Vec<i64>>().into_py(py), "int64")?)?;
+    dict.set_item("amount", array(bars.iter().map(|bar| bar.trade_data.amount as f64).collect::<Vec<f64>>().into_py(py), "float64")?)?;
+    Ok(dict)
+}
+
+/// 按与配置文件相同的格式转换 Python 对象, 例如 `{"Percent": "0.001"}` 或 `"Fifo"`
+fn from_py<T: DeserializeOwned>(py: Python, value: &PyAny, what: &str) -> PyResult<T> {
+    let json: String = py.import("json")?.call_method1("dumps", (value,))?.extract()?;
+    serde_json::from_str(&json).map_err(|e| PyValueError::new_err(format!("invalid {} {}: {}", what, value, e)))
+}
+
+fn frame<'py>(py: Python<'py>, columns: &'py PyDict) -> PyResult<&'py PyAny> {
+    py.import("pandas")?.call_method1("DataFrame", (columns,))
+}
+
+fn parse_adjust(mode: &str) -> PyResult<Adjust> {
+    match mode.to_lowercase().as_str() {
+        "none" | "" => Ok(Adjust::None),
+        "forward" | "qfq" => Ok(Adjust::Forward),
+        "backward" | "hfq" => Ok(Adjust::Backward),
+        _ => Err(PyValueError::new_err(format!("unknown adjust {}, expect none forward backward", mode))),
+    }
+}
+
+/// Python 传入的公司行为, (除权除息日, 每股派息, 每股送转), 有配股时再加上 (配股价, 每股配股)
+#[derive(FromPyObject)]
+enum PyAction {
+    Rights(i32, f64, f64, f64, f64),
+    Plain(i32, f64, f64),
+}
+
+impl PyAction {
+    fn corporate_action(self, code: StockCode) -> CorporateAction {
+        let (date, cash, shares, rights_price, rights) = match self {
+            PyAction::Rights(date, cash, shares, rights_price, rights) => (date, cash, shares, rights_price, rights),
+            PyAction::Plain(date, cash, shares) => (date, cash, shares, 0.0, 0.0),
+        };
+        let decimal = |value: f64| Decimal::from_f64(value).unwrap_or_default();
+        CorporateAction {
+            code,
+            date,
+            cash_per_share: decimal(cash),
+            shares_per_share: decimal(shares),
+            rights_price: decimal(rights_price),
+            rights_per_share: decimal(rights),
+        }
+    }
+}
+
+/// 通达信数据目录, 对应 Rust 中的 `TradeDataSource`
+///
+/// ```python
+/// source = millions.DataSource("/path/to/vipdoc")
+/// bars = source.day_frame("603339", start=20220101, adjust="forward", actions=[(20220615, 0.5, 0.0)])
+/// ```
+#[pyclass(name = "DataSource")]
+#[derive(Clone)]
+struct PyDataSource {
+    root: PathBuf,
+}
+
+impl PyDataSource {
+    fn bars(
+        &self,
+        code: String,
+        start: Option<i32>,
+        end: Option<i32>,
+        adjust_mode: &str,
+        actions: Vec<PyAction>,
+    ) -> PyResult<Vec<DayTradeUnit>> {
+        let mode = parse_adjust(adjust_mode)?;
+        let code = intern(&code);
+        let path = get_day_path_in(&self.root, code).map_err(error)?;
+        let bars: Vec<DayTradeUnit> = DayTradeUnitIter::new(&path).map_err(error)?.collect();
+        let actions: Vec<CorporateAction> = actions.into_iter().map(|action| action.corporate_action(code)).collect();
+        // 先在全部历史上复权, 再截取区间, 前复权的基准始终是最新价格
+        Ok(adjust(&bars, &actions, mode)
+            .into_iter()
+            .filter(|bar| bar.date >= start.unwrap_or(i32::MIN) && bar.date <= end.unwrap_or(i32::MAX))
+            .collect())
+    }
+}
+
+#[pymethods]
+impl PyDataSource {
+    /// `root` 为空时使用环境变量 MILLIONS_TDX, 不修改进程的环境变量
+    #[new]
+    #[pyo3(signature = (root = None))]
+    fn new(root: Option<String>) -> PyResult<Self> {
+        let root = match root {
+            Some(root) => root,
+            None => env::var("MILLIONS_TDX").map_err(|_| PyValueError::new_err("root is required when MILLIONS_TDX is not set"))?,
+        };
+        Ok(PyDataSource { root: PathBuf::from(root) })
+    }
+
+    /// 全部股票代码, 带市场前缀
+    fn codes(&self) -> PyResult<Vec<String>> {
+        Ok(codes_in(&self.root).map_err(error)?.into_iter().map(String::from).collect())
+    }
+
+    /// 日线, 返回各列的 NumPy 数组, `actions` 为 (除权除息日, 每股派息, 每股送转) 列表,
+    /// 有配股时为 (除权除息日, 每股派息, 每股送转, 配股价, 每股配股)
+    #[pyo3(signature = (code, start = None, end = None, adjust = "none", actions = vec![]))]
+    fn day<'py>(
+        &self,
+        py: Python<'py>,
+        code: String,
+        start: Option<i32>,
+        end: Option<i32>,
+        adjust: &str,
+        actions: Vec<PyAction>,
+    ) -> PyResult<&'py PyDict> {
+        columns(py, &self.bars(code, start, end, adjust, actions)?)
+    }
+
+    /// 日线 pandas DataFrame
+    #[pyo3(signature = (code, start = None, end = None, adjust = "none", actions = vec![]))]
+    fn day_frame<'py>(
+        &self,
+        py: Python<'py>,
+        code: String,
+        start: Option<i32>,
+        end: Option<i32>,
+        adjust: &str,
+        actions: Vec<PyAction>,
+    ) -> PyResult<&'py PyAny> {
+        frame(py, columns(py, &self.bars(code, start, end, adjust, actions)?)?)
+    }
+
+    /// 分钟线 pandas DataFrame, `minutes` 为 1 或 5, `code` 也可以是 .lc1 .lc5 文件路径
+    #[pyo3(signature = (code, minutes = 1))]
+    fn minute_frame<'py>(&self, py: Python<'py>, code: String, minutes: u8) -> PyResult<&'py PyAny> {
+        if minutes != 1 && minutes != 5 {
+            return Err(PyValueError::new_err(format!("invalid minutes {}, expect 1 or 5", minutes)));
+        }
+        let path = if code.ends_with(".lc1") || code.ends_with(".lc5") {
+            Path::new(&code).to_path_buf()
+        } else {
+            get_minute_path_in(&self.root, intern(&code), minutes).map_err(error)?
+        };
+        let bars: Vec<_> = MinuteTradeUnitIter::new(&path).map_err(error)?.collect();
+        let days: Vec<DayTradeUnit> =
+            bars.iter().map(|bar| DayTradeUnit { date: bar.date, trade_data: bar.trade_data.clone() }).collect();
+        let dict = columns(py, &days)?;
+        dict.set_item("time", py.import("numpy")?.call_method1("asarray", (bars.iter().map(|bar| bar.time()).collect::<Vec<i32>>(), "int32"))?)?;
+        frame(py, dict)
+    }
+
+    /// 报告期的财务数据 pandas DataFrame, 行为股票代码, 列为字段序号(从 1 开始)
+    fn finance<'py>(&self, py: Python<'py>, report_date: i32) -> PyResult<&'py PyAny> {
+        let finance = FinanceData::open(get_finance_path_in(&self.root, report_date)).map_err(error)?;
+        let rows = PyDict::new(py);
+        for (code, values) in finance.rows() {
+            let row = PyDict::new(py);
+            for (i, value) in values.iter().enumerate() {
+                row.set_item(i + 1, *value)?;
+            }
+            rows.set_item(code, row)?;
+        }
+        let kwargs = PyDict::new(py);
+        kwargs.set_item("orient", "index")?;
+        py.import("pandas")?.getattr("DataFrame")?.call_method("from_dict", (rows,), Some(kwargs))
+    }
+}
+
+/// 读取方式与 [`crate::data::StockTradeData`] 相同, 只是目录换成 `root`, 供 `BackTest(source=...)` 回测使用
+impl TradeDataSource for PyDataSource {
+    fn prepare(&self) -> crate::data::Result<()> {
+        Ok(())
+    }
+
+    fn day(&self, code: StockCode, day: TradeTime) -> crate::data::Result<DayTradeUnit> {
+        let date = time_to_date(&day);
+        self.day_duration(code, None, None)?
+            .find(|bar| bar.date == date)
+            .ok_or(DataSourceError::NoTradeData)
+    }
+
+    fn minue(&self, code: StockCode, day: TradeTime) -> crate::data::Result<MinuteTradeUnit> {
+        let offset = (day.hour() * 60 + day.minute()) as i16;
+        let path = get_minute_path_in(&self.root, code, 1)?;
+        MinuteTradeUnitIter::between(&path, time_to_date(&day), offset, offset)
+            .next()
+            .ok_or(DataSourceError::NoTradeData)
+    }
+
+    fn day_duration(&self, code: StockCode, _from: Option<TradeTime>, _to: Option<TradeTime>) -> crate::data::Result<DayTradeUnitIter> {
+        DayTradeUnitIter::new(&get_day_path_in(&self.root, code)?)
+    }
+
+    fn minue_duration(&self, code: StockCode, day: TradeTime, from: TradeTime, to: TradeTime) -> MinuteTradeUnitIter {
+        let offset = |time: TradeTime| (time.hour() * 60 + time.minute()) as i16;
+        let path = get_minute_path_in(&self.root, code, 1).unwrap_or_default();
+        MinuteTradeUnitIter::between(&path, time_to_date(&day), offset(from), offset(to))
+    }
+
+    fn codes(&self) -> crate::data::Result<Vec<StockCode>> {
+        codes_in(&self.root)
+    }
+
+    fn finance(&self, report_date: i32) -> crate::data::Result<FinanceData> {
+        FinanceData::open(get_finance_path_in(&self.root, report_date))
+    }
+
+    fn minute_bars(&self, code: StockCode, minutes: u8) -> crate::data::Result<MinuteTradeUnitIter> {
+        MinuteTradeUnitIter::new(&get_minute_path_in(&self.root, code, minutes)?)
+    }
+}
+
+/// 传给 Python 策略 `on_bar(ctx)` 的上下文
+///
+/// 委托在 `on_bar` 返回后交给回测引擎撮合
+#[pyclass(name = "Context", unsendable)]
+struct PyContext {
+    #[pyo3(get)]
+    date: i32,
+    #[pyo3(get)]
+    codes: Vec<String>,
+    balance: Decimal,
+    fees: FeeModel,
+    positions: HashMap<StockCode, Decimal>,
+    bars: HashMap<StockCode, Rc<Vec<DayTradeUnit>>>,
+    orders: Vec<(StockCode, Side, Decimal, OrderKind)>,
+}
+
+impl PyContext {
+    fn code(&self, code: &str) -> PyResult<StockCode> {
+        self.bars.keys().find(|c| **c == code).copied().ok_or_else(|| PyValueError::new_err(format!("{} not in universe", code)))
+    }
+
+    fn submit(&mut self, code: &str, side: Side, vol: i64, price: Option<f64>) -> PyResult<()> {
+        let code = self.code(code)?;
+        if vol <= 0 {
+            return Err(PyValueError::new_err(format!("invalid volume {}", vol)));
+        }
+        let kind = match price {
+            None => OrderKind::Market,
+            Some(price) => match Decimal::from_f64(price) {
+                Some(price) if price > Decimal::ZERO => OrderKind::Limit(price.round_dp(2)),
+                _ => return Err(PyValueError::new_err(format!("invalid price {}", price))),
+            },
+        };
+        self.push(code, side, Decimal::from(vol), kind);
+        Ok(())
+    }
+
+    /// 记下委托, 买入时从余额中扣除估算成本, 之后读到的余额不会重复使用这部分资金
+    fn push(&mut self, code: StockCode, side: Side, vol: Decimal, kind: OrderKind) {
+        if side == Side::Buy {
+            let price = match kind {
+                OrderKind::Limit(price) => price,
+                OrderKind::Market => self.bars[code].last().map(|bar| Decimal::new(bar.trade_data.close as i64, 2)).unwrap_or_default(),
+            };
+            self.balance = (self.balance - self.fees.buy_cost(price, vol)).max(Decimal::ZERO);
+        }
+        self.orders.push((code, side, vol, kind));
+    }
+}
+
+#[pymethods]
+impl PyContext {
+    /// 可用余额, 已扣除本次回调中买入委托的估算成本
+    #[getter]
+    fn balance(&self) -> f64 {
+        self.balance.to_f64().unwrap_or(0.0)
+    }
+
+    /// 持仓股数
+    fn position(&self, code: &str) -> i64 {
+        self.positions.get(code).and_then(|vol| vol.to_i64()).unwrap_or(0)
+    }
+
+    /// 截止到当日的历史K线, 各列为 NumPy 数组
+    fn history<'py>(&self, py: Python<'py>, code: &str) -> PyResult<&'py PyDict> {
+        columns(py, &self.bars[self.code(code)?])
+    }
+
+    /// 当日收盘价, 停牌时为停牌前的收盘价
+    fn close(&self, code: &str) -> PyResult<Option<f64>> {
+        Ok(self.bars[self.code(code)?].last().map(|bar| bar.trade_data.close as f64 / 100.0))
+    }
+
+    /// 市价委托, 给出 `price` 时为限价委托
+    #[pyo3(signature = (code, vol, price = None))]
+    fn buy(&mut self, code: &str, vol: i64, price: Option<f64>) -> PyResult<()> {
+        self.submit(code, Side::Buy, vol, price)
+    }
+
+    #[pyo3(signature = (code, vol, price = None))]
+    fn sell(&mut self, code: &str, vol: i64, price: Option<f64>) -> PyResult<()> {
+        self.submit(code, Side::Sell, vol, price)
+    }
+
+    /// 按收盘价用余额的百分比整手买入
+    fn buy_percent(&mut self, code: &str, percent: f64) -> PyResult<()> {
+        let close = Decimal::from_f64(self.close(code)?.unwrap_or(0.0)).unwrap_or_default();
+        let amount = self.balance * Decimal::from_f64(percent.clamp(0.0, 100.0)).unwrap_or_default() / Decimal::from(100);
+        let vol = lots_of(amount, close);
+        if !vol.is_zero() {
+            self.push(self.code(code)?, Side::Buy, vol, OrderKind::Market);
+        }
+        Ok(())
+    }
+
+    /// 卖出全部持仓
+    fn sell_all(&mut self, code: &str) -> PyResult<()> {
+        let code = self.code(code)?;
+        if let Some(vol) = self.positions.get(code).filter(|vol| !vol.is_zero()) {
+            let vol = *vol;
+            self.push(code, Side::Sell, vol, OrderKind::Market);
+        }
+        Ok(())
+    }
+}
+
+/// 把 Python 对象适配为组合策略
+///
+/// 对象必须有 `on_bar(ctx)` 方法, 可选 `on_fill(fill)` 和 `on_order_rejected(order, reason)`, 参数为 dict.
+/// 回调抛出的异常在回测结束后重新抛出, 异常之后不再调用策略
+struct PyStrategy {
+    object: PyObject,
+    bars: HashMap<StockCode, Rc<Vec<DayTradeUnit>>>,
+    error: Option<PyErr>,
+}
+
+impl PyStrategy {
+    fn call(&mut self, name: &str, args: impl IntoPy<Py<pyo3::types::PyTuple>>) {
+        if self.error.is_some() {
+            return;
+        }
+        let result = Python::with_gil(|py| {
+            let object = self.object.as_ref(py);
+            if object.hasattr(name)? {
+                object.call_method1(name, args)?;
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            self.error = Some(e);
+        }
+    }
+}
+
+fn order_dict(py: Python, order: &OrderRequest) -> PyResult<PyObject> {
+    let dict = PyDict::new(py);
+    dict.set_item("id", order.id)?;
+    dict.set_item("code", order.code)?;
+    dict.set_item("side", if order.side == Side::Buy { "buy" } else { "sell" })?;
+    dict.set_item("vol", order.vol.to_i64().unwrap_or(0))?;
+    Ok(dict.into())
+}
+
+impl PortfolioStrategy for PyStrategy {
+    fn on_bar(&mut self, ctx: &mut Context, market: &MarketSnapshot) {
+        if self.error.is_some() {
+            return;
+        }
+        for code in market.codes() {
+            sync_history(self.bars.entry(code).or_default(), market.history(code));
+        }
+        let result = Python::with_gil(|py| -> PyResult<Vec<(StockCode, Side, Decimal, OrderKind)>> {
+            let positions = self
+                .bars
+                .keys()
+                .filter_map(|code| match ctx.get_position(code) {
+                    Ok(Some(position)) => Some((*code, position.volume())),
+                    _ => None,
+                })
+                .collect();
+            let context = Py::new(
+                py,
+                PyContext {
+                    date: market.date(),
+                    codes: market.codes().iter().map(|code| code.to_string()).collect(),
+                    balance: ctx.get_balance(),
+                    fees: ctx.account().fee_model(),
+                    positions,
+                    bars: self.bars.clone(),
+                    orders: vec![],
+                },
+            )?;
+            self.object.call_method1(py, "on_bar", (context.clone_ref(py),))?;
+            let orders = std::mem::take(&mut context.borrow_mut(py).orders);
+            Ok(orders)
+        });
+        match result {
+            Ok(orders) => {
+                for (code, side, vol, kind) in orders {
+                    ctx.submit(code, side, vol, kind);
+                }
+            }
+            Err(e) => self.error = Some(e),
+        }
+    }
+
+    fn on_fill(&mut self, _ctx: &mut Context, fill: &Fill) {
+        let fill = Python::with_gil(|py| -> PyResult<PyObject> {
+            let order = order_dict(py, &fill.order)?;
+            let dict: &PyDict = order.downcast(py)?;
+            dict.set_item("date", fill.date)?;
+            dict.set_item("price", fill.price.to_f64().unwrap_or(0.0))?;
+            Ok(order)
+        });
+        match fill {
+            Ok(fill) => self.call("on_fill", (fill,)),
+            Err(e) => self.error = Some(e),
+        }
+    }
+
+    fn on_order_rejected(&mut self, _ctx: &mut Context, order: &OrderRequest, reason: &TradeError) {
+        match Python::with_gil(|py| order_dict(py, order)) {
+            Ok(order) => self.call("on_order_rejected", (order, reason.to_string())),
+            Err(e) => self.error = Some(e),
+        }
+    }
+}
+
+/// 回测, 策略为 Python 对象, 由 Rust 回测引擎驱动
+///
+/// ```python
+/// class MaCross:
+///     def on_bar(self, ctx):
+///         for code in ctx.codes:
+///             close = ctx.history(code)["close"]
+///             if len(close) >= 20 and close[-5:].mean() > close[-20:].mean() and ctx.position(code) == 0:
+///                 ctx.buy_percent(code, 90)
+///
+/// result = millions.BackTest(["603339"], MaCross()).run(20220101, 20221101)
+/// print(result["statistics"]["sharpe"])
+/// ```
+#[pyclass(name = "BackTest", unsendable)]
+struct PyBackTest {
+    codes: Vec<StockCode>,
+    strategy: PyStrategy,
+    capital: String,
+    fill_timing: FillTiming,
+    benchmark: Option<StockCode>,
+    fees: FeeModel,
+    slippage: Slippage,
+    cost_method: CostMethod,
+    source: Option<PyDataSource>,
+}
+
+#[pymethods]
+impl PyBackTest {
+    /// `fill_timing` 为 SameClose NextOpen NextVwap, `cost_method` 为 Average Fifo,
+    /// `fees` 和 `slippage` 与配置文件的格式相同, 例如 `{"min_brokerage": "5"}` 和 `{"Percent": "0.001"}`,
+    /// `source` 为 `DataSource`, 为空时读取环境变量 MILLIONS_TDX 的目录
+    #[new]
+    #[pyo3(signature = (
+        codes, strategy, capital = "100000", fill_timing = "NextOpen", benchmark = None,
+        fees = None, slippage = None, cost_method = "Average", source = None
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        py: Python,
+        codes: Vec<String>,
+        strategy: PyObject,
+        capital: &str,
+        fill_timing: &str,
+        benchmark: Option<String>,
+        fees: Option<&PyAny>,
+        slippage: Option<&PyAny>,
+        cost_method: &str,
+        source: Option<PyDataSource>,
+    ) -> PyResult<Self> {
+        let fill_timing = serde_json::from_value(serde_json::Value::String(fill_timing.to_string()))
+            .map_err(|_| PyValueError::new_err(format!("unknown fill timing {}", fill_timing)))?;
+        let cost_method = serde_json::from_value(serde_json::Value::String(cost_method.to_string()))
+            .map_err(|_| PyValueError::new_err(format!("unknown cost method {}", cost_method)))?;
+        Ok(PyBackTest {
+            codes: codes.into_iter().map(|code| intern(&code)).collect(),
+            strategy: PyStrategy { object: strategy, bars: HashMap::new(), error: None },
+            capital: capital.to_string(),
+            fill_timing,
+            benchmark: benchmark.map(|code| intern(&code)),
+            fees: fees.map(|fees| from_py(py, fees, "fees")).transpose()?.unwrap_or_default(),
+            slippage: slippage.map(|slippage| from_py(py, slippage, "slippage")).transpose()?.unwrap_or_default(),
+            cost_method,
+            source,
+        })
+    }
+
+    /// 回测 [start, end] 区间, 返回与 JSON 导出相同结构的 dict
+    fn run(&mut self, py: Python, start: i32, end: i32) -> PyResult<PyObject> {
+        self.strategy.error = None;
+        self.strategy.bars.clear();
+        let mut builder = BackTest::builder()
+            .codes(self.codes.clone())
+            .capital(&self.capital)
+            .fees(self.fees)
+            .slippage(self.slippage)
+            .cost_method(self.cost_method)
+            .fill_timing(self.fill_timing)
+            .strategy(&mut self.strategy);
+        if let Some(benchmark) = self.benchmark {
+            builder = builder.benchmark(benchmark);
+        }
+        if let Some(source) = &self.source {
+            builder = builder.source(source);
+        }
+        let result = builder.build().map_err(error)?.run(&start.to_string(), &end.to_string()).map_err(error)?;
+        if let Some(e) = self.strategy.error.take() {
+            return Err(e);
+        }
+        let json = result.to_json().map_err(error)?;
+        Ok(py.import("json")?.call_method1("loads", (json,))?.into())
+    }
+}
+
+/// 数据读取和回测的 Python 模块, 用 maturin 构建 wheel
+#[pymodule]
+fn millions(py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<PyDataSource>()?;
+    m.add_class::<PyContext>()?;
+    m.add_class::<PyBackTest>()?;
+    m.add("__all__", PyList::new(py, ["DataSource", "Context", "BackTest"]))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use pyo3::{exceptions::PyValueError, prelude::*, types::PyModule};
+
+    use super::{PyBackTest, PyDataSource};
+
+    const STRATEGIES: &str = r#"
+class Buy:
+    def __init__(self):
+        self.balances = []
+        self.fills = []
+
+    def on_bar(self, ctx):
+        if ctx.position("603339") == 0 and not self.fills:
+            before = ctx.balance
+            ctx.buy("603339", 100)
+            self.balances.append((before, ctx.balance))
+
+    def on_fill(self, fill):
+        self.fills.append((fill["code"], fill["side"], fill["vol"]))
+
+class Broken:
+    def on_bar(self, ctx):
+        raise ValueError("broken")
+"#;
+
+    fn back_test(py: Python, strategy: PyObject) -> PyResult<PyBackTest> {
+        let source = PyDataSource { root: env::var("MILLIONS_TDX").unwrap().into() };
+        PyBackTest::new(py, vec!["603339".to_string()], strategy, "100000", "NextOpen", None, None, None, "Average", Some(source))
+    }
+
+    #[test]
+    fn py_strategy() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let module = PyModule::from_code(py, STRATEGIES, "strategies.py", "strategies").unwrap();
+
+            // on_bar 中的委托交给回测撮合, 成交后回调 on_fill, 同一回调中读到的余额已扣除买入成本
+            let strategy: PyObject = module.getattr("Buy").unwrap().call0().unwrap().into();
+            let result = back_test(py, strategy.clone_ref(py)).unwrap().run(py, 20221001, 20221101).unwrap();
+            let trades: usize = result.as_ref(py).get_item("trades").unwrap().len().unwrap();
+            assert_eq!(trades, 1);
+            let fills: Vec<(String, String, i64)> = strategy.getattr(py, "fills").unwrap().extract(py).unwrap();
+            assert_eq!(fills, vec![("603339".to_string(), "buy".to_string(), 100)]);
+            let balances: Vec<(f64, f64)> = strategy.getattr(py, "balances").unwrap().extract(py).unwrap();
+            assert!(balances.iter().all(|(before, after)| after < before), "{:?}", balances);
+
+            // 策略抛出的异常原样返回
+            let strategy: PyObject = module.getattr("Broken").unwrap().call0().unwrap().into();
+            let error = back_test(py, strategy).unwrap().run(py, 20221001, 20221101).unwrap_err();
+            assert!(error.is_instance_of::<PyValueError>(py), "{}", error);
+        });
+    }
+}
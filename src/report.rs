@@ -0,0 +1,398 @@
+use std::{collections::HashMap, fmt::Write as _, fs, io, path::Path};
+
+use rust_decimal::prelude::ToPrimitive;
+
+use crate::{
+    data::{DayTradeUnit, StockCode},
+    result::{BacktestResult, TradeKind},
+    statistics::{drawdowns, monthly_returns},
+};
+
+const WIDTH: f64 = 960.0;
+const HEIGHT: f64 = 280.0;
+const MARGIN: f64 = 56.0;
+/// A股习惯: 红涨绿跌
+const UP: &str = "#d62728";
+const DOWN: &str = "#2ca02c";
+const BUY: &str = "#1f77b4";
+const SELL: &str = "#ff7f0e";
+
+const STYLE: &str = "body{font-family:sans-serif;margin:24px;color:#222}\
+h2{margin-top:32px;font-size:18px}\
+table{border-collapse:collapse;font-size:13px}\
+td,th{border:1px solid #ddd;padding:4px 8px;text-align:right}\
+svg{background:#fafafa;border:1px solid #eee}\
+svg text{font-size:11px;fill:#555}";
+
+/// 单文件 HTML 回测报告, 图表为内联 SVG, 不依赖任何外部资源, 可离线打开
+pub struct Report<'a> {
+    result: &'a BacktestResult,
+    title: String,
+    bars: Vec<(StockCode, &'a [DayTradeUnit])>,
+}
+
+impl<'a> Report<'a> {
+    pub fn new(result: &'a BacktestResult) -> Self {
+        let config = &result.config;
+        let title = format!("{} {}-{}", config.codes.join(","), config.from, config.to);
+        Report { result, title, bars: vec![] }
+    }
+
+    pub fn title(mut self, title: &str) -> Self {
+        self.title = title.to_string();
+        self
+    }
+
+    /// 加入一只股票的K线, 在K线图上标注该股票的成交
+    pub fn bars(mut self, code: StockCode, bars: &'a [DayTradeUnit]) -> Self {
+        self.bars.push((code, bars));
+        self
+    }
+
+    pub fn render(&self) -> String {
+        let mut html = String::new();
+        let _ = write!(
+            html,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n",
+            escape(&self.title),
+            STYLE
+        );
+        let _ = writeln!(html, "<h1>{}</h1>", escape(&self.title));
+        let _ = writeln!(html, "<h2>Equity</h2>\n{}", self.equity_chart());
+        let _ = writeln!(html, "<h2>Drawdown</h2>\n{}", self.drawdown_chart());
+        let _ = writeln!(html, "<h2>Monthly returns</h2>\n{}", self.monthly_table());
+        for (code, bars) in self.bars.iter() {
+            let _ = writeln!(html, "<h2>{}</h2>\n{}", code, self.candle_chart(code, bars));
+        }
+        let _ = writeln!(html, "<h2>Statistics</h2>\n{}", self.statistics_table());
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.render())
+    }
+
+    fn dates(&self) -> Vec<i32> {
+        self.result.equity_curve.iter().map(|snapshot| snapshot.date).collect()
+    }
+
+    fn equity(&self) -> Vec<f64> {
+        self.result.equity_curve.iter().map(|snapshot| to_f64(snapshot.equity)).collect()
+    }
+
+    /// 策略与基准的累计收益率
+    fn equity_chart(&self) -> String {
+        let dates = self.dates();
+        let capital = to_f64(self.result.config.capital);
+        let strategy: Vec<(usize, f64)> = self
+            .equity()
+            .iter()
+            .enumerate()
+            .map(|(i, equity)| (i, if capital != 0.0 { equity / capital - 1.0 } else { 0.0 }))
+            .collect();
+        let mut series = vec![("strategy", UP, strategy)];
+        if let Some(benchmark) = self.result.benchmark.as_ref() {
+            let index: HashMap<i32, usize> = dates.iter().enumerate().map(|(i, date)| (*date, i)).collect();
+            let points = benchmark
+                .benchmark_curve
+                .iter()
+                .filter_map(|(date, r)| index.get(date).map(|i| (*i, *r)))
+                .collect();
+            series.push(("benchmark", "#7f7f7f", points));
+        }
+        line_chart(&dates, &series, false)
+    }
+
+    fn drawdown_chart(&self) -> String {
+        let points = drawdowns(&self.equity()).iter().map(|d| -d).enumerate().collect();
+        line_chart(&self.dates(), &[("drawdown", DOWN, points)], true)
+    }
+
+    /// 月度收益热力图, 每年一行
+    fn monthly_table(&self) -> String {
+        let months = monthly_returns(&self.dates(), &self.equity(), to_f64(self.result.config.capital));
+        let scale = months.iter().map(|(_, r)| r.abs()).fold(0.0, f64::max);
+        let mut years: Vec<(i32, [Option<f64>; 12])> = vec![];
+        for (month, r) in months {
+            let year = month / 100;
+            if years.last().map(|(y, _)| *y) != Some(year) {
+                years.push((year, [None; 12]));
+            }
+            if let Some((_, row)) = years.last_mut() {
+                row[(month % 100 - 1) as usize] = Some(r);
+            }
+        }
+        let mut table = String::from("<table>\n<tr><th></th>");
+        for month in 1..=12 {
+            let _ = write!(table, "<th>{}</th>", month);
+        }
+        table.push_str("<th>year</th></tr>\n");
+        for (year, row) in years {
+            let _ = write!(table, "<tr><th>{}</th>", year);
+            for r in row.iter() {
+                match r {
+                    Some(r) => table.push_str(&heat_cell(*r, scale)),
+                    None => table.push_str("<td></td>"),
+                }
+            }
+            let total = row.iter().flatten().fold(1.0, |acc, r| acc * (1.0 + r)) - 1.0;
+            table.push_str(&heat_cell(total, scale.max(total.abs())));
+            table.push_str("</tr>\n");
+        }
+        table.push_str("</table>");
+        table
+    }
+
+    /// 回测区间内的K线, 买入标在最低价下方, 卖出标在最高价上方
+    fn candle_chart(&self, code: StockCode, bars: &[DayTradeUnit]) -> String {
+        let config = &self.result.config;
+        let bars: Vec<&DayTradeUnit> = bars.iter().filter(|bar| bar.date >= config.from && bar.date <= config.to).collect();
+        let dates: Vec<i32> = bars.iter().map(|bar| bar.date).collect();
+        let low = bars.iter().map(|bar| bar.trade_data.low).min().unwrap_or(0) as f64 / 100.0;
+        let high = bars.iter().map(|bar| bar.trade_data.high).max().unwrap_or(0) as f64 / 100.0;
+        let scale = Scale::new(low, high, bars.len());
+        let mut svg = open_svg();
+        axes(&mut svg, &dates, &scale, |v| format!("{:.2}", v));
+        let body = (scale.step() * 0.6).max(1.0);
+        for (i, bar) in bars.iter().enumerate() {
+            let unit = &bar.trade_data;
+            let color = if unit.close >= unit.open { UP } else { DOWN };
+            let x = scale.x(i);
+            let (open, close) = (unit.open as f64 / 100.0, unit.close as f64 / 100.0);
+            let top = scale.y(open.max(close));
+            let height = (scale.y(open.min(close)) - top).max(1.0);
+            let _ = write!(
+                svg,
+                "<line x1=\"{x:.1}\" y1=\"{:.1}\" x2=\"{x:.1}\" y2=\"{:.1}\" stroke=\"{color}\"/>\
+                 <rect x=\"{:.1}\" y=\"{top:.1}\" width=\"{body:.1}\" height=\"{height:.1}\" fill=\"{color}\"/>",
+                scale.y(unit.high as f64 / 100.0),
+                scale.y(unit.low as f64 / 100.0),
+                x - body / 2.0,
+            );
+        }
+        let index: HashMap<i32, usize> = dates.iter().enumerate().map(|(i, date)| (*date, i)).collect();
+        for trade in self.result.trades.iter().filter(|trade| trade.code == code) {
+            let (i, bar) = match index.get(&trade.date) {
+                Some(i) => (*i, bars[*i]),
+                None => continue,
+            };
+            let x = scale.x(i);
+            let (points, color) = match trade.kind {
+                TradeKind::Buy => {
+                    let y = scale.y(bar.trade_data.low as f64 / 100.0) + 4.0;
+                    (format!("{x:.1},{y:.1} {:.1},{:.1} {:.1},{:.1}", x - 5.0, y + 8.0, x + 5.0, y + 8.0), BUY)
+                }
+                TradeKind::Sell => {
+                    let y = scale.y(bar.trade_data.high as f64 / 100.0) - 4.0;
+                    (format!("{x:.1},{y:.1} {:.1},{:.1} {:.1},{:.1}", x - 5.0, y - 8.0, x + 5.0, y - 8.0), SELL)
+                }
+                TradeKind::Dividend => continue,
+            };
+            let _ = write!(
+                svg,
+                "<polygon points=\"{points}\" fill=\"{color}\"><title>{} {:?} {} @ {}</title></polygon>",
+                trade.date, trade.kind, trade.vol, trade.price
+            );
+        }
+        svg.push_str("</svg>");
+        svg
+    }
+
+    fn statistics_table(&self) -> String {
+        let s = &self.result.statistics;
+        let mut rows = vec![
+            ("final equity", self.result.final_equity().round_dp(2).to_string()),
+            ("days", s.days.to_string()),
+            ("total return", percent(s.total_return)),
+            ("annualized return", percent(s.annualized_return)),
+            ("volatility", percent(s.volatility)),
+            ("sharpe", format!("{:.2}", s.sharpe)),
+            ("sortino", format!("{:.2}", s.sortino)),
+            ("calmar", format!("{:.2}", s.calmar)),
+            ("max drawdown", percent(s.max_drawdown)),
+            ("max drawdown days", s.max_drawdown_duration.to_string()),
+            ("trades", s.trades.to_string()),
+            ("win rate", percent(s.win_rate)),
+            ("profit factor", format!("{:.2}", s.profit_factor)),
+            ("average win", format!("{:.2}", s.average_win)),
+            ("average loss", format!("{:.2}", s.average_loss)),
+            ("exposure", percent(s.exposure)),
+            ("turnover", format!("{:.2}", s.turnover)),
+            ("rejected orders", self.result.rejected.len().to_string()),
+        ];
+        if let Some(b) = self.result.benchmark.as_ref() {
+            rows.extend([
+                ("benchmark return", percent(b.benchmark_return)),
+                ("alpha", percent(b.alpha)),
+                ("beta", format!("{:.2}", b.beta)),
+                ("information ratio", format!("{:.2}", b.information_ratio)),
+                ("tracking error", percent(b.tracking_error)),
+                ("up capture", percent(b.up_capture)),
+                ("down capture", percent(b.down_capture)),
+            ]);
+        }
+        let mut table = String::from("<table>\n");
+        for (name, value) in rows {
+            let _ = writeln!(table, "<tr><th>{}</th><td>{}</td></tr>", name, value);
+        }
+        table.push_str("</table>");
+        table
+    }
+}
+
+/// 序号和数值到画布坐标的映射
+struct Scale {
+    min: f64,
+    max: f64,
+    count: usize,
+}
+
+impl Scale {
+    fn new(min: f64, max: f64, count: usize) -> Self {
+        // 数值没有波动时上下留出空间, 避免除零
+        let (min, max) = if max > min { (min, max) } else { (min - 1.0, max + 1.0) };
+        Scale { min, max, count }
+    }
+
+    fn step(&self) -> f64 {
+        (WIDTH - 2.0 * MARGIN) / self.count.max(1) as f64
+    }
+
+    fn x(&self, i: usize) -> f64 {
+        MARGIN + (i as f64 + 0.5) * self.step()
+    }
+
+    fn y(&self, value: f64) -> f64 {
+        HEIGHT - MARGIN - (value - self.min) / (self.max - self.min) * (HEIGHT - 2.0 * MARGIN)
+    }
+}
+
+fn open_svg() -> String {
+    format!("<svg width=\"{WIDTH}\" height=\"{HEIGHT}\" viewBox=\"0 0 {WIDTH} {HEIGHT}\">")
+}
+
+/// 坐标轴, y轴标注最大最小值, x轴标注首尾日期
+fn axes(svg: &mut String, dates: &[i32], scale: &Scale, label: impl Fn(f64) -> String) {
+    let (left, right, bottom) = (MARGIN, WIDTH - MARGIN, HEIGHT - MARGIN);
+    let _ = write!(
+        svg,
+        "<line x1=\"{left}\" y1=\"{bottom}\" x2=\"{right}\" y2=\"{bottom}\" stroke=\"#999\"/>\
+         <line x1=\"{left}\" y1=\"{MARGIN}\" x2=\"{left}\" y2=\"{bottom}\" stroke=\"#999\"/>\
+         <text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>\
+         <text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>",
+        left - 4.0,
+        scale.y(scale.max) + 4.0,
+        label(scale.max),
+        left - 4.0,
+        scale.y(scale.min) + 4.0,
+        label(scale.min),
+    );
+    if let (Some(first), Some(last)) = (dates.first(), dates.last()) {
+        let _ = write!(
+            svg,
+            "<text x=\"{left}\" y=\"{:.1}\">{first}</text><text x=\"{right}\" y=\"{:.1}\" text-anchor=\"end\">{last}</text>",
+            bottom + 16.0,
+            bottom + 16.0,
+        );
+    }
+}
+
+/// 折线: 名称, 颜色, (序号, 数值)
+type Series<'s> = (&'s str, &'s str, Vec<(usize, f64)>);
+
+/// 折线图, 数值为收益率; `area` 为 true 时填充到0轴
+fn line_chart(dates: &[i32], series: &[Series], area: bool) -> String {
+    let values = series.iter().flat_map(|(_, _, points)| points.iter().map(|(_, v)| *v));
+    let min = values.clone().fold(0.0, f64::min);
+    let max = values.fold(0.0, f64::max);
+    let scale = Scale::new(min, max, dates.len());
+    let mut svg = open_svg();
+    axes(&mut svg, dates, &scale, percent);
+    let zero = scale.y(0.0);
+    let _ = write!(
+        svg,
+        "<line x1=\"{MARGIN}\" y1=\"{zero:.1}\" x2=\"{}\" y2=\"{zero:.1}\" stroke=\"#ccc\" stroke-dasharray=\"4\"/>",
+        WIDTH - MARGIN
+    );
+    for (n, (name, color, points)) in series.iter().enumerate() {
+        let mut path: String = points
+            .iter()
+            .map(|(i, v)| format!("{:.1},{:.1}", scale.x(*i), scale.y(*v)))
+            .collect::<Vec<_>>()
+            .join(" ");
+        if area {
+            if let (Some((first, _)), Some((last, _))) = (points.first(), points.last()) {
+                path = format!("{:.1},{zero:.1} {path} {:.1},{zero:.1}", scale.x(*first), scale.x(*last));
+            }
+            let _ = write!(svg, "<polygon points=\"{path}\" fill=\"{color}\" fill-opacity=\"0.4\" stroke=\"{color}\"/>");
+        } else {
+            let _ = write!(svg, "<polyline points=\"{path}\" fill=\"none\" stroke=\"{color}\" stroke-width=\"1.5\"/>");
+        }
+        let _ = write!(
+            svg,
+            "<text x=\"{:.1}\" y=\"{:.1}\" fill=\"{color}\">{name}</text>",
+            MARGIN + 8.0 + 90.0 * n as f64,
+            MARGIN - 12.0
+        );
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+/// 热力图单元格, 颜色深浅按 |r| / scale
+fn heat_cell(r: f64, scale: f64) -> String {
+    let alpha = if scale > 0.0 { (r.abs() / scale).min(1.0) * 0.8 } else { 0.0 };
+    let rgb = if r >= 0.0 { "214,39,40" } else { "44,160,44" };
+    format!("<td style=\"background:rgba({rgb},{alpha:.2})\">{}</td>", percent(r))
+}
+
+fn percent(value: f64) -> String {
+    format!("{:.2}%", value * 100.0)
+}
+
+fn to_f64(value: rust_decimal::Decimal) -> f64 {
+    value.to_f64().unwrap_or(0.0)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        backtest::{BackTest, MarketSnapshot, PortfolioStrategy},
+        event::Context,
+        strategy::Trade,
+    };
+
+    use super::Report;
+
+    /// 空仓时买入, 持仓时卖出
+    struct Swing;
+
+    impl PortfolioStrategy for Swing {
+        fn on_bar(&mut self, ctx: &mut Context, market: &MarketSnapshot) {
+            let held = matches!(ctx.get_position("603339"), Ok(Some(position)) if !position.volume().is_zero());
+            if market.date() % 7 == 0 {
+                let _ = if held { ctx.sell("603339", "0.01", "1000") } else { ctx.buy("603339", "1000", "1000") };
+            }
+        }
+    }
+
+    #[test]
+    fn report() {
+        let mut back_test = BackTest::with_universe(vec!["603339"], Swing).unwrap();
+        back_test.set_benchmark("sh603338");
+        let result = back_test.run("20220601", "20221101").unwrap();
+        assert!(!result.trades.is_empty());
+        let html = Report::new(&result).title("swing <603339>").bars("603339", back_test.history("603339")).render();
+        assert!(html.contains("<title>swing &lt;603339&gt;</title>"));
+        assert_eq!(html.matches("<svg").count(), 3);
+        assert!(html.contains("Buy 1000 @"));
+        assert!(html.contains("<th>benchmark return</th>"));
+        // 不引用外部资源
+        assert!(!html.contains("http") && !html.contains("<script") && !html.contains("src="));
+    }
+}
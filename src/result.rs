@@ -0,0 +1,291 @@
+use std::{
+    fmt::Write as _,
+    fs, io,
+    path::Path,
+};
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{
+    backtest::{FillTiming, Slippage},
+    data::StockCode,
+    event::{OrderKind, OrderRequest, Side},
+    optimizer::Params,
+    statistics::{BenchmarkStatistics, Statistics},
+    strategy::{Account, CostMethod, EquitySnapshot, FeeModel, Order, TradeError},
+};
+
+#[derive(Debug, Error)]
+pub enum ExportError {
+    #[error("io error")]
+    IoError {
+        #[from]
+        source: io::Error,
+    },
+    #[error("serialize json fail")]
+    JsonError {
+        #[from]
+        source: serde_json::Error,
+    },
+}
+
+pub type Result<T, E = ExportError> = std::result::Result<T, E>;
+
+/// 回测使用的配置, 随结果一起保存, 便于比较不同版本的策略
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BackTestConfig {
+    /// 股票池
+    pub codes: Vec<StockCode>,
+    /// 开始日期 例如 20230103
+    pub from: i32,
+    /// 结束日期
+    pub to: i32,
+    /// 初始资金
+    pub capital: Decimal,
+    /// 交易费用
+    pub fees: FeeModel,
+    /// 持仓成本计算方式
+    pub cost_method: CostMethod,
+    pub fill_timing: FillTiming,
+    /// 滑点
+    pub slippage: Slippage,
+    /// 是否提供了分笔成交
+    pub ticks: bool,
+    /// 策略参数
+    pub params: Params,
+    /// 比较基准
+    pub benchmark: Option<StockCode>,
+}
+
+/// 成交记录的类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum TradeKind {
+    Buy,
+    Sell,
+    /// 派息, 数量为0, 已实现盈亏为派息金额
+    Dividend,
+}
+
+/// 成交记录, 由账户中的订单展开
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TradeRecord {
+    /// 成交日期
+    pub date: i32,
+    pub code: StockCode,
+    pub kind: TradeKind,
+    /// 成交量(股), 卖出也为正数
+    pub vol: Decimal,
+    pub price: Decimal,
+    /// 成交额
+    pub amount: Decimal,
+    /// 印花税
+    pub stamp_duty: Decimal,
+    /// 佣金
+    pub brokerage_fee: Decimal,
+    /// 过户费
+    pub transfer_fee: Decimal,
+    /// 已实现盈亏
+    pub realized: Decimal,
+}
+
+impl TradeRecord {
+    pub fn new(code: StockCode, order: &Order) -> Self {
+        let kind = if order.vol().is_zero() {
+            TradeKind::Dividend
+        } else if order.vol().is_sign_positive() {
+            TradeKind::Buy
+        } else {
+            TradeKind::Sell
+        };
+        let vol = order.vol().abs();
+        TradeRecord {
+            date: order.date(),
+            code,
+            kind,
+            vol,
+            price: order.price(),
+            amount: vol * order.price(),
+            stamp_duty: order.stamp_duty(),
+            brokerage_fee: order.brokerage_fee(),
+            transfer_fee: order.transfer_fee(),
+            realized: order.realized(),
+        }
+    }
+
+    /// 总费用
+    pub fn fees(&self) -> Decimal {
+        self.stamp_duty + self.brokerage_fee + self.transfer_fee
+    }
+}
+
+/// 被拒绝的委托和原因
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RejectedOrder {
+    pub id: u64,
+    /// 下单日期
+    pub date: i32,
+    pub code: StockCode,
+    pub side: Side,
+    pub vol: Decimal,
+    pub kind: OrderKind,
+    /// 拒绝原因
+    pub reason: String,
+}
+
+impl RejectedOrder {
+    pub fn new(order: &OrderRequest, reason: &TradeError) -> Self {
+        RejectedOrder {
+            id: order.id,
+            date: order.date,
+            code: order.code,
+            side: order.side,
+            vol: order.vol,
+            kind: order.kind,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+/// 回测结束时的持仓
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PositionRecord {
+    pub code: StockCode,
+    pub vol: Decimal,
+    /// 成本价
+    pub cost: Decimal,
+    /// 最后一个交易日的收盘价
+    pub price: Decimal,
+    /// 市值
+    pub market_value: Decimal,
+}
+
+/// 回测结果
+#[derive(Debug, Clone, Serialize)]
+pub struct BacktestResult {
+    pub config: BackTestConfig,
+    /// 每日权益
+    pub equity_curve: Vec<EquitySnapshot>,
+    /// 回测结束时的现金
+    pub cash: Decimal,
+    /// 回测结束时的持仓
+    pub positions: Vec<PositionRecord>,
+    /// 按时间排序的全部成交
+    pub trades: Vec<TradeRecord>,
+    /// 被拒绝的委托
+    pub rejected: Vec<RejectedOrder>,
+    pub statistics: Statistics,
+    /// 相对基准的统计, 没有设置基准时为 None
+    pub benchmark: Option<BenchmarkStatistics>,
+}
+
+impl BacktestResult {
+    /// 从回测结束时的账户收集结果
+    ///
+    /// # Parameter
+    ///
+    /// * `prices` - 各股票最后的收盘价, 用于计算持仓市值
+    pub fn new(
+        config: BackTestConfig,
+        account: &Account,
+        prices: &[(StockCode, Decimal)],
+        rejected: Vec<RejectedOrder>,
+        benchmark: Option<BenchmarkStatistics>,
+    ) -> Self {
+        let positions = account
+            .get_positions()
+            .iter()
+            .map(|position| {
+                let price = prices
+                    .iter()
+                    .find(|(code, _)| *code == position.code())
+                    .map(|(_, price)| *price)
+                    .unwrap_or_else(|| position.cost());
+                PositionRecord {
+                    code: position.code(),
+                    vol: position.volume(),
+                    cost: position.cost(),
+                    price,
+                    market_value: position.volume() * price,
+                }
+            })
+            .collect();
+        BacktestResult {
+            config,
+            equity_curve: account.equity_curve().clone(),
+            cash: account.get_balance(),
+            positions,
+            trades: account.orders().into_iter().map(|(code, order)| TradeRecord::new(code, order)).collect(),
+            rejected,
+            statistics: Statistics::from_account(account),
+            benchmark,
+        }
+    }
+
+    /// 最终权益
+    pub fn final_equity(&self) -> Decimal {
+        self.equity_curve.last().map(|snapshot| snapshot.equity).unwrap_or(self.config.capital)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn write_json<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, self.to_json()?)?;
+        Ok(())
+    }
+
+    pub fn equity_csv(&self) -> String {
+        let mut csv = String::from("date,cash,market_value,equity,realized,unrealized\n");
+        for s in self.equity_curve.iter() {
+            let _ = writeln!(csv, "{},{},{},{},{},{}", s.date, s.cash, s.market_value, s.equity, s.realized, s.unrealized);
+        }
+        csv
+    }
+
+    pub fn trades_csv(&self) -> String {
+        let mut csv = String::from("date,code,kind,vol,price,amount,stamp_duty,brokerage_fee,transfer_fee,realized\n");
+        for t in self.trades.iter() {
+            let _ = writeln!(
+                csv,
+                "{},{},{:?},{},{},{},{},{},{},{}",
+                t.date, t.code, t.kind, t.vol, t.price, t.amount, t.stamp_duty, t.brokerage_fee, t.transfer_fee, t.realized
+            );
+        }
+        csv
+    }
+
+    /// 限价委托的价格列为限价, 市价委托为空
+    pub fn rejected_csv(&self) -> String {
+        let mut csv = String::from("id,date,code,side,vol,limit,reason\n");
+        for r in self.rejected.iter() {
+            let limit = match r.kind {
+                OrderKind::Market => String::new(),
+                OrderKind::Limit(price) => price.to_string(),
+            };
+            let _ = writeln!(csv, "{},{},{},{:?},{},{},{}", r.id, r.date, r.code, r.side, r.vol, limit, r.reason);
+        }
+        csv
+    }
+
+    pub fn positions_csv(&self) -> String {
+        let mut csv = String::from("code,vol,cost,price,market_value\n");
+        for p in self.positions.iter() {
+            let _ = writeln!(csv, "{},{},{},{},{}", p.code, p.vol, p.cost, p.price, p.market_value);
+        }
+        csv
+    }
+
+    /// 在目录下写入 equity.csv trades.csv rejected.csv positions.csv
+    pub fn write_csv<P: AsRef<Path>>(&self, dir: P) -> Result<()> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        fs::write(dir.join("equity.csv"), self.equity_csv())?;
+        fs::write(dir.join("trades.csv"), self.trades_csv())?;
+        fs::write(dir.join("rejected.csv"), self.rejected_csv())?;
+        fs::write(dir.join("positions.csv"), self.positions_csv())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,285 @@
+use std::cmp::Ordering;
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use rust_decimal::prelude::ToPrimitive;
+use serde::Serialize;
+
+use crate::{
+    backtest::PortfolioStrategy,
+    data::TradeDataSource,
+    optimizer::{self, Optimizer, Params},
+    result::BacktestResult,
+    statistics::{daily_returns, max_drawdown, mean, sharpe, std_dev},
+};
+
+/// 一个指标在多次模拟中的分布
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Distribution {
+    /// 从小到大排列的模拟结果
+    pub samples: Vec<f64>,
+}
+
+impl Distribution {
+    fn new(mut samples: Vec<f64>) -> Self {
+        samples.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        Distribution { samples }
+    }
+
+    pub fn mean(&self) -> f64 {
+        mean(&self.samples)
+    }
+
+    pub fn std(&self) -> f64 {
+        std_dev(&self.samples)
+    }
+
+    /// 分位数, `p` 取 0 到 1, 相邻样本间线性插值
+    pub fn percentile(&self, p: f64) -> f64 {
+        if self.samples.is_empty() {
+            return f64::NAN;
+        }
+        let pos = p.clamp(0.0, 1.0) * (self.samples.len() - 1) as f64;
+        let (lower, upper) = (pos.floor() as usize, pos.ceil() as usize);
+        self.samples[lower] + (self.samples[upper] - self.samples[lower]) * (pos - lower as f64)
+    }
+
+    /// 置信区间, 例如 `confidence_interval(0.95)` 为 2.5% 和 97.5% 分位数
+    pub fn confidence_interval(&self, level: f64) -> (f64, f64) {
+        let tail = (1.0 - level) / 2.0;
+        (self.percentile(tail), self.percentile(1.0 - tail))
+    }
+}
+
+/// 一种模拟方法的结果
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Simulation {
+    /// 最终权益
+    pub final_equity: Distribution,
+    /// 最大回撤, 0.2 表示 20%
+    pub max_drawdown: Distribution,
+    /// 年化夏普比率
+    pub sharpe: Distribution,
+}
+
+impl Simulation {
+    fn new(paths: impl Iterator<Item = Vec<f64>>) -> Self {
+        let (mut equity, mut drawdown, mut ratio) = (vec![], vec![], vec![]);
+        for path in paths {
+            equity.push(path.last().copied().unwrap_or(0.0));
+            drawdown.push(max_drawdown(&path).0);
+            ratio.push(sharpe(&daily_returns(&path), 0.0));
+        }
+        Simulation {
+            final_equity: Distribution::new(equity),
+            max_drawdown: Distribution::new(drawdown),
+            sharpe: Distribution::new(ratio),
+        }
+    }
+}
+
+/// 蒙特卡洛稳健性检验, 对一次回测的成交和权益曲线重复抽样, 判断结果有多少来自运气
+///
+/// ```ignore
+/// let result = back_test.run("20200101", "20221231")?;
+/// let simulation = MonteCarlo::new(&result).seed(7).bootstrap(20);
+/// let (low, high) = simulation.sharpe.confidence_interval(0.95);
+/// ```
+pub struct MonteCarlo {
+    capital: f64,
+    /** 权益曲线的日期 */
+    dates: Vec<i32>,
+    /** 每日权益 */
+    equity: Vec<f64>,
+    /** 每日新增的已实现盈亏 */
+    realized: Vec<f64>,
+    /** 原回测的策略参数 */
+    params: Params,
+    runs: usize,
+    rng: StdRng,
+}
+
+impl MonteCarlo {
+    /// 默认模拟 1000 次, 随机种子取自系统
+    pub fn new(result: &BacktestResult) -> Self {
+        let to_f64 = |value: rust_decimal::Decimal| value.to_f64().unwrap_or(0.0);
+        let mut realized = vec![0.0; result.equity_curve.len()];
+        for trade in result.trades.iter() {
+            if let Ok(i) = result.equity_curve.binary_search_by_key(&trade.date, |snapshot| snapshot.date) {
+                realized[i] += to_f64(trade.realized);
+            }
+        }
+        MonteCarlo {
+            capital: to_f64(result.config.capital),
+            dates: result.equity_curve.iter().map(|snapshot| snapshot.date).collect(),
+            equity: result.equity_curve.iter().map(|snapshot| to_f64(snapshot.equity)).collect(),
+            realized,
+            params: result.config.params.clone(),
+            runs: 1000,
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    pub fn runs(mut self, runs: usize) -> Self {
+        self.runs = runs;
+        self
+    }
+
+    /// 固定随机种子, 结果可以重现
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    /// 成交重抽样
+    ///
+    /// 每个原来的平仓日从全部平仓的已实现盈亏中有放回地抽取一笔, 权益曲线为初始资金加累计已实现盈亏,
+    /// 有的成交被抽到多次, 有的没有抽到, 最终权益和回撤都随抽样变化.
+    /// 不包含持仓期间的浮动盈亏, 因此持仓中的回撤不会体现, 最大回撤通常比实际偏小;
+    /// 回测结束时仍持有的仓位也不计入最终权益
+    pub fn shuffle_trades(&mut self) -> Simulation {
+        let days: Vec<usize> = (0..self.realized.len()).filter(|i| self.realized[*i] != 0.0).collect();
+        let pnls: Vec<f64> = days.iter().map(|i| self.realized[*i]).collect();
+        let mut paths = vec![];
+        for _ in 0..self.runs {
+            let mut daily = vec![0.0; self.realized.len()];
+            for day in days.iter() {
+                daily[*day] = *pnls.choose(&mut self.rng).unwrap_or(&0.0);
+            }
+            let mut path = Vec::with_capacity(daily.len());
+            let mut equity = self.capital;
+            for pnl in daily {
+                equity += pnl;
+                path.push(equity);
+            }
+            paths.push(path);
+        }
+        Simulation::new(paths.into_iter())
+    }
+
+    /// 分块自助法, 每次随机抽取长度为 `block` 的连续日收益率拼接成与原曲线等长的路径,
+    /// 连续的块保留了收益率的自相关
+    pub fn bootstrap(&mut self, block: usize) -> Simulation {
+        let returns = daily_returns(&self.equity);
+        let block = block.clamp(1, returns.len().max(1));
+        let mut paths = vec![];
+        for _ in 0..self.runs {
+            let mut path = Vec::with_capacity(returns.len() + 1);
+            path.push(self.capital);
+            while !returns.is_empty() && path.len() <= returns.len() {
+                let start = self.rng.gen_range(0..=returns.len() - block);
+                for ret in returns[start..start + block].iter() {
+                    if path.len() > returns.len() {
+                        break;
+                    }
+                    path.push(path[path.len() - 1] * (1.0 + ret));
+                }
+            }
+            paths.push(path);
+        }
+        Simulation::new(paths.into_iter())
+    }
+
+    /// 截取原权益曲线的随机后缀, 从随机的一天开始以初始资金跟随原策略的日收益率到回测结束,
+    /// 至少保留 `min_days` 个交易日
+    ///
+    /// 这不是从随机日期重新回测: 开始日之前建立的持仓照常延续, 收益率来自同一条权益曲线.
+    /// 需要检验开始日期的影响时使用 [`MonteCarlo::random_starts`]
+    pub fn random_suffix(&mut self, min_days: usize) -> Simulation {
+        let returns = daily_returns(&self.equity);
+        let last = returns.len().saturating_sub(min_days);
+        let mut paths = vec![];
+        for _ in 0..self.runs {
+            let start = self.rng.gen_range(0..=last);
+            let mut equity = self.capital;
+            let mut path = vec![equity];
+            for ret in returns[start..].iter() {
+                equity *= 1.0 + ret;
+                path.push(equity);
+            }
+            paths.push(path);
+        }
+        Simulation::new(paths.into_iter())
+    }
+
+    /// 随机开始日期, 从原权益曲线中随机的一天开始用原参数重新回测到最后一天, 至少保留 `min_days` 个交易日
+    ///
+    /// 每次由 `optimizer` 的策略工厂创建新的策略, 从空仓开始, 指标按 [`Optimizer::warm_up`] 预先载入;
+    /// 股票池和数据源也取自 `optimizer`, 应与原回测相同. 每次模拟都是一次完整的回测, 耗时与 `runs` 成正比
+    pub fn random_starts<F, S, D>(&mut self, optimizer: &Optimizer<F, D>, min_days: usize) -> optimizer::Result<Simulation>
+    where
+        F: Fn(&Params) -> S + Sync,
+        S: PortfolioStrategy,
+        D: TradeDataSource + Sync,
+    {
+        let Some(&end) = self.dates.last() else { return Ok(Simulation::new(std::iter::empty())) };
+        let last = self.dates.len().saturating_sub(min_days.max(1));
+        let mut paths = vec![];
+        for _ in 0..self.runs {
+            let start = self.dates[self.rng.gen_range(0..=last)];
+            let result = optimizer.backtest(&self.params, start, end)?;
+            paths.push(result.equity_curve.iter().map(|snapshot| snapshot.equity.to_f64().unwrap_or(0.0)).collect());
+        }
+        Ok(Simulation::new(paths.into_iter()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        backtest::BackTest,
+        formula::{Formula, FormulaStrategy},
+        optimizer::{Optimizer, Params},
+    };
+
+    use super::{Distribution, MonteCarlo};
+
+    #[test]
+    fn distribution() {
+        let distribution = Distribution::new(vec![5.0, 1.0, 3.0, 2.0, 4.0]);
+        assert_eq!(distribution.samples, vec![1.0, 2.0, 3.0, 4.0, 5.0]);
+        assert_eq!(distribution.percentile(0.5), 3.0);
+        assert_eq!(distribution.percentile(0.125), 1.5);
+        assert_eq!(distribution.confidence_interval(1.0), (1.0, 5.0));
+    }
+
+    #[test]
+    fn monte_carlo() {
+        let formula = Formula::parse("BUY:CROSS(MA(C,5),MA(C,20)); SELL:CROSS(MA(C,20),MA(C,5));").unwrap();
+        let mut strategy = FormulaStrategy::with_signals(formula, "BUY", "SELL", 90).unwrap();
+        let mut back_test = BackTest::with_universe(vec!["603339"], &mut strategy).unwrap();
+        let result = back_test.run("20210101", "20221101").unwrap();
+
+        let shuffled = MonteCarlo::new(&result).runs(50).seed(1).shuffle_trades();
+        assert_eq!(shuffled.final_equity.samples.len(), 50);
+        // 有放回抽样, 最终权益随抽样变化
+        let (low, high) = shuffled.final_equity.confidence_interval(0.95);
+        assert!(high - low > 1.0);
+        assert!(shuffled.final_equity.samples.iter().all(|equity| equity.is_finite()));
+
+        let bootstrap = MonteCarlo::new(&result).runs(50).seed(1).bootstrap(10);
+        assert_eq!(bootstrap, MonteCarlo::new(&result).runs(50).seed(1).bootstrap(10));
+        let (low, high) = bootstrap.sharpe.confidence_interval(0.9);
+        assert!(low <= high);
+
+        let random_suffix = MonteCarlo::new(&result).runs(50).seed(1).random_suffix(60);
+        assert!(random_suffix.max_drawdown.samples.iter().all(|drawdown| (0.0..1.0).contains(drawdown)));
+    }
+
+    #[test]
+    fn random_starts() {
+        let factory = |_: &Params| {
+            let formula = Formula::parse("BUY:CROSS(MA(C,5),MA(C,20)); SELL:CROSS(MA(C,20),MA(C,5));").unwrap();
+            FormulaStrategy::with_signals(formula, "BUY", "SELL", 90).unwrap()
+        };
+        let mut back_test = BackTest::with_universe(vec!["603339"], factory(&Params::new())).unwrap();
+        let result = back_test.run("20210101", "20221101").unwrap();
+
+        // 每次从随机的开始日期重新回测, 开始日期不同结果也不同
+        let optimizer = Optimizer::new(vec!["603339"], factory).warm_up(20);
+        let simulation = MonteCarlo::new(&result).runs(8).seed(3).random_starts(&optimizer, 60).unwrap();
+        assert_eq!(simulation.final_equity.samples.len(), 8);
+        assert!(simulation.final_equity.std() > 0.0);
+        assert!(simulation.max_drawdown.samples.iter().all(|drawdown| (0.0..1.0).contains(drawdown)));
+        assert_eq!(simulation, MonteCarlo::new(&result).runs(8).seed(3).random_starts(&optimizer, 60).unwrap());
+    }
+}
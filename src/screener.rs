@@ -0,0 +1,263 @@
+use std::cmp::Ordering;
+
+use log::warn;
+use ta::{Close, Volume};
+
+use crate::{
+    data::{is_a_share, DataSourceError, DayTradeUnit, FinanceData, Result, StockCode, TradeDataSource},
+    formula::Formula,
+};
+
+/// 选股时一只股票的数据
+pub struct ScreenContext<'a> {
+    pub code: StockCode,
+    /// 截止到选股日(含)的K线
+    pub bars: &'a [DayTradeUnit],
+    /// 财务数据, 没有设置时为 None
+    pub finance: Option<&'a FinanceData>,
+    /// 选股日的后复权因子, K线收盘价 / 因子 为当日实际价格, K线不复权时为 1
+    pub adjust_factor: f64,
+}
+
+impl<'a> ScreenContext<'a> {
+    /// 选股日的K线
+    pub fn bar(&self) -> &DayTradeUnit {
+        &self.bars[self.bars.len() - 1]
+    }
+
+    /// 选股日的涨跌幅(%)
+    pub fn change_percent(&self) -> Option<f64> {
+        let prev = self.bars.len().checked_sub(2).map(|i| self.bars[i].close())?;
+        Some((self.bar().close() / prev - 1.0) * 100.0)
+    }
+
+    /// 选股日成交量与前 `days` 日平均成交量之比
+    pub fn volume_ratio(&self, days: usize) -> Option<f64> {
+        let len = self.bars.len();
+        if days == 0 || len <= days {
+            return None
+        }
+        let avg = self.bars[len - 1 - days..len - 1].iter().map(|bar| bar.volume()).sum::<f64>() / days as f64;
+        if avg == 0.0 {
+            return None
+        }
+        Some(self.bar().volume() / avg)
+    }
+
+    /// 公式输出线在选股日的值, 公式出错或值为 NaN 时为 None
+    pub fn formula(&self, formula: &Formula, line: &str) -> Option<f64> {
+        let output = formula.eval(self.bars).ok()?;
+        let value = *output.get(line)?.last()?;
+        (!value.is_nan()).then_some(value)
+    }
+
+    /// 财务数据, 列号见 [`FinanceData::get`]
+    pub fn finance(&self, column: usize) -> Option<f64> {
+        self.finance?.get(self.code, column).map(f64::from)
+    }
+}
+
+/// 自定义条件
+pub type CheckFn = Box<dyn Fn(&ScreenContext) -> bool + Send + Sync>;
+/// 自定义排序依据
+pub type ScoreFn = Box<dyn Fn(&ScreenContext) -> Option<f64> + Send + Sync>;
+
+/// 选股条件, 全部条件都满足的股票才会入选
+pub enum Condition {
+    /// 收盘价(元)区间
+    Close { min: f64, max: f64 },
+    /// 涨跌幅(%)区间
+    ChangePercent { min: f64, max: f64 },
+    /// 量比, 选股日成交量不低于前 `days` 日平均成交量的 `min` 倍
+    VolumeRatio { days: usize, min: f64 },
+    /// 公式输出线在选股日非 0, 例如 `CROSS(MA(C,5),MA(C,10))` 金叉
+    Formula(Formula, String),
+    /// 财务数据区间, 例如 `Finance { column: 6, min: 10.0, max: f64::MAX }` 净资产收益率不低于 10%
+    Finance { column: usize, min: f64, max: f64 },
+    Custom(CheckFn),
+}
+
+impl Condition {
+    /// 公式的第一条输出线非 0
+    pub fn formula(source: &str) -> crate::formula::Result<Self> {
+        let formula = Formula::parse(source)?;
+        let line = formula.outputs().first().map(|line| line.to_string()).unwrap_or_default();
+        Ok(Condition::Formula(formula, line))
+    }
+
+    pub fn check(&self, ctx: &ScreenContext) -> bool {
+        let between = |value: Option<f64>, min: f64, max: f64| value.map(|v| v >= min && v <= max).unwrap_or(false);
+        match self {
+            Condition::Close { min, max } => between(Some(ctx.bar().close()), *min, *max),
+            Condition::ChangePercent { min, max } => between(ctx.change_percent(), *min, *max),
+            Condition::VolumeRatio { days, min } => between(ctx.volume_ratio(*days), *min, f64::MAX),
+            Condition::Formula(formula, line) => ctx.formula(formula, line).map(|v| v != 0.0).unwrap_or(false),
+            Condition::Finance { column, min, max } => between(ctx.finance(*column), *min, *max),
+            Condition::Custom(check) => check(ctx),
+        }
+    }
+}
+
+/// 入选股票的排序依据, 从大到小排列
+pub enum Score {
+    ChangePercent,
+    VolumeRatio(usize),
+    /// 公式输出线在选股日的值
+    Formula(Formula, String),
+    /// 财务数据
+    Finance(usize),
+    Custom(ScoreFn),
+}
+
+impl Score {
+    pub fn score(&self, ctx: &ScreenContext) -> Option<f64> {
+        match self {
+            Score::ChangePercent => ctx.change_percent(),
+            Score::VolumeRatio(days) => ctx.volume_ratio(*days),
+            Score::Formula(formula, line) => ctx.formula(formula, line),
+            Score::Finance(column) => ctx.finance(*column),
+            Score::Custom(score) => score(ctx),
+        }
+    }
+}
+
+/// 入选的股票
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScreenResult {
+    pub code: StockCode,
+    /// 没有设置排序依据或无法计算时为 0
+    pub score: f64,
+    /// 选股日的收盘价
+    pub close: f64,
+}
+
+/// 一次选股的结果
+#[derive(Debug)]
+pub struct Screening {
+    /// 入选的股票, 按得分从大到小排列
+    pub results: Vec<ScreenResult>,
+    /// 读取数据出错而跳过的股票
+    pub skipped: Vec<(StockCode, DataSourceError)>,
+}
+
+/// 全市场选股
+///
+/// ```ignore
+/// let screener = Screener::new()
+///     .condition(Condition::Close { min: 5.0, max: 50.0 })
+///     .condition(Condition::formula("CROSS(MA(C,5),MA(C,10))")?)
+///     .score(Score::VolumeRatio(5));
+/// let universe = screener.universe(&StockTradeData {}, 20221101, 10)?;
+/// let back_test = BackTest::with_universe(universe, strategy)?;
+/// ```
+#[derive(Default)]
+pub struct Screener {
+    conditions: Vec<Condition>,
+    score: Option<Score>,
+    finance: Option<FinanceData>,
+    /** 全市场选股时的股票范围, 没有设置时只选A股 */
+    filter: Option<fn(StockCode) -> bool>,
+}
+
+impl Screener {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn condition(mut self, condition: Condition) -> Self {
+        self.conditions.push(condition);
+        self
+    }
+
+    pub fn score(mut self, score: Score) -> Self {
+        self.score = Some(score);
+        self
+    }
+
+    /// 财务数据, 使用 `Condition::Finance` 或 `Score::Finance` 时需要设置
+    pub fn finance(mut self, finance: FinanceData) -> Self {
+        self.finance = Some(finance);
+        self
+    }
+
+    /// 全市场选股时的股票范围, 默认 [`is_a_share`] 排除指数 基金 债券
+    pub fn filter(mut self, filter: fn(StockCode) -> bool) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// 在指定股票中选股, 选股日停牌或没有数据的股票不会入选, 读取出错的股票跳过并记录在结果中
+    pub fn screen_codes<S: TradeDataSource>(&self, source: &S, codes: &[StockCode], date: i32) -> Screening {
+        let mut results = vec![];
+        let mut skipped = vec![];
+        for &code in codes {
+            let bars: Vec<DayTradeUnit> = match source.day_duration(code, None, None) {
+                Ok(bars) => bars.take_while(|bar| bar.date <= date).collect(),
+                Err(e) => {
+                    warn!("skip {} in screening: {}", code, e);
+                    skipped.push((code, e));
+                    continue
+                }
+            };
+            if bars.last().map(|bar| bar.date) != Some(date) {
+                continue
+            }
+            let ctx = ScreenContext { code, bars: &bars, finance: self.finance.as_ref(), adjust_factor: 1.0 };
+            if !self.conditions.iter().all(|condition| condition.check(&ctx)) {
+                continue
+            }
+            let score = self.score.as_ref().and_then(|score| score.score(&ctx)).unwrap_or(0.0);
+            results.push(ScreenResult { code, score, close: ctx.bar().close() });
+        }
+        results.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
+        Screening { results, skipped }
+    }
+
+    /// 在 lday 目录下范围内的全部股票中选股
+    pub fn screen<S: TradeDataSource>(&self, source: &S, date: i32) -> Result<Screening> {
+        let filter = self.filter.unwrap_or(is_a_share);
+        let codes: Vec<StockCode> = source.codes()?.into_iter().filter(|code| filter(code)).collect();
+        Ok(self.screen_codes(source, &codes, date))
+    }
+
+    /// 得分最高的 `top` 只股票, 作为回测的股票池
+    pub fn universe<S: TradeDataSource>(&self, source: &S, date: i32, top: usize) -> Result<Vec<StockCode>> {
+        Ok(self.screen(source, date)?.results.into_iter().take(top).map(|result| result.code).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::StockTradeData;
+
+    use super::{Condition, Score, Screener};
+
+    #[test]
+    fn screen() {
+        let source = StockTradeData {};
+        let all = Screener::new().score(Score::ChangePercent).screen(&source, 20221101).unwrap().results;
+        assert_eq!(all.len(), 2);
+        assert!(all[0].score >= all[1].score);
+
+        let screener = Screener::new()
+            .condition(Condition::Close { min: 0.0, max: all[0].close })
+            .condition(Condition::formula("C>0").unwrap())
+            .condition(Condition::VolumeRatio { days: 5, min: 0.0 });
+        let codes = screener.universe(&source, 20221101, 1).unwrap();
+        assert_eq!(codes.len(), 1);
+        assert!(codes[0] == "sh603338" || codes[0] == "sh603339");
+
+        // 没有财务数据, 或当日没有交易
+        let screener = Screener::new().condition(Condition::Finance { column: 6, min: 0.0, max: f64::MAX });
+        assert!(screener.screen(&source, 20221101).unwrap().results.is_empty());
+        assert!(Screener::new().screen(&source, 20221030).unwrap().results.is_empty());
+
+        // 读取出错的股票跳过, 不影响其他股票
+        let screening = Screener::new().screen_codes(&source, &["sh603339", "sh600000"], 20221101);
+        assert_eq!(screening.results.len(), 1);
+        assert_eq!(screening.skipped.iter().map(|(code, _)| *code).collect::<Vec<_>>(), vec!["sh600000"]);
+        // 范围之外的股票不参与选股
+        let screening = Screener::new().filter(|code| code.ends_with('8')).screen(&source, 20221101).unwrap();
+        assert_eq!(screening.results.iter().map(|result| result.code).collect::<Vec<_>>(), vec!["sh603338"]);
+    }
+}
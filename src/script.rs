@@ -0,0 +1,431 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::Path,
+    rc::Rc,
+};
+
+use log::{info, warn};
+use rhai::{module_resolvers::DummyModuleResolver, CallFnOptions, Dynamic, Engine, EvalAltResult, Map, ParseError, Scope, AST};
+use rust_decimal::{
+    prelude::{FromPrimitive, ToPrimitive},
+    Decimal,
+};
+use thiserror::Error;
+
+use crate::{
+    backtest::{sync_history, MarketSnapshot, PortfolioStrategy},
+    data::{DayTradeUnit, StockCode},
+    event::{lots_of, Context, Fill, OrderKind, OrderRequest, Side},
+    formula::{Formula, FormulaState},
+    optimizer::Params,
+    strategy::TradeError,
+};
+
+#[derive(Debug, Error)]
+pub enum ScriptError {
+    #[error("read script fail")]
+    IoError {
+        #[from]
+        source: io::Error,
+    },
+    #[error("parse script fail")]
+    ParseError {
+        #[from]
+        source: ParseError,
+    },
+    #[error("run script fail")]
+    EvalError {
+        #[from]
+        source: Box<EvalAltResult>,
+    },
+    #[error("script must define fn next(ctx)")]
+    MissingNext,
+}
+
+pub type Result<T, E = ScriptError> = std::result::Result<T, E>;
+
+/// 脚本的执行限制, 防止死循环或占用过多内存拖垮回测
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScriptLimits {
+    /// 每次回调最多执行的操作数
+    pub max_operations: u64,
+    /// 函数调用的最大深度
+    pub max_call_levels: usize,
+    pub max_string_size: usize,
+    pub max_array_size: usize,
+    pub max_map_size: usize,
+}
+
+impl Default for ScriptLimits {
+    fn default() -> Self {
+        ScriptLimits {
+            max_operations: 1_000_000,
+            max_call_levels: 32,
+            max_string_size: 4096,
+            max_array_size: 100_000,
+            max_map_size: 10_000,
+        }
+    }
+}
+
+type Orders = Rc<RefCell<Vec<(Side, Decimal, OrderKind)>>>;
+
+/// 按股票和公式源码缓存的公式计算状态
+type Formulas = Rc<RefCell<HashMap<(StockCode, String), FormulaState>>>;
+
+/// 读取K线的一个字段
+type Field = fn(&DayTradeUnit) -> i32;
+
+/// 传给脚本的上下文, 脚本中的类型名为 `Context`
+///
+/// 只暴露当前股票的K线, 账户的只读信息和下单接口, 委托在回调返回后交给回测引擎撮合
+#[derive(Clone)]
+struct ScriptContext {
+    code: StockCode,
+    date: i32,
+    balance: Decimal,
+    position: Decimal,
+    pending: bool,
+    bars: Rc<Vec<DayTradeUnit>>,
+    params: Rc<Params>,
+    formulas: Formulas,
+    orders: Orders,
+}
+
+impl ScriptContext {
+    /// `ago` 根K线之前的字段值, 0 为当日, 超出范围为 NaN
+    fn field(&self, ago: i64, field: Field) -> f64 {
+        match usize::try_from(ago) {
+            Ok(ago) if ago < self.bars.len() => field(&self.bars[self.bars.len() - 1 - ago]) as f64,
+            _ => f64::NAN,
+        }
+    }
+
+    /// 公式输出线的最新值, 每只股票的公式按源码缓存计算状态, 只计算新增的K线
+    fn formula(&self, source: &str, line: Option<&str>) -> std::result::Result<f64, Box<EvalAltResult>> {
+        let mut formulas = self.formulas.borrow_mut();
+        let key = (self.code, source.to_string());
+        if !formulas.contains_key(&key) {
+            let mut formula = Formula::parse(source).map_err(|e| e.to_string())?;
+            for (param, value) in self.params.iter() {
+                formula = formula.param(param, *value);
+            }
+            formulas.insert(key.clone(), FormulaState::new(&formula).map_err(|e| e.to_string())?);
+        }
+        let state = formulas.get_mut(&key).unwrap();
+        state.sync(&self.bars);
+        match line {
+            Some(line) => state.latest(line).ok_or_else(|| format!("formula has no line {}", line).into()),
+            None => state.current().last().copied().ok_or_else(|| "formula has no output line".into()),
+        }
+    }
+
+    fn submit(&mut self, side: Side, vol: i64, kind: OrderKind) -> std::result::Result<(), Box<EvalAltResult>> {
+        if vol <= 0 {
+            return Err(format!("invalid volume {}", vol).into());
+        }
+        self.orders.borrow_mut().push((side, Decimal::from(vol), kind));
+        Ok(())
+    }
+
+    fn limit(price: f64) -> std::result::Result<OrderKind, Box<EvalAltResult>> {
+        match Decimal::from_f64(price) {
+            Some(price) if price > Decimal::ZERO => Ok(OrderKind::Limit(price.round_dp(2))),
+            _ => Err(format!("invalid price {}", price).into()),
+        }
+    }
+}
+
+fn register(engine: &mut Engine) {
+    engine
+        .register_type_with_name::<ScriptContext>("Context")
+        .register_get("code", |ctx: &mut ScriptContext| ctx.code.to_string())
+        .register_get("date", |ctx: &mut ScriptContext| ctx.date as i64)
+        .register_get("balance", |ctx: &mut ScriptContext| ctx.balance.to_f64().unwrap_or(0.0))
+        .register_get("position", |ctx: &mut ScriptContext| ctx.position.to_i64().unwrap_or(0))
+        .register_get("pending", |ctx: &mut ScriptContext| ctx.pending)
+        .register_fn("len", |ctx: &mut ScriptContext| ctx.bars.len() as i64)
+        .register_fn("param", |ctx: &mut ScriptContext, name: &str| -> std::result::Result<f64, Box<EvalAltResult>> {
+            ctx.params.get(name).copied().ok_or_else(|| format!("unknown param {}", name).into())
+        })
+        .register_fn("formula", |ctx: &mut ScriptContext, source: &str| ctx.formula(source, None))
+        .register_fn("formula", |ctx: &mut ScriptContext, source: &str, line: &str| ctx.formula(source, Some(line)))
+        .register_fn("buy", |ctx: &mut ScriptContext, vol: i64| ctx.submit(Side::Buy, vol, OrderKind::Market))
+        .register_fn("buy", |ctx: &mut ScriptContext, vol: i64, price: f64| ctx.submit(Side::Buy, vol, ScriptContext::limit(price)?))
+        .register_fn("sell", |ctx: &mut ScriptContext, vol: i64| ctx.submit(Side::Sell, vol, OrderKind::Market))
+        .register_fn("sell", |ctx: &mut ScriptContext, vol: i64, price: f64| ctx.submit(Side::Sell, vol, ScriptContext::limit(price)?))
+        .register_fn("buy_percent", |ctx: &mut ScriptContext, percent: f64| {
+            let close = Decimal::from(ctx.bars.last().map(|bar| bar.trade_data.close).unwrap_or(0)) / Decimal::from(100);
+            let percent = Decimal::from_f64(percent.clamp(0.0, 100.0)).unwrap_or_default();
+            let vol = lots_of(ctx.balance * percent / Decimal::from(100), close);
+            if !vol.is_zero() {
+                ctx.orders.borrow_mut().push((Side::Buy, vol, OrderKind::Market));
+            }
+        })
+        .register_fn("sell_all", |ctx: &mut ScriptContext| {
+            if !ctx.position.is_zero() {
+                let position = ctx.position;
+                ctx.orders.borrow_mut().push((Side::Sell, position, OrderKind::Market));
+            }
+        });
+    let fields: [(&str, Field); 4] = [
+        ("open", |bar| bar.trade_data.open),
+        ("high", |bar| bar.trade_data.high),
+        ("low", |bar| bar.trade_data.low),
+        ("close", |bar| bar.trade_data.close),
+    ];
+    for (name, field) in fields {
+        engine
+            .register_fn(name, move |ctx: &mut ScriptContext| ctx.field(0, field) / 100.0)
+            .register_fn(name, move |ctx: &mut ScriptContext, ago: i64| ctx.field(ago, field) / 100.0);
+    }
+    engine
+        .register_fn("volume", |ctx: &mut ScriptContext| ctx.field(0, |bar| bar.trade_data.volume))
+        .register_fn("volume", |ctx: &mut ScriptContext, ago: i64| ctx.field(ago, |bar| bar.trade_data.volume));
+}
+
+/// 用 Rhai 脚本编写的策略, 修改策略不需要重新编译
+///
+/// 脚本必须定义 `fn next(ctx)`, 每个交易日对股票池内每只有交易的股票调用一次.
+/// 可选定义 `fn on_fill(side, vol, price)`, 成交后调用, `side` 为 "buy" 或 "sell".
+/// 回调中的 `this` 是该股票独立的对象, 可以在交易日之间保存状态.
+///
+/// `ctx` 提供:
+/// - `ctx.code` `ctx.date` `ctx.balance`(已扣除当日先处理的股票买入委托的估算成本) `ctx.position`(持仓股数) `ctx.pending`(有未成交的委托)
+/// - `ctx.open()` `ctx.high()` `ctx.low()` `ctx.close()` `ctx.volume()`, 带参数 `ctx.close(1)` 为前一日, 超出范围为 NaN
+/// - `ctx.len()` K线数量, `ctx.param("fast")` 策略参数
+/// - `ctx.formula("MA(C,FAST)")` 通达信公式最后一条输出线的最新值, `ctx.formula(src, "DIF")` 取指定输出线
+/// - `ctx.buy(vol)` `ctx.sell(vol)` 市价委托, `ctx.buy(vol, price)` `ctx.sell(vol, price)` 限价委托,
+///   `ctx.buy_percent(50.0)` 按收盘价用余额的百分比整手买入, `ctx.sell_all()` 卖出全部持仓
+///
+/// ```ignore
+/// let mut strategy = ScriptStrategy::new(r#"
+///     fn next(ctx) {
+///         let fast = ctx.formula("MA(C,5)");
+///         let slow = ctx.formula("MA(C,20)");
+///         if ctx.pending { return; }
+///         if fast > slow && ctx.position == 0 { ctx.buy_percent(90.0); }
+///         if fast < slow && ctx.position > 0 { ctx.sell_all(); }
+///     }
+/// "#)?;
+/// let mut back_test = BackTest::with_universe(vec!["603339"], &mut strategy)?;
+/// ```
+pub struct ScriptStrategy {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    on_fill: bool,
+    params: Rc<Params>,
+    formulas: Formulas,
+    /** 每只股票的K线, 随行情追加 */
+    bars: HashMap<StockCode, Rc<Vec<DayTradeUnit>>>,
+    /** 每只股票脚本中的 this */
+    states: HashMap<StockCode, Dynamic>,
+    /** 已提交但还没有回报的委托 */
+    pending: HashSet<StockCode>,
+    errors: Vec<String>,
+}
+
+impl ScriptStrategy {
+    pub fn new(source: &str) -> Result<Self> {
+        Self::with_limits(source, ScriptLimits::default())
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::new(&fs::read_to_string(path)?)
+    }
+
+    /// 脚本不能导入模块或使用 eval, 超出执行限制时该次回调中止并记录错误
+    pub fn with_limits(source: &str, limits: ScriptLimits) -> Result<Self> {
+        let mut engine = Engine::new();
+        engine
+            .set_max_operations(limits.max_operations)
+            .set_max_call_levels(limits.max_call_levels)
+            .set_max_string_size(limits.max_string_size)
+            .set_max_array_size(limits.max_array_size)
+            .set_max_map_size(limits.max_map_size)
+            .set_max_expr_depths(64, 32)
+            .set_module_resolver(DummyModuleResolver::new())
+            .disable_symbol("eval")
+            .on_print(|text| info!("script: {}", text))
+            .on_debug(|text, _, pos| info!("script {}: {}", pos, text));
+        register(&mut engine);
+        let ast = engine.compile(source)?;
+        let has = |name: &str, arity: usize| ast.iter_functions().any(|f| f.name == name && f.params.len() == arity);
+        if !has("next", 1) {
+            return Err(ScriptError::MissingNext);
+        }
+        let on_fill = has("on_fill", 3);
+        // 执行顶层语句, 例如全局常量
+        let mut scope = Scope::new();
+        engine.run_ast_with_scope(&mut scope, &ast)?;
+        Ok(ScriptStrategy {
+            engine,
+            ast,
+            scope,
+            on_fill,
+            params: Rc::new(Params::new()),
+            formulas: Rc::new(RefCell::new(HashMap::new())),
+            bars: HashMap::new(),
+            states: HashMap::new(),
+            pending: HashSet::new(),
+            errors: vec![],
+        })
+    }
+
+    /// 策略参数, 脚本中通过 `ctx.param(name)` 读取, 也作为 `ctx.formula` 中公式的参数
+    pub fn params(mut self, params: Params) -> Self {
+        self.params = Rc::new(params);
+        self.formulas.borrow_mut().clear();
+        self
+    }
+
+    /// 脚本运行时的错误, 出错的回调被跳过, 其中已下的委托也被丢弃, 回测继续
+    pub fn errors(&self) -> &[String] {
+        &self.errors
+    }
+
+    /// 调用脚本函数, 出错时记录错误并返回 false
+    fn call(&mut self, code: StockCode, date: i32, name: &str, args: impl rhai::FuncArgs) -> bool {
+        let state = self.states.entry(code).or_insert_with(|| Dynamic::from(Map::new()));
+        let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(state);
+        match self.engine.call_fn_with_options::<Dynamic>(options, &mut self.scope, &self.ast, name, args) {
+            Ok(_) => true,
+            Err(e) => {
+                warn!("script {} failed for {} on {}: {}", name, code, date, e);
+                self.errors.push(format!("{} {} {}: {}", date, code, name, e));
+                false
+            }
+        }
+    }
+
+    fn history(&mut self, code: StockCode, market: &MarketSnapshot) -> Rc<Vec<DayTradeUnit>> {
+        let bars = self.bars.entry(code).or_default();
+        sync_history(bars, market.history(code));
+        bars.clone()
+    }
+}
+
+impl PortfolioStrategy for ScriptStrategy {
+    fn on_bar(&mut self, ctx: &mut Context, market: &MarketSnapshot) {
+        // 先处理的股票提交的买入委托占用资金, 后面的股票看到的是扣除估算成本后的余额
+        let fees = ctx.account().fee_model();
+        let mut balance = ctx.get_balance();
+        for code in market.codes() {
+            let orders: Orders = Rc::new(RefCell::new(vec![]));
+            let script_ctx = ScriptContext {
+                code,
+                date: market.date(),
+                balance,
+                position: match ctx.get_position(code) {
+                    Ok(Some(position)) => position.volume(),
+                    _ => Decimal::ZERO,
+                },
+                pending: self.pending.contains(code),
+                bars: self.history(code, market),
+                params: self.params.clone(),
+                formulas: self.formulas.clone(),
+                orders: orders.clone(),
+            };
+            // 脚本出错或超出运算次数时, 这次回调中的委托不提交
+            if !self.call(code, market.date(), "next", (script_ctx,)) {
+                continue;
+            }
+            for (side, vol, kind) in orders.take() {
+                if side == Side::Buy {
+                    let price = match kind {
+                        OrderKind::Limit(price) => price,
+                        OrderKind::Market => market.close(code).unwrap_or_default(),
+                    };
+                    balance = (balance - fees.buy_cost(price, vol)).max(Decimal::ZERO);
+                }
+                ctx.submit(code, side, vol, kind);
+                self.pending.insert(code);
+            }
+        }
+    }
+
+    fn on_fill(&mut self, _ctx: &mut Context, fill: &Fill) {
+        self.pending.remove(fill.order.code);
+        if self.on_fill {
+            let side = match fill.order.side {
+                Side::Buy => "buy",
+                Side::Sell => "sell",
+            };
+            let args = (side.to_string(), fill.order.vol.to_i64().unwrap_or(0), fill.price.to_f64().unwrap_or(0.0));
+            self.call(fill.order.code, fill.date, "on_fill", args);
+        }
+    }
+
+    fn on_order_rejected(&mut self, _ctx: &mut Context, order: &OrderRequest, _reason: &TradeError) {
+        self.pending.remove(order.code);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, path::Path};
+
+    use crate::{
+        backtest::BackTest,
+        formula::{Formula, FormulaStrategy},
+        optimizer::Params,
+    };
+
+    use super::{ScriptError, ScriptLimits, ScriptStrategy};
+
+    #[test]
+    fn script_strategy() {
+        let root = env::var("MILLIONS_TDX").unwrap();
+        let params: Params = [("FAST".to_string(), 5.0), ("SLOW".to_string(), 20.0)].into_iter().collect();
+        let mut strategy = ScriptStrategy::open(Path::new(&root).join("ma_cross.rhai")).unwrap().params(params);
+        let result = BackTest::with_universe(vec!["603339"], &mut strategy).unwrap().run("20210101", "20221101").unwrap();
+        assert!(strategy.errors().is_empty(), "{:?}", strategy.errors());
+
+        // 与同样逻辑的公式策略成交一致
+        let formula = Formula::parse("BUY:CROSS(MA(C,5),MA(C,20)); SELL:CROSS(MA(C,20),MA(C,5));").unwrap();
+        let mut expected = FormulaStrategy::with_signals(formula, "BUY", "SELL", 90).unwrap();
+        let mut back_test = BackTest::with_universe(vec!["603339"], &mut expected).unwrap();
+        let expected = back_test.run("20210101", "20221101").unwrap();
+        assert!(!result.trades.is_empty());
+        assert_eq!(result.trades, expected.trades);
+    }
+
+    #[test]
+    fn queued_buys_reduce_balance() {
+        // 两只股票各用余额的 60% 买入, 第二只看到的余额已扣除第一只的委托, 不会因资金不足被拒
+        let mut strategy = ScriptStrategy::new("fn next(ctx) { if ctx.position == 0 && !ctx.pending { ctx.buy_percent(60.0); } }").unwrap();
+        let result = BackTest::with_universe(vec!["603339", "603338"], &mut strategy).unwrap().run("20221010", "20221101").unwrap();
+        assert!(strategy.errors().is_empty(), "{:?}", strategy.errors());
+        assert!(result.rejected.is_empty(), "{:?}", result.rejected);
+        assert_eq!(result.trades.len(), 2);
+    }
+
+    #[test]
+    fn sandbox() {
+        assert!(matches!(ScriptStrategy::new("fn on_bar(ctx) {}"), Err(ScriptError::MissingNext)));
+        assert!(matches!(ScriptStrategy::new("import \"os\" as os; fn next(ctx) {}"), Err(ScriptError::EvalError { .. })));
+
+        let limits = ScriptLimits { max_operations: 10_000, ..ScriptLimits::default() };
+        let mut strategy = ScriptStrategy::with_limits("fn next(ctx) { loop { ctx.buy(-1); } }", limits).unwrap();
+        let result = BackTest::with_universe(vec!["603339"], &mut strategy).unwrap().run("20221001", "20221101").unwrap();
+        assert!(result.trades.is_empty());
+        assert!(!strategy.errors().is_empty());
+
+        let mut strategy = ScriptStrategy::with_limits("fn next(ctx) { loop {} }", limits).unwrap();
+        BackTest::with_universe(vec!["603339"], &mut strategy).unwrap().run("20221001", "20221101").unwrap();
+        assert!(strategy.errors()[0].contains("operations"), "{}", strategy.errors()[0]);
+
+        // 出错前已下的委托不会提交
+        let mut strategy = ScriptStrategy::with_limits("fn next(ctx) { ctx.buy(100); loop {} }", limits).unwrap();
+        let result = BackTest::with_universe(vec!["603339"], &mut strategy).unwrap().run("20221001", "20221101").unwrap();
+        assert!(result.trades.is_empty() && result.rejected.is_empty());
+        assert!(strategy.errors()[0].contains("operations"), "{}", strategy.errors()[0]);
+        let mut strategy = ScriptStrategy::new("fn next(ctx) { ctx.buy(100); throw \"stop\"; }").unwrap();
+        let result = BackTest::with_universe(vec!["603339"], &mut strategy).unwrap().run("20221001", "20221101").unwrap();
+        assert!(result.trades.is_empty());
+        assert!(!strategy.errors().is_empty());
+    }
+}
@@ -0,0 +1,177 @@
+use std::{any::Any, collections::HashMap};
+
+use ta::Next;
+
+use crate::data::DayTradeUnit;
+
+/// 指标序列, 每根K线计算一次, 保存回测开始以来的全部结果
+///
+/// 结果与K线一一对应, 第 i 个结果对应回测区间内第 i 根K线
+pub trait Serise {
+    /// 用新的K线计算指标
+    fn add(&mut self, bar: &DayTradeUnit);
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 指标结果 `Vec<D>`, 由 `get` 按结果类型取回
+    fn data(&self) -> &dyn Any;
+}
+
+impl<'s> dyn Serise + 's {
+    /// 按结果类型取回指标历史, 类型不符时为 None
+    pub fn get<T: 'static>(&self) -> Option<&Vec<T>> {
+        self.data().downcast_ref()
+    }
+}
+
+/// 把任意 `ta::Next` 指标包装为序列
+///
+/// `ta` 的指标大多对实现了 `Close` 的输入按收盘价计算, ATR 等按最高最低价和收盘价计算
+pub struct SeriseContainer<F, D> {
+    data: Vec<D>,
+    formula: F,
+}
+
+impl<F, D> SeriseContainer<F, D> {
+    pub fn new(formula: F) -> Self {
+        SeriseContainer { data: vec![], formula }
+    }
+
+    pub fn formula(&self) -> &F {
+        &self.formula
+    }
+}
+
+impl<F, D> Serise for SeriseContainer<F, D>
+where
+    F: for<'b> Next<&'b DayTradeUnit, Output = D>,
+    D: 'static,
+{
+    fn add(&mut self, bar: &DayTradeUnit) {
+        let result = self.formula.next(bar);
+        self.data.push(result);
+    }
+
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    fn data(&self) -> &dyn Any {
+        &self.data
+    }
+}
+
+/// 一只股票的指标, 策略按名称声明一次, 回测引擎在每根K线自动计算
+///
+/// ```ignore
+/// registry.add("sma5", SimpleMovingAverage::new(5).unwrap());
+/// registry.add("macd", MovingAverageConvergenceDivergence::new(12, 26, 9).unwrap());
+/// let macd: &Vec<MovingAverageConvergenceDivergenceOutput> = registry.get("macd").unwrap();
+/// ```
+#[derive(Default)]
+pub struct SeriseRegistry {
+    serises: HashMap<String, Box<dyn Serise>>,
+}
+
+impl SeriseRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 声明指标, 同名的指标会被替换
+    pub fn add<F, D>(&mut self, name: &str, formula: F)
+    where
+        F: for<'b> Next<&'b DayTradeUnit, Output = D> + 'static,
+        D: 'static,
+    {
+        self.insert(name, Box::new(SeriseContainer::new(formula)));
+    }
+
+    /// 声明自定义的指标序列
+    pub fn insert(&mut self, name: &str, serise: Box<dyn Serise>) {
+        self.serises.insert(name.to_string(), serise);
+    }
+
+    /// 用新的K线计算全部指标
+    pub fn feed(&mut self, bar: &DayTradeUnit) {
+        for serise in self.serises.values_mut() {
+            serise.add(bar);
+        }
+    }
+
+    pub fn serise(&self, name: &str) -> Option<&dyn Serise> {
+        self.serises.get(name).map(|serise| serise.as_ref())
+    }
+
+    /// 指标历史, 名称不存在或结果类型不符时为 None
+    pub fn get<T: 'static>(&self, name: &str) -> Option<&Vec<T>> {
+        self.serise(name)?.get()
+    }
+
+    /// 最新的指标值
+    pub fn last<T: 'static>(&self, name: &str) -> Option<&T> {
+        self.get(name)?.last()
+    }
+
+    /// 已声明的指标名称
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.serises.keys().map(|name| name.as_str()).collect();
+        names.sort();
+        names
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.serises.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ta::indicators::{
+        AverageTrueRange, BollingerBands, BollingerBandsOutput, RelativeStrengthIndex, SimpleMovingAverage,
+    };
+
+    use crate::{
+        data::{DayTradeUnit, TradeUnit},
+        indicator::{KDJOutput, KDJ},
+    };
+
+    use super::SeriseRegistry;
+
+    fn bar(date: i32, high: i32, low: i32, close: i32) -> DayTradeUnit {
+        DayTradeUnit { date, trade_data: TradeUnit { open: close, high, low, close, volume: 100, amount: 0.0 } }
+    }
+
+    #[test]
+    fn registry() {
+        let mut registry = SeriseRegistry::new();
+        registry.add("sma3", SimpleMovingAverage::new(3).unwrap());
+        registry.add("rsi", RelativeStrengthIndex::new(6).unwrap());
+        registry.add("boll", BollingerBands::new(3, 2.0).unwrap());
+        registry.add("atr", AverageTrueRange::new(3).unwrap());
+        registry.add("kdj", KDJ::default());
+        assert_eq!(registry.names(), vec!["atr", "boll", "kdj", "rsi", "sma3"]);
+
+        let bars = [bar(1, 1100, 1000, 1050), bar(2, 1200, 1050, 1200), bar(3, 1200, 1100, 1150), bar(4, 1150, 1000, 1000)];
+        for bar in bars.iter() {
+            registry.feed(bar);
+        }
+        let sma: &Vec<f64> = registry.get("sma3").unwrap();
+        assert_eq!(sma.len(), 4);
+        assert!((sma[3] - (12.0 + 11.5 + 10.0) / 3.0).abs() < 1e-9);
+        assert_eq!(registry.get::<f64>("boll"), None);
+        assert_eq!(registry.get::<BollingerBandsOutput>("boll").unwrap().len(), 4);
+        assert!(registry.get::<f64>("missing").is_none());
+
+        // 第一根K线 RSV = 50, K D 取 RSV 本身; 第二根 RSV = 100
+        let kdj: &Vec<KDJOutput> = registry.get("kdj").unwrap();
+        assert_eq!(kdj[0], KDJOutput { k: 50.0, d: 50.0, j: 50.0 });
+        assert!((kdj[1].k - 200.0 / 3.0).abs() < 1e-9);
+        assert!((kdj[1].d - (200.0 / 3.0 + 100.0) / 3.0).abs() < 1e-9);
+        assert!(registry.last::<KDJOutput>("kdj").map(|kdj| (kdj.j - (3.0 * kdj.k - 2.0 * kdj.d)).abs() < 1e-9).unwrap());
+    }
+}
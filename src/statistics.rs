@@ -1,3 +1,486 @@
-pub fn EMA() -> () {
-    
-}
\ No newline at end of file
+use std::{collections::HashMap, fmt};
+
+use rust_decimal::prelude::ToPrimitive;
+use serde::Serialize;
+
+use crate::{
+    data::DayTradeUnit,
+    strategy::{Account, EquitySnapshot, Order},
+};
+
+/// 年化使用的交易日数
+pub const TRADING_DAYS: f64 = 252.0;
+
+/// 回测绩效统计
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Statistics {
+    /// 交易日数
+    pub days: usize,
+    /// 总收益率
+    pub total_return: f64,
+    /// 年化收益率
+    pub annualized_return: f64,
+    /// 年化波动率
+    pub volatility: f64,
+    pub sharpe: f64,
+    pub sortino: f64,
+    /// 年化收益率 / 最大回撤
+    pub calmar: f64,
+    /// 最大回撤, 0.2 表示 20%
+    pub max_drawdown: f64,
+    /// 最大回撤持续的交易日数, 从前高到收复前高(或回测结束)
+    pub max_drawdown_duration: usize,
+    /// 卖出次数
+    pub trades: usize,
+    /// 盈利的卖出占比
+    pub win_rate: f64,
+    /// 总盈利 / 总亏损
+    pub profit_factor: f64,
+    /// 平均每笔盈利
+    pub average_win: f64,
+    /// 平均每笔亏损(负数)
+    pub average_loss: f64,
+    /// 持仓市值占总权益的平均比例
+    pub exposure: f64,
+    /// 年化换手率: 成交额 / 平均权益
+    pub turnover: f64,
+}
+
+impl Statistics {
+    /// 用权益曲线和订单计算统计, 无风险利率为0
+    pub fn new(equity: &[EquitySnapshot], orders: &[&Order]) -> Self {
+        Self::with_risk_free(equity, orders, 0.0)
+    }
+
+    /// 用权益曲线和订单计算统计
+    ///
+    /// # Parameter
+    ///
+    /// * `risk_free` - 年化无风险利率 例如 0.02 表示 2%
+    pub fn with_risk_free(equity: &[EquitySnapshot], orders: &[&Order], risk_free: f64) -> Self {
+        let values: Vec<f64> = equity.iter().map(|snapshot| to_f64(snapshot.equity)).collect();
+        let returns = daily_returns(&values);
+        let total_return = match (values.first(), values.last()) {
+            (Some(first), Some(last)) if *first != 0.0 => last / first - 1.0,
+            _ => 0.0,
+        };
+        let annualized_return = annualize(total_return, returns.len());
+        let volatility = std_dev(&returns) * TRADING_DAYS.sqrt();
+        let (max_drawdown, max_drawdown_duration) = max_drawdown(&values);
+        let calmar = if max_drawdown > 0.0 { annualized_return / max_drawdown } else { 0.0 };
+
+        let closed: Vec<f64> = orders
+            .iter()
+            .filter(|order| order.vol().is_sign_negative() && !order.vol().is_zero())
+            .map(|order| to_f64(order.realized()))
+            .collect();
+        let wins: Vec<f64> = closed.iter().copied().filter(|pnl| *pnl > 0.0).collect();
+        let losses: Vec<f64> = closed.iter().copied().filter(|pnl| *pnl <= 0.0).collect();
+        let gross_profit: f64 = wins.iter().sum();
+        let gross_loss: f64 = -losses.iter().sum::<f64>();
+        let profit_factor = if gross_loss > 0.0 {
+            gross_profit / gross_loss
+        } else if gross_profit > 0.0 {
+            f64::INFINITY
+        } else {
+            0.0
+        };
+
+        let exposure = mean(
+            &equity
+                .iter()
+                .filter(|snapshot| !snapshot.equity.is_zero())
+                .map(|snapshot| to_f64(snapshot.market_value / snapshot.equity))
+                .collect::<Vec<f64>>(),
+        );
+        let traded: f64 = orders.iter().map(|order| to_f64((order.vol() * order.price()).abs())).sum();
+        let average_equity = mean(&values);
+        let turnover = if average_equity > 0.0 && !values.is_empty() {
+            traded / average_equity * TRADING_DAYS / values.len() as f64
+        } else {
+            0.0
+        };
+
+        Statistics {
+            days: values.len(),
+            total_return,
+            annualized_return,
+            volatility,
+            sharpe: sharpe(&returns, risk_free),
+            sortino: sortino(&returns, risk_free),
+            calmar,
+            max_drawdown,
+            max_drawdown_duration,
+            trades: closed.len(),
+            win_rate: if closed.is_empty() { 0.0 } else { wins.len() as f64 / closed.len() as f64 },
+            profit_factor,
+            average_win: mean(&wins),
+            average_loss: mean(&losses),
+            exposure,
+            turnover,
+        }
+    }
+
+    /// 用账户的权益曲线和全部订单计算统计
+    pub fn from_account(account: &Account) -> Self {
+        let orders: Vec<&Order> = account.orders().into_iter().map(|(_, order)| order).collect();
+        Self::new(account.equity_curve(), &orders)
+    }
+}
+
+impl fmt::Display for Statistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<24}{:>12}", "days", self.days)?;
+        writeln!(f, "{:<24}{:>11.2}%", "total return", self.total_return * 100.0)?;
+        writeln!(f, "{:<24}{:>11.2}%", "annualized return", self.annualized_return * 100.0)?;
+        writeln!(f, "{:<24}{:>11.2}%", "volatility", self.volatility * 100.0)?;
+        writeln!(f, "{:<24}{:>12.2}", "sharpe", self.sharpe)?;
+        writeln!(f, "{:<24}{:>12.2}", "sortino", self.sortino)?;
+        writeln!(f, "{:<24}{:>12.2}", "calmar", self.calmar)?;
+        writeln!(f, "{:<24}{:>11.2}%", "max drawdown", self.max_drawdown * 100.0)?;
+        writeln!(f, "{:<24}{:>12}", "max drawdown days", self.max_drawdown_duration)?;
+        writeln!(f, "{:<24}{:>12}", "trades", self.trades)?;
+        writeln!(f, "{:<24}{:>11.2}%", "win rate", self.win_rate * 100.0)?;
+        writeln!(f, "{:<24}{:>12.2}", "profit factor", self.profit_factor)?;
+        writeln!(f, "{:<24}{:>12.2}", "average win", self.average_win)?;
+        writeln!(f, "{:<24}{:>12.2}", "average loss", self.average_loss)?;
+        writeln!(f, "{:<24}{:>11.2}%", "exposure", self.exposure * 100.0)?;
+        write!(f, "{:<24}{:>12.2}", "turnover", self.turnover)
+    }
+}
+
+/// 相对基准(例如沪深300)的统计
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BenchmarkStatistics {
+    /// 基准总收益率
+    pub benchmark_return: f64,
+    /// 年化 alpha
+    pub alpha: f64,
+    pub beta: f64,
+    /// 信息比率
+    pub information_ratio: f64,
+    /// 年化跟踪误差
+    pub tracking_error: f64,
+    /// 上行捕获率: 基准上涨日策略平均收益 / 基准平均收益
+    pub up_capture: f64,
+    /// 下行捕获率: 基准下跌日策略平均收益 / 基准平均收益
+    pub down_capture: f64,
+    /// 基准累计收益曲线, (日期, 累计收益率)
+    pub benchmark_curve: Vec<(i32, f64)>,
+    /// 超额收益曲线, (日期, 策略累计收益率 - 基准累计收益率)
+    pub excess_curve: Vec<(i32, f64)>,
+}
+
+impl BenchmarkStatistics {
+    /// 按日期对齐权益曲线和基准K线, 只使用两者都有数据的交易日
+    pub fn new(equity: &[EquitySnapshot], benchmark: &[DayTradeUnit]) -> Self {
+        let closes: HashMap<i32, f64> = benchmark
+            .iter()
+            .map(|bar| (bar.date, bar.trade_data.close as f64 / 100.0))
+            .collect();
+        let aligned: Vec<(i32, f64, f64)> = equity
+            .iter()
+            .filter_map(|snapshot| {
+                closes
+                    .get(&snapshot.date)
+                    .map(|close| (snapshot.date, to_f64(snapshot.equity), *close))
+            })
+            .collect();
+        let strategy: Vec<f64> = aligned.iter().map(|(_, equity, _)| *equity).collect();
+        let index: Vec<f64> = aligned.iter().map(|(_, _, close)| *close).collect();
+        let rs = daily_returns(&strategy);
+        let rb = daily_returns(&index);
+
+        let beta = match variance(&rb) {
+            v if v > 0.0 => covariance(&rs, &rb) / v,
+            _ => 0.0,
+        };
+        let alpha = (mean(&rs) - beta * mean(&rb)) * TRADING_DAYS;
+        let excess: Vec<f64> = rs.iter().zip(rb.iter()).map(|(s, b)| s - b).collect();
+        let tracking_error = std_dev(&excess) * TRADING_DAYS.sqrt();
+        let information_ratio = if tracking_error > 0.0 { mean(&excess) * TRADING_DAYS / tracking_error } else { 0.0 };
+
+        let benchmark_curve: Vec<(i32, f64)> = aligned
+            .iter()
+            .map(|(date, _, close)| (*date, cumulative(*close, index.first())))
+            .collect();
+        let excess_curve = aligned
+            .iter()
+            .zip(benchmark_curve.iter())
+            .map(|((date, equity, _), (_, benchmark))| (*date, cumulative(*equity, strategy.first()) - benchmark))
+            .collect();
+
+        BenchmarkStatistics {
+            benchmark_return: benchmark_curve.last().map(|(_, r)| *r).unwrap_or(0.0),
+            alpha,
+            beta,
+            information_ratio,
+            tracking_error,
+            up_capture: capture(&rs, &rb, |r| r > 0.0),
+            down_capture: capture(&rs, &rb, |r| r < 0.0),
+            benchmark_curve,
+            excess_curve,
+        }
+    }
+}
+
+impl fmt::Display for BenchmarkStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<24}{:>11.2}%", "benchmark return", self.benchmark_return * 100.0)?;
+        writeln!(f, "{:<24}{:>11.2}%", "alpha", self.alpha * 100.0)?;
+        writeln!(f, "{:<24}{:>12.2}", "beta", self.beta)?;
+        writeln!(f, "{:<24}{:>12.2}", "information ratio", self.information_ratio)?;
+        writeln!(f, "{:<24}{:>11.2}%", "tracking error", self.tracking_error * 100.0)?;
+        writeln!(f, "{:<24}{:>11.2}%", "up capture", self.up_capture * 100.0)?;
+        write!(f, "{:<24}{:>11.2}%", "down capture", self.down_capture * 100.0)
+    }
+}
+
+fn cumulative(value: f64, first: Option<&f64>) -> f64 {
+    match first {
+        Some(first) if *first != 0.0 => value / first - 1.0,
+        _ => 0.0,
+    }
+}
+
+/// 按基准收益率筛选交易日, 策略平均收益 / 基准平均收益
+fn capture(rs: &[f64], rb: &[f64], select: impl Fn(f64) -> bool) -> f64 {
+    let (s, b): (Vec<f64>, Vec<f64>) = rs
+        .iter()
+        .zip(rb.iter())
+        .filter(|(_, b)| select(**b))
+        .map(|(s, b)| (*s, *b))
+        .unzip();
+    match mean(&b) {
+        m if m != 0.0 => mean(&s) / m,
+        _ => 0.0,
+    }
+}
+
+fn to_f64(value: rust_decimal::Decimal) -> f64 {
+    value.to_f64().unwrap_or(0.0)
+}
+
+/// 日收益率序列
+pub fn daily_returns(values: &[f64]) -> Vec<f64> {
+    values
+        .windows(2)
+        .map(|w| if w[0] != 0.0 { w[1] / w[0] - 1.0 } else { 0.0 })
+        .collect()
+}
+
+/// 把 `periods` 个交易日的总收益率换算为年化收益率
+pub fn annualize(total_return: f64, periods: usize) -> f64 {
+    if periods == 0 || total_return <= -1.0 {
+        return 0.0;
+    }
+    (1.0 + total_return).powf(TRADING_DAYS / periods as f64) - 1.0
+}
+
+pub fn mean(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// 样本标准差
+pub fn std_dev(values: &[f64]) -> f64 {
+    if values.len() < 2 {
+        return 0.0;
+    }
+    let avg = mean(values);
+    let variance = values.iter().map(|v| (v - avg).powi(2)).sum::<f64>() / (values.len() - 1) as f64;
+    variance.sqrt()
+}
+
+/// 平均绝对偏差
+pub fn avedev(values: &[f64]) -> f64 {
+    let avg = mean(values);
+    mean(&values.iter().map(|v| (v - avg).abs()).collect::<Vec<f64>>())
+}
+
+/// 样本方差
+pub fn variance(values: &[f64]) -> f64 {
+    std_dev(values).powi(2)
+}
+
+/// 样本协方差
+pub fn covariance(a: &[f64], b: &[f64]) -> f64 {
+    let n = a.len().min(b.len());
+    if n < 2 {
+        return 0.0;
+    }
+    let (ma, mb) = (mean(&a[..n]), mean(&b[..n]));
+    a.iter().zip(b.iter()).map(|(x, y)| (x - ma) * (y - mb)).sum::<f64>() / (n - 1) as f64
+}
+
+/// 年化夏普比率
+pub fn sharpe(returns: &[f64], risk_free: f64) -> f64 {
+    let std = std_dev(returns);
+    if std == 0.0 {
+        return 0.0;
+    }
+    (mean(returns) - risk_free / TRADING_DAYS) / std * TRADING_DAYS.sqrt()
+}
+
+/// 年化索提诺比率, 只用低于无风险收益的部分计算波动
+pub fn sortino(returns: &[f64], risk_free: f64) -> f64 {
+    if returns.is_empty() {
+        return 0.0;
+    }
+    let target = risk_free / TRADING_DAYS;
+    let downside = (returns.iter().map(|r| (r - target).min(0.0).powi(2)).sum::<f64>() / returns.len() as f64).sqrt();
+    if downside == 0.0 {
+        return 0.0;
+    }
+    (mean(returns) - target) / downside * TRADING_DAYS.sqrt()
+}
+
+/// 最大回撤和最长回撤持续的交易日数
+pub fn max_drawdown(values: &[f64]) -> (f64, usize) {
+    let mut peak = f64::MIN;
+    let mut peak_index = 0;
+    let mut max_drawdown = 0.0;
+    let mut max_duration = 0;
+    for (i, value) in values.iter().enumerate() {
+        if *value >= peak {
+            peak = *value;
+            peak_index = i;
+        } else if peak > 0.0 {
+            max_drawdown = f64::max(max_drawdown, 1.0 - value / peak);
+        }
+        max_duration = max_duration.max(i - peak_index);
+    }
+    (max_drawdown, max_duration)
+}
+
+/// 回撤曲线, 0.1 表示距前高回撤 10%
+pub fn drawdowns(values: &[f64]) -> Vec<f64> {
+    let mut peak = f64::MIN;
+    values
+        .iter()
+        .map(|value| {
+            peak = peak.max(*value);
+            if peak > 0.0 { 1.0 - value / peak } else { 0.0 }
+        })
+        .collect()
+}
+
+/// 月度收益率, 返回 (年月 例如 202301, 收益率)
+///
+/// 每月以上月最后一个交易日的值为基数, 第一个月以 `start` 为基数
+pub fn monthly_returns(dates: &[i32], values: &[f64], start: f64) -> Vec<(i32, f64)> {
+    let mut months: Vec<(i32, f64)> = vec![];
+    let mut base = start;
+    let mut last = start;
+    for (date, value) in dates.iter().zip(values.iter()) {
+        let month = date / 100;
+        match months.last_mut() {
+            Some((current, _)) if *current == month => {}
+            Some(_) => {
+                base = last;
+                months.push((month, 0.0));
+            }
+            None => months.push((month, 0.0)),
+        }
+        last = *value;
+        if let Some((_, r)) = months.last_mut() {
+            *r = if base != 0.0 { value / base - 1.0 } else { 0.0 };
+        }
+    }
+    months
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rust_decimal::Decimal;
+
+    use crate::{
+        data::{DayTradeUnit, TradeUnit},
+        strategy::{Account, EquitySnapshot, Trade},
+    };
+
+    use super::{max_drawdown, monthly_returns, BenchmarkStatistics, Statistics};
+
+    #[test]
+    fn statistics() {
+        assert_eq!(max_drawdown(&[100.0, 120.0, 90.0, 110.0, 130.0, 125.0]), (0.25, 2));
+        assert_eq!(
+            monthly_returns(&[20230130, 20230131, 20230201, 20230228], &[100.0, 150.0, 120.0, 75.0], 100.0),
+            vec![(202301, 0.5), (202302, -0.5)]
+        );
+
+        let mut account = Account::new("100_000.0", "0.00025").unwrap();
+        let mut prices = HashMap::new();
+        account.buy("603339", "10.0", "1_000").unwrap();
+        for (date, price) in [(20230103, "10"), (20230104, "12"), (20230105, "9"), (20230106, "11")] {
+            prices.insert("603339", Decimal::from_str_exact(price).unwrap());
+            account.snapshot(date, &prices);
+        }
+        account.sell("603339", "11.0", "500").unwrap();
+        account.sell("603339", "9.0", "500").unwrap();
+        prices.insert("603339", Decimal::from(9));
+        account.snapshot(20230109, &prices);
+
+        let stats = Statistics::from_account(&account);
+        assert_eq!(stats.days, 5);
+        assert_eq!(stats.trades, 2);
+        assert_eq!(stats.win_rate, 0.5);
+        assert!(stats.average_win > 0.0 && stats.average_loss < 0.0);
+        assert!(stats.profit_factor > 0.0 && stats.profit_factor < 1.0);
+        assert!(stats.total_return < 0.0);
+        // 最高 102000 回撤到 99000
+        assert!((stats.max_drawdown - 3000.0 / 102_000.0).abs() < 1e-4);
+        assert_eq!(stats.max_drawdown_duration, 3);
+        assert!(stats.volatility > 0.0 && stats.sharpe.is_finite() && stats.sortino.is_finite());
+        assert!(stats.exposure > 0.0 && stats.exposure < 0.2);
+    }
+
+    #[test]
+    fn benchmark() {
+        let dates = [20230103, 20230104, 20230105, 20230106, 20230109];
+        let closes = [1000, 1100, 990, 1089, 1200];
+        let benchmark: Vec<DayTradeUnit> = dates
+            .iter()
+            .zip(closes.iter())
+            .map(|(date, close)| DayTradeUnit {
+                date: *date,
+                trade_data: TradeUnit { open: *close, high: *close, low: *close, close: *close, volume: 0, amount: 0.0 },
+            })
+            .collect();
+        // 策略每日收益是基准的两倍, 基准缺少的日期不参与计算
+        let snapshot = |date: i32, value: f64| {
+            let value = Decimal::from_f64_retain(value).unwrap();
+            EquitySnapshot {
+                date,
+                cash: value,
+                market_value: Decimal::ZERO,
+                equity: value,
+                realized: Decimal::ZERO,
+                unrealized: Decimal::ZERO,
+            }
+        };
+        let mut equity = vec![snapshot(20221230, 90_000.0)];
+        let mut value = 100_000.0;
+        for i in 0..dates.len() {
+            if i > 0 {
+                value *= 1.0 + 2.0 * (closes[i] as f64 / closes[i - 1] as f64 - 1.0);
+            }
+            equity.push(snapshot(dates[i], value));
+        }
+
+        let stats = BenchmarkStatistics::new(&equity, &benchmark);
+        assert!((stats.beta - 2.0).abs() < 1e-9);
+        assert!(stats.alpha.abs() < 1e-9);
+        assert!((stats.up_capture - 2.0).abs() < 1e-9);
+        assert!((stats.down_capture - 2.0).abs() < 1e-9);
+        assert!(stats.tracking_error > 0.0);
+        assert_eq!(stats.benchmark_curve.len(), 5);
+        assert!((stats.benchmark_return - 0.2).abs() < 1e-9);
+        assert_eq!(stats.excess_curve[0], (20230103, 0.0));
+        assert!(stats.excess_curve[4].1 > 0.0);
+    }
+}
@@ -0,0 +1,466 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{self, OpenOptions},
+    io::{self, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::data::{
+    codes_in, get_day_path_in, get_minute_path_in, time_to_date, DataSourceError, DayTradeUnit, DayTradeUnitIter,
+    DayTradeUnitSize, FinanceData, Market, MinuteTradeUnit, MinuteTradeUnitIter, MinuteTradeUnitSize, StockCode, TradeDataSource,
+    TradeTime, WhereIsFrom,
+};
+
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("read or write store fail")]
+    IoError {
+        #[from]
+        source: io::Error,
+    },
+    #[error("parse store state fail")]
+    JsonError {
+        #[from]
+        source: serde_json::Error,
+    },
+    #[error("data source error")]
+    DataSourceError {
+        #[from]
+        source: DataSourceError,
+    },
+}
+
+pub type Result<T, E = StoreError> = std::result::Result<T, E>;
+
+/// 一个数据文件的存储状态
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Partition {
+    /// 第一根K线的时间, 日线为日期, 分钟线为 日期 * 10000 + 时分
+    pub first: i64,
+    /// 最后一根K线的时间
+    pub last: i64,
+    pub records: u64,
+    /// 最后一次写入新K线的时间, unix 秒
+    pub updated_at: i64,
+}
+
+impl Partition {
+    fn of(keys: &BTreeSet<i64>, updated_at: i64) -> Self {
+        Partition {
+            first: keys.iter().next().copied().unwrap_or_default(),
+            last: keys.iter().next_back().copied().unwrap_or_default(),
+            records: keys.len() as u64,
+            updated_at,
+        }
+    }
+}
+
+/// 每只股票的存储状态
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CodeState {
+    pub day: Option<Partition>,
+    pub minute1: Option<Partition>,
+    pub minute5: Option<Partition>,
+    /// 最后一次刷新的时间, 没有新K线也会更新
+    pub checked_at: i64,
+}
+
+/// 一次刷新新增的K线数量
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Refresh {
+    pub day: usize,
+    pub minute1: usize,
+    pub minute5: usize,
+}
+
+/// 可以按时间合并的K线
+trait Bar: Sized {
+    const SIZE: usize;
+    fn key(&self) -> i64;
+    fn bytes(&self) -> Vec<u8>;
+    fn read_all(path: &Path) -> Result<Vec<Self>>;
+}
+
+impl Bar for DayTradeUnit {
+    const SIZE: usize = DayTradeUnitSize;
+
+    fn key(&self) -> i64 {
+        self.date as i64
+    }
+
+    fn bytes(&self) -> Vec<u8> {
+        self.to_bytes().to_vec()
+    }
+
+    fn read_all(path: &Path) -> Result<Vec<Self>> {
+        Ok(DayTradeUnit::read_from(path, 0)?)
+    }
+}
+
+impl Bar for MinuteTradeUnit {
+    const SIZE: usize = MinuteTradeUnitSize;
+
+    fn key(&self) -> i64 {
+        self.date as i64 * 10000 + self.time() as i64
+    }
+
+    fn bytes(&self) -> Vec<u8> {
+        self.to_bytes().to_vec()
+    }
+
+    fn read_all(path: &Path) -> Result<Vec<Self>> {
+        Ok(MinuteTradeUnitIter::new(path)?.collect())
+    }
+}
+
+/**
+ * 本地K线存储, 目录结构和记录格式与通达信 vipdoc 相同, 另有 state.json 记录每只股票的存储状态
+ *
+ * ```text
+ * store/
+ *   state.json
+ *   sh/lday/sh603339.day
+ *   sh/minline/sh603339.lc1
+ *   sh/fzline/sh603339.lc5
+ * ```
+ *
+ * 写入时按时间去重: 已存的K线保持不变, 缺少的K线都会补入, 包括已存区间中间的缺口;
+ * 只有晚于最后一根的新K线时追加到文件末尾, 否则按时间重写整个文件. 通达信客户端丢失的历史因此会保留在这里
+ */
+pub struct LocalStore {
+    root: PathBuf,
+    state: BTreeMap<String, CodeState>,
+}
+
+impl LocalStore {
+    /// 打开存储目录, 不存在时创建
+    pub fn open<P: AsRef<Path>>(root: P) -> Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        fs::create_dir_all(&root)?;
+        let state = match fs::read(root.join("state.json")) {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(LocalStore { root, state })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// 股票的存储状态, 代码可以不带市场前缀
+    pub fn state(&self, code: &str) -> Option<&CodeState> {
+        self.state.get(&state_key(code).ok()?)
+    }
+
+    /// 全部股票的存储状态, 以带市场前缀的代码为键
+    pub fn states(&self) -> &BTreeMap<String, CodeState> {
+        &self.state
+    }
+
+    /// 合并日线, 返回新增的数量
+    pub fn append_day<I: IntoIterator<Item = DayTradeUnit>>(&mut self, code: StockCode, bars: I) -> Result<usize> {
+        let added = self.merge_day(code, bars)?;
+        self.save()?;
+        Ok(added)
+    }
+
+    /// 合并分钟线, `minutes` 为 1 或 5
+    pub fn append_minutes<I: IntoIterator<Item = MinuteTradeUnit>>(&mut self, code: StockCode, minutes: u8, bars: I) -> Result<usize> {
+        let added = self.merge_minutes(code, minutes, bars)?;
+        self.save()?;
+        Ok(added)
+    }
+
+    fn merge_day<I: IntoIterator<Item = DayTradeUnit>>(&mut self, code: StockCode, bars: I) -> Result<usize> {
+        let path = get_day_path_in(&self.root, code)?;
+        let state = self.state.entry(state_key(code)?).or_default();
+        merge(&path, &mut state.day, bars)
+    }
+
+    fn merge_minutes<I: IntoIterator<Item = MinuteTradeUnit>>(&mut self, code: StockCode, minutes: u8, bars: I) -> Result<usize> {
+        let path = get_minute_path_in(&self.root, code, minutes)?;
+        let state = self.state.entry(state_key(code)?).or_default();
+        let partition = if minutes == 5 { &mut state.minute5 } else { &mut state.minute1 };
+        merge(&path, partition, bars)
+    }
+
+    /// 从数据源导入一只股票的日线和分钟线, 数据源没有分钟线文件时跳过, 全部写入后保存一次状态
+    ///
+    /// 已有存储时只读取数据源中最后一根之后的K线, 不读取已存的数据文件; 补入中间的缺口使用 [`LocalStore::append_day`]
+    pub fn refresh<S: TradeDataSource>(&mut self, source: &S, code: StockCode) -> Result<Refresh> {
+        let state = self.state(code).cloned().unwrap_or_default();
+        let mut bars = source.day_duration(code, None, None)?;
+        if let Some(partition) = state.day {
+            bars.seek_to(partition.last as i32 + 1)?;
+        }
+        let mut refresh = Refresh { day: self.merge_day(code, bars)?, ..Default::default() };
+        for minutes in [1, 5] {
+            let mut bars = match source.minute_bars(code, minutes) {
+                Ok(bars) => bars,
+                Err(DataSourceError::Io { source }) if source.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            if let Some(partition) = if minutes == 5 { state.minute5 } else { state.minute1 } {
+                bars.seek_to((partition.last / 10000) as i32, (partition.last % 10000) as i32 + 1)?;
+            }
+            let added = self.merge_minutes(code, minutes, bars)?;
+            if minutes == 5 {
+                refresh.minute5 = added;
+            } else {
+                refresh.minute1 = added;
+            }
+        }
+        self.state.entry(state_key(code)?).or_default().checked_at = Utc::now().timestamp();
+        self.save()?;
+        Ok(refresh)
+    }
+
+    /// 导入数据源的全部股票
+    pub fn refresh_all<S: TradeDataSource>(&mut self, source: &S) -> Result<Vec<(StockCode, Refresh)>> {
+        source.codes()?.into_iter().map(|code| Ok((code, self.refresh(source, code)?))).collect()
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = self.root.join("state.json");
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(&self.state)?)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+}
+
+/// 带市场前缀的代码
+fn state_key(code: &str) -> Result<String> {
+    let market = code.where_is_from().ok_or(DataSourceError::StockCodeNotExistInMarket)?;
+    let code = code.strip_prefix("sh").or_else(|| code.strip_prefix("sz")).unwrap_or(code);
+    let market = match market {
+        Market::SH => "sh",
+        Market::SZ => "sz",
+    };
+    Ok(format!("{}{}", market, code))
+}
+
+/// 按时间合并K线, 已存的K线取自数据文件本身, 状态只记录合并后的结果
+///
+/// 状态与数据文件一致且全部晚于最后一根时直接追加, 不读取已存的K线.
+/// 写入中断留下的不完整记录被丢弃; 状态没来得及保存时, 已写入的K线也不会重复
+fn merge<B: Bar, I: IntoIterator<Item = B>>(path: &Path, partition: &mut Option<Partition>, bars: I) -> Result<usize> {
+    let mut incoming = BTreeMap::new();
+    for bar in bars {
+        incoming.entry(bar.key()).or_insert(bar);
+    }
+    if let Some(known) = partition.as_mut().filter(|known| known.records > 0) {
+        let intact = fs::metadata(path).map(|meta| meta.len() == known.records * B::SIZE as u64).unwrap_or(false);
+        if intact && incoming.keys().next().map(|first| *first > known.last).unwrap_or(true) {
+            let Some(last) = incoming.keys().next_back().copied() else { return Ok(0) };
+            append(path, known.records as usize, &incoming)?;
+            known.last = last;
+            known.records += incoming.len() as u64;
+            known.updated_at = Utc::now().timestamp();
+            return Ok(incoming.len());
+        }
+    }
+    let stored = match B::read_all(path) {
+        Ok(stored) => stored,
+        Err(StoreError::DataSourceError { source: DataSourceError::Io { source } }) if source.kind() == io::ErrorKind::NotFound => vec![],
+        Err(e) => return Err(e),
+    };
+    let keys: BTreeSet<i64> = stored.iter().map(Bar::key).collect();
+    let missing: BTreeMap<i64, B> = incoming.into_iter().filter(|(key, _)| !keys.contains(key)).collect();
+    let added = missing.len();
+    if added == 0 {
+        if !stored.is_empty() {
+            let updated_at = partition.map(|partition| partition.updated_at).unwrap_or_else(|| Utc::now().timestamp());
+            *partition = Some(Partition::of(&keys, updated_at));
+        }
+        return Ok(0);
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let appending = stored.last().map(|last| missing.keys().all(|key| *key > last.key())).unwrap_or(true);
+    if appending && stored.windows(2).all(|pair| pair[0].key() < pair[1].key()) {
+        append(path, stored.len(), &missing)?;
+    } else {
+        let mut merged: BTreeMap<i64, &B> = missing.iter().map(|(key, bar)| (*key, bar)).collect();
+        for bar in stored.iter() {
+            merged.entry(bar.key()).or_insert(bar);
+        }
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, merged.values().flat_map(|bar| bar.bytes()).collect::<Vec<u8>>())?;
+        fs::rename(tmp, path)?;
+    }
+    let mut keys = keys;
+    keys.extend(missing.keys());
+    *partition = Some(Partition::of(&keys, Utc::now().timestamp()));
+    Ok(added)
+}
+
+/// 在前 `records` 条完整记录之后写入新K线, 丢弃之后不完整的记录
+fn append<B: Bar>(path: &Path, records: usize, bars: &BTreeMap<i64, B>) -> Result<()> {
+    let mut file = OpenOptions::new().create(true).write(true).truncate(false).open(path)?;
+    file.set_len((records * B::SIZE) as u64)?;
+    file.seek(SeekFrom::End(0))?;
+    file.write_all(&bars.values().flat_map(|bar| bar.bytes()).collect::<Vec<u8>>())?;
+    Ok(())
+}
+
+/// 读取方式与 [`crate::data::StockTradeData`] 相同, 只是目录换成存储目录
+impl TradeDataSource for LocalStore {
+    fn prepare(&self) -> crate::data::Result<()> {
+        fs::create_dir_all(&self.root)?;
+        Ok(())
+    }
+
+    fn day(&self, code: StockCode, day: TradeTime) -> crate::data::Result<DayTradeUnit> {
+        let date = time_to_date(&day);
+        self.day_duration(code, None, None)?
+            .find(|bar| bar.date == date)
+            .ok_or(DataSourceError::NoTradeData)
+    }
+
+    fn minue(&self, code: StockCode, day: TradeTime) -> crate::data::Result<MinuteTradeUnit> {
+        let offset = (day.hour() * 60 + day.minute()) as i16;
+        let path = get_minute_path_in(&self.root, code, 1)?;
+        MinuteTradeUnitIter::between(&path, time_to_date(&day), offset, offset)
+            .next()
+            .ok_or(DataSourceError::NoTradeData)
+    }
+
+    fn day_duration(&self, code: StockCode, _from: Option<TradeTime>, _to: Option<TradeTime>) -> crate::data::Result<DayTradeUnitIter> {
+        DayTradeUnitIter::new(&get_day_path_in(&self.root, code)?)
+    }
+
+    /// `day` 当日 `from` 到 `to` 时刻的1分钟线
+    fn minue_duration(&self, code: StockCode, day: TradeTime, from: TradeTime, to: TradeTime) -> MinuteTradeUnitIter {
+        let offset = |time: TradeTime| (time.hour() * 60 + time.minute()) as i16;
+        let path = get_minute_path_in(&self.root, code, 1).unwrap_or_default();
+        MinuteTradeUnitIter::between(&path, time_to_date(&day), offset(from), offset(to))
+    }
+
+    fn codes(&self) -> crate::data::Result<Vec<StockCode>> {
+        codes_in(&self.root)
+    }
+
+    fn finance(&self, report_date: i32) -> crate::data::Result<FinanceData> {
+        FinanceData::open(self.root.join("cw").join(format!("gpcw{}.dat", report_date)))
+    }
+
+    fn minute_bars(&self, code: StockCode, minutes: u8) -> crate::data::Result<MinuteTradeUnitIter> {
+        MinuteTradeUnitIter::new(&get_minute_path_in(&self.root, code, minutes)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use chrono::{TimeZone, Utc};
+
+    use crate::{
+        backtest::BackTest,
+        data::{DayTradeUnit, MinuteTradeUnit, StockTradeData, TradeDataSource, TradeUnit},
+        strategies,
+    };
+
+    use super::{LocalStore, Refresh};
+
+    #[test]
+    fn merge_day() {
+        let dir = env::temp_dir().join(format!("millions-store-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let bars: Vec<DayTradeUnit> = StockTradeData {}.day_duration("603339", None, None).unwrap().collect();
+        let mut store = LocalStore::open(&dir).unwrap();
+        let gap = [&bars[100..140], &bars[150..200]].concat();
+        assert_eq!(store.append_day("603339", gap).unwrap(), 90);
+        // 已存区间中间缺少的K线也会补入
+        assert_eq!(store.append_day("603339", bars[100..200].to_vec()).unwrap(), 10);
+        let stored: Vec<i32> = store.day_duration("603339", None, None).unwrap().map(|bar| bar.date).collect();
+        assert_eq!(stored, bars[100..200].iter().map(|bar| bar.date).collect::<Vec<_>>());
+
+        // 乱序和重复的K线按日期去重, 更早和更新的分别补到两端
+        let mut incoming = bars[50..250].to_vec();
+        incoming.reverse();
+        incoming.extend_from_slice(&bars[150..160]);
+        assert_eq!(store.append_day("sh603339", incoming).unwrap(), 100);
+        let stored: Vec<i32> = store.day_duration("603339", None, None).unwrap().map(|bar| bar.date).collect();
+        assert_eq!(stored, bars[50..250].iter().map(|bar| bar.date).collect::<Vec<_>>());
+
+        // 重新打开后刷新只读取数据源中最后一根之后的K线并追加
+        let mut store = LocalStore::open(&dir).unwrap();
+        let state = store.state("603339").unwrap().day.unwrap();
+        assert_eq!((state.first, state.last, state.records), (bars[50].date as i64, bars[249].date as i64, 200));
+        let refresh = store.refresh(&StockTradeData {}, "sh603339").unwrap();
+        assert_eq!(refresh, Refresh { day: bars.len() - 250, minute1: 0, minute5: 0 });
+        let state = store.state("603339").unwrap().day.unwrap();
+        assert_eq!((state.last, state.records), (bars.last().unwrap().date as i64, bars.len() as u64 - 50));
+        assert_eq!(store.refresh(&StockTradeData {}, "sh603339").unwrap().day, 0);
+        // 更早的历史通过合并补入
+        assert_eq!(store.append_day("603339", bars.clone()).unwrap(), 50);
+        let stored: Vec<DayTradeUnit> = store.day_duration("603339", None, None).unwrap().collect();
+        assert_eq!(stored.len(), bars.len());
+        assert_eq!(stored.last().unwrap().trade_data.close, bars.last().unwrap().trade_data.close);
+        assert_eq!(store.codes().unwrap(), vec!["sh603339"]);
+
+        // 状态文件丢失时从数据文件恢复
+        fs::remove_file(dir.join("state.json")).unwrap();
+        let mut store = LocalStore::open(&dir).unwrap();
+        assert_eq!(store.append_day("603339", bars.clone()).unwrap(), 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn merge_minutes() {
+        let dir = env::temp_dir().join(format!("millions-store-minute-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let bar = |date: i32, offset: i16| MinuteTradeUnit {
+            date,
+            offset,
+            trade_data: TradeUnit { open: 1350, close: 1362, high: 1370, low: 1341, volume: 10000, amount: 136000.0 },
+        };
+        let mut store = LocalStore::open(&dir).unwrap();
+        assert_eq!(store.append_minutes("603339", 1, (571..=600).map(|offset| bar(20221101, offset))).unwrap(), 30);
+        assert_eq!(store.append_minutes("603339", 1, (591..=620).map(|offset| bar(20221101, offset))).unwrap(), 20);
+        assert_eq!(store.append_minutes("603339", 1, vec![bar(20221102, 571)]).unwrap(), 1);
+
+        let time = |hour, minute| Utc.with_ymd_and_hms(2022, 11, 1, hour, minute, 0).unwrap();
+        let bars: Vec<_> = store.minue_duration("603339", time(0, 0), time(9, 40), time(9, 49)).collect();
+        assert_eq!(bars.iter().map(|bar| bar.time()).collect::<Vec<_>>(), (940..=949).collect::<Vec<_>>());
+        assert_eq!(bars[0].trade_data.high, 1370);
+        assert_eq!(store.minue("603339", time(10, 20)).unwrap().time(), 1020);
+        assert!(store.minue("603339", time(10, 21)).is_err());
+        assert_eq!(store.minute_bars("603339", 1).unwrap().count(), 51);
+        let mut tail = store.minute_bars("603339", 1).unwrap();
+        tail.seek_to(20221101, 1020).unwrap();
+        assert_eq!(tail.map(|bar| (bar.date, bar.time())).collect::<Vec<_>>(), vec![(20221101, 1020), (20221102, 931)]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn back_test_source() {
+        let dir = env::temp_dir().join(format!("millions-store-source-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut store = LocalStore::open(&dir).unwrap();
+        store.refresh(&StockTradeData {}, "sh603339").unwrap();
+
+        let run = |store: Option<&LocalStore>| {
+            let strategy = strategies::by_name("ma-cross", &Default::default()).unwrap();
+            let mut builder = BackTest::builder().codes(vec!["sh603339"]).strategy(strategy);
+            if let Some(store) = store {
+                builder = builder.source(store);
+            }
+            let result = builder.build().unwrap().run("20210101", "20221101").unwrap();
+            result
+        };
+        let expected = run(None);
+        assert!(!expected.trades.is_empty());
+        assert_eq!(run(Some(&store)).trades, expected.trades);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,74 @@
+use crate::{
+    formula::{Formula, FormulaStrategy},
+    optimizer::Params,
+};
+
+/// 内置策略的名称, 命令行和参数优化按名称创建策略
+pub const NAMES: &[&str] = &["ma-cross", "macd", "boll"];
+
+/// 内置策略的公式和默认参数
+fn source(name: &str) -> Option<(&'static str, &'static [(&'static str, f64)])> {
+    match name {
+        // 均线金叉买入, 死叉卖出
+        "ma-cross" => Some((
+            "BUY:CROSS(MA(C,FAST),MA(C,SLOW)); SELL:CROSS(MA(C,SLOW),MA(C,FAST));",
+            &[("fast", 5.0), ("slow", 20.0)],
+        )),
+        // DIF 上穿 DEA 买入, 下穿卖出
+        "macd" => Some((
+            "DIF:=EMA(C,SHORT)-EMA(C,LONG); DEA:=EMA(DIF,MID); BUY:CROSS(DIF,DEA); SELL:CROSS(DEA,DIF);",
+            &[("short", 12.0), ("long", 26.0), ("mid", 9.0)],
+        )),
+        // 收盘价上穿下轨买入, 跌破上轨卖出
+        "boll" => Some((
+            "MID:=MA(C,N); UPPER:=MID+P*STD(C,N); LOWER:=MID-P*STD(C,N); BUY:CROSS(C,LOWER); SELL:CROSS(UPPER,C);",
+            &[("n", 20.0), ("p", 2.0)],
+        )),
+        _ => None,
+    }
+}
+
+/// 内置策略的默认参数
+pub fn defaults(name: &str) -> Option<Params> {
+    let (_, defaults) = source(name)?;
+    Some(defaults.iter().map(|(param, value)| (param.to_string(), *value)).collect())
+}
+
+/// 按名称创建策略, 没有给出的参数取默认值, 名称不存在时为 None
+///
+/// `percent` 参数为每次买入使用余额的百分比, 默认 90
+pub fn by_name(name: &str, params: &Params) -> Option<FormulaStrategy> {
+    let (source, _) = source(name)?;
+    let mut formula = Formula::parse(source).ok()?;
+    let mut merged = defaults(name)?;
+    merged.extend(params.iter().map(|(param, value)| (param.clone(), *value)));
+    let percent = merged.remove("percent").unwrap_or(90.0).clamp(0.0, 100.0) as u8;
+    for (param, value) in merged {
+        formula = formula.param(&param, value);
+    }
+    FormulaStrategy::with_signals(formula, "BUY", "SELL", percent).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{backtest::BackTest, optimizer::Params};
+
+    use super::{by_name, defaults, NAMES};
+
+    #[test]
+    fn strategies() {
+        for name in NAMES {
+            let mut strategy = by_name(name, &Params::new()).unwrap();
+            let mut back_test = BackTest::with_universe(vec!["603339"], &mut strategy).unwrap();
+            back_test.run("20210101", "20221101").unwrap();
+        }
+        assert!(by_name("missing", &Params::new()).is_none());
+        assert_eq!(defaults("ma-cross").unwrap()["slow"], 20.0);
+
+        let params: Params = [("fast".to_string(), 3.0)].into_iter().collect();
+        let mut strategy = by_name("ma-cross", &params).unwrap();
+        let mut back_test = BackTest::with_universe(vec!["603339"], &mut strategy).unwrap();
+        let result = back_test.run("20210101", "20221101").unwrap();
+        assert!(!result.trades.is_empty());
+    }
+}
@@ -1,9 +1,10 @@
 use std::{collections::HashMap, num::ParseIntError};
 
-use crate::data::{StockCode, TradeTime};
+use crate::data::{time_to_date, StockCode, TradeTime, TradeUnit};
 
 use chrono::{DateTime, Utc, Local};
-use rust_decimal::{Decimal};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 #[derive(Debug, Error, PartialEq)]
 pub enum TradeError {
@@ -24,6 +25,12 @@ pub enum TradeError {
     DecimalToNumberError,
     #[error("out of position")]
     OutOfPosition,
+    /// 停牌或没有行情
+    #[error("no market data")]
+    NoMarketData,
+    /// 限价未成交
+    #[error("limit price not reached")]
+    LimitNotReached,
 }
 
 pub type Result<T, E = TradeError> = std::result::Result<T, E>;
@@ -69,6 +76,98 @@ pub struct Account {
      * 交易费
      */
     rate_transfer_fee: Decimal,
+    /**
+     * 最低佣金
+     */
+    min_brokerage_fee: Decimal,
+    /**
+     * 持仓附带的止损止盈
+     */
+    exits: HashMap<StockCode, ProtectiveExit>,
+    /**
+     * 成本计算方式
+     */
+    cost_method: CostMethod,
+    /**
+     * 每日权益快照
+     */
+    equity_curve: Vec<EquitySnapshot>,
+    /**
+     * 回测时的当前时间, 未设置时使用系统时间
+     */
+    time: Option<TradeTime>,
+    /**
+     * 下一个订单的序号
+     */
+    next_seq: u64,
+}
+
+/// 交易费用, 费率按成交额计算
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeeModel {
+    /// 佣金费率, 买卖双向收取
+    pub brokerage: Decimal,
+    /// 每笔最低佣金
+    pub min_brokerage: Decimal,
+    /// 印花税费率, 仅卖出收取
+    pub stamp_duty: Decimal,
+    /// 过户费费率, 买卖双向收取
+    pub transfer: Decimal,
+}
+
+impl Default for FeeModel {
+    fn default() -> Self {
+        FeeModel {
+            brokerage: Decimal::new(25, 5),
+            min_brokerage: Decimal::new(5, 1),
+            stamp_duty: Decimal::new(1, 3),
+            transfer: Decimal::new(1, 5),
+        }
+    }
+}
+
+impl FeeModel {
+    /// 买入成交额为 `charge` 时的佣金和过户费
+    pub fn buy_fees(&self, charge: Decimal) -> (Decimal, Decimal) {
+        let brokerage_fee = (charge * self.brokerage).ceil_point(2).max(self.min_brokerage);
+        let transfer_fee = (charge * self.transfer).ceil_point(2);
+        (brokerage_fee, transfer_fee)
+    }
+
+    /// 以 `price` 买入 `vol` 股所需的资金, 与 [`Account`] 买入时的扣款相同
+    pub fn buy_cost(&self, price: Decimal, vol: Decimal) -> Decimal {
+        let charge = price * vol;
+        let (brokerage_fee, transfer_fee) = self.buy_fees(charge);
+        charge + brokerage_fee + transfer_fee
+    }
+}
+
+/// 持仓成本计算方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum CostMethod {
+    /// 移动平均成本, 卖出不改变剩余持仓的成本价
+    #[default]
+    Average,
+    /// 先进先出, 卖出时按买入批次顺序结转成本
+    Fifo,
+}
+
+/// 每日权益快照
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EquitySnapshot {
+    /// 日期 例如 20230103
+    pub date: i32,
+    /// 现金
+    pub cash: Decimal,
+    /// 持仓市值
+    pub market_value: Decimal,
+    /// 总权益 = 现金 + 持仓市值
+    pub equity: Decimal,
+    /// 累计已实现盈亏
+    pub realized: Decimal,
+    /// 浮动盈亏
+    pub unrealized: Decimal,
 }
 
 pub type TransactionRecord = HashMap<StockCode, Vec<Order>>;
@@ -85,30 +184,252 @@ pub struct OrderSettle(
     StockCostPrice
 );
 
+impl Position {
+    pub fn code(&self) -> StockCode {
+        self.0
+    }
+
+    /// 持仓数量(股)
+    pub fn volume(&self) -> Decimal {
+        self.1 .0
+    }
+
+    /// 持仓成本价(含费用)
+    pub fn cost(&self) -> StockCostPrice {
+        self.1 .1
+    }
+}
+
+/// 持仓批次
+#[derive(Debug, Clone)]
+struct Lot {
+    vol: Decimal,
+    /// 每股成本(含买入费用)
+    cost: StockCostPrice,
+}
+
+/// 按成本计算方式重放订单, 得到剩余持仓批次
+fn replay(orders: &[Order], method: CostMethod) -> Vec<Lot> {
+    let mut lots: Vec<Lot> = vec![];
+    for order in orders {
+        if order.vol.is_zero() {
+            // 派息记录
+            continue;
+        }
+        if order.vol.is_sign_positive() {
+            let lot = Lot { vol: order.vol, cost: order.cost_per_share() };
+            match (method, lots.first_mut()) {
+                (CostMethod::Average, Some(held)) => {
+                    let vol = held.vol + lot.vol;
+                    held.cost = (held.cost * held.vol + lot.cost * lot.vol) / vol;
+                    held.vol = vol;
+                }
+                _ => lots.push(lot),
+            }
+        } else {
+            consume(&mut lots, -order.vol);
+        }
+    }
+    lots
+}
+
+/// 从持仓批次中扣减卖出数量, 返回结转的成本
+fn consume(lots: &mut Vec<Lot>, mut vol: Decimal) -> Decimal {
+    let mut cost = Decimal::ZERO;
+    while vol > Decimal::ZERO && !lots.is_empty() {
+        let lot = &mut lots[0];
+        let take = lot.vol.min(vol);
+        cost += take * lot.cost;
+        lot.vol -= take;
+        vol -= take;
+        if lot.vol.is_zero() {
+            lots.remove(0);
+        }
+    }
+    cost
+}
+
+impl OrderSettle {
+    /// 按成本计算方式结算订单
+    pub fn settle(orders: &[Order], method: CostMethod) -> Self {
+        let lots = replay(orders, method);
+        let vol: Decimal = lots.iter().map(|lot| lot.vol).sum();
+        if vol.is_zero() {
+            return OrderSettle(Decimal::ZERO, Decimal::ZERO);
+        }
+        let cost: Decimal = lots.iter().map(|lot| lot.vol * lot.cost).sum();
+        OrderSettle(vol, cost / vol)
+    }
+}
 
 impl Into<OrderSettle> for &Vec<Order>  {
     fn into(self) -> OrderSettle {
-        self.iter().fold(OrderSettle(StockCostPrice::new(0, 2), StockCostPrice::new(0, 2)), |mut acc, x| {
-            if (acc.0 + x.vol).is_zero() == false {
-                acc.1 = (acc.1 * acc.0 + x.vol * x.price + x.brokerage_fee + x.stamp_duty + x.transfer_fee) / (acc.0 + x.vol);
-                acc.0 = acc.0 + x.vol;
+        OrderSettle::settle(self, CostMethod::Average)
+    }
+}
+
+/// 止损止盈规则, 开仓后附加到持仓上, 回测时按每根K线的最高/最低价检查
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExitRule {
+    /// 固定价格止损
+    StopLoss(Decimal),
+    /// 按成本价百分比止损, 例如 5 表示跌破成本 5% 止损
+    StopLossPercent(Decimal),
+    /// ATR止损, 止损价 = 成本价 - multiplier * atr
+    StopLossAtr { atr: Decimal, multiplier: Decimal },
+    /// 移动止损, 从持仓以来最高价回撤百分比
+    TrailingStop(Decimal),
+    /// 固定价格止盈
+    TakeProfit(Decimal),
+    /// 按成本价百分比止盈, 例如 20 表示高于成本 20% 止盈
+    TakeProfitPercent(Decimal),
+}
+
+/// 持仓上的止损止盈状态
+#[derive(Debug, Clone)]
+pub struct ProtectiveExit {
+    rules: Vec<ExitRule>,
+    /// 入场成本价
+    entry: Decimal,
+    /// 持仓以来最高价, 用于移动止损
+    highest: Decimal,
+}
+
+impl ProtectiveExit {
+    fn new(entry: Decimal) -> Self {
+        ProtectiveExit { rules: vec![], entry, highest: entry }
+    }
+
+    pub fn rules(&self) -> &Vec<ExitRule> {
+        &self.rules
+    }
+
+    /// 当前有效的止损价, 多条止损规则取最高(最紧)的一条, 向下取整到 0.01 元
+    pub fn stop_price(&self) -> Option<Decimal> {
+        let hundred = Decimal::from(100);
+        self.rules
+            .iter()
+            .filter_map(|rule| match rule {
+                ExitRule::StopLoss(price) => Some(*price),
+                ExitRule::StopLossPercent(percent) => Some(self.entry * (hundred - percent) / hundred),
+                ExitRule::StopLossAtr { atr, multiplier } => Some(self.entry - atr * multiplier),
+                ExitRule::TrailingStop(percent) => Some(self.highest * (hundred - percent) / hundred),
+                _ => None,
+            })
+            .max()
+            .map(|price| price.round_dp_with_strategy(2, RoundingStrategy::ToNegativeInfinity))
+    }
+
+    /// 当前有效的止盈价, 多条止盈规则取最低的一条, 向上取整到 0.01 元
+    pub fn take_profit_price(&self) -> Option<Decimal> {
+        let hundred = Decimal::from(100);
+        self.rules
+            .iter()
+            .filter_map(|rule| match rule {
+                ExitRule::TakeProfit(price) => Some(*price),
+                ExitRule::TakeProfitPercent(percent) => Some(self.entry * (hundred + percent) / hundred),
+                _ => None,
+            })
+            .min()
+            .map(|price| price.round_dp_with_strategy(2, RoundingStrategy::ToPositiveInfinity))
+    }
+
+    /// 检查K线是否触发止损止盈, 返回成交价
+    ///
+    /// 开盘价直接跳空越过止损/止盈价时按开盘价成交;
+    /// 同一根K线同时触及止损和止盈时, 保守地按止损处理
+    pub fn trigger(&self, bar: &TradeUnit) -> Option<Decimal> {
+        let open = price_of(bar.open);
+        if let Some(stop) = self.stop_price() {
+            if open <= stop {
+                return Some(open);
             }
-            acc
-        })
+            if price_of(bar.low) <= stop {
+                return Some(stop);
+            }
+        }
+        if let Some(take_profit) = self.take_profit_price() {
+            if open >= take_profit {
+                return Some(open);
+            }
+            if price_of(bar.high) >= take_profit {
+                return Some(take_profit);
+            }
+        }
+        None
     }
 }
 
+/// 通达信价格为 价格*100 的整数
+fn price_of(raw: i32) -> Decimal {
+    Decimal::new(raw as i64, 2)
+}
+
 /**
  * 订单
  */
 #[derive(Debug)]
 pub struct Order {
     time: TradeTime,
+    /// 账户内递增的序号, 同一天内按序号排列即为实际的处理顺序
+    seq: u64,
     vol: Decimal,
     price: Decimal,
     stamp_duty: Decimal,
     brokerage_fee: Decimal,
     transfer_fee: Decimal,
+    /// 卖出时的已实现盈亏, 买入为0
+    realized: Decimal,
+}
+
+impl Order {
+    /// 每股成本(含费用), 仅对买入有意义
+    fn cost_per_share(&self) -> Decimal {
+        (self.vol * self.price + self.fees()) / self.vol
+    }
+
+    /// 总费用
+    pub fn fees(&self) -> Decimal {
+        self.brokerage_fee + self.stamp_duty + self.transfer_fee
+    }
+
+    pub fn time(&self) -> TradeTime {
+        self.time
+    }
+
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// 成交日期 例如 20230103
+    pub fn date(&self) -> i32 {
+        time_to_date(&self.time)
+    }
+
+    /// 成交量, 卖出为负数
+    pub fn vol(&self) -> Decimal {
+        self.vol
+    }
+
+    pub fn price(&self) -> Decimal {
+        self.price
+    }
+
+    pub fn realized(&self) -> Decimal {
+        self.realized
+    }
+
+    pub fn stamp_duty(&self) -> Decimal {
+        self.stamp_duty
+    }
+
+    pub fn brokerage_fee(&self) -> Decimal {
+        self.brokerage_fee
+    }
+
+    pub fn transfer_fee(&self) -> Decimal {
+        self.transfer_fee
+    }
 }
 
 impl Account {
@@ -119,27 +440,253 @@ impl Account {
             rate_brokerage_fee: Decimal::from_str_exact(rate_brokerage_fee)?, // 0.00025
             rate_stamp_duty: Decimal::from_str_exact("0.001")?,
             rate_transfer_fee: Decimal::from_str_exact("0.00001")?,
+            min_brokerage_fee: Decimal::from_str_exact("0.5")?,
+            exits: HashMap::new(),
+            cost_method: CostMethod::default(),
+            equity_curve: vec![],
+            time: None,
+            next_seq: 0,
         })
     }
+
+    /// 设置当前时间, 回测时订单按K线日期记录
+    pub fn set_time(&mut self, time: TradeTime) {
+        self.time = Some(time);
+    }
+
+    fn now(&self) -> TradeTime {
+        self.time.unwrap_or_else(|| DateTime::<Utc>::from(Local::now()))
+    }
+
+    fn next_seq(&mut self) -> u64 {
+        self.next_seq += 1;
+        self.next_seq
+    }
+
+    /// 佣金费率
+    pub fn rate_brokerage_fee(&self) -> Decimal {
+        self.rate_brokerage_fee
+    }
+
+    /// 指定交易费用, 需在交易前设置
+    pub fn with_fee_model(mut self, fees: FeeModel) -> Self {
+        self.rate_brokerage_fee = fees.brokerage;
+        self.min_brokerage_fee = fees.min_brokerage;
+        self.rate_stamp_duty = fees.stamp_duty;
+        self.rate_transfer_fee = fees.transfer;
+        self
+    }
+
+    pub fn fee_model(&self) -> FeeModel {
+        FeeModel {
+            brokerage: self.rate_brokerage_fee,
+            min_brokerage: self.min_brokerage_fee,
+            stamp_duty: self.rate_stamp_duty,
+            transfer: self.rate_transfer_fee,
+        }
+    }
+
+    /// 指定成本计算方式, 需在交易前设置
+    pub fn with_cost_method(mut self, method: CostMethod) -> Self {
+        self.cost_method = method;
+        self
+    }
+
+    pub fn cost_method(&self) -> CostMethod {
+        self.cost_method
+    }
     
     /// 查看指定股票的持仓状态
     pub fn get_position(&self, code: StockCode) -> Result<Option<Position>> {
         if let Some(record) = self.transaction_record.get(code) {
-            let settle: OrderSettle = record.into();
+            let settle = OrderSettle::settle(record, self.cost_method);
             Ok(Some(Position(code, settle)))
         } else {
             Ok(None)
         }
     }
 
+    /// 查看所有未清仓的持仓
+    pub fn get_positions(&self) -> Vec<Position> {
+        let mut positions: Vec<Position> = self
+            .transaction_record
+            .iter()
+            .map(|(code, record)| Position(code, OrderSettle::settle(record, self.cost_method)))
+            .filter(|position| !position.volume().is_zero())
+            .collect();
+        positions.sort_by_key(|position| position.0);
+        positions
+    }
+
     pub fn get_transaction(&self, code: StockCode) -> Option<&Vec<Order>> {
         self.transaction_record.get(code)
     }
 
+    /// 所有股票的订单
+    pub fn orders(&self) -> Vec<(StockCode, &Order)> {
+        let mut orders: Vec<(StockCode, &Order)> = self
+            .transaction_record
+            .iter()
+            .flat_map(|(code, orders)| orders.iter().map(move |order| (*code, order)))
+            .collect();
+        orders.sort_by_key(|(_, order)| order.seq);
+        orders
+    }
+
     /// 查看余额
     pub fn get_balance(&self) -> Decimal {
         self.balance
     }
+
+    /// 累计已实现盈亏
+    pub fn realized_pnl(&self) -> Decimal {
+        self.transaction_record
+            .values()
+            .flat_map(|orders| orders.iter())
+            .map(|order| order.realized)
+            .sum()
+    }
+
+    /// 按给定价格计算指定股票的浮动盈亏
+    pub fn unrealized_pnl(&self, code: StockCode, price: Decimal) -> Decimal {
+        match self.transaction_record.get(code) {
+            Some(orders) => replay(orders, self.cost_method)
+                .iter()
+                .map(|lot| (price - lot.cost) * lot.vol)
+                .sum(),
+            None => Decimal::ZERO,
+        }
+    }
+
+    /// 按给定价格计算持仓市值, 缺少价格的股票(例如停牌)按成本价计算
+    pub fn market_value(&self, prices: &HashMap<StockCode, Decimal>) -> Decimal {
+        self.get_positions()
+            .iter()
+            .map(|position| position.volume() * prices.get(position.0).copied().unwrap_or(position.cost()))
+            .sum()
+    }
+
+    /// 总权益 = 现金 + 持仓市值
+    pub fn equity(&self, prices: &HashMap<StockCode, Decimal>) -> Decimal {
+        self.balance + self.market_value(prices)
+    }
+
+    /// 记录当日权益快照, 同一日期重复记录时覆盖
+    pub fn snapshot(&mut self, date: i32, prices: &HashMap<StockCode, Decimal>) -> &EquitySnapshot {
+        let market_value = self.market_value(prices);
+        let unrealized = self
+            .get_positions()
+            .iter()
+            .map(|position| {
+                let price = prices.get(position.0).copied().unwrap_or(position.cost());
+                self.unrealized_pnl(position.0, price)
+            })
+            .sum();
+        let snapshot = EquitySnapshot {
+            date,
+            cash: self.balance,
+            market_value,
+            equity: self.balance + market_value,
+            realized: self.realized_pnl(),
+            unrealized,
+        };
+        if matches!(self.equity_curve.last(), Some(last) if last.date == date) {
+            self.equity_curve.pop();
+        }
+        self.equity_curve.push(snapshot);
+        self.equity_curve.last().unwrap()
+    }
+
+    /// 每日权益曲线
+    pub fn equity_curve(&self) -> &Vec<EquitySnapshot> {
+        &self.equity_curve
+    }
+
+    /// 处理除权除息: 现金派息记入余额和已实现盈亏, 送转股按零成本记入持仓
+    pub fn apply_corporate_action(&mut self, code: StockCode, cash_per_share: Decimal, shares_per_share: Decimal) -> Result<()> {
+        let current = match self.get_position(code)? {
+            Some(position) if position.volume() > Decimal::ZERO => position.volume(),
+            _ => return Ok(()),
+        };
+        let time = self.now();
+        let (dividend_seq, bonus_seq) = (self.next_seq(), self.next_seq());
+        let orders = self.transaction_record.entry(code).or_default();
+        let dividend = (current * cash_per_share).round_dp(2);
+        if !dividend.is_zero() {
+            self.balance += dividend;
+            orders.push(Order {
+                time,
+                seq: dividend_seq,
+                vol: Decimal::ZERO,
+                price: Decimal::ZERO,
+                stamp_duty: Decimal::ZERO,
+                brokerage_fee: Decimal::ZERO,
+                transfer_fee: Decimal::ZERO,
+                realized: dividend,
+            });
+        }
+        let bonus = (current * shares_per_share).floor();
+        if !bonus.is_zero() {
+            orders.push(Order {
+                time,
+                seq: bonus_seq,
+                vol: bonus,
+                price: Decimal::ZERO,
+                stamp_duty: Decimal::ZERO,
+                brokerage_fee: Decimal::ZERO,
+                transfer_fee: Decimal::ZERO,
+                realized: Decimal::ZERO,
+            });
+        }
+        Ok(())
+    }
+
+    /// 给持仓附加止损止盈规则, 入场价取当前持仓成本
+    pub fn attach_exit(&mut self, code: StockCode, rule: ExitRule) -> Result<()> {
+        let entry = match self.get_position(code)? {
+            Some(Position(.., OrderSettle(current, cost))) if current > Decimal::ZERO => cost,
+            _ => return Err(TradeError::OutOfPosition),
+        };
+        self.exits
+            .entry(code)
+            .or_insert_with(|| ProtectiveExit::new(entry))
+            .rules
+            .push(rule);
+        Ok(())
+    }
+
+    /// 查看持仓附带的止损止盈
+    pub fn get_exit(&self, code: StockCode) -> Option<&ProtectiveExit> {
+        self.exits.get(code)
+    }
+
+    /// 撤销持仓附带的止损止盈
+    pub fn detach_exit(&mut self, code: StockCode) -> Option<ProtectiveExit> {
+        self.exits.remove(code)
+    }
+
+    /// 用新K线检查止损止盈, 触发时全部卖出并返回成交价
+    pub fn check_exits(&mut self, code: StockCode, bar: &TradeUnit) -> Result<Option<Decimal>> {
+        let price = match self.exits.get(code) {
+            Some(exit) => exit.trigger(bar),
+            None => return Ok(None),
+        };
+        match price {
+            Some(price) => {
+                if let Some(Position(.., OrderSettle(current, ..))) = self.get_position(code)? {
+                    self.sell(code, &price.to_string(), &current.to_string())?;
+                }
+                self.exits.remove(code);
+                Ok(Some(price))
+            }
+            None => {
+                if let Some(exit) = self.exits.get_mut(code) {
+                    exit.highest = exit.highest.max(price_of(bar.high));
+                }
+                Ok(None)
+            }
+        }
+    }
 }
 
 impl Trade for Account {
@@ -148,23 +695,20 @@ impl Trade for Account {
         let vol = Decimal::from_str_exact(vol)?;
         let charge = price * vol;
         let stamp_duty = Decimal::from_str_exact("0")?;
-        let mut brokerage_fee = (charge * self.rate_brokerage_fee).ceil_point(2);
-        let min_brokerage_fee = Decimal::from_str_exact("0.5")?;
-        if brokerage_fee < min_brokerage_fee {
-            brokerage_fee = min_brokerage_fee;
-        }
-        let transfer_fee = (charge * self.rate_transfer_fee).ceil_point(2);
+        let (brokerage_fee, transfer_fee) = self.fee_model().buy_fees(charge);
         let balance = self.balance - brokerage_fee - transfer_fee - charge;
         if balance.is_sign_negative() {
             return Err(TradeError::OutOfBalance);
         }
         self.balance = balance;
-        let time = DateTime::<Utc>::from(Local::now());
+        let time = self.now();
+        let seq = self.next_seq();
+        let realized = Decimal::ZERO;
         if let Some(orders) = self.transaction_record.get_mut(code) {
-            orders.push(Order { time, vol, price, stamp_duty, brokerage_fee, transfer_fee, });
+            orders.push(Order { time, seq, vol, price, stamp_duty, brokerage_fee, transfer_fee, realized, });
         } else {
             let mut orders = vec![];
-            orders.push(Order { time, vol, price, stamp_duty, brokerage_fee, transfer_fee, });
+            orders.push(Order { time, seq, vol, price, stamp_duty, brokerage_fee, transfer_fee, realized, });
             self.transaction_record.insert(code, orders);
         }
         Ok(())
@@ -185,9 +729,8 @@ impl Trade for Account {
         let charge = price * vol;
         let stamp_duty = self.rate_stamp_duty * charge;
         let mut brokerage_fee = (charge * self.rate_brokerage_fee).ceil_point(2);
-        let min_brokerage_fee = Decimal::from_str_exact("0.5")?;
-        if brokerage_fee < min_brokerage_fee {
-            brokerage_fee = min_brokerage_fee;
+        if brokerage_fee < self.min_brokerage_fee {
+            brokerage_fee = self.min_brokerage_fee;
         }
         let transfer_fee = (charge * self.rate_transfer_fee).ceil_point(2);
         let balance = self.balance + charge - brokerage_fee - transfer_fee  - stamp_duty;
@@ -195,10 +738,19 @@ impl Trade for Account {
             return Err(TradeError::OutOfBalance);
         }
         self.balance = balance;
-        let time = DateTime::<Utc>::from(Local::now());
+        let time = self.now();
+        let seq = self.next_seq();
+        let cost_method = self.cost_method;
         if let Some(orders) = self.transaction_record.get_mut(code) {
-            orders.push(Order { time, vol: -vol, price, stamp_duty, brokerage_fee, transfer_fee, });
+            let cost = consume(&mut replay(orders, cost_method), vol);
+            let realized = charge - brokerage_fee - transfer_fee - stamp_duty - cost;
+            orders.push(Order { time, seq, vol: -vol, price, stamp_duty, brokerage_fee, transfer_fee, realized, });
         } // unreach else
+        if let Some(Position(.., OrderSettle(current, ..))) = self.get_position(code)? {
+            if current.is_zero() {
+                self.exits.remove(code);
+            }
+        }
         Ok(())
     }
 
@@ -228,9 +780,9 @@ impl Ceil for Decimal {
 mod tests {
     use rust_decimal::Decimal;
 
-    use crate::strategy::{Position, OrderSettle};
+    use crate::{strategy::{Position, OrderSettle}, data::TradeUnit};
 
-    use super::{Account, Trade, TradeError};
+    use super::{Account, Trade, TradeError, ExitRule, CostMethod};
 
 
     #[test]
@@ -272,10 +824,96 @@ mod tests {
         assert_eq!(balance, Decimal::from_str_exact("69946.6").unwrap());
         let Position(.., OrderSettle(current, cost)) = account.get_position("603339").unwrap().unwrap();
         assert_eq!(current, Decimal::from_str_exact("2000").unwrap());
-        assert_eq!(cost, Decimal::from_str_exact("15.0267").unwrap());
+        // 卖出不改变剩余持仓的成本
+        assert_eq!(cost, Decimal::from_str_exact("20.0052").unwrap());
+        // 30000 - 30(印花税) - 7.5(佣金) - 0.3(过户费) - 20005.2(成本)
+        assert_eq!(account.realized_pnl(), Decimal::from_str_exact("9957.0").unwrap());
 
         let records = account.get_transaction("603339").unwrap();
         println!("{:?}", records)
         
     }
+
+    fn bar(open: i32, high: i32, low: i32, close: i32) -> TradeUnit {
+        TradeUnit { open, high, low, close, volume: 0, amount: 0.0 }
+    }
+
+    #[test]
+    fn exit_rules() {
+        let mut account = Account::new("100_000.0", "0.00025").unwrap();
+        let not_hold = account.attach_exit("603339", ExitRule::StopLoss(Decimal::from(18))).unwrap_err();
+        assert_eq!(not_hold, TradeError::OutOfPosition);
+
+        // 跳空低开越过止损价, 按开盘价成交
+        account.buy("603339", "20.0", "1_000").unwrap();
+        account.attach_exit("603339", ExitRule::StopLossPercent(Decimal::from(5))).unwrap();
+        assert_eq!(account.check_exits("603339", &bar(2000, 2050, 1950, 2010)).unwrap(), None);
+        let filled = account.check_exits("603339", &bar(1850, 1900, 1800, 1880)).unwrap();
+        assert_eq!(filled, Some(Decimal::from_str_exact("18.50").unwrap()));
+        let Position(.., OrderSettle(current, ..)) = account.get_position("603339").unwrap().unwrap();
+        assert!(current.is_zero());
+        assert!(account.get_exit("603339").is_none());
+
+        // 盘中触及止损价, 成本 20.0052 的 95% 向下取整为 19.00
+        account.buy("603339", "20.0", "1_000").unwrap();
+        account.attach_exit("603339", ExitRule::StopLossPercent(Decimal::from(5))).unwrap();
+        let filled = account.check_exits("603339", &bar(1950, 1960, 1890, 1900)).unwrap();
+        assert_eq!(filled, Some(Decimal::from_str_exact("19.00").unwrap()));
+
+        // 移动止损跟随最高价
+        account.buy("603338", "20.0", "1_000").unwrap();
+        account.attach_exit("603338", ExitRule::TrailingStop(Decimal::from(10))).unwrap();
+        account.attach_exit("603338", ExitRule::TakeProfit(Decimal::from(30))).unwrap();
+        assert_eq!(account.check_exits("603338", &bar(2100, 2500, 2050, 2400)).unwrap(), None);
+        assert_eq!(account.get_exit("603338").unwrap().stop_price(), Some(Decimal::from_str_exact("22.5").unwrap()));
+        let filled = account.check_exits("603338", &bar(2400, 2420, 2200, 2250)).unwrap();
+        assert_eq!(filled, Some(Decimal::from_str_exact("22.5").unwrap()));
+
+        // 止盈
+        account.buy("603338", "20.0", "1_000").unwrap();
+        account.attach_exit("603338", ExitRule::TakeProfitPercent(Decimal::from(20))).unwrap();
+        let filled = account.check_exits("603338", &bar(2300, 2600, 2300, 2500)).unwrap().unwrap();
+        assert_eq!(filled, Decimal::from_str_exact("24.01").unwrap());
+    }
+
+    #[test]
+    fn fifo_and_equity() {
+        let mut account = Account::new("100_000.0", "0.00025").unwrap().with_cost_method(CostMethod::Fifo);
+        account.buy("603339", "10.0", "1_000").unwrap();
+        account.buy("603339", "20.0", "1_000").unwrap();
+        account.sell("603339", "25.0", "1_000").unwrap();
+
+        // 先进先出: 卖出结转第一批 10.0026 的成本, 剩余第二批
+        let position = account.get_position("603339").unwrap().unwrap();
+        assert_eq!(position.volume(), Decimal::from(1000));
+        assert_eq!(position.cost(), Decimal::from_str_exact("20.0052").unwrap());
+        // 25000 - 25(印花税) - 6.25(佣金) - 0.25(过户费) - 10002.6(成本)
+        assert_eq!(account.realized_pnl(), Decimal::from_str_exact("14965.90").unwrap());
+        assert_eq!(
+            account.unrealized_pnl("603339", Decimal::from(22)),
+            Decimal::from_str_exact("1994.8").unwrap()
+        );
+
+        let prices = [("603339", Decimal::from(22))].into_iter().collect();
+        let balance = account.get_balance();
+        let snapshot = account.snapshot(20230103, &prices).clone();
+        assert_eq!(snapshot.market_value, Decimal::from(22_000));
+        assert_eq!(snapshot.equity, balance + Decimal::from(22_000));
+        assert_eq!(snapshot.unrealized, Decimal::from_str_exact("1994.8").unwrap());
+        account.snapshot(20230103, &prices);
+        assert_eq!(account.equity_curve().len(), 1);
+    }
+
+    #[test]
+    fn orders_in_sequence() {
+        let mut account = Account::new("30_000.0", "0.00025").unwrap();
+        account.set_time(crate::data::date_to_time(20221031).unwrap());
+        account.buy("603339", "10.0", "2_000").unwrap();
+        // 同一天先卖出 603339 再用回笼的资金买入 603338, 导出的顺序与处理顺序一致
+        account.set_time(crate::data::date_to_time(20221101).unwrap());
+        account.sell("603339", "12.0", "2_000").unwrap();
+        account.buy("603338", "20.0", "1_000").unwrap();
+        let orders: Vec<(&str, Decimal)> = account.orders().iter().map(|(code, order)| (*code, order.vol)).collect();
+        assert_eq!(orders, vec![("603339", Decimal::from(2000)), ("603339", Decimal::from(-2000)), ("603338", Decimal::from(1000))]);
+    }
 }
@@ -0,0 +1,279 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::data::{Market, MinuteTradeUnit, StockCode, TradeUnit, WhereIsFrom};
+
+#[derive(Debug, Error)]
+pub enum TickError {
+    #[error("read tick file fail")]
+    IoError {
+        #[from]
+        source: io::Error,
+    },
+    #[error("malformed tick line {0}")]
+    Format(usize),
+    #[error("tick file has no date")]
+    MissingDate,
+}
+
+pub type Result<T, E = TickError> = std::result::Result<T, E>;
+
+/// 主动成交方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Direction {
+    /// 主动买入
+    Buy,
+    /// 主动卖出
+    Sell,
+    /// 集合竞价或无法判断
+    #[default]
+    Neutral,
+}
+
+/// 分笔成交
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tick {
+    /// 日期 例如 20230103
+    pub date: i32,
+    /// 时分秒 例如 93003 表示 09:30:03, 只精确到分钟的数据秒为 0
+    pub time: i32,
+    /// 价格, 单位为分
+    pub price: i32,
+    /// 成交量, 单位为手
+    pub volume: i32,
+    /// 成交笔数, 没有时为 0
+    pub count: i32,
+    pub direction: Direction,
+}
+
+impl Tick {
+    /// 0点至目前的分钟数
+    pub fn minutes(&self) -> i16 {
+        (self.time / 10000 * 60 + self.time / 100 % 100) as i16
+    }
+
+    /// 所属1分钟线的 offset, 与通达信相同: 集合竞价并入 09:31, 每分钟的成交记在下一分钟,
+    /// 11:30 和 15:00 收盘时刻的成交记在当分钟
+    pub fn bar_offset(&self) -> i16 {
+        match self.minutes() {
+            minutes if minutes < 9 * 60 + 30 => 9 * 60 + 31,
+            minutes if (11 * 60 + 30..13 * 60).contains(&minutes) => 11 * 60 + 30,
+            minutes if minutes >= 15 * 60 => 15 * 60,
+            minutes => minutes + 1,
+        }
+    }
+}
+
+/// 读取通达信导出的分笔成交文件, 文件为 GBK 编码
+///
+/// 日期取 `date`, 没有给出时从标题行中找 2023-01-03 或 20230103 格式的日期
+pub fn read_ticks<P: AsRef<Path>>(path: P, date: Option<i32>) -> Result<Vec<Tick>> {
+    let bytes = fs::read(path)?;
+    let (text, ..) = encoding_rs::GBK.decode(&bytes);
+    parse_ticks(&text, date)
+}
+
+/// 解析分笔成交文本
+///
+/// 以 `HH:MM` 或 `HH:MM:SS` 开头的行为一笔成交, 之后依次为价格, 成交量(手)和可选的笔数,
+/// 方向可以是单独一列 B S 买 卖, 也可以紧跟在成交量后面, 例如 `52B`; 其他行视为标题跳过
+pub fn parse_ticks(text: &str, date: Option<i32>) -> Result<Vec<Tick>> {
+    let mut date = date;
+    let mut ticks = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let mut tokens = line.split_whitespace();
+        let time = match tokens.next().and_then(parse_time) {
+            Some(time) => time,
+            None => {
+                if date.is_none() {
+                    date = line.split(|c: char| !c.is_ascii_digit() && c != '-').find_map(parse_date);
+                }
+                continue;
+            }
+        };
+        let mut numbers = vec![];
+        let mut direction = Direction::Neutral;
+        for token in tokens {
+            let (number, suffix) = match token.find(|c: char| !c.is_ascii_digit() && c != '.') {
+                Some(at) => token.split_at(at),
+                None => (token, ""),
+            };
+            match suffix {
+                "" => {}
+                "B" | "b" | "买" | "买入" => direction = Direction::Buy,
+                "S" | "s" | "卖" | "卖出" => direction = Direction::Sell,
+                "N" | "n" | "中性" | "-" | "--" => direction = Direction::Neutral,
+                _ => return Err(TickError::Format(i + 1)),
+            }
+            if !number.is_empty() {
+                numbers.push(number.parse::<f64>().map_err(|_| TickError::Format(i + 1))?);
+            }
+        }
+        let (price, volume, count) = match numbers[..] {
+            [price, volume] => (price, volume, 0.0),
+            [price, volume, count, ..] => (price, volume, count),
+            _ => return Err(TickError::Format(i + 1)),
+        };
+        ticks.push(Tick {
+            date: date.ok_or(TickError::MissingDate)?,
+            time,
+            price: (price * 100.0).round() as i32,
+            volume: volume as i32,
+            count: count as i32,
+            direction,
+        });
+    }
+    Ok(ticks)
+}
+
+/// 时分秒 例如 09:30:03 为 93003
+fn parse_time(token: &str) -> Option<i32> {
+    let mut parts = token.split(':');
+    let hour: i32 = parts.next()?.parse().ok()?;
+    let minute: i32 = parts.next()?.parse().ok()?;
+    let second: i32 = parts.next().map(|second| second.parse().ok()).unwrap_or(Some(0))?;
+    (hour < 24 && minute < 60 && second < 60 && parts.next().is_none()).then_some(hour * 10000 + minute * 100 + second)
+}
+
+fn parse_date(token: &str) -> Option<i32> {
+    let digits: String = token.chars().filter(char::is_ascii_digit).collect();
+    let valid = (token.len() == 8 || token.len() == 10) && digits.len() == 8 && digits.starts_with('2');
+    valid.then(|| digits.parse().ok()).flatten()
+}
+
+/// 分笔成交合成1分钟线, 分笔需要按时间排序
+///
+/// 同一交易日第一根到最后一根K线之间没有成交的分钟按前一分钟收盘价补齐, 成交量为 0, 与通达信的 `.lc1` 一致
+pub fn minute_bars(ticks: &[Tick]) -> Vec<MinuteTradeUnit> {
+    let mut bars: Vec<MinuteTradeUnit> = vec![];
+    for tick in ticks {
+        let offset = tick.bar_offset();
+        let amount = tick.price as f32 * tick.volume as f32;
+        match bars.last_mut() {
+            Some(bar) if bar.date == tick.date && bar.offset == offset => {
+                let data = &mut bar.trade_data;
+                data.high = data.high.max(tick.price);
+                data.low = data.low.min(tick.price);
+                data.close = tick.price;
+                data.volume += tick.volume * 100;
+                data.amount += amount;
+            }
+            last => {
+                // 补齐与上一根K线之间的空白分钟
+                if let Some(last) = last.filter(|last| last.date == tick.date).cloned() {
+                    let close = last.trade_data.close;
+                    for offset in session_offsets().filter(|minute| *minute > last.offset && *minute < offset) {
+                        bars.push(MinuteTradeUnit {
+                            date: tick.date,
+                            offset,
+                            trade_data: TradeUnit { open: close, high: close, low: close, close, volume: 0, amount: 0.0 },
+                        });
+                    }
+                }
+                bars.push(MinuteTradeUnit {
+                    date: tick.date,
+                    offset,
+                    trade_data: TradeUnit {
+                        open: tick.price,
+                        high: tick.price,
+                        low: tick.price,
+                        close: tick.price,
+                        volume: tick.volume * 100,
+                        amount,
+                    },
+                });
+            }
+        }
+    }
+    bars
+}
+
+/// 1分钟线的 offset, 09:31 到 11:30, 13:01 到 15:00
+fn session_offsets() -> impl Iterator<Item = i16> {
+    (9 * 60 + 31..=11 * 60 + 30).chain(13 * 60 + 1..=15 * 60)
+}
+
+/// 分笔成交的来源, 回测按分笔撮合时使用
+pub trait TickSource {
+    /// 某只股票一个交易日按时间排序的分笔, 没有数据时为 None, 数据存在但读取失败时出错
+    fn ticks(&self, code: StockCode, date: i32) -> Result<Option<Vec<Tick>>>;
+}
+
+impl TickSource for HashMap<(StockCode, i32), Vec<Tick>> {
+    fn ticks(&self, code: StockCode, date: i32) -> Result<Option<Vec<Tick>>> {
+        Ok(self.get(&(code, date)).cloned())
+    }
+}
+
+/**
+ * 按交易日存放的通达信分笔导出文件, 即 `root/20230103/sh603339.txt`, 文件名也可以不带市场前缀
+ */
+pub struct TickFiles {
+    root: PathBuf,
+}
+
+impl TickFiles {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        TickFiles { root: root.as_ref().to_path_buf() }
+    }
+}
+
+impl TickSource for TickFiles {
+    fn ticks(&self, code: StockCode, date: i32) -> Result<Option<Vec<Tick>>> {
+        let dir = self.root.join(date.to_string());
+        let market = match code.where_is_from() {
+            Some(Market::SH) => "sh",
+            Some(Market::SZ) => "sz",
+            None => return Ok(None),
+        };
+        let plain = code.strip_prefix("sh").or_else(|| code.strip_prefix("sz")).unwrap_or(code);
+        [format!("{}{}", market, plain), plain.to_string()]
+            .iter()
+            .map(|name| dir.join(format!("{}.txt", name)))
+            .find(|path| path.exists())
+            .map(|path| read_ticks(path, Some(date)))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{minute_bars, parse_ticks, Direction, Tick};
+
+    #[test]
+    fn parse_and_aggregate() {
+        let text = "       四方科技(603339) 2022-11-01 分笔成交明细\n\
+                    时间\t价格\t成交\t笔数\n\
+                    09:25\t13.80\t120\t15\n\
+                    09:30\t13.82\t52B\t6\n\
+                    09:30:41\t13.78\t30 S\t2\n\
+                    09:33\t13.75\t10\t1\tS\n\
+                    15:00\t13.74\t200\n";
+        let ticks = parse_ticks(text, None).unwrap();
+        assert_eq!(ticks.len(), 5);
+        assert_eq!(ticks[0], Tick { date: 20221101, time: 92500, price: 1380, volume: 120, count: 15, direction: Direction::Neutral });
+        assert_eq!((ticks[1].direction, ticks[2].direction, ticks[3].direction), (Direction::Buy, Direction::Sell, Direction::Sell));
+        assert_eq!((ticks[2].time, ticks[4].count), (93041, 0));
+        assert!(parse_ticks("09:30\t13.82\n", Some(20221101)).is_err());
+        assert!(parse_ticks("09:30\t13.82\t10\n", None).is_err());
+
+        let bars = minute_bars(&ticks);
+        // 集合竞价和 09:30 的成交并入 09:31, 09:32 09:33 补齐, 15:00 之前补齐到 14:59
+        assert_eq!(bars[0].time(), 931);
+        assert_eq!(
+            (bars[0].trade_data.open, bars[0].trade_data.high, bars[0].trade_data.low, bars[0].trade_data.close),
+            (1380, 1382, 1378, 1378)
+        );
+        assert_eq!(bars[0].trade_data.volume, 20200);
+        assert_eq!((bars[1].time(), bars[1].trade_data.close, bars[1].trade_data.volume), (932, 1378, 0));
+        assert_eq!((bars[3].time(), bars[3].trade_data.close, bars[3].trade_data.volume), (934, 1375, 1000));
+        assert_eq!(bars.len(), 240);
+        assert_eq!((bars[239].time(), bars[239].trade_data.close), (1500, 1374));
+    }
+}